@@ -0,0 +1,114 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    if env::var_os("CARGO_FEATURE_LTTNG").is_some() && target_os == "linux" {
+        lttng();
+    }
+    if env::var_os("CARGO_FEATURE_ITM").is_some() {
+        itm();
+    }
+    if env::var_os("CARGO_FEATURE_RTT").is_some() {
+        rtt();
+    }
+    if env::var_os("CARGO_FEATURE_STM").is_some() {
+        stm();
+    }
+}
+
+/// Pass on the ITM stimulus port for probes, checking that it's valid.
+fn itm() {
+    println!("cargo:rerun-if-env-changed=PROBE_ITM_PORT");
+    let port = env::var("PROBE_ITM_PORT").unwrap_or_else(|_| "1".into());
+    match port.parse::<u8>() {
+        Ok(n) if n < 32 => println!("cargo:rustc-env=PROBE_ITM_PORT={}", n),
+        _ => panic!("PROBE_ITM_PORT must be a port from 0 to 31, not {:?}", port),
+    }
+}
+
+/// Compile the LTTng-UST tracepoint provider and link it with liblttng-ust.
+fn lttng() {
+    let src = PathBuf::from("src/platform/lttng");
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let obj = out.join("tp.o");
+    let lib = out.join("libprobe_lttng.a");
+
+    println!("cargo:rerun-if-changed=src/platform/lttng/tp.c");
+    println!("cargo:rerun-if-changed=src/platform/lttng/tp.h");
+    println!("cargo:rerun-if-env-changed=CC");
+    println!("cargo:rerun-if-env-changed=AR");
+    println!("cargo:rerun-if-env-changed=CFLAGS");
+
+    let mut cc = Command::new(env::var_os("CC").unwrap_or_else(|| "cc".into()));
+    if let Some(flags) = env::var_os("CFLAGS") {
+        cc.args(flags.to_str().unwrap().split_whitespace());
+    }
+    cc.args(["-c", "-fPIC", "-O2", "-I"])
+        .arg(&src)
+        .arg(src.join("tp.c"))
+        .arg("-o")
+        .arg(&obj);
+    run(cc);
+
+    let mut ar = Command::new(env::var_os("AR").unwrap_or_else(|| "ar".into()));
+    ar.arg("crs").arg(&lib).arg(&obj);
+    run(ar);
+
+    println!("cargo:rustc-link-search=native={}", out.display());
+    println!("cargo:rustc-link-lib=static=probe_lttng");
+    println!("cargo:rustc-link-lib=lttng-ust");
+    println!("cargo:rustc-link-lib=dl");
+}
+
+/// Pass on the size of the RTT channel buffer for probes, checking that it's valid.
+fn rtt() {
+    println!("cargo:rerun-if-env-changed=PROBE_RTT_BUFFER_SIZE");
+    let size = env::var("PROBE_RTT_BUFFER_SIZE").unwrap_or_else(|_| "1024".into());
+    match size.parse::<u32>() {
+        Ok(n) if n >= 16 => println!("cargo:rustc-env=PROBE_RTT_BUFFER_SIZE={}", n),
+        _ => panic!(
+            "PROBE_RTT_BUFFER_SIZE must be at least 16 bytes, not {:?}",
+            size
+        ),
+    }
+}
+
+/// Pass on the STM stimulus port address and channel count for probes.
+fn stm() {
+    println!("cargo:rerun-if-env-changed=PROBE_STM_BASE");
+    println!("cargo:rerun-if-env-changed=PROBE_STM_CHANNELS");
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let base = match env::var("PROBE_STM_BASE") {
+        Ok(base) => base,
+        // Only bare-metal targets need it, for the `stm` backend.
+        Err(_) if target_os != "none" => return,
+        Err(_) => panic!("PROBE_STM_BASE must be set to the address of the STM stimulus ports"),
+    };
+    let hex = base.trim_start_matches("0x").trim_start_matches("0X");
+    match u64::from_str_radix(hex, 16) {
+        Ok(n) if n % 0x100 == 0 => println!("cargo:rustc-env=PROBE_STM_BASE={}", n),
+        _ => panic!(
+            "PROBE_STM_BASE must be an aligned hex address, not {:?}",
+            base
+        ),
+    }
+    let channels = env::var("PROBE_STM_CHANNELS").unwrap_or_else(|_| "65536".into());
+    match channels.parse::<u32>() {
+        Ok(n) if (1..=65536).contains(&n) => println!("cargo:rustc-env=PROBE_STM_CHANNELS={}", n),
+        _ => panic!(
+            "PROBE_STM_CHANNELS must be from 1 to 65536, not {:?}",
+            channels
+        ),
+    }
+}
+
+fn run(mut cmd: Command) {
+    let status = cmd
+        .status()
+        .unwrap_or_else(|e| panic!("failed to run {:?}: {}", cmd, e));
+    assert!(status.success(), "{:?} failed: {}", cmd, status);
+}
@@ -0,0 +1,669 @@
+//! Derive and attribute macros for the `probe` crate
+//!
+//! These are re-exported by `probe` with its `derive` feature, which is how
+//! they should be used.
+
+//
+// DEVELOPER NOTES
+//
+// This only uses `proc_macro`, without `syn`, so `probe` still doesn't pull in
+// any other crates. A struct is simple enough to take apart by hand: its
+// fields are the top-level comma-separated parts of its body, and a named
+// field's name is the identifier before its first `:`. Types can have commas
+// in angle brackets, which aren't groups, so those are counted, apart from
+// the `>` of an `->`.
+//
+// `instrument` only has to find the name of the function, after `fn`, and its
+// body, which is the last group of the item, so it leaves everything else as
+// it is, generics and all. The probes go at the start of the body, after any
+// inner attributes. With `ret` or `err`, the rest of the body is wrapped in a
+// closure that's called at once, with the function's return type, which is
+// whatever is between `->` and the body or `where`, so that its `return`s and
+// `?`s give the value to the return probe.
+//
+// `named`, and `instrument` too, look for the macros of `probe` in the body
+// as an identifier starting with `probe`, a `!`, and a group, and count the
+// commas in the group to find the name, which only ever follows simple
+// arguments. Macros are expanded after the attribute, so this sees the calls
+// as they were written, but not any that come from other macros. The bodies
+// of nested functions are left alone, since they're functions of their own.
+//
+// `namespace` finds them in the same way, and turns their providers into
+// groups like `(foo cache lru)`, which `probe_provider!` joins into a string.
+// An outer module's attribute is expanded first, so an inner one finds its
+// providers as groups already, and leaves them. `instrument` attributes in
+// the module aren't expanded yet either, so their providers are changed in
+// the same way.
+//
+
+use proc_macro::{Delimiter, Group, Ident, Spacing, TokenStream, TokenTree};
+
+/// Derive `probe::ProbeArgs` for a struct, with each of its fields as an
+/// argument, in the order they're declared. Each field is cloned and turned
+/// into an `isize` by `probe::ProbeArg`.
+#[proc_macro_derive(ProbeArgs)]
+pub fn derive_probe_args(input: TokenStream) -> TokenStream {
+    match probe_args(input) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+/// Derive `probe::ProbeArg` for an enum without fields, as the discriminant
+/// of each variant, like `as isize`.
+#[proc_macro_derive(ProbeArg)]
+pub fn derive_probe_arg(input: TokenStream) -> TokenStream {
+    match probe_arg(input) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+/// Instrument a function with probes on its entry and its return, as
+/// `fn_name__entry` and `fn_name__return` of the given provider, with the
+/// parameters listed in `args` as named arguments of the entry probe. With
+/// `ret`, the return probe has the return value, and with `err`, the error of
+/// a `Result`, or `0`.
+#[proc_macro_attribute]
+pub fn instrument(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match instrument_fn(attr, item) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+/// Name the probes in a function that are named `_` after the function, like
+/// `probe!(foo, _, x)`, so their names can't go stale when it's renamed.
+#[proc_macro_attribute]
+pub fn named(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match named_fn(attr, item) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+/// Put the probes in a module in namespaces of their own, with the names of
+/// the module and the modules in it as suffixes of their providers, like
+/// `foo__cache` for `probe!(foo, ...)` in `mod cache`.
+#[proc_macro_attribute]
+pub fn namespace(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match namespace_mod(attr, item) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+fn namespace_mod(attr: TokenStream, item: TokenStream) -> Result<TokenStream, String> {
+    if !attr.is_empty() {
+        return Err("`namespace` doesn't take any arguments".into());
+    }
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let name = tokens
+        .windows(2)
+        .find_map(|pair| match pair {
+            [TokenTree::Ident(keyword), TokenTree::Ident(name)] if keyword.to_string() == "mod" => {
+                Some(name.clone())
+            }
+            _ => None,
+        })
+        .ok_or("namespace can only be used on modules")?;
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+        _ => return Err("namespace can only be used on modules with a body".into()),
+    };
+    let mut new_body = Group::new(Delimiter::Brace, namespace_probes(body.stream(), &[name]));
+    new_body.set_span(body.span());
+    tokens.push(TokenTree::Group(new_body));
+    Ok(tokens.into_iter().collect())
+}
+
+// Adds the modules to the providers of the probes in `stream`, and those of
+// any modules in it to the probes in them.
+fn namespace_probes(stream: TokenStream, modules: &[Ident]) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = stream.into_iter().collect();
+    let mut nested_mod = None;
+    for i in 0..tokens.len() {
+        let group = match &tokens[i] {
+            TokenTree::Ident(keyword) if keyword.to_string() == "mod" => {
+                nested_mod = match tokens.get(i + 1) {
+                    Some(TokenTree::Ident(name)) => Some(name.clone()),
+                    _ => None,
+                };
+                continue;
+            }
+            TokenTree::Group(group) => group,
+            _ => continue,
+        };
+        let mut stream = match nested_mod.take() {
+            Some(name) if group.delimiter() == Delimiter::Brace => {
+                let mut modules = modules.to_vec();
+                modules.push(name);
+                namespace_probes(group.stream(), &modules)
+            }
+            _ => namespace_probes(group.stream(), modules),
+        };
+        if let (Some(TokenTree::Ident(mac)), Some(TokenTree::Punct(bang))) = (
+            i.checked_sub(2).map(|i| &tokens[i]),
+            i.checked_sub(1).map(|i| &tokens[i]),
+        ) {
+            let mac = mac.to_string();
+            if bang.as_char() == '!' && mac.starts_with("probe") {
+                stream = namespace_probe(&mac, stream, modules);
+            }
+        }
+        if let Some(TokenTree::Punct(hash)) = i.checked_sub(1).map(|i| &tokens[i]) {
+            if hash.as_char() == '#' && group.delimiter() == Delimiter::Bracket {
+                stream = namespace_instrument(stream, modules);
+            }
+        }
+        let mut new_group = Group::new(group.delimiter(), stream);
+        new_group.set_span(group.span());
+        tokens[i] = TokenTree::Group(new_group);
+    }
+    tokens.into_iter().collect()
+}
+
+// Adds the modules to the provider of a probe, in the arguments of one of the
+// `probe` macros, as a group like `(foo cache)`, unless it's a group already,
+// from a namespace around this one, or a string, which is the whole provider.
+fn namespace_probe(mac: &str, args: TokenStream, modules: &[Ident]) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = args.into_iter().collect();
+    if let Some(provider) = provider_index(mac, &tokens) {
+        if let TokenTree::Ident(ident) = &tokens[provider] {
+            let mut namespaced = vec![TokenTree::Ident(ident.clone())];
+            namespaced.extend(modules.iter().cloned().map(TokenTree::Ident));
+            let mut group = Group::new(Delimiter::Parenthesis, namespaced.into_iter().collect());
+            group.set_span(ident.span());
+            tokens[provider] = TokenTree::Group(group);
+        }
+    }
+    tokens.into_iter().collect()
+}
+
+// Adds the modules to the provider of an `instrument` attribute, which is the
+// first of its arguments.
+fn namespace_instrument(attr: TokenStream, modules: &[Ident]) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = attr.into_iter().collect();
+    if let [.., TokenTree::Ident(name), TokenTree::Group(args)] = tokens.as_slice() {
+        if name.to_string() == "instrument" && args.delimiter() == Delimiter::Parenthesis {
+            let args = namespace_probe("instrument", args.stream(), modules);
+            let last = tokens.len() - 1;
+            let mut group = Group::new(Delimiter::Parenthesis, args);
+            group.set_span(tokens[last].span());
+            tokens[last] = TokenTree::Group(group);
+        }
+    }
+    tokens.into_iter().collect()
+}
+
+fn named_fn(attr: TokenStream, item: TokenStream) -> Result<TokenStream, String> {
+    if !attr.is_empty() {
+        return Err("`named` doesn't take any arguments".into());
+    }
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let name = match fn_name(&tokens).map(|name| &tokens[name]) {
+        Some(TokenTree::Ident(name)) => name.clone(),
+        _ => return Err("named can only be used on functions".into()),
+    };
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+        _ => return Err("named can only be used on functions with a body".into()),
+    };
+    let mut new_body = Group::new(Delimiter::Brace, name_probes(body.stream(), &name));
+    new_body.set_span(body.span());
+    tokens.push(TokenTree::Group(new_body));
+    Ok(tokens.into_iter().collect())
+}
+
+// The index of the name of a function, after `fn`.
+fn fn_name(tokens: &[TokenTree]) -> Option<usize> {
+    tokens
+        .windows(2)
+        .position(|pair| match pair {
+            [TokenTree::Ident(keyword), TokenTree::Ident(_)] => keyword.to_string() == "fn",
+            _ => false,
+        })
+        .map(|keyword| keyword + 1)
+}
+
+// Names the probes in `stream` that are named `_` after the function, apart
+// from those in the bodies of functions defined in it.
+fn name_probes(stream: TokenStream, function: &Ident) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = stream.into_iter().collect();
+    let mut nested_fn = false;
+    for i in 0..tokens.len() {
+        let group = match &tokens[i] {
+            TokenTree::Ident(keyword) if keyword.to_string() == "fn" => {
+                nested_fn = matches!(tokens.get(i + 1), Some(TokenTree::Ident(_)));
+                continue;
+            }
+            TokenTree::Group(group) => group,
+            _ => continue,
+        };
+        if nested_fn && group.delimiter() == Delimiter::Brace {
+            nested_fn = false;
+            continue;
+        }
+        let mut stream = name_probes(group.stream(), function);
+        if let (Some(TokenTree::Ident(mac)), Some(TokenTree::Punct(bang))) = (
+            i.checked_sub(2).map(|i| &tokens[i]),
+            i.checked_sub(1).map(|i| &tokens[i]),
+        ) {
+            let mac = mac.to_string();
+            if bang.as_char() == '!' && mac.starts_with("probe") {
+                stream = name_probe(&mac, stream, function);
+            }
+        }
+        let mut new_group = Group::new(group.delimiter(), stream);
+        new_group.set_span(group.span());
+        tokens[i] = TokenTree::Group(new_group);
+    }
+    tokens.into_iter().collect()
+}
+
+// Names a probe after the function, if its name is `_`, in the arguments of
+// one of the `probe` macros.
+fn name_probe(mac: &str, args: TokenStream, function: &Ident) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = args.into_iter().collect();
+    let name = match provider_index(mac, &tokens) {
+        Some(provider) => name_index(&tokens, provider),
+        None => return tokens.into_iter().collect(),
+    };
+    let unnamed = match tokens.get(name..) {
+        Some([TokenTree::Ident(ident), rest @ ..]) => {
+            ident.to_string() == "_"
+                && match rest.first() {
+                    None => true,
+                    Some(TokenTree::Punct(next)) => matches!(next.as_char(), ',' | ';'),
+                    Some(_) => false,
+                }
+        }
+        _ => false,
+    };
+    if unnamed {
+        let mut function = function.clone();
+        function.set_span(tokens[name].span());
+        tokens[name] = TokenTree::Ident(function);
+    }
+    tokens.into_iter().collect()
+}
+
+// The index of the provider in the arguments of one of the `probe` macros,
+// which is after the `level:` prefix, if any, and the rate of a sampled or
+// rate-limited probe, if it's on its own, with its version, if any.
+fn provider_index(mac: &str, tokens: &[TokenTree]) -> Option<usize> {
+    let mut fields = vec![0];
+    for (i, token) in tokens.iter().enumerate() {
+        if let TokenTree::Punct(comma) = token {
+            if comma.as_char() == ',' {
+                fields.push(i + 1);
+            }
+        }
+    }
+    let mut provider = match mac {
+        "probe_sampled" | "probe_rate_limited" => 1,
+        _ => 0,
+    };
+    if let Some([TokenTree::Ident(level), TokenTree::Punct(colon), ..]) =
+        fields.get(provider).map(|&start| &tokens[start..])
+    {
+        if level.to_string() == "level" && colon.as_char() == ':' {
+            provider += 1;
+        }
+    }
+    let provider = *fields.get(provider)?;
+    let name = name_index(tokens, provider);
+    if name > provider + 2 && !matches!(tokens.get(provider + 2), Some(TokenTree::Literal(_))) {
+        return None;
+    }
+    match tokens.get(name - 1) {
+        None => Some(provider),
+        Some(TokenTree::Punct(comma)) if comma.as_char() == ',' => Some(provider),
+        _ => None,
+    }
+}
+
+// The index of the name after the provider at `provider`, past its version,
+// like the `2` of `foo@2`, if it has one.
+fn name_index(tokens: &[TokenTree], provider: usize) -> usize {
+    match tokens.get(provider + 1) {
+        Some(TokenTree::Punct(at)) if at.as_char() == '@' => provider + 4,
+        _ => provider + 2,
+    }
+}
+
+fn instrument_fn(attr: TokenStream, item: TokenStream) -> Result<TokenStream, String> {
+    let mut attr = split_fields(attr).into_iter();
+    let provider = match attr.next().as_deref() {
+        Some([TokenTree::Ident(provider)]) => provider.to_string(),
+        Some([TokenTree::Literal(provider)]) => provider.to_string(),
+        Some([provider, TokenTree::Punct(at), TokenTree::Literal(version)])
+            if at.as_char() == '@' && !matches!(provider, TokenTree::Punct(_)) =>
+        {
+            format!("{}@{}", provider, version)
+        }
+        // From `namespace`.
+        Some([TokenTree::Group(provider)]) if provider.delimiter() == Delimiter::Parenthesis => {
+            provider.to_string()
+        }
+        _ => return Err("expected the provider of the probes, like `instrument(foo)`".into()),
+    };
+    let mut args = Vec::new();
+    let mut ret = None;
+    for option in attr {
+        match option.as_slice() {
+            [TokenTree::Ident(key), TokenTree::Group(list)]
+                if key.to_string() == "args" && list.delimiter() == Delimiter::Parenthesis =>
+            {
+                for arg in split_fields(list.stream()) {
+                    match arg.as_slice() {
+                        [TokenTree::Ident(name)] => args.push(name.to_string()),
+                        _ => return Err("expected the names of parameters in `args(...)`".into()),
+                    }
+                }
+            }
+            [TokenTree::Ident(key)] if key.to_string() == "ret" || key.to_string() == "err" => {
+                if ret.is_some() {
+                    return Err("only one of `ret` and `err` can be given".into());
+                }
+                ret = Some(key.to_string());
+            }
+            _ => return Err("expected `args(...)`, `ret` or `err` after the provider".into()),
+        }
+    }
+
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let name = fn_name(&tokens).ok_or("instrument can only be used on functions")?;
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+        _ => return Err("instrument can only be used on functions with a body".into()),
+    };
+    let asyncness = tokens[..name]
+        .iter()
+        .any(|token| matches!(token, TokenTree::Ident(ident) if ident.to_string() == "async"));
+    let ret = match ret {
+        Some(_) if asyncness => {
+            return Err("`ret` and `err` can't be used on an `async fn`".into());
+        }
+        Some(key) => Some((key, return_type(&tokens[name + 1..])?)),
+        None => None,
+    };
+    let function = match &tokens[name] {
+        TokenTree::Ident(function) => function.clone(),
+        _ => unreachable!(),
+    };
+    let name = function.to_string();
+
+    let args: String = args
+        .iter()
+        .map(|arg| format!(", {arg} = ::core::clone::Clone::clone(&{arg})", arg = arg))
+        .collect();
+    let entry = format!(
+        "::probe::probe!({provider}, {name}__entry{args});",
+        provider = provider,
+        name = name,
+        args = args,
+    );
+
+    // Inner attributes have to stay at the start of the body.
+    let span = body.span();
+    let mut body_tokens: Vec<TokenTree> =
+        name_probes(body.stream(), &function).into_iter().collect();
+    let mut start = 0;
+    while let [TokenTree::Punct(hash), TokenTree::Punct(bang), TokenTree::Group(_), ..] =
+        &body_tokens[start..]
+    {
+        if hash.as_char() != '#' || bang.as_char() != '!' {
+            break;
+        }
+        start += 3;
+    }
+    let rest = body_tokens.split_off(start);
+    let mut stream: TokenStream = body_tokens.into_iter().collect();
+    match ret {
+        Some((key, output)) => {
+            // The rest of the body goes in a closure, so that its `return`s
+            // come back here, with the value to give the probe.
+            let value = if key == "ret" {
+                "::probe::ProbeArg::into_probe_arg(::core::clone::Clone::clone(&__probe_return))"
+            } else {
+                "match &__probe_return {
+                    ::core::result::Result::Ok(_) => 0,
+                    ::core::result::Result::Err(err) => {
+                        ::probe::ProbeArg::into_probe_arg(::core::clone::Clone::clone(err))
+                    }
+                }"
+            };
+            let mut body = Group::new(Delimiter::Brace, rest.into_iter().collect());
+            body.set_span(span);
+            let mut closure: TokenStream = "move || ->".parse().unwrap();
+            closure.extend(output);
+            closure.extend(Some(TokenTree::Group(body)));
+            stream.extend(
+                format!(
+                    "{entry}
+                    #[allow(clippy::redundant_closure_call)]
+                    let __probe_return = ",
+                    entry = entry,
+                )
+                .parse::<TokenStream>()
+                .unwrap(),
+            );
+            stream.extend(vec![
+                TokenTree::Group(Group::new(Delimiter::Parenthesis, closure)),
+                TokenTree::Group(Group::new(Delimiter::Parenthesis, TokenStream::new())),
+            ]);
+            stream.extend(
+                format!(
+                    ";
+                    ::probe::probe!({provider}, {name}__return, {key} = {value});
+                    __probe_return",
+                    provider = provider,
+                    name = name,
+                    key = key,
+                    value = value,
+                )
+                .parse::<TokenStream>()
+                .unwrap(),
+            );
+        }
+        None => {
+            stream.extend(
+                format!(
+                    "let __probe_return = ::probe::span::SpanGuard::new(|| {{
+                        ::probe::probe!({provider}, {name}__return);
+                    }});
+                    {entry}",
+                    provider = provider,
+                    name = name,
+                    entry = entry,
+                )
+                .parse::<TokenStream>()
+                .unwrap(),
+            );
+            stream.extend(rest);
+        }
+    }
+    let mut new_body = Group::new(Delimiter::Brace, stream);
+    new_body.set_span(span);
+    tokens.push(TokenTree::Group(new_body));
+    Ok(tokens.into_iter().collect())
+}
+
+// The return type of a function, from the tokens after its name, up to its
+// `where` clause or its body, or `()` if it doesn't have one.
+fn return_type(signature: &[TokenTree]) -> Result<Vec<TokenTree>, String> {
+    let params = signature
+        .iter()
+        .position(|token| matches!(token, TokenTree::Group(group) if group.delimiter() == Delimiter::Parenthesis))
+        .ok_or("expected the parameters of the function")?;
+    let output = match &signature[params + 1..] {
+        [TokenTree::Punct(minus), TokenTree::Punct(gt), output @ ..]
+            if minus.as_char() == '-' && gt.as_char() == '>' =>
+        {
+            output
+        }
+        _ => return Ok("()".parse::<TokenStream>().unwrap().into_iter().collect()),
+    };
+    let output: Vec<_> = output
+        .iter()
+        .cloned()
+        .take_while(
+            |token| !matches!(token, TokenTree::Ident(ident) if ident.to_string() == "where"),
+        )
+        .collect();
+    if let Some(TokenTree::Ident(ident)) = output.first() {
+        if ident.to_string() == "impl" {
+            return Err("`ret` and `err` can't be used with an `impl Trait` return type".into());
+        }
+    }
+    Ok(output)
+}
+
+fn probe_arg(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter();
+    let name = loop {
+        match tokens.next() {
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "enum" => match tokens.next() {
+                Some(TokenTree::Ident(name)) => break name,
+                _ => return Err("expected the name of the enum".into()),
+            },
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => {
+                return Err("ProbeArg can only be derived for enums".into())
+            }
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "union" => {
+                return Err("ProbeArg can only be derived for enums".into())
+            }
+            Some(_) => {}
+            None => return Err("expected an enum".into()),
+        }
+    };
+
+    match tokens.next() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => {
+            for variant in split_fields(body.stream()) {
+                // After the attributes and the name, there's either nothing, or
+                // the `=` of a discriminant, but not the group of any fields.
+                let name = variant
+                    .iter()
+                    .position(|token| matches!(token, TokenTree::Ident(_)));
+                if let Some(TokenTree::Group(_)) = name.and_then(|name| variant.get(name + 1)) {
+                    return Err("ProbeArg can only be derived for enums without fields".into());
+                }
+            }
+        }
+        _ => return Err("ProbeArg can't be derived for generic enums".into()),
+    }
+
+    let output = format!(
+        "impl ::probe::ProbeArg for {name} {{
+            #[inline]
+            fn into_probe_arg(self) -> isize {{
+                self as isize
+            }}
+        }}",
+        name = name,
+    );
+    Ok(output.parse().unwrap())
+}
+
+fn probe_args(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter();
+    let name = loop {
+        match tokens.next() {
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => match tokens.next() {
+                Some(TokenTree::Ident(name)) => break name,
+                _ => return Err("expected the name of the struct".into()),
+            },
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "enum" => {
+                return Err("ProbeArgs can only be derived for structs".into())
+            }
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "union" => {
+                return Err("ProbeArgs can only be derived for structs".into())
+            }
+            Some(_) => {}
+            None => return Err("expected a struct".into()),
+        }
+    };
+
+    let fields = match tokens.next() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => {
+            let mut fields = Vec::new();
+            for field in split_fields(body.stream()) {
+                let colon = field.iter().position(|token| match token {
+                    TokenTree::Punct(punct) => punct.as_char() == ':',
+                    _ => false,
+                });
+                match colon
+                    .and_then(|colon| colon.checked_sub(1))
+                    .map(|i| &field[i])
+                {
+                    Some(TokenTree::Ident(name)) => fields.push(name.to_string()),
+                    _ => return Err("expected the name of a field".into()),
+                }
+            }
+            fields
+        }
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Parenthesis => {
+            let count = split_fields(body.stream()).len();
+            (0..count).map(|i| i.to_string()).collect()
+        }
+        Some(TokenTree::Punct(punct)) if punct.as_char() == ';' => Vec::new(),
+        _ => return Err("ProbeArgs can't be derived for generic structs".into()),
+    };
+
+    let args: Vec<_> = fields
+        .iter()
+        .map(|field| {
+            format!(
+                "::probe::ProbeArg::into_probe_arg(::core::clone::Clone::clone(&self.{}))",
+                field
+            )
+        })
+        .collect();
+    let output = format!(
+        "impl ::probe::arg::ProbeArgs for {name} {{
+            type Args = [isize; {count}];
+
+            #[inline]
+            fn probe_args(&self) -> Self::Args {{
+                [{args}]
+            }}
+        }}",
+        name = name,
+        count = args.len(),
+        args = args.join(", "),
+    );
+    Ok(output.parse().unwrap())
+}
+
+// The tokens of each field, split at the commas between them.
+fn split_fields(body: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    let mut depth = 0;
+    let mut arrow = false;
+    for token in body {
+        if let TokenTree::Punct(punct) = &token {
+            match punct.as_char() {
+                ',' if depth == 0 => {
+                    fields.push(field);
+                    field = Vec::new();
+                    arrow = false;
+                    continue;
+                }
+                '<' => depth += 1,
+                '>' if !arrow => depth -= 1,
+                _ => {}
+            }
+            arrow = punct.as_char() == '-' && punct.spacing() == Spacing::Joint;
+        } else {
+            arrow = false;
+        }
+        field.push(token);
+    }
+    if !field.is_empty() {
+        fields.push(field);
+    }
+    fields
+}
@@ -0,0 +1,1037 @@
+//! Kinds of probe arguments
+//!
+//! Probe arguments are turned into an `isize` by [`ProbeArg`], which is
+//! implemented for integers, `bool`, `char`, and the other types that can be
+//! cast `as isize`, for pointers and references, as their addresses, or 0 for
+//! an `Option` of one that's `None`, and for a `Duration`, as nanoseconds. A
+//! program can implement it for its own types, like newtypes, enums, and
+//! handles. With the `derive` feature, `#[derive(ProbeArg)]` implements it
+//! for an enum without fields, as its discriminant, which is what this does by
+//! hand:
+//!
+//! ```
+//! # use probe::{probe, ProbeArg};
+//! #[derive(Clone, Copy)]
+//! enum State {
+//!     Idle,
+//!     Busy,
+//! }
+//!
+//! impl ProbeArg for State {
+//!     fn into_probe_arg(self) -> isize {
+//!         self as isize
+//!     }
+//! }
+//!
+//! probe!(foo, state, State::Busy);
+//! ```
+//!
+//! Arguments can also be marked with a kind, like `@str`, for values that are
+//! passed as more than one operand, or with a specific type:
+//!
+//! * `@str` - A string, as anything that derefs to `&str`, which is passed as
+//!   a pointer to its bytes and their length. In SystemTap, that's
+//!   `user_string_n($arg1, $arg2)`, and in bpftrace, `str(arg0, arg1)`.
+//!
+//! * `@bytes` - A byte string, as anything that's `AsRef<[u8]>`, like a
+//!   `&[u8]`, a byte array, or a `Vec<u8>`, which is passed as a pointer to
+//!   its bytes and their length, like `@str`. In bpftrace, that's
+//!   `buf(arg0, arg1)`.
+//!
+//! * `@cstr` - A NUL-terminated string, as a `&CStr`, a `CString` with the
+//!   `use_std` feature, or a `*const c_char`, which is passed as just the
+//!   pointer. In SystemTap, that's `user_string($arg1)`, and in bpftrace,
+//!   `str(arg0)`.
+//!
+//! * `@debug` or `@display` - A value that implements `Debug` or `Display`,
+//!   which is formatted into a buffer on the stack, and passed like `@str`.
+//!   Only the first [`FMT_CAPACITY`] bytes are kept. The formatting happens
+//!   when the argument is evaluated, so with [`probe_lazy!`](crate::probe_lazy),
+//!   it's only done while the probe is enabled, but `probe!` always does it.
+//!
+//! * `@payload` - A value that implements [`ProbePayload`], which encodes it
+//!   as bytes, in whatever format the program chooses, like postcard or CBOR
+//!   for a `serde::Serialize` type, and which is passed like `@bytes`. This
+//!   needs the `use_std` feature, for the buffer it's encoded into, which each
+//!   thread reuses. Like `@debug`, it's only encoded while a lazy probe is
+//!   enabled.
+//!
+//! * `@serde` - A value that implements `serde::Serialize`, which is encoded
+//!   with postcard, and passed like `@payload`, for consumers to decode with
+//!   `postcard::from_bytes`. This needs the `serde` feature.
+//!
+//! * `@fields` - A value that implements [`ProbeArgs`], like a struct with
+//!   `#[derive(ProbeArgs)]` and the `derive` feature, which is passed as a
+//!   record of its arguments, as for a probe with more than 12 operands,
+//!   below. A macro can't see the fields of a type, so they can't be operands
+//!   of their own, but the record has all of them, in order.
+//!
+//! * `@location` - A `&Location`, usually from `Location::caller()` in a
+//!   `#[track_caller]` function, so a library's probe can say which call in
+//!   the program it's for, which is passed as a pointer to the bytes of its
+//!   file, their length, and its line, as a `u32`. The file is a string in the
+//!   program's read-only data, so the pointer stays valid.
+//!
+//! * `@u8`, `@i8`, `@u16`, `@i16`, `@u32`, `@i32`, `@u64`, `@i64`, `@usize`,
+//!   or `@isize` - An integer of that type, which is passed with its size and
+//!   signedness, where the platform records them, like SDT notes, so tools
+//!   read e.g. a `u8` of 255 as 255, not -1. Other arguments are `isize`.
+//!   On 32-bit targets, a 64-bit integer is passed as two 32-bit operands, the
+//!   low half first, and then the high half, which is signed for an `i64`, so
+//!   it isn't truncated. [`u64_from_args`] and [`i64_from_args`] put them back
+//!   together.
+//!
+//! * `@u128` or `@i128` - A 128-bit integer, which is passed as two 64-bit
+//!   operands, the low half first, and then the high half, which is signed
+//!   for an `i128`. [`u128_from_args`] and [`i128_from_args`] put them back
+//!   together. On 32-bit targets, each half is two operands of its own, like
+//!   any other 64-bit integer, so there are four.
+//!
+//! * `@f32` or `@f64` - A floating-point number, which is passed as its bits,
+//!   as an unsigned integer of the same size, so a consumer can turn it back
+//!   into a number with [`f32_from_arg`] or [`f64_from_arg`], or a script with
+//!   e.g. a union or pointer cast. On 32-bit targets, the bits of an `f64` are
+//!   two operands, like a `u64`, which [`f64_from_args`] turns back into it.
+//!
+//! ```
+//! # use probe::{probe, probe_lazy};
+//! let path = String::from("/etc/hosts");
+//! let fd = 3;
+//! probe!(foo, open, @str path, fd);
+//!
+//! let packet = vec![0x45, 0x00, 0x00, 0x54];
+//! probe!(foo, recv, @bytes packet);
+//!
+//! let name = std::ffi::CStr::from_bytes_with_nul(b"eth0\0").unwrap();
+//! probe!(foo, link_up, @cstr name);
+//!
+//! let peer = std::net::Ipv4Addr::new(10, 0, 0, 1);
+//! probe_lazy!(foo, connect, @display peer, @debug Some(80));
+//!
+//! let (flags, delta) = (0xffu8, -2i16);
+//! probe!(foo, update, @u8 flags, @i16 delta);
+//!
+//! let load = 0.75;
+//! probe!(foo, load, @f64 load);
+//!
+//! let id = 0x1234_5678_9abc_def0_0fed_cba9_8765_4321u128;
+//! probe!(foo, request, @u128 id);
+//!
+//! #[track_caller]
+//! fn lookup(key: u32) {
+//!     probe!(foo, lookup, @location std::panic::Location::caller(), key);
+//! }
+//! lookup(7);
+//! ```
+//!
+//! The operands count as arguments of their own, e.g. for the number of them
+//! that a platform can take, so the probe above has three.
+//!
+//! An argument can also be marked `@raw("...")`, with an SDT operand
+//! descriptor that's written to the argstr as it is, in place of the one for
+//! its register, e.g. for stap scripts that expect exactly that descriptor,
+//! or for a value that's somewhere an unusual ABI puts it. The argument is
+//! still evaluated, and other platforms pass it like an unmarked argument, but
+//! SDT probes don't pass it in a register, so it's up to the program that the
+//! value is where the descriptor says it is:
+//!
+//! ```
+//! # use probe::probe;
+//! # let x = 1;
+//! probe!(foo, bar, @raw("8@%rdi") x);
+//! ```
+//!
+//! SDT notes have room for at most 12 operands, so a probe with more passes
+//! the first 10 as they are, and the rest in a record on the stack, as an
+//! array of `isize`, with a pointer to it as the 11th argument, and their
+//! number as the 12th. In SystemTap, the 13th operand is then
+//! `user_long($arg11)`, and in bpftrace, `*(int64 *)arg10`, and a consumer in
+//! the same process reads them with [`record`]. The operands in the record are
+//! cast `as isize`, like unmarked arguments, so they keep their values, but not
+//! their sizes. On 32-bit x86, SDT probes only have registers for 6 operands,
+//! so a probe with more is a compile error there.
+//!
+//! Arguments can also be named, before their kind, if any, so tools can label
+//! them, rather than showing `$arg1` and so on:
+//!
+//! ```
+//! # use probe::probe;
+//! # let (i, total, key) = (1, 2, "k");
+//! probe!(foo, step, i = i, total = total, key = @str key);
+//! ```
+//!
+//! The names are recorded for the operands, so `key` above is the pointer, and
+//! `key_len` is its length, and for `@u128` and `@i128`, the high half is e.g.
+//! `id_hi`, and for `@location`, the line is e.g. `caller_line`. Other
+//! arguments of the same probe are `_`. With SDT, they're in an ELF note of
+//! their own, in the `.note.probe` section, with the owner `probe` and the
+//! type 1, which has the provider, the name, and the names of the operands,
+//! separated by spaces, each as a NUL-terminated string. Other platforms don't
+//! record them.
+//!
+//! A marked argument is evaluated once, before it's passed to the probe, so
+//! all of the probe's arguments are evaluated first, in order. A lazy probe
+//! with one checks whether it's enabled before that, so its arguments are
+//! still only evaluated while it is.
+
+//
+// DEVELOPER NOTES
+//
+// Platforms only take plain expressions, so the arguments are lowered to them
+// first. Without any kinds, that's just the arguments as they were. With
+// them, each argument is bound in turn, with a `match` like `format_args!`,
+// so temporaries live until the probe is fired, and marked ones are passed on
+// as their operands. The bindings are all called `arg`, but each is from its
+// own macro expansion, so they're different identifiers.
+//
+// A macro can't know the type of an expression, so integer types are only
+// known from their kinds. The operands' types are passed to the platform in
+// the hidden `__operands` option, which SDT turns into the sizes in the
+// argstr, and which other platforms skip like any option they don't use.
+//
+// Whether a 64-bit value is one operand or two depends on the target, so the
+// macros that bind them are defined for the target that `probe` is built for,
+// which is always the target of the probes. A 128-bit integer is bound as its
+// two 64-bit halves, so they're split again on 32-bit targets.
+//
+// Floats are marked as `f32` or `f64` in `__operands`, but SDT only gives
+// them the size of their bits. Newer <sys/sdt.h> can write an `f` after the
+// size, for values in floating-point registers, but these are in integer
+// registers, and libbpf doesn't parse it.
+//
+// SDT notes only have the size and signedness of each operand, not whether
+// it's a string, so tools can't tell `@cstr` pointers from other arguments,
+// and scripts still read them with `user_string` or `str`.
+//
+// A named argument would parse as an assignment expression, so the arguments
+// are munched one at a time, with the names and kinds ahead of the `expr`,
+// and then passed on as before.
+//
+// A raw descriptor comes to the platform as the operand's type in
+// `__operands`, as a string literal rather than an identifier, so platforms
+// that skip the option pass the argument like any other. In a record, it's
+// just a value, like the other types.
+//
+// The record is only built once the operands are bound, so a probe with more
+// than 12 arguments goes through the binding even without any kinds, and then
+// counts its operands by their types.
+//
+
+use core::ffi::{c_char, CStr};
+use core::fmt;
+use core::ptr::NonNull;
+use core::str;
+use core::time::Duration;
+
+// Parses the arguments of a probe, with their kinds and names, and its
+// options, to the `$target` macro, with the arguments lowered as for
+// `probe_args!`, and the names in the hidden `__names` option, if any.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_parse(
+    ($target:ident, [$($targs:tt)*], [$provider:tt, $name:tt], [level = $level:ident],
+        $args:tt, $names:tt, $(,)? $(; $($opt:tt)+)?
+    ) => (
+        $crate::probe_parse!(@names $target, [$($targs)*],
+            [$provider, $name, [level = $level $(, $($opt)+)?]], $args, $names)
+    );
+    ($target:ident, [$($targs:tt)*], [$provider:tt, $name:tt], [],
+        $args:tt, $names:tt, $(,)? $(; $($opt:tt)+)?
+    ) => (
+        $crate::probe_parse!(@names $target, [$($targs)*],
+            [$provider, $name, [$($($opt)+)?]], $args, $names)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , $arg_name:ident = @raw($desc:literal) $arg:expr $(, $($rest:tt)*)?
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [raw $desc] $arg,],
+            [$($names)* [raw $desc] $arg_name], $(, $($rest)*)?)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , $arg_name:ident = @raw($desc:literal) $arg:expr; $($opt:tt)+
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [raw $desc] $arg,],
+            [$($names)* [raw $desc] $arg_name], ; $($opt)+)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , @raw($desc:literal) $arg:expr $(, $($rest:tt)*)?
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [raw $desc] $arg,],
+            [$($names)* [raw $desc] _], $(, $($rest)*)?)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , @raw($desc:literal) $arg:expr; $($opt:tt)+
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [raw $desc] $arg,],
+            [$($names)* [raw $desc] _], ; $($opt)+)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , $arg_name:ident = $(@$kind:ident)? $arg:expr $(, $($rest:tt)*)?
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [$($kind)?] $arg,],
+            [$($names)* [$($kind)?] $arg_name], $(, $($rest)*)?)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , $arg_name:ident = $(@$kind:ident)? $arg:expr; $($opt:tt)+
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [$($kind)?] $arg,],
+            [$($names)* [$($kind)?] $arg_name], ; $($opt)+)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , $(@$kind:ident)? $arg:expr $(, $($rest:tt)*)?
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [$($kind)?] $arg,],
+            [$($names)* [$($kind)?] _], $(, $($rest)*)?)
+    );
+    ($target:ident, $targs:tt, $head:tt, $level:tt, [$($args:tt)*], [$($names:tt)*],
+        , $(@$kind:ident)? $arg:expr; $($opt:tt)+
+    ) => (
+        $crate::probe_parse!($target, $targs, $head, $level, [$($args)* [$($kind)?] $arg,],
+            [$($names)* [$($kind)?] _], ; $($opt)+)
+    );
+
+    (@names $target:ident, [$($targs:tt)*], [$provider:tt, $name:tt, $opts:tt],
+        [$([$($kind:tt)*] $arg:expr,)*], [$([$($name_kind:tt)*] _)*]
+    ) => (
+        $crate::$target!($($targs)*, [$provider, $name, $opts],
+            [$([$($kind)*])*], $([$($kind)*] $arg,)*)
+    );
+    (@names $target:ident, $targs:tt, [$provider:tt, $name:tt, []], $args:tt, [$($names:tt)*]) => (
+        $crate::probe_parse!(@names $target, $targs,
+            [$provider, $name, [__names = $crate::probe_names!($($names)*)]], $args, [])
+    );
+    (@names $target:ident, $targs:tt, [$provider:tt, $name:tt, [$($opt:tt)+]], $args:tt,
+        [$($names:tt)*]
+    ) => (
+        $crate::probe_parse!(@names $target, $targs,
+            [$provider, $name, [__names = $crate::probe_names!($($names)*), $($opt)+]], $args, [])
+    );
+);
+
+// The names of the operands of the arguments, separated by spaces.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_names(
+    ([$($kind:tt)*] $name:tt) => ($crate::probe_operand_names!([$($kind)*] $name));
+    ([$($kind:tt)*] $name:tt $($rest:tt)+) => (
+        concat!($crate::probe_operand_names!([$($kind)*] $name), " ", $crate::probe_names!($($rest)+))
+    );
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_operand_names(
+    ([str] _) => ("_ _");
+    ([bytes] _) => ("_ _");
+    ([fields] _) => ("_ _");
+    ([payload] _) => ("_ _");
+    ([serde] _) => ("_ _");
+    ([location] _) => ("_ _ _");
+    ([debug] _) => ("_ _");
+    ([display] _) => ("_ _");
+    ([str] $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_len"));
+    ([bytes] $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_len"));
+    ([fields] $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_len"));
+    ([payload] $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_len"));
+    ([serde] $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_len"));
+    ([location] $name:ident) => (
+        concat!(stringify!($name), " ", stringify!($name), "_len ", stringify!($name), "_line")
+    );
+    ([debug] $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_len"));
+    ([display] $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_len"));
+    ([u64] $name:tt) => ($crate::probe_wide_names!(64, $name));
+    ([i64] $name:tt) => ($crate::probe_wide_names!(64, $name));
+    ([f64] $name:tt) => ($crate::probe_wide_names!(64, $name));
+    ([u128] $name:tt) => ($crate::probe_wide_names!(128, $name));
+    ([i128] $name:tt) => ($crate::probe_wide_names!(128, $name));
+    ([$($kind:tt)*] $name:tt) => (stringify!($name));
+);
+
+// Lowers the arguments, with the list of their kinds, to the `$plain` macro
+// if there are no kinds and at most 12 arguments, or the `$bound` macro
+// otherwise.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_args(
+    ($plain:ident, $bound:ident, [$($head:tt)*],
+        [$a:tt $b:tt $c:tt $d:tt $e:tt $f:tt $g:tt $h:tt $i:tt $j:tt $k:tt $l:tt $m:tt $($n:tt)*],
+        $($args:tt)*
+    ) => (
+        $crate::$bound!([$($head)*], [], [], $($args)*)
+    );
+    ($plain:ident, $bound:ident, [$($head:tt)*], [$([])*], $([] $arg:expr,)*) => (
+        $crate::$plain!($($head)*, $($crate::arg::ProbeArg::into_probe_arg($arg),)*)
+    );
+    ($plain:ident, $bound:ident, [$($head:tt)*], [$($kind:tt)*], $($args:tt)*) => (
+        $crate::$bound!([$($head)*], [], [], $($args)*)
+    );
+);
+
+// Binds each argument, and fires the probe with their operands, and their
+// types as the hidden `__operands` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_bind(
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*],) => (
+        $crate::probe_record!([$($head)*], [$($ty)*], $($out)*)
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [] $arg:expr, $($rest:tt)*) => (
+        match $crate::arg::ProbeArg::into_probe_arg($arg) {
+            arg => $crate::probe_bind!([$($head)*], [$($out)* arg,], [$($ty)* isize], $($rest)*),
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [raw $desc:literal] $arg:expr, $($rest:tt)*) => (
+        match $crate::arg::ProbeArg::into_probe_arg($arg) {
+            arg => $crate::probe_bind!([$($head)*], [$($out)* arg,], [$($ty)* $desc], $($rest)*),
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [str] $arg:expr, $($rest:tt)*) => (
+        match &$arg {
+            arg => {
+                let arg: &str = arg;
+                $crate::probe_bind!([$($head)*], [$($out)* arg.as_ptr(), arg.len(),],
+                    [$($ty)* usize usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [bytes] $arg:expr, $($rest:tt)*) => (
+        match &$arg {
+            arg => {
+                let arg: &[u8] = ::core::convert::AsRef::as_ref(arg);
+                $crate::probe_bind!([$($head)*], [$($out)* arg.as_ptr(), arg.len(),],
+                    [$($ty)* usize usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [debug] $arg:expr, $($rest:tt)*) => (
+        match $crate::arg::FmtArg::debug(&$arg) {
+            buf => {
+                let arg = buf.as_str();
+                $crate::probe_bind!([$($head)*], [$($out)* arg.as_ptr(), arg.len(),],
+                    [$($ty)* usize usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [display] $arg:expr, $($rest:tt)*) => (
+        match $crate::arg::FmtArg::display(&$arg) {
+            buf => {
+                let arg = buf.as_str();
+                $crate::probe_bind!([$($head)*], [$($out)* arg.as_ptr(), arg.len(),],
+                    [$($ty)* usize usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [payload] $arg:expr, $($rest:tt)*) => (
+        match $crate::probe_payload!(&$arg) {
+            payload => {
+                let arg = payload.as_bytes();
+                $crate::probe_bind!([$($head)*], [$($out)* arg.as_ptr(), arg.len(),],
+                    [$($ty)* usize usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [serde] $arg:expr, $($rest:tt)*) => (
+        match $crate::probe_serde!(&$arg) {
+            payload => {
+                let arg = payload.as_bytes();
+                $crate::probe_bind!([$($head)*], [$($out)* arg.as_ptr(), arg.len(),],
+                    [$($ty)* usize usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [fields] $arg:expr, $($rest:tt)*) => (
+        match $crate::arg::ProbeArgs::probe_args(&$arg) {
+            args => {
+                let arg: &[isize] = ::core::convert::AsRef::as_ref(&args);
+                $crate::probe_bind!([$($head)*], [$($out)* arg.as_ptr(), arg.len(),],
+                    [$($ty)* usize usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [location] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                let arg: &::core::panic::Location<'_> = arg;
+                $crate::probe_bind!([$($head)*],
+                    [$($out)* arg.file().as_ptr(), arg.file().len(), arg.line(),],
+                    [$($ty)* usize usize u32], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [cstr] $arg:expr, $($rest:tt)*) => (
+        match &$arg {
+            arg => {
+                let arg = $crate::arg::CStrArg::as_c_ptr(arg);
+                $crate::probe_bind!([$($head)*], [$($out)* arg,], [$($ty)* usize], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [u64] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                let arg: u64 = arg;
+                $crate::probe_bind_wide!([$($head)*], [$($out)*], [$($ty)*], u64 arg, $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [i64] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                let arg: i64 = arg;
+                $crate::probe_bind_wide!([$($head)*], [$($out)*], [$($ty)*], i64 arg, $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [u128] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                let arg: u128 = arg;
+                $crate::probe_bind!([$($head)*], [$($out)*], [$($ty)*],
+                    [u64] arg as u64, [u64] (arg >> 64) as u64, $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [i128] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                let arg: i128 = arg;
+                $crate::probe_bind!([$($head)*], [$($out)*], [$($ty)*],
+                    [u64] arg as u64, [i64] (arg >> 64) as i64, $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [f32] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                let arg: f32 = arg;
+                $crate::probe_bind!([$($head)*], [$($out)* arg.to_bits(),], [$($ty)* f32], $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [f64] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                let arg: f64 = arg;
+                $crate::probe_bind_wide!([$($head)*], [$($out)*], [$($ty)*], f64 arg.to_bits(),
+                    $($rest)*)
+            }
+        }
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], [$kind:ident] $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => {
+                $crate::probe_int_kind!($kind);
+                let arg: $kind = arg;
+                $crate::probe_bind!([$($head)*], [$($out)* arg,], [$($ty)* $kind], $($rest)*)
+            }
+        }
+    );
+);
+
+// Fires the probe with its operands, or with the first 10 of them and a record
+// of the rest, if there are more than 12.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_record(
+    ([$provider:tt, $name:tt, [$($opt:tt)*]],
+        [$t1:tt $t2:tt $t3:tt $t4:tt $t5:tt $t6:tt $t7:tt $t8:tt
+            $t9:tt $t10:tt $t11:tt $t12:tt $t13:tt $($tn:tt)*],
+        $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr, $a6:expr, $a7:expr, $a8:expr,
+        $a9:expr, $a10:expr, $($rest:expr,)*
+    ) => ({
+        let record = [$(($rest) as isize),*];
+        $crate::probe_fire!($provider, $name,
+            [__operands = [$t1, $t2, $t3, $t4, $t5, $t6, $t7, $t8, $t9, $t10, usize, usize],
+                $($opt)*],
+            $a1, $a2, $a3, $a4, $a5, $a6, $a7, $a8, $a9, $a10, record.as_ptr(), record.len(),)
+    });
+    ([$provider:tt, $name:tt, [$($opt:tt)*]], [$($ty:tt)*], $($out:tt)*) => (
+        $crate::probe_fire!($provider, $name, [__operands = [$($ty),*], $($opt)*], $($out)*)
+    );
+);
+
+// Binds a 64-bit value, the bits of an `f64` included, as one operand on
+// 64-bit targets, or two 32-bit halves on 32-bit targets, low half first.
+#[cfg(not(target_pointer_width = "32"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_bind_wide(
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], $kind:ident $arg:expr, $($rest:tt)*) => (
+        $crate::probe_bind!([$($head)*], [$($out)* $arg,], [$($ty)* $kind], $($rest)*)
+    );
+);
+
+#[cfg(target_pointer_width = "32")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_bind_wide(
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], i64 $arg:expr, $($rest:tt)*) => (
+        $crate::probe_bind!([$($head)*], [$($out)* $arg as u32, ($arg >> 32) as i32,],
+            [$($ty)* u32 i32], $($rest)*)
+    );
+    ([$($head:tt)*], [$($out:tt)*], [$($ty:tt)*], $kind:ident $arg:expr, $($rest:tt)*) => (
+        $crate::probe_bind!([$($head)*], [$($out)* $arg as u32, ($arg >> 32) as u32,],
+            [$($ty)* u32 u32], $($rest)*)
+    );
+);
+
+// The names of the operands of a 64-bit or 128-bit value.
+#[cfg(not(target_pointer_width = "32"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_wide_names(
+    (64, _) => ("_");
+    (64, $name:ident) => (stringify!($name));
+    (128, _) => ("_ _");
+    (128, $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_hi"));
+);
+
+#[cfg(target_pointer_width = "32")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_wide_names(
+    (64, _) => ("_ _");
+    (64, $name:ident) => (concat!(stringify!($name), " ", stringify!($name), "_hi"));
+    (128, _) => ("_ _ _ _");
+    (128, $name:ident) => (concat!(
+        stringify!($name), " ", stringify!($name), "_1 ",
+        stringify!($name), "_2 ", stringify!($name), "_3"
+    ));
+);
+
+// Encodes a `@payload` argument, which needs `std` for the thread's buffer.
+#[cfg(feature = "use_std")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_payload(
+    ($arg:expr) => ($crate::arg::PayloadArg::encode($arg));
+);
+
+#[cfg(not(feature = "use_std"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_payload(
+    ($arg:expr) => (compile_error!("`@payload` arguments need the `use_std` feature"));
+);
+
+// Encodes a `@serde` argument with postcard, into the buffer of `@payload`.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_serde(
+    ($arg:expr) => ($crate::arg::PayloadArg::encode(&$crate::arg::SerdeArg($arg)));
+);
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_serde(
+    ($arg:expr) => (compile_error!("`@serde` arguments need the `serde` feature"));
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_int_kind(
+    (u8) => ();
+    (i8) => ();
+    (u16) => ();
+    (i16) => ();
+    (u32) => ();
+    (i32) => ();
+    (u64) => ();
+    (i64) => ();
+    (usize) => ();
+    (isize) => ();
+    ($kind:ident) => (
+        compile_error!(concat!("unknown probe argument kind `@", stringify!($kind), "`"))
+    );
+);
+
+// A lazy probe with kinds, which checks whether it's enabled before binding
+// its arguments.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_lazy_bind(
+    ([$provider:tt, $name:tt, [$($opt:tt)*]], [], [], $($args:tt)*) => (
+        if $crate::probe_compiled_in!($provider, [$($opt)*])
+            && $crate::probe_enabled_site!($provider, $name, [$($opt)*])
+        {
+            $crate::probe_bind!([$provider, $name, [$($opt)*]], [], [], $($args)*);
+            true
+        } else {
+            $crate::probe_options!($($opt)*);
+            false
+        }
+    );
+);
+
+/// A value that can be passed as an unmarked probe argument.
+///
+/// With the `derive` feature, `#[derive(ProbeArg)]` implements this for an
+/// enum without fields, as the discriminant of each variant.
+pub trait ProbeArg {
+    /// The value as a probe argument.
+    fn into_probe_arg(self) -> isize;
+}
+
+macro_rules! probe_arg_as_isize(
+    ($($ty:ty),*) => ($(
+        impl ProbeArg for $ty {
+            #[inline]
+            fn into_probe_arg(self) -> isize {
+                self as isize
+            }
+        }
+    )*);
+);
+
+probe_arg_as_isize!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, usize, isize);
+probe_arg_as_isize!(bool, char);
+
+/// Floats are truncated, as with `as isize`, so `@f32` or `@f64` should be used
+/// to pass the number itself.
+impl ProbeArg for f32 {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        self as isize
+    }
+}
+
+/// Floats are truncated, as with `as isize`, so `@f32` or `@f64` should be used
+/// to pass the number itself.
+impl ProbeArg for f64 {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        self as isize
+    }
+}
+
+/// A `Duration` is its number of nanoseconds, up to `isize::MAX`, which is
+/// 292 years on 64-bit targets, but only 2 seconds on 32-bit ones. A time
+/// between two `Instant`s is a `Duration` too, like `start.elapsed()`.
+impl ProbeArg for Duration {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        isize::try_from(self.as_nanos()).unwrap_or(isize::MAX)
+    }
+}
+
+impl<T: ?Sized> ProbeArg for *const T {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        address(self)
+    }
+}
+
+impl<T: ?Sized> ProbeArg for *mut T {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        address(self)
+    }
+}
+
+impl<T: ?Sized> ProbeArg for NonNull<T> {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        address(self.as_ptr())
+    }
+}
+
+/// References are passed as their addresses, like pointers, not their values.
+impl<T: ?Sized> ProbeArg for &T {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        address(self)
+    }
+}
+
+/// References are passed as their addresses, like pointers, not their values.
+impl<T: ?Sized> ProbeArg for &mut T {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        address(self)
+    }
+}
+
+/// `None` is a null pointer, as with the `Option` of a pointer in FFI.
+impl<T: ?Sized> ProbeArg for Option<NonNull<T>> {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        self.map_or(0, ProbeArg::into_probe_arg)
+    }
+}
+
+/// `None` is a null pointer, as with the `Option` of a pointer in FFI.
+impl<T: ?Sized> ProbeArg for Option<&T> {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        self.map_or(0, ProbeArg::into_probe_arg)
+    }
+}
+
+/// `None` is a null pointer, as with the `Option` of a pointer in FFI.
+impl<T: ?Sized> ProbeArg for Option<&mut T> {
+    #[inline]
+    fn into_probe_arg(self) -> isize {
+        self.map_or(0, ProbeArg::into_probe_arg)
+    }
+}
+
+// The address of a pointer, without its metadata. Tracers only read it as a
+// number, so it doesn't need to keep its provenance.
+#[inline]
+fn address<T: ?Sized>(ptr: *const T) -> isize {
+    ptr as *const () as usize as isize
+}
+
+/// A value that can be passed as a record of arguments, with `@fields`.
+///
+/// With the `derive` feature, `#[derive(ProbeArgs)]` implements this for a
+/// struct, with each of its fields as an argument, in the order they're
+/// declared, converted by [`ProbeArg`].
+pub trait ProbeArgs {
+    /// The arguments, usually an array of them.
+    type Args: AsRef<[isize]>;
+
+    /// The value as probe arguments.
+    fn probe_args(&self) -> Self::Args;
+}
+
+/// The rest of the arguments of a probe with more than 12 operands, from its
+/// 11th and 12th arguments, or those of a `@fields` argument, from its two.
+///
+/// # Safety
+///
+/// They must be the arguments of a probe that's still running, i.e. this can
+/// only be called from its handler, and the slice can't outlive it.
+pub unsafe fn record<'a>(ptr: isize, len: isize) -> &'a [isize] {
+    core::slice::from_raw_parts(ptr as *const isize, len as usize)
+}
+
+/// The number of an `@f32` argument, from the probe's argument.
+pub fn f32_from_arg(arg: isize) -> f32 {
+    f32::from_bits(arg as u32)
+}
+
+/// The number of an `@f64` argument, from the probe's argument.
+pub fn f64_from_arg(arg: isize) -> f64 {
+    f64::from_bits(arg as u64)
+}
+
+/// The number of a `@u64` argument on a 32-bit target, from the probe's two
+/// arguments for it.
+pub fn u64_from_args(low: isize, high: isize) -> u64 {
+    (high as u32 as u64) << 32 | low as u32 as u64
+}
+
+/// The number of an `@i64` argument on a 32-bit target, from the probe's two
+/// arguments for it.
+pub fn i64_from_args(low: isize, high: isize) -> i64 {
+    (high as i32 as i64) << 32 | low as u32 as i64
+}
+
+/// The number of an `@f64` argument on a 32-bit target, from the probe's two
+/// arguments for it.
+pub fn f64_from_args(low: isize, high: isize) -> f64 {
+    f64::from_bits(u64_from_args(low, high))
+}
+
+/// The number of a `@u128` argument, from the probe's two arguments for it.
+pub fn u128_from_args(low: isize, high: isize) -> u128 {
+    (high as u64 as u128) << 64 | low as u64 as u128
+}
+
+/// The number of an `@i128` argument, from the probe's two arguments for it.
+pub fn i128_from_args(low: isize, high: isize) -> i128 {
+    (high as i64 as i128) << 64 | low as u64 as i128
+}
+
+/// NUL-terminated strings for `@cstr` arguments.
+#[doc(hidden)]
+pub trait CStrArg {
+    fn as_c_ptr(&self) -> *const c_char;
+}
+
+impl<T: CStrArg + ?Sized> CStrArg for &T {
+    fn as_c_ptr(&self) -> *const c_char {
+        (**self).as_c_ptr()
+    }
+}
+
+impl CStrArg for CStr {
+    fn as_c_ptr(&self) -> *const c_char {
+        self.as_ptr()
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl CStrArg for std::ffi::CString {
+    fn as_c_ptr(&self) -> *const c_char {
+        self.as_ptr()
+    }
+}
+
+impl CStrArg for *const c_char {
+    fn as_c_ptr(&self) -> *const c_char {
+        *self
+    }
+}
+
+impl CStrArg for *mut c_char {
+    fn as_c_ptr(&self) -> *const c_char {
+        *self
+    }
+}
+
+/// The most bytes of a `@debug` or `@display` argument that are passed to the
+/// probe. The rest is cut off, at a character boundary.
+pub const FMT_CAPACITY: usize = 256;
+
+/// A value formatted into a buffer on the stack, for `@debug` and `@display`
+/// arguments.
+#[doc(hidden)]
+pub struct FmtArg {
+    buf: [u8; FMT_CAPACITY],
+    len: usize,
+}
+
+impl FmtArg {
+    #[inline]
+    pub fn debug<T: fmt::Debug + ?Sized>(value: &T) -> Self {
+        let mut arg = FmtArg::new();
+        let _ = fmt::write(&mut arg, format_args!("{:?}", value));
+        arg
+    }
+
+    #[inline]
+    pub fn display<T: fmt::Display + ?Sized>(value: &T) -> Self {
+        let mut arg = FmtArg::new();
+        let _ = fmt::write(&mut arg, format_args!("{}", value));
+        arg
+    }
+
+    fn new() -> Self {
+        FmtArg {
+            buf: [0; FMT_CAPACITY],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are ever written.
+        unsafe { str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl fmt::Write for FmtArg {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = FMT_CAPACITY - self.len;
+        if s.len() <= room {
+            self.buf[self.len..][..s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            return Ok(());
+        }
+        // Keep what fits, and stop the formatting there.
+        let mut end = room;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf[self.len..][..end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Err(fmt::Error)
+    }
+}
+
+/// A value that can be passed as an encoded payload, with `@payload`, which
+/// needs the `use_std` feature.
+///
+/// The encoding is up to the program, as long as its consumers can decode it,
+/// and with the `serde` feature, `@serde` encodes a `serde::Serialize` type
+/// with postcard instead:
+///
+/// ```
+/// use probe::{probe_lazy, ProbePayload};
+///
+/// struct Request {
+///     id: u32,
+///     len: u16,
+/// }
+///
+/// impl ProbePayload for Request {
+///     fn encode_payload(&self, buf: &mut Vec<u8>) {
+///         buf.extend_from_slice(&self.id.to_le_bytes());
+///         buf.extend_from_slice(&self.len.to_le_bytes());
+///     }
+/// }
+///
+/// let request = Request { id: 7, len: 64 };
+/// probe_lazy!(server, request, @payload request);
+/// ```
+#[cfg(feature = "use_std")]
+pub trait ProbePayload {
+    /// Appends the encoded value to `buf`.
+    fn encode_payload(&self, buf: &mut std::vec::Vec<u8>);
+}
+
+#[cfg(feature = "use_std")]
+impl<T: ProbePayload + ?Sized> ProbePayload for &T {
+    fn encode_payload(&self, buf: &mut std::vec::Vec<u8>) {
+        (**self).encode_payload(buf)
+    }
+}
+
+// Each thread keeps the buffer of its last payload, so it's only allocated
+// again for a bigger one.
+#[cfg(feature = "use_std")]
+std::thread_local! {
+    static PAYLOAD: core::cell::Cell<std::vec::Vec<u8>> = const {
+        core::cell::Cell::new(std::vec::Vec::new())
+    };
+}
+
+/// A value encoded into the thread's buffer, for `@payload` arguments.
+#[cfg(feature = "use_std")]
+#[doc(hidden)]
+pub struct PayloadArg {
+    buf: std::vec::Vec<u8>,
+}
+
+#[cfg(feature = "use_std")]
+impl PayloadArg {
+    pub fn encode<T: ProbePayload + ?Sized>(value: &T) -> Self {
+        let mut buf = PAYLOAD
+            .try_with(|payload| payload.take())
+            .unwrap_or_default();
+        buf.clear();
+        value.encode_payload(&mut buf);
+        PayloadArg { buf }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl Drop for PayloadArg {
+    fn drop(&mut self) {
+        let buf = core::mem::take(&mut self.buf);
+        let _ = PAYLOAD.try_with(|payload| payload.set(buf));
+    }
+}
+
+/// A value encoded with postcard, for `@serde` arguments.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub struct SerdeArg<'a, T: ?Sized>(pub &'a T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize + ?Sized> ProbePayload for SerdeArg<'_, T> {
+    fn encode_payload(&self, buf: &mut std::vec::Vec<u8>) {
+        // A value that fails to serialize is left empty, with nowhere to
+        // report it.
+        if let Ok(out) = postcard::to_extend(self.0, core::mem::take(buf)) {
+            *buf = out;
+        }
+    }
+}
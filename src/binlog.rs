@@ -0,0 +1,289 @@
+//! Logging probes in a compact binary format
+//!
+//! With the `binlog` feature, [`start`] writes every probe that's handled by
+//! the [`callback`] module to a binary log, which takes a few bytes for each
+//! probe, so a long capture stays small, e.g. in a file. The [`decode`] module
+//! reads a log back, to process it in a program. [`finish`] ends the log.
+//!
+//! ```no_run
+//! use probe::binlog;
+//! use std::fs::File;
+//! use std::io::{BufReader, BufWriter};
+//!
+//! binlog::start(BufWriter::new(File::create("probes.log").unwrap()));
+//! probe::control::enable("server");
+//! probe::probe!(server, parsed, len = 64);
+//! binlog::finish().unwrap();
+//!
+//! let log = BufReader::new(File::open("probes.log").unwrap());
+//! for event in binlog::decode::Reader::new(log).unwrap() {
+//!     let event = event.unwrap();
+//!     println!("{}:{} {:?}", event.site.provider, event.site.name, event.args);
+//! }
+//! ```
+//!
+//! Each probe site and thread is written once, the first time it's in the
+//! log, as is each string of a site, like its provider or its file, and the
+//! events only refer to them, with their operands as varints, and their times
+//! as the difference from the last event's, from the
+//! [`latency`](crate::latency) clock, since the log started.
+//!
+//! With the `recorder` feature, `write_records` writes the records of the
+//! `recorder` module as a log too, e.g. to save what was in the flight
+//! recorder when something went wrong, and read it back like any other.
+//!
+//! [`callback`]: crate::callback
+
+//
+// DEVELOPER NOTES
+//
+// A log is the magic `probelog`, and the format's version as a varint, and
+// then records, each a tag byte, and its fields:
+//
+//   1, a site: provider, name, names, format, file, line, level byte, span byte
+//   2, a thread: number, name
+//   3, an event: site, thread, time delta, operand count, operands
+//   4, a string: its bytes
+//
+// Integers are unsigned LEB128 varints, operands are zigzag-encoded first, and
+// strings are their length and their bytes. The strings of a site are the
+// numbers of string records, which are numbered from 0 in the order they're
+// written, and each is written once, the first time a site has it, so the
+// sites of a provider, or of a file, share its string, and names and formats
+// are empty for none. Sites are numbered from 0 in the order they're written,
+// and are interned by the address of their `ProbeInfo`, which is a static at
+// each site, or by their ID, for records of the recorder. Threads are numbered
+// like those of the `chrome` module, and written again in each log, like
+// theirs. The decoder takes a new tag as an error, so a new record needs a new
+// version.
+//
+
+use crate::callback::{self, ProbeInfo};
+use crate::latency::Start;
+use crate::span::Span;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::boxed::Box;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::thread;
+use std::vec::Vec;
+
+pub mod decode;
+
+const MAGIC: &[u8; 8] = b"probelog";
+const VERSION: u64 = 1;
+
+const TAG_SITE: u8 = 1;
+const TAG_THREAD: u8 = 2;
+const TAG_EVENT: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+struct Log {
+    out: Box<dyn Write + Send>,
+    start: Start,
+    number: usize,
+    encoder: Encoder,
+    error: Option<io::Error>,
+}
+
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+
+// The number of the next log, and of the next thread.
+static LOGS: AtomicUsize = AtomicUsize::new(1);
+static THREADS: AtomicUsize = AtomicUsize::new(1);
+
+std::thread_local! {
+    static THREAD: usize = THREADS.fetch_add(1, Ordering::Relaxed);
+    // The last log that has the thread.
+    static LOGGED: Cell<usize> = const { Cell::new(0) };
+}
+
+fn log() -> std::sync::MutexGuard<'static, Option<Log>> {
+    LOG.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts a log, written to `out`, by adding a `callback` handler, after
+/// finishing any other.
+pub fn start<W: Write + Send + 'static>(out: W) {
+    let _ = finish();
+    let mut new = Log {
+        out: Box::new(out),
+        start: Start::now(),
+        number: LOGS.fetch_add(1, Ordering::Relaxed),
+        encoder: Encoder::new(),
+        error: None,
+    };
+    new.flush_buf();
+    *log() = Some(new);
+    callback::add_handler(record);
+}
+
+/// Finishes the log, by removing its `callback` handler, and flushing what's
+/// been written, with the first error while it was written, if there was one.
+pub fn finish() -> io::Result<()> {
+    callback::remove_handler(record);
+    match log().take() {
+        Some(mut log) => match log.error {
+            Some(error) => Err(error),
+            None => log.out.flush(),
+        },
+        None => Ok(()),
+    }
+}
+
+/// Writes a probe to the log, if it's started, as a `callback` handler.
+pub fn record(info: &ProbeInfo, args: &[isize]) {
+    let mut log = log();
+    let log = match log.as_mut() {
+        Some(log) => log,
+        None => return,
+    };
+    if log.error.is_some() {
+        return;
+    }
+    // Not while the thread's locals are being destroyed.
+    let thread = THREAD
+        .try_with(|&tid| tid)
+        .and_then(|tid| LOGGED.try_with(|logged| (tid, logged.replace(log.number) == log.number)));
+    let (tid, logged) = match thread {
+        Ok(thread) => thread,
+        Err(_) => return,
+    };
+    let encoder = &mut log.encoder;
+    let site = encoder.site(info as *const ProbeInfo as usize, info);
+    if !logged {
+        encoder.buf.push(TAG_THREAD);
+        varint(&mut encoder.buf, tid as u64);
+        string(&mut encoder.buf, thread::current().name().unwrap_or(""));
+    }
+    encoder.event(site, tid as u64, log.start.elapsed(), args);
+    log.flush_buf();
+}
+
+/// Writes records of the [`recorder`](crate::recorder), like those from
+/// [`recorder::drain`](crate::recorder::drain), to `out`, as a whole log, so a
+/// flight recorder's records can be saved, and read back by the [`decode`]
+/// module, like any other log.
+///
+/// Each record's site is one that the recorder has [`stats`](crate::stats)
+/// of, with the record's ID, or one with an empty provider and name if there's
+/// none. Records don't keep their threads, so their events are all of thread
+/// 0, with only the operands that the recorder keeps, and their times are
+/// since the recorder started, in the order the recorder gives them.
+#[cfg(all(feature = "recorder", target_has_atomic = "64"))]
+pub fn write_records<W: Write>(mut out: W, records: &[crate::recorder::Record]) -> io::Result<()> {
+    let unknown = ProbeInfo::new("", "", 0, 0, false, "", "", "", 0);
+    let mut encoder = Encoder::new();
+    for record in records {
+        let info = crate::recorder::info(record.id);
+        let site = encoder.site(record.id as usize, info.as_ref().unwrap_or(&unknown));
+        encoder.event(site, 0, record.time, record.args());
+    }
+    out.write_all(&encoder.buf)?;
+    out.flush()
+}
+
+impl Log {
+    fn flush_buf(&mut self) {
+        if self.error.is_none() {
+            if let Err(error) = self.out.write_all(&self.encoder.buf) {
+                self.error = Some(error);
+            }
+        }
+        self.encoder.buf.clear();
+    }
+}
+
+// The records of a log, as they're encoded, with the sites and strings that
+// have been written.
+struct Encoder {
+    sites: HashMap<usize, u64>,
+    strings: HashMap<&'static str, u64>,
+    time: u64,
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    // An encoder that starts with the start of a log.
+    fn new() -> Self {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        varint(&mut buf, VERSION);
+        Encoder {
+            sites: HashMap::new(),
+            strings: HashMap::new(),
+            time: 0,
+            buf,
+        }
+    }
+
+    // The number of a site, which is written first if it's new.
+    fn site(&mut self, key: usize, info: &ProbeInfo) -> u64 {
+        if let Some(&site) = self.sites.get(&key) {
+            return site;
+        }
+        let strings = [
+            self.string(info.provider()),
+            self.string(info.name()),
+            self.string(info.names().unwrap_or("")),
+            self.string(info.format().unwrap_or("")),
+            self.string(info.file()),
+        ];
+        let buf = &mut self.buf;
+        buf.push(TAG_SITE);
+        for string in strings {
+            varint(buf, string);
+        }
+        varint(buf, info.line() as u64);
+        buf.push(info.level() as u8);
+        buf.push(match info.span() {
+            None => 0,
+            Some(Span::Begin) => 1,
+            Some(Span::End) => 2,
+        });
+        let site = self.sites.len() as u64;
+        self.sites.insert(key, site);
+        site
+    }
+
+    // The number of a string, which is written first if it's new.
+    fn string(&mut self, new: &'static str) -> u64 {
+        let next = self.strings.len() as u64;
+        let buf = &mut self.buf;
+        *self.strings.entry(new).or_insert_with(|| {
+            buf.push(TAG_STRING);
+            string(buf, new);
+            next
+        })
+    }
+
+    fn event(&mut self, site: u64, thread: u64, time: u64, args: &[isize]) {
+        let time = time.max(self.time);
+        let buf = &mut self.buf;
+        buf.push(TAG_EVENT);
+        varint(buf, site);
+        varint(buf, thread);
+        varint(buf, time - self.time);
+        varint(buf, args.len() as u64);
+        for &arg in args {
+            let arg = arg as i64;
+            varint(buf, ((arg << 1) ^ (arg >> 63)) as u64);
+        }
+        self.time = time;
+    }
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn string(buf: &mut Vec<u8>, string: &str) {
+    varint(buf, string.len() as u64);
+    buf.extend_from_slice(string.as_bytes());
+}
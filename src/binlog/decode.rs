@@ -0,0 +1,209 @@
+//! Reading a binary log
+//!
+//! A [`Reader`] reads the events of a log from the [`binlog`](super) module,
+//! in the order they were written, with their sites, and the names of their
+//! threads. A log that wasn't finished, e.g. after a crash, is read up to its
+//! last whole record, which is followed by an error of the `UnexpectedEof`
+//! kind if the log ends in the middle of one.
+
+use super::{MAGIC, TAG_EVENT, TAG_SITE, TAG_STRING, TAG_THREAD, VERSION};
+use crate::level::Level;
+use crate::span::Span;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read};
+use std::string::String;
+use std::sync::Arc;
+use std::vec::Vec;
+
+/// A probe site in a log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Site {
+    /// The probe's provider.
+    pub provider: String,
+    /// The probe's name.
+    pub name: String,
+    /// The names of the probe's operands, as in
+    /// [`ProbeInfo::names`](crate::callback::ProbeInfo::names).
+    pub names: Option<String>,
+    /// The probe's format, if it has one.
+    pub format: Option<String>,
+    /// The file of the probe's site.
+    pub file: String,
+    /// The line of the probe's site.
+    pub line: u32,
+    /// The probe's level.
+    pub level: Level,
+    /// Which end of a span the probe is, if it's one.
+    pub span: Option<Span>,
+}
+
+/// A probe that fired, in a log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// The time the probe fired, since the log started, in the units of the
+    /// [`latency`](crate::latency) clock, which are nanoseconds by default.
+    pub time: u64,
+    /// The number of the thread the probe fired on.
+    pub thread: u64,
+    /// The probe's site.
+    pub site: Arc<Site>,
+    /// The probe's operands.
+    pub args: Vec<i64>,
+}
+
+/// The events of a log.
+#[derive(Debug)]
+pub struct Reader<R> {
+    read: R,
+    sites: Vec<Arc<Site>>,
+    strings: Vec<String>,
+    threads: HashMap<u64, String>,
+    time: u64,
+}
+
+impl<R: Read> Reader<R> {
+    /// Reads the start of a log, failing if it isn't one, or if it's of a
+    /// newer version.
+    pub fn new(mut read: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        read.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a probe log"));
+        }
+        if varint(&mut read)? != VERSION {
+            return Err(invalid("unknown probe log version"));
+        }
+        Ok(Reader {
+            read,
+            sites: Vec::new(),
+            strings: Vec::new(),
+            threads: HashMap::new(),
+            time: 0,
+        })
+    }
+
+    /// The sites that have been read so far, by their order in the log.
+    pub fn sites(&self) -> &[Arc<Site>] {
+        &self.sites
+    }
+
+    /// The name of a thread, if it has one, and it's been read so far.
+    pub fn thread_name(&self, thread: u64) -> Option<&str> {
+        self.threads.get(&thread).map(String::as_str)
+    }
+
+    fn event(&mut self) -> io::Result<Option<Event>> {
+        loop {
+            let mut tag = [0];
+            match self.read.read(&mut tag) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {}
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+            match tag[0] {
+                TAG_SITE => {
+                    let (read, strings) = (&mut self.read, &self.strings);
+                    let mut string = || -> io::Result<String> {
+                        strings
+                            .get(varint(read)? as usize)
+                            .cloned()
+                            .ok_or_else(|| invalid("site of an unknown string"))
+                    };
+                    let site = Site {
+                        provider: string()?,
+                        name: string()?,
+                        names: Some(string()?).filter(|names| !names.is_empty()),
+                        format: Some(string()?).filter(|format| !format.is_empty()),
+                        file: string()?,
+                        line: varint(read)? as u32,
+                        level: Level::from_u8(byte(read)?)
+                            .ok_or_else(|| invalid("unknown probe level"))?,
+                        span: match byte(read)? {
+                            0 => None,
+                            1 => Some(Span::Begin),
+                            2 => Some(Span::End),
+                            _ => return Err(invalid("unknown span end")),
+                        },
+                    };
+                    self.sites.push(Arc::new(site));
+                }
+                TAG_STRING => {
+                    let string = string(&mut self.read)?;
+                    self.strings.push(string);
+                }
+                TAG_THREAD => {
+                    let thread = varint(&mut self.read)?;
+                    let name = string(&mut self.read)?;
+                    if !name.is_empty() {
+                        self.threads.insert(thread, name);
+                    }
+                }
+                TAG_EVENT => {
+                    let read = &mut self.read;
+                    let site = self
+                        .sites
+                        .get(varint(read)? as usize)
+                        .ok_or_else(|| invalid("event of an unknown site"))?
+                        .clone();
+                    let thread = varint(read)?;
+                    self.time = self.time.saturating_add(varint(read)?);
+                    let len = varint(read)?;
+                    let args = (0..len)
+                        .map(|_| {
+                            let arg = varint(read)?;
+                            Ok((arg >> 1) as i64 ^ -((arg & 1) as i64))
+                        })
+                        .collect::<io::Result<_>>()?;
+                    return Ok(Some(Event {
+                        time: self.time,
+                        thread,
+                        site,
+                        args,
+                    }));
+                }
+                _ => return Err(invalid("unknown record")),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.event().transpose()
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn byte(read: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    read.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn varint(read: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = byte(read)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+fn string(read: &mut impl Read) -> io::Result<String> {
+    let len = varint(read)?;
+    let mut bytes = Vec::new();
+    read.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("string isn't UTF-8"))
+}
@@ -0,0 +1,389 @@
+//! Handling probes in the process
+//!
+//! With the `callback` feature, a program can [`set_handler`] to a function
+//! that's called with every probe that fires while it's enabled in the
+//! program, by its provider's flag or its own in the [`control`] module, on
+//! every platform, as well as whatever the platform does with it. This makes
+//! probes an event bus within the process, where there's no tracer, or for a
+//! program that monitors itself.
+//!
+//! The handler gets the probe's [`ProbeInfo`], and its operands as `isize`s,
+//! as a tracer would see them, so a `@str` argument is its address and its
+//! length, and the operands are named by [`ProbeInfo::names`] where that's
+//! known. Lazy probes evaluate their arguments for it too, and `probe_enabled!`
+//! is `true` for it.
+//!
+//! The handler doesn't take the place of the platform's probe: each probe
+//! still goes to a tracer that's attached to it, like `bpftrace` on an SDT
+//! probe, while it goes to the handler, so a service can expose its probes to
+//! tracers and keep them in its own flight recorder at once. A lazy probe
+//! evaluates its arguments once, if either of them wants it, and both get the
+//! same operands.
+//!
+//! ```
+//! use probe::callback::{self, ProbeInfo};
+//! use probe::probe;
+//!
+//! fn handler(info: &ProbeInfo, args: &[isize]) {
+//!     println!("{}:{} {:?}", info.provider(), info.name(), args);
+//! }
+//!
+//! callback::set_handler(handler);
+//! probe::control::enable("foo");
+//! probe!(foo, bar, 1, 2);
+//! ```
+//!
+//! There can be up to [`MAX_HANDLERS`] handlers at once. [`add_handler`] adds
+//! one alongside the others, and [`remove_handler`] takes it out again, so
+//! consumers can come and go while the program runs, e.g. a recorder only
+//! while a diagnostics mode is on, and [`set_handler`] replaces them all with
+//! one. The recorder and the exporters of this crate, like `chrome`, `jsonl`,
+//! `binlog`, `metrics`, and `shm`, add and remove their own handlers like
+//! this, so any of them can run at once, and like any handler, they only get
+//! the probes that are enabled with the [`control`] module. Each probe goes to
+//! every handler, in no particular order.
+//!
+//! [`control`]: crate::control
+
+//
+// DEVELOPER NOTES
+//
+// The handlers are function pointers in a fixed array of atomics, with a mask
+// of the slots in use, so a probe never locks, and checks for any handler with
+// a relaxed load of the mask before its flags. Adding and removing handlers
+// take a spin lock, so two of them can't both take a slot for the same
+// handler, or set the bit of a slot that another is emptying. A slot's bit is
+// set after it's stored, and cleared before it's emptied, so a probe only
+// calls a handler from a slot whose bit it saw, and skips an empty one. Nothing
+// waits for the probes that are calling a handler when it's removed, which
+// would take a count that every probe updates.
+//
+// The probe macros bind the arguments once, so they can go to both the
+// handlers and the platform's probe, and each site has a static `ProbeInfo`,
+// which a handler can keep, e.g. to compare probes by address.
+//
+
+use crate::level::Level;
+use crate::span::Span;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// A function that handles probes.
+pub type Handler = fn(&ProbeInfo, &[isize]);
+
+/// The most handlers there can be at once.
+pub const MAX_HANDLERS: usize = 8;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static HANDLERS: [AtomicPtr<()>; MAX_HANDLERS] = [EMPTY; MAX_HANDLERS];
+static INSTALLED: AtomicUsize = AtomicUsize::new(0);
+
+// Taken while the handlers are changed.
+static CHANGING: AtomicBool = AtomicBool::new(false);
+
+struct Changing;
+
+fn changing() -> Changing {
+    while CHANGING
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    Changing
+}
+
+impl Drop for Changing {
+    fn drop(&mut self) {
+        CHANGING.store(false, Ordering::Release);
+    }
+}
+
+/// Sets the function that handles probes, replacing any others.
+pub fn set_handler(handler: Handler) {
+    clear_handler();
+    add_handler(handler);
+}
+
+/// Stops handling probes, by removing every handler.
+///
+/// A probe that was already calling a handler on another thread can still be
+/// in it after this returns, so what a handler uses has to outlive any probe
+/// that's in flight, not just this call.
+pub fn clear_handler() {
+    let _changing = changing();
+    for (i, slot) in HANDLERS.iter().enumerate() {
+        INSTALLED.fetch_and(!(1 << i), Ordering::AcqRel);
+        slot.store(ptr::null_mut(), Ordering::Release);
+    }
+}
+
+/// Adds a function that handles probes, alongside any others, unless it's
+/// already there. Returns `false` if there are already [`MAX_HANDLERS`].
+pub fn add_handler(handler: Handler) -> bool {
+    let handler = handler as *mut ();
+    let _changing = changing();
+    if HANDLERS
+        .iter()
+        .any(|slot| slot.load(Ordering::Acquire) == handler)
+    {
+        return true;
+    }
+    for (i, slot) in HANDLERS.iter().enumerate() {
+        if slot.load(Ordering::Acquire).is_null() {
+            slot.store(handler, Ordering::Release);
+            INSTALLED.fetch_or(1 << i, Ordering::AcqRel);
+            return true;
+        }
+    }
+    false
+}
+
+/// Removes a function that handles probes, if it's there, and leaves any
+/// others.
+///
+/// Like [`clear_handler`], this doesn't wait for probes that are already
+/// calling the handler on other threads, which can still be in it after this
+/// returns.
+pub fn remove_handler(handler: Handler) {
+    let handler = handler as *mut ();
+    let _changing = changing();
+    for (i, slot) in HANDLERS.iter().enumerate() {
+        if slot.load(Ordering::Acquire) == handler {
+            INSTALLED.fetch_and(!(1 << i), Ordering::AcqRel);
+            slot.store(ptr::null_mut(), Ordering::Release);
+        }
+    }
+}
+
+/// Whether a function is one of the handlers.
+pub fn has_handler(handler: Handler) -> bool {
+    let handler = handler as *mut ();
+    HANDLERS.iter().enumerate().any(|(i, slot)| {
+        INSTALLED.load(Ordering::Acquire) & 1 << i != 0 && slot.load(Ordering::Acquire) == handler
+    })
+}
+
+// The handlers, as of when it's called.
+fn handlers() -> impl Iterator<Item = Handler> {
+    let installed = INSTALLED.load(Ordering::Acquire);
+    HANDLERS
+        .iter()
+        .enumerate()
+        .filter(move |&(i, _)| installed & 1 << i != 0)
+        .filter_map(|(_, slot)| {
+            let handler = slot.load(Ordering::Acquire);
+            if handler.is_null() {
+                None
+            } else {
+                // SAFETY: a slot is only ever null or a `Handler`, from
+                // `add_handler`.
+                Some(unsafe { core::mem::transmute::<*mut (), Handler>(handler) })
+            }
+        })
+}
+
+/// Whether the probe with these hashes is enabled for the handlers, for the
+/// probe macros.
+#[doc(hidden)]
+#[inline]
+pub fn enabled(provider: u16, probe: u16) -> bool {
+    INSTALLED.load(Ordering::Relaxed) != 0 && crate::control::enabled(provider, probe)
+}
+
+/// Whether a probe that fires goes to the handlers, or to stderr, for the
+/// probe macros.
+#[doc(hidden)]
+#[inline]
+pub fn handled(provider: u16, probe: u16) -> bool {
+    (cfg!(feature = "stderr") && crate::control::live()) || enabled(provider, probe)
+}
+
+/// Calls the handlers that are still there, if the probe is enabled for them,
+/// for the probe macros.
+#[doc(hidden)]
+#[cold]
+#[inline(never)]
+pub fn fire(info: &ProbeInfo, args: &[isize]) {
+    #[cfg(feature = "stderr")]
+    crate::stderr::print(info, args);
+    if !cfg!(feature = "stderr") || crate::control::enabled(info.hashes.0, info.hashes.1) {
+        for handler in handlers() {
+            handler(info, args);
+        }
+    }
+}
+
+/// A probe, as its handler sees it.
+#[derive(Clone, Debug)]
+pub struct ProbeInfo {
+    id: u32,
+    hashes: (u16, u16),
+    provider: &'static str,
+    name: &'static str,
+    level: Level,
+    span: Option<Span>,
+    timed: bool,
+    format: &'static str,
+    names: &'static str,
+    file: &'static str,
+    line: u32,
+}
+
+impl ProbeInfo {
+    #[doc(hidden)]
+    #[allow(clippy::too_many_arguments)]
+    pub const fn new(
+        provider: &'static str,
+        name: &'static str,
+        level: u8,
+        span: u8,
+        timed: bool,
+        format: &'static str,
+        names: &'static str,
+        file: &'static str,
+        line: u32,
+    ) -> Self {
+        let level = match Level::from_u8(level) {
+            Some(level) => level,
+            None => Level::Trace,
+        };
+        let span = match span {
+            1 => Some(Span::Begin),
+            2 => Some(Span::End),
+            _ => None,
+        };
+        ProbeInfo {
+            id: crate::rtt::id(provider, name),
+            hashes: (
+                crate::control::provider(provider),
+                crate::control::probe(provider, name),
+            ),
+            provider,
+            name,
+            level,
+            span,
+            timed,
+            format,
+            names,
+            file,
+            line,
+        }
+    }
+
+    /// The probe's ID, from [`rtt::id`](crate::rtt::id).
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The probe's provider.
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    /// The probe's name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The probe's level.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Which end of a span the probe is, if it's one.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Whether the probe is timed, by [`probe_latency!`](crate::probe_latency),
+    /// so its first operand is the elapsed time.
+    pub fn timed(&self) -> bool {
+        self.timed
+    }
+
+    /// The probe's format, if it has one.
+    pub fn format(&self) -> Option<&'static str> {
+        Some(self.format).filter(|format| !format.is_empty())
+    }
+
+    /// The names of the probe's operands, separated by spaces, with `_` for
+    /// those without one, if any of its arguments are named or have a kind.
+    pub fn names(&self) -> Option<&'static str> {
+        Some(self.names).filter(|names| !names.is_empty())
+    }
+
+    /// The operands of a probe from this site, each with its name, if it has
+    /// one.
+    pub fn operands<'a>(
+        &self,
+        args: &'a [isize],
+    ) -> impl Iterator<Item = (Option<&'static str>, isize)> + 'a {
+        let mut names = self.names.split(' ');
+        args.iter().map(move |&arg| {
+            let name = names.next().filter(|name| !name.is_empty() && *name != "_");
+            (name, arg)
+        })
+    }
+
+    /// The file of the probe's site.
+    pub fn file(&self) -> &'static str {
+        self.file
+    }
+
+    /// The line of the probe's site.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+}
+
+// The names of a probe's operands from its options, or "" without them.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_names_value(
+    () => ("");
+    (__names = $names:expr $(, $($rest:tt)*)?) => ($names);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::probe_names_value!($($($rest)*)?));
+);
+
+// Whether a probe is enabled for the handler, or handled when it fires, by the
+// function of the `callback` module.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_callback_enabled(
+    ($check:ident, $provider:tt, $name:tt) => ({
+        const PROVIDER: u16 = $crate::control::provider($crate::probe_provider!($provider));
+        const PROBE: u16 = $crate::control::probe($crate::probe_provider!($provider), $crate::probe_name!($name));
+        $crate::callback::$check(PROVIDER, PROBE)
+    })
+);
+
+// Binds the operands of a probe, so they go to the handler, if it's enabled,
+// and then to the platform.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_callback(
+    ([$provider:tt, $name:tt, [$($opt:tt)*]], [$($out:tt)*],) => ({
+        if $crate::probe_callback_enabled!(handled, $provider, $name) {
+            static INFO: $crate::callback::ProbeInfo = $crate::callback::ProbeInfo::new(
+                $crate::probe_provider!($provider),
+                $crate::probe_name!($name),
+                $crate::probe_level_value!($($opt)*),
+                $crate::probe_span_value!($($opt)*),
+                $crate::probe_timed_value!($($opt)*),
+                $crate::probe_format_value!($($opt)*),
+                $crate::probe_names_value!($($opt)*),
+                file!(),
+                line!(),
+            );
+            $crate::callback::fire(&INFO, &[$(($out) as isize),*]);
+        }
+        $crate::platform_probe!($provider, $name, [$($opt)*], $($out,)*)
+    });
+    ([$($head:tt)*], [$($out:tt)*], $arg:expr, $($rest:tt)*) => (
+        match $arg {
+            arg => $crate::probe_callback!([$($head)*], [$($out)* arg], $($rest)*),
+        }
+    );
+);
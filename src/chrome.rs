@@ -0,0 +1,189 @@
+//! Exporting probes as a Chrome trace
+//!
+//! With the `chrome` feature, [`start`] writes every probe that's handled by
+//! the [`callback`] module as an event of the Chrome Trace Event Format, in
+//! JSON, which `chrome://tracing` and the Perfetto UI can open, so a program
+//! can be traced where there's no tracer, or without setting one up. [`finish`]
+//! ends the trace.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufWriter;
+//!
+//! let file = File::create("trace.json").unwrap();
+//! probe::chrome::start(BufWriter::new(file));
+//! probe::control::enable("server");
+//!
+//! {
+//!     let _span = probe::probe_span!(server, request, id = 1);
+//!     probe::probe!(server, parsed, len = 64);
+//! }
+//!
+//! probe::chrome::finish().unwrap();
+//! ```
+//!
+//! Each probe is an event of its thread, named by the probe, in a category of
+//! its provider. The two ends of a span, like those of
+//! [`probe_span!`](crate::probe_span), are a duration event, and other probes
+//! are instant events. The operands are the event's arguments, by their names
+//! where they have one, or by their index otherwise, and the timestamps are
+//! from the [`latency`](crate::latency) clock, since the trace started, which
+//! are taken as nanoseconds, and written as microseconds. A clock that's set
+//! with [`latency::set_clock`](crate::latency::set_clock) has to count
+//! nanoseconds too, like the default one, for the times to be right.
+//!
+//! [`callback`]: crate::callback
+
+//
+// DEVELOPER NOTES
+//
+// The trace is the JSON array format, with an event a line, and a comma ahead
+// of every one but the first, so it's valid JSON once it's finished, but the
+// viewers open it without the closing bracket too, after a crash. Events are
+// written while the writer is locked, so those of different threads aren't
+// mixed, and errors are kept for `finish`.
+//
+// Threads are numbered in the order they first write an event, since a
+// `ThreadId` has no number to take yet, and their first event in each trace is
+// preceded by a metadata event with the thread's name, if it has one.
+//
+
+use crate::callback::{self, ProbeInfo};
+use crate::json::Escaped;
+use crate::latency::Start;
+use crate::span::Span;
+use core::cell::Cell;
+use core::fmt::{self, Write as _};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::boxed::Box;
+use std::io::{self, Write};
+use std::string::String;
+use std::sync::Mutex;
+use std::thread;
+
+struct Trace {
+    out: Box<dyn Write + Send>,
+    start: Start,
+    number: usize,
+    first: bool,
+    error: Option<io::Error>,
+}
+
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+// The number of the next trace, and of the next thread.
+static TRACES: AtomicUsize = AtomicUsize::new(1);
+static THREADS: AtomicUsize = AtomicUsize::new(1);
+
+std::thread_local! {
+    static THREAD: usize = THREADS.fetch_add(1, Ordering::Relaxed);
+    // The last trace that has the thread's name.
+    static NAMED: Cell<usize> = const { Cell::new(0) };
+}
+
+fn trace() -> std::sync::MutexGuard<'static, Option<Trace>> {
+    TRACE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts a trace, written to `out`, by adding a `callback` handler, after
+/// finishing any other.
+pub fn start<W: Write + Send + 'static>(out: W) {
+    let _ = finish();
+    let mut trace = trace();
+    let mut new = Trace {
+        out: Box::new(out),
+        start: Start::now(),
+        number: TRACES.fetch_add(1, Ordering::Relaxed),
+        first: true,
+        error: None,
+    };
+    new.write(format_args!("["));
+    *trace = Some(new);
+    callback::add_handler(record);
+}
+
+/// Finishes the trace, by removing its `callback` handler, and ending and
+/// flushing what's been written, with the first error while it was written,
+/// if there was one.
+pub fn finish() -> io::Result<()> {
+    callback::remove_handler(record);
+    match trace().take() {
+        Some(mut trace) => {
+            trace.write(format_args!("\n]\n"));
+            match trace.error {
+                Some(error) => Err(error),
+                None => trace.out.flush(),
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+/// Writes a probe to the trace, if it's started, as a `callback` handler.
+pub fn record(info: &ProbeInfo, args: &[isize]) {
+    let mut trace = trace();
+    let trace = match trace.as_mut() {
+        Some(trace) => trace,
+        None => return,
+    };
+    // Not while the thread's locals are being destroyed.
+    let thread = THREAD
+        .try_with(|&tid| tid)
+        .and_then(|tid| NAMED.try_with(|named| (tid, named.replace(trace.number) == trace.number)));
+    let (tid, named) = match thread {
+        Ok(thread) => thread,
+        Err(_) => return,
+    };
+    let pid = std::process::id();
+    if !named {
+        if let Some(name) = thread::current().name() {
+            trace.event(format_args!(
+                r#"{{"name":"thread_name","ph":"M","pid":{},"tid":{},"args":{{"name":"{}"}}}}"#,
+                pid,
+                tid,
+                Escaped(name)
+            ));
+        }
+    }
+    let ts = trace.start.elapsed();
+    let ph = match info.span() {
+        Some(Span::Begin) => r#""B""#,
+        Some(Span::End) => r#""E""#,
+        None => r#""i","s":"t""#,
+    };
+    let mut fields = String::new();
+    for (i, (name, arg)) in info.operands(args).enumerate() {
+        let comma = if i > 0 { "," } else { "" };
+        let _ = match name {
+            Some(name) => write!(fields, r#"{}"{}":{}"#, comma, Escaped(name), arg),
+            None => write!(fields, r#"{}"{}":{}"#, comma, i, arg),
+        };
+    }
+    trace.event(format_args!(
+        r#"{{"name":"{}","cat":"{}","ph":{},"ts":{}.{:03},"pid":{},"tid":{},"args":{{{}}}}}"#,
+        Escaped(info.name()),
+        Escaped(info.provider()),
+        ph,
+        ts / 1000,
+        ts % 1000,
+        pid,
+        tid,
+        fields
+    ));
+}
+
+impl Trace {
+    fn write(&mut self, args: fmt::Arguments<'_>) {
+        if self.error.is_none() {
+            if let Err(error) = self.out.write_fmt(args) {
+                self.error = Some(error);
+            }
+        }
+    }
+
+    fn event(&mut self, event: fmt::Arguments<'_>) {
+        let comma = if self.first { "" } else { "," };
+        self.first = false;
+        self.write(format_args!("{}\n{}", comma, event));
+    }
+}
@@ -0,0 +1,576 @@
+//! Turning providers on and off from the program
+//!
+//! Where there's no tracer to enable probes, each provider, and each probe,
+//! has a flag that the program can set at runtime instead, which `probe_lazy!`
+//! and `probe_enabled!` check on those platforms, so probes can be switched on
+//! for consumers in the process itself. A probe is enabled if either its own
+//! flag or its provider's is set, and they all start disabled.
+//!
+//! ```
+//! probe::control::enable("foo");
+//! assert!(probe::control::is_enabled("foo"));
+//! probe::control::toggle("foo");
+//! assert!(!probe::control::is_enabled("foo"));
+//! ```
+//!
+//! With the `use_std` feature, [`configure_from_env`] enables the probes
+//! listed in the `PROBE_ENABLE` environment variable, like `RUST_LOG` for
+//! logs, e.g. `PROBE_ENABLE=foo,bar:loop` enables every probe of `foo`, and
+//! `bar`'s `loop` probe. A program calls it once at startup.
+//!
+//! With the `use_std` feature on Unix, [`toggle_on_signal`] flips a provider
+//! whenever the process gets a signal, `SIGUSR2` by default, so an operator
+//! can turn diagnostics on and off in a running service with `kill -USR2`.
+//!
+//! With the `use_std` feature on Unix, [`share`] moves the flags into a named
+//! shared-memory segment, so every process that shares the same name sees the
+//! same flags. A controller process that shares it too can then enable and
+//! disable probes in all of its workers at once, with the same functions.
+//!
+//! There's also a kill switch for every probe, [`kill`], which disables them
+//! whatever their provider's flag, including on platforms that write every
+//! probe without a tracer, like RTT, semihosting, and QNX, so `probe_lazy!`
+//! stops evaluating its arguments there too, until [`revive`].
+//!
+//! Providers are identified by a 16-bit hash of their name, the high half of
+//! [`rtt::id`](crate::rtt::id), and probes by a 16-bit hash of both names, so
+//! two providers or probes whose hashes collide share a flag.
+
+//
+// DEVELOPER NOTES
+//
+// The flags are a bitmap of every 16-bit hash, 8 KiB of atomics, so setting
+// and checking one never allocates or locks, and is safe in a signal handler.
+// Providers and probes share it, with different hashes. The probe macros hash
+// their names at compile time.
+//
+// The flags are used through a pointer, so they can be moved into shared
+// memory, which is mapped once and never unmapped. The flags that were set
+// before are merged into it, so nothing is lost, though one that's set on
+// another thread while it's moved might be.
+//
+// Signal toggles are a fixed table of signal and provider hash pairs, since
+// the handler can't allocate either. `signal` is used rather than `sigaction`,
+// since its struct differs between systems, and every libc here gives it BSD
+// semantics, so the handler stays installed.
+//
+
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+const WORDS: usize = (u16::MAX as usize + 1) / 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const DISABLED: AtomicU32 = AtomicU32::new(0);
+
+/// Every flag, and the kill switch.
+#[repr(C)]
+struct Flags {
+    words: [AtomicU32; WORDS],
+    killed: AtomicU32,
+}
+
+static LOCAL: Flags = Flags {
+    words: [DISABLED; WORDS],
+    killed: DISABLED,
+};
+
+/// The flags in use, which are `LOCAL` unless they're shared.
+static FLAGS: AtomicPtr<Flags> = AtomicPtr::new(&LOCAL as *const Flags as *mut Flags);
+
+fn flags() -> &'static Flags {
+    unsafe { &*FLAGS.load(Ordering::Acquire) }
+}
+
+/// The hash of a provider, which indexes its flag.
+#[doc(hidden)]
+pub const fn provider(provider: &str) -> u16 {
+    (crate::rtt::id(provider, "") >> 16) as u16
+}
+
+/// The hash of a probe, which indexes its flag.
+#[doc(hidden)]
+pub const fn probe(provider: &str, name: &str) -> u16 {
+    let id = crate::rtt::id(provider, name);
+    (id >> 16) as u16 ^ (id as u16).rotate_left(8)
+}
+
+fn flag(hash: u16) -> (&'static AtomicU32, u32) {
+    (&flags().words[hash as usize / 32], 1 << (hash % 32))
+}
+
+fn is_set(hash: u16) -> bool {
+    let (word, bit) = flag(hash);
+    word.load(Ordering::Relaxed) & bit != 0
+}
+
+fn set(hash: u16, enabled: bool) {
+    let (word, bit) = flag(hash);
+    if enabled {
+        word.fetch_or(bit, Ordering::Relaxed);
+    } else {
+        word.fetch_and(!bit, Ordering::Relaxed);
+    }
+}
+
+/// Whether the kill switch is off, for the probe macros.
+#[doc(hidden)]
+#[inline]
+pub fn live() -> bool {
+    flags().killed.load(Ordering::Relaxed) == 0
+}
+
+/// Whether the probe with these hashes is enabled, for the probe macros.
+#[doc(hidden)]
+#[inline]
+pub fn enabled(provider: u16, probe: u16) -> bool {
+    (is_set(provider) || is_set(probe)) && live()
+}
+
+/// Disables every probe, whatever its provider's flag.
+pub fn kill() {
+    flags().killed.store(1, Ordering::Relaxed);
+}
+
+/// Lets probes be enabled again after [`kill`], by their provider's flag, or
+/// on every probe where they don't have one.
+pub fn revive() {
+    flags().killed.store(0, Ordering::Relaxed);
+}
+
+/// Whether every probe is disabled by [`kill`].
+pub fn is_killed() -> bool {
+    !live()
+}
+
+/// Whether a provider is enabled, by its flag, even if probes are disabled by
+/// [`kill`].
+pub fn is_enabled(name: &str) -> bool {
+    is_set(provider(name))
+}
+
+/// Enables a provider's probes.
+pub fn enable(name: &str) {
+    set(provider(name), true);
+}
+
+/// Disables a provider's probes, except those that are enabled by their own
+/// flag.
+pub fn disable(name: &str) {
+    set(provider(name), false);
+}
+
+/// Whether a probe is enabled by its own flag, even if probes are disabled by
+/// [`kill`].
+pub fn is_probe_enabled(provider: &str, name: &str) -> bool {
+    is_set(probe(provider, name))
+}
+
+/// Enables a single probe.
+pub fn enable_probe(provider: &str, name: &str) {
+    set(probe(provider, name), true);
+}
+
+/// Disables a single probe, unless its provider is enabled.
+pub fn disable_probe(provider: &str, name: &str) {
+    set(probe(provider, name), false);
+}
+
+/// Enables the probes in a list like `foo,bar:loop`, of providers, and of
+/// single probes as `provider:name`, separated by commas.
+///
+/// Whitespace around each item is ignored, and nothing is disabled.
+pub fn configure(list: &str) {
+    for item in list
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        match item.split_once(':') {
+            Some((provider, name)) => enable_probe(provider.trim(), name.trim()),
+            None => enable(item),
+        }
+    }
+}
+
+/// Enables the probes listed in the `PROBE_ENABLE` environment variable, as
+/// with [`configure`], if it's set.
+#[cfg(feature = "use_std")]
+pub fn configure_from_env() {
+    if let Some(list) = std::env::var_os("PROBE_ENABLE") {
+        configure(&list.to_string_lossy());
+    }
+}
+
+/// Whether a provider's probes are compiled in, unless they're in a list like
+/// `foo,bar` of disabled providers, which is `PROBE_DISABLE` at build time.
+#[doc(hidden)]
+pub const fn compiled_in(provider: &str, disabled: Option<&str>) -> bool {
+    let list = match disabled {
+        Some(list) => list.as_bytes(),
+        None => return true,
+    };
+    let provider = provider.as_bytes();
+    let mut start = 0;
+    while start <= list.len() {
+        let mut end = start;
+        while end < list.len() && list[end] != b',' {
+            end += 1;
+        }
+        let next = end + 1;
+        while start < end && list[start].is_ascii_whitespace() {
+            start += 1;
+        }
+        while end > start && list[end - 1].is_ascii_whitespace() {
+            end -= 1;
+        }
+        if end - start == provider.len() {
+            let mut i = 0;
+            while i < provider.len() && list[start + i] == provider[i] {
+                i += 1;
+            }
+            if i == provider.len() {
+                return false;
+            }
+        }
+        start = next;
+    }
+    true
+}
+
+/// Enables a provider's probes if they're disabled, or disables them if
+/// they're enabled.
+pub fn toggle(name: &str) {
+    toggle_hash(provider(name));
+}
+
+fn toggle_hash(provider: u16) {
+    let (word, bit) = flag(provider);
+    word.fetch_xor(bit, Ordering::Relaxed);
+}
+
+#[cfg(all(
+    feature = "use_std",
+    any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+        target_os = "illumos",
+        target_os = "solaris",
+    )
+))]
+pub use self::shared::share;
+
+#[cfg(all(
+    feature = "shm",
+    target_has_atomic = "64",
+    target_pointer_width = "64",
+    any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+        target_os = "illumos",
+        target_os = "solaris",
+    )
+))]
+pub(crate) use self::shared::{map, unmap};
+
+#[cfg(all(
+    feature = "use_std",
+    any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+        target_os = "illumos",
+        target_os = "solaris",
+    )
+))]
+mod shared {
+    use super::{Flags, FLAGS};
+    use core::ffi::{c_char, c_int, c_uint, c_void};
+    use core::mem;
+    use core::ptr;
+    use core::sync::atomic::Ordering;
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::FromRawFd;
+
+    #[cfg(all(
+        target_os = "linux",
+        not(any(
+            target_arch = "mips",
+            target_arch = "mips64",
+            target_arch = "sparc",
+            target_arch = "sparc64",
+        ))
+    ))]
+    const O_CREAT: c_int = 0o100;
+
+    #[cfg(any(
+        all(target_os = "linux", any(target_arch = "mips", target_arch = "mips64"),),
+        target_os = "illumos",
+        target_os = "solaris",
+    ))]
+    const O_CREAT: c_int = 0x100;
+
+    #[cfg(any(
+        all(
+            target_os = "linux",
+            any(target_arch = "sparc", target_arch = "sparc64"),
+        ),
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+    ))]
+    const O_CREAT: c_int = 0x200;
+
+    const O_RDONLY: c_int = 0;
+    const O_RDWR: c_int = 2;
+
+    const PROT_READ: c_int = 1;
+    /// PROT_READ | PROT_WRITE
+    const PROT_READ_WRITE: c_int = 3;
+
+    const MAP_SHARED: c_int = 1;
+
+    /// `off_t`, which is a C `long` with glibc and on illumos, and 64-bit
+    /// everywhere else.
+    #[cfg(any(
+        all(target_os = "linux", not(target_env = "musl")),
+        target_os = "illumos",
+        target_os = "solaris",
+    ))]
+    type OffT = core::ffi::c_long;
+
+    #[cfg(not(any(
+        all(target_os = "linux", not(target_env = "musl")),
+        target_os = "illumos",
+        target_os = "solaris",
+    )))]
+    type OffT = i64;
+
+    // `shm_open` was in librt before glibc 2.34, which still has a stub.
+    #[cfg_attr(all(target_os = "linux", target_env = "gnu"), link(name = "rt"))]
+    extern "C" {
+        fn shm_open(name: *const c_char, oflag: c_int, ...) -> c_int;
+    }
+
+    extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: OffT,
+        ) -> *mut c_void;
+    }
+
+    /// Moves the flags into the shared-memory segment called `name`, like
+    /// `/myapp-probes`, creating it if it doesn't exist yet.
+    ///
+    /// The flags that are already set are set in the segment too, so they
+    /// stay set, along with any that other processes have set there. The
+    /// segment is left behind when the processes exit, for the next ones to
+    /// share, until it's removed, e.g. from `/dev/shm` on Linux.
+    pub fn share(name: &str) -> io::Result<()> {
+        let (addr, _) = map(name, Some((mem::size_of::<Flags>(), 0o600)))?;
+        let shared = unsafe { &*(addr as *const Flags) };
+        let current = super::flags();
+        for (word, set) in shared.words.iter().zip(&current.words) {
+            word.fetch_or(set.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        shared
+            .killed
+            .fetch_or(current.killed.load(Ordering::Relaxed), Ordering::Relaxed);
+        FLAGS.store(addr as *mut Flags, Ordering::Release);
+        Ok(())
+    }
+
+    /// Maps the shared-memory segment called `name`, for reading and writing,
+    /// and creates it with at least `len` bytes and the permissions `mode` if
+    /// it's given, or for reading only, with its whole length, otherwise.
+    /// Mappings stay until they're unmapped with [`unmap`].
+    pub(crate) fn map(name: &str, create: Option<(usize, c_uint)>) -> io::Result<(*mut u8, usize)> {
+        let name = CString::new(name).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "segment name contains a NUL")
+        })?;
+        let fd = match create {
+            Some((_, mode)) => unsafe { shm_open(name.as_ptr(), O_RDWR | O_CREAT, mode) },
+            None => unsafe { shm_open(name.as_ptr(), O_RDONLY) },
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let size = file.metadata()?.len();
+        let (len, prot) = match create {
+            Some((len, _)) => {
+                if size < len as u64 {
+                    file.set_len(len as u64)?;
+                }
+                (len, PROT_READ_WRITE)
+            }
+            None => (size as usize, PROT_READ),
+        };
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "segment is empty",
+            ));
+        }
+        let addr = unsafe { mmap(ptr::null_mut(), len, prot, MAP_SHARED, fd, 0) };
+        if addr as usize == !0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((addr as *mut u8, len))
+    }
+
+    /// Unmaps a mapping from [`map`], which mustn't be used after.
+    #[cfg(all(feature = "shm", target_has_atomic = "64", target_pointer_width = "64"))]
+    pub(crate) unsafe fn unmap(addr: *mut u8, len: usize) {
+        extern "C" {
+            fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        }
+
+        munmap(addr as *mut c_void, len);
+    }
+}
+
+#[cfg(all(
+    feature = "use_std",
+    any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+        target_os = "illumos",
+        target_os = "solaris",
+    )
+))]
+pub use self::signal::{toggle_on_signal, SIGUSR2};
+
+#[cfg(all(
+    feature = "use_std",
+    any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+        target_os = "illumos",
+        target_os = "solaris",
+    )
+))]
+mod signal {
+    use core::ffi::c_int;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use std::io;
+
+    /// The number of `SIGUSR2` on this system.
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        not(any(
+            target_arch = "mips",
+            target_arch = "mips64",
+            target_arch = "sparc",
+            target_arch = "sparc64",
+        ))
+    ))]
+    pub const SIGUSR2: c_int = 12;
+
+    /// The number of `SIGUSR2` on this system.
+    #[cfg(any(
+        all(
+            any(target_os = "linux", target_os = "android"),
+            any(target_arch = "mips", target_arch = "mips64"),
+        ),
+        target_os = "illumos",
+        target_os = "solaris",
+    ))]
+    pub const SIGUSR2: c_int = 17;
+
+    /// The number of `SIGUSR2` on this system.
+    #[cfg(any(
+        all(
+            any(target_os = "linux", target_os = "android"),
+            any(target_arch = "sparc", target_arch = "sparc64"),
+        ),
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+    ))]
+    pub const SIGUSR2: c_int = 31;
+
+    /// SIG_ERR
+    const SIG_ERR: usize = !0;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicU32 = AtomicU32::new(0);
+
+    /// Each entry is a signal in the high half and a provider in the low
+    /// half, or zero.
+    static TOGGLES: [AtomicU32; 16] = [EMPTY; 16];
+
+    /// Toggles a provider each time the process gets the signal `signum`, such as
+    /// [`SIGUSR2`].
+    ///
+    /// This replaces any other handler for the signal, and several providers
+    /// can be toggled by the same one. It fails if the signal can't be
+    /// handled, or there are already 16 toggles.
+    pub fn toggle_on_signal(provider: &str, signum: c_int) -> io::Result<()> {
+        let entry = (signum as u32) << 16 | super::provider(provider) as u32;
+        if !TOGGLES.iter().any(|toggle| {
+            toggle
+                .compare_exchange(0, entry, Ordering::Relaxed, Ordering::Relaxed)
+                .map_or_else(|current| current == entry, |_| true)
+        }) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "too many providers toggled on signals",
+            ));
+        }
+        if unsafe { signal(signum, handler) } == SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    extern "C" fn handler(signum: c_int) {
+        for toggle in &TOGGLES {
+            let entry = toggle.load(Ordering::Relaxed);
+            if entry != 0 && entry >> 16 == signum as u32 {
+                super::toggle_hash(entry as u16);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Hit counters
+//!
+//! [`probe_counter!`](crate::probe_counter) fires a probe like `probe!`, and
+//! also counts every hit of its site, whether or not anything is attached, so
+//! a program can report how often its probes were hit when a tracer can't be
+//! attached, e.g. in a container without the privileges for one.
+//!
+//! On Linux, Android, and FreeBSD, each site's [`Counter`] is in the
+//! `probe_counters` section, and [`counters`] has all of them, for a snapshot
+//! of the counts at any time. Elsewhere, the sites still count, but they
+//! can't be listed.
+//!
+//! ```
+//! # use probe::probe_counter;
+//! for i in 0..3 {
+//!     probe_counter!(foo, iteration, i);
+//! }
+//!
+//! # #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+//! for counter in probe::counter::counters() {
+//!     println!("{}:{} {}", counter.provider(), counter.name(), counter.hits());
+//! }
+//! ```
+
+//
+// DEVELOPER NOTES
+//
+// The counters are statics in the macro expansion, so they're per site, like
+// the counters of sampled probes, but with the provider and name, so the
+// linker gathers them into one array in their section. They're all the same
+// type, so there's no padding between them, and the linker defines
+// `__start_probe_counters` and `__stop_probe_counters` around them, since
+// the section name is a C identifier. A probe that's compiled out has an
+// empty array of them, like the entries of probe tables.
+//
+// The section is writable, so it can't be on WebAssembly, where sections are
+// only metadata, or in firmware, where a linker script might not copy it to
+// RAM.
+//
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The hit counter of a probe site.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Counter {
+    provider: &'static str,
+    name: &'static str,
+    hits: AtomicUsize,
+}
+
+impl Counter {
+    #[doc(hidden)]
+    pub const fn new(provider: &'static str, name: &'static str) -> Self {
+        Counter {
+            provider,
+            name,
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The provider of the site's probe.
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    /// The name of the site's probe.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The number of times the site has been hit so far, wrapping at
+    /// `usize::MAX`.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+// So the section is there, for its bounds, even without any counters.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[used]
+#[link_section = "probe_counters"]
+static NONE: [Counter; 0] = [];
+
+/// The counters of all of the `probe_counter!` sites in this program.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub fn counters() -> &'static [Counter] {
+    extern "C" {
+        static __start_probe_counters: u8;
+        static __stop_probe_counters: u8;
+    }
+    unsafe {
+        let start = core::ptr::addr_of!(__start_probe_counters);
+        let stop = core::ptr::addr_of!(__stop_probe_counters);
+        let len = (stop as usize - start as usize) / core::mem::size_of::<Counter>();
+        core::slice::from_raw_parts(start as *const Counter, len)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_counter_hit(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        #[allow(clippy::declare_interior_mutable_const)]
+        const COUNTER: $crate::counter::Counter = $crate::counter::Counter::new(
+            $crate::probe_provider!($provider),
+            $crate::probe_name!($name),
+        );
+        const COMPILED_IN: usize = $crate::probe_compiled_in!($provider, [$($opt)*]) as usize;
+
+        #[used]
+        #[link_section = "probe_counters"]
+        static COUNTERS: [$crate::counter::Counter; COMPILED_IN] = [COUNTER; COMPILED_IN];
+        if let Some(counter) = COUNTERS.first() {
+            counter.hit();
+        }
+    })
+);
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_counter_hit(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        static COUNTER: $crate::counter::Counter = $crate::counter::Counter::new(
+            $crate::probe_provider!($provider),
+            $crate::probe_name!($name),
+        );
+        if $crate::probe_compiled_in!($provider, [$($opt)*]) {
+            COUNTER.hit();
+        }
+    })
+);
@@ -0,0 +1,107 @@
+//! Format strings
+//!
+//! Probes can have a `format` option, a string literal like
+//! `"alloc size=%d align=%d"`, which [`probe_fmt!`](crate::probe_fmt) gives
+//! ahead of the arguments. It isn't formatted when the probe fires. It's kept
+//! with the probe's metadata, so a consumer can show a message from the raw
+//! arguments, with [`write`] or on its own:
+//!
+//! * SDT probes have it in a note in the `.note.probe` section, with the owner
+//!   `probe` and the type 2, which has the provider, the name, and the format,
+//!   each as a NUL-terminated string, like the note of the names of the
+//!   operands described in the [`arg`](crate::arg) module.
+//! * The probe tables of firmware and the records of WebAssembly have it at
+//!   the end of each entry, as described in the [`table`](crate::table) module.
+//!
+//! Other platforms don't record it. A format can't have a `"`, a `\`, or
+//! braces, since it goes into an assembler string as it is.
+
+//
+// DEVELOPER NOTES
+//
+// The format is an option like the level, so it goes everywhere the options
+// go without any changes along the way, and it's checked with the others, so
+// a format that one platform can't take is an error on all of them.
+//
+
+use core::fmt;
+
+// The format of a probe from its options, or "" without one.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_format_value(
+    () => ("");
+    (format = $format:literal $(, $($rest:tt)*)?) => ($format);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::probe_format_value!($($($rest)*)?));
+);
+
+/// Whether a format can be recorded, without a `"`, a `\`, or braces.
+#[doc(hidden)]
+pub const fn valid(format: &str) -> bool {
+    let bytes = format.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if matches!(bytes[i], b'"' | b'\\' | b'{' | b'}') {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Writes a probe's message from its format and its arguments.
+///
+/// Each conversion takes the next argument, as `%d` or `%i` for a signed
+/// integer, `%u` for an unsigned one, `%x` or `%X` for hexadecimal, and `%p`
+/// for a pointer, and `%%` is a `%`. Flags, widths, and length modifiers, like
+/// the `l` of `%ld`, are skipped, and a conversion past the last argument, or
+/// of another kind, is written as it is.
+///
+/// ```
+/// let mut message = String::new();
+/// probe::format::write(&mut message, "alloc size=%d align=%#lx", &[64, 16]).unwrap();
+/// assert_eq!(message, "alloc size=64 align=0x10");
+/// ```
+pub fn write<W: fmt::Write>(w: &mut W, format: &str, args: &[isize]) -> fmt::Result {
+    let mut args = args.iter();
+    let mut rest = format;
+    while let Some(percent) = rest.find('%') {
+        w.write_str(&rest[..percent])?;
+        let spec = &rest[percent + 1..];
+        let end = spec
+            .find(|c: char| {
+                !matches!(
+                    c,
+                    '#' | '-' | '+' | ' ' | '0'..='9' | '.' | 'l' | 'h' | 'z' | 'j' | 't'
+                )
+            })
+            .unwrap_or(spec.len());
+        let alternate = spec[..end].contains('#');
+        let conversion = spec[end..].chars().next();
+        let arg = match conversion {
+            Some('%') if end == 0 => {
+                w.write_char('%')?;
+                rest = &spec[1..];
+                continue;
+            }
+            Some('d' | 'i' | 'u' | 'x' | 'X' | 'p') => args.next(),
+            _ => None,
+        };
+        match (conversion, arg) {
+            (Some('d' | 'i'), Some(&arg)) => write!(w, "{}", arg)?,
+            (Some('u'), Some(&arg)) => write!(w, "{}", arg as usize)?,
+            (Some('x'), Some(&arg)) if alternate => write!(w, "{:#x}", arg as usize)?,
+            (Some('x'), Some(&arg)) => write!(w, "{:x}", arg as usize)?,
+            (Some('X'), Some(&arg)) if alternate => write!(w, "0X{:X}", arg as usize)?,
+            (Some('X'), Some(&arg)) => write!(w, "{:X}", arg as usize)?,
+            (Some('p'), Some(&arg)) => write!(w, "{:#x}", arg as usize)?,
+            _ => {
+                w.write_char('%')?;
+                rest = spec;
+                continue;
+            }
+        }
+        rest = &spec[end + 1..];
+    }
+    w.write_str(rest)
+}
@@ -0,0 +1,85 @@
+//! Probe sites as values
+//!
+//! [`probe_site!`](crate::probe_site) makes a [`ProbeHandle`] for a probe,
+//! which can be passed to generic code that fires it, or checks whether it's
+//! enabled, without knowing which probe it is. The handle is zero-sized, and
+//! its type is unique to the site where it was made, so the probe is still
+//! compiled in there, with its name, like any other.
+
+//
+// DEVELOPER NOTES
+//
+// The macro defines a unit type for the site, which implements `Site` with
+// `probe_enabled!` and `probe!`, and the handle is generic over it. Probes
+// need the number of arguments at compile time, so `fire` always passes
+// `MAX_ARGS` of them, padded with zeros, for a single site with a fixed
+// signature. Six is the most that every platform can take.
+//
+
+use core::fmt;
+use core::marker::PhantomData;
+
+/// The number of arguments that [`ProbeHandle::fire`] passes to the probe.
+pub const MAX_ARGS: usize = 6;
+
+/// A probe site, implemented by the type that
+/// [`probe_site!`](crate::probe_site) defines for each site.
+pub trait Site {
+    /// Whether the probe is enabled, as with
+    /// [`probe_enabled!`](crate::probe_enabled).
+    fn enabled() -> bool;
+
+    /// Fires the probe with exactly [`MAX_ARGS`] arguments.
+    fn fire(args: [isize; MAX_ARGS]);
+}
+
+/// A probe that can be fired from anywhere, made by
+/// [`probe_site!`](crate::probe_site).
+pub struct ProbeHandle<S> {
+    site: PhantomData<S>,
+}
+
+impl<S: Site> ProbeHandle<S> {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        ProbeHandle { site: PhantomData }
+    }
+
+    /// Returns `true` if a debugger or tracing tool is attached to the probe.
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        S::enabled()
+    }
+
+    /// Fires the probe with `args`.
+    ///
+    /// The probe always has [`MAX_ARGS`] arguments, so `args` is padded with
+    /// zeros, and any arguments after that are dropped.
+    #[inline]
+    pub fn fire(&self, args: &[isize]) {
+        let mut padded = [0; MAX_ARGS];
+        let len = args.len().min(MAX_ARGS);
+        padded[..len].copy_from_slice(&args[..len]);
+        S::fire(padded);
+    }
+}
+
+impl<S> Clone for ProbeHandle<S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for ProbeHandle<S> {}
+
+impl<S> Default for ProbeHandle<S> {
+    fn default() -> Self {
+        ProbeHandle { site: PhantomData }
+    }
+}
+
+impl<S> fmt::Debug for ProbeHandle<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProbeHandle")
+    }
+}
@@ -0,0 +1,164 @@
+//! Histograms
+//!
+//! [`probe_histogram!`](crate::probe_histogram) fires a probe with a value as
+//! its first argument, like `probe!`, and also counts the value in a log2
+//! histogram of its site on every hit, whether or not anything is attached,
+//! so a program can report the distribution of e.g. its latencies or sizes
+//! without a tracer.
+//!
+//! The value is anything that implements [`ProbeArg`](crate::ProbeArg), like
+//! a `Duration`, as nanoseconds, and it's counted as an unsigned number, so
+//! negative values are in the last buckets. Bucket 0 counts zeros, and bucket
+//! `k` counts the values from `2^(k-1)` up to `2^k - 1`, so there are
+//! [`BUCKETS`] of them, one more than the bits of a `usize`.
+//!
+//! On Linux, Android, and FreeBSD, each site's [`Histogram`] is in the
+//! `probe_histograms` section, and [`histograms`] has all of them. Elsewhere,
+//! the sites still count, but they can't be listed.
+//!
+//! ```
+//! # use probe::probe_histogram;
+//! # use std::time::Instant;
+//! let start = Instant::now();
+//! probe_histogram!(foo, request, start.elapsed());
+//!
+//! # #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+//! for histogram in probe::histogram::histograms() {
+//!     let buckets = histogram.buckets();
+//!     println!("{}:{} {:?}", histogram.provider(), histogram.name(), buckets);
+//! }
+//! ```
+
+//
+// DEVELOPER NOTES
+//
+// The histograms are kept like the counters of `probe_counter!`, in a section
+// of their own, with an empty array of them for a probe that's compiled out.
+// Each bucket is counted with a relaxed `fetch_add`, as is the sum, so a
+// snapshot can be taken while they're counting, though its buckets might be
+// from slightly different moments. The sum is a `usize`, for targets without
+// 64-bit atomics, so it wraps around rather than taking a CAS loop on every
+// hit.
+//
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of buckets in a histogram.
+pub const BUCKETS: usize = usize::BITS as usize + 1;
+
+/// The histogram of the values of a probe site.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Histogram {
+    provider: &'static str,
+    name: &'static str,
+    buckets: [AtomicUsize; BUCKETS],
+    sum: AtomicUsize,
+}
+
+impl Histogram {
+    #[doc(hidden)]
+    pub const fn new(provider: &'static str, name: &'static str) -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Histogram {
+            provider,
+            name,
+            buckets: [ZERO; BUCKETS],
+            sum: ZERO,
+        }
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn record(&self, value: isize) {
+        self.buckets[bucket(value)].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value as usize, Ordering::Relaxed);
+    }
+
+    /// The provider of the site's probe.
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    /// The name of the site's probe.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// A snapshot of the counts of the buckets.
+    pub fn buckets(&self) -> [usize; BUCKETS] {
+        let mut buckets = [0; BUCKETS];
+        for (count, bucket) in buckets.iter_mut().zip(&self.buckets) {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+        buckets
+    }
+
+    /// The sum of the values, as unsigned numbers, which wraps around.
+    pub fn sum(&self) -> usize {
+        self.sum.load(Ordering::Relaxed)
+    }
+}
+
+/// The bucket that counts a value, as a probe argument.
+pub fn bucket(value: isize) -> usize {
+    (usize::BITS - (value as usize).leading_zeros()) as usize
+}
+
+// So the section is there, for its bounds, even without any histograms.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[used]
+#[link_section = "probe_histograms"]
+static NONE: [Histogram; 0] = [];
+
+/// The histograms of all of the `probe_histogram!` sites in this program.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub fn histograms() -> &'static [Histogram] {
+    extern "C" {
+        static __start_probe_histograms: u8;
+        static __stop_probe_histograms: u8;
+    }
+    unsafe {
+        let start = core::ptr::addr_of!(__start_probe_histograms);
+        let stop = core::ptr::addr_of!(__stop_probe_histograms);
+        let len = (stop as usize - start as usize) / core::mem::size_of::<Histogram>();
+        core::slice::from_raw_parts(start as *const Histogram, len)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_histogram_record(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $value:expr) => ({
+        #[allow(clippy::declare_interior_mutable_const)]
+        const HISTOGRAM: $crate::histogram::Histogram = $crate::histogram::Histogram::new(
+            $crate::probe_provider!($provider),
+            $crate::probe_name!($name),
+        );
+        const COMPILED_IN: usize = $crate::probe_compiled_in!($provider, [$($opt)*]) as usize;
+
+        #[used]
+        #[link_section = "probe_histograms"]
+        static HISTOGRAMS: [$crate::histogram::Histogram; COMPILED_IN] = [HISTOGRAM; COMPILED_IN];
+        if let Some(histogram) = HISTOGRAMS.first() {
+            histogram.record($value);
+        }
+    })
+);
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_histogram_record(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $value:expr) => ({
+        static HISTOGRAM: $crate::histogram::Histogram = $crate::histogram::Histogram::new(
+            $crate::probe_provider!($provider),
+            $crate::probe_name!($name),
+        );
+        if $crate::probe_compiled_in!($provider, [$($opt)*]) {
+            HISTOGRAM.record($value);
+        }
+    })
+);
@@ -0,0 +1,82 @@
+//! Instrumented functions
+//!
+//! With the `derive` feature, [`#[instrument]`](crate::instrument) puts a probe
+//! on the entry to a function, and another on its return, so a whole module
+//! can be traced without touching the bodies of its functions:
+//!
+//! ```
+//! #[probe::instrument(db, args(key))]
+//! fn lookup(key: u32) -> Option<u32> {
+//!     if key == 0 {
+//!         return None;
+//!     }
+//!     Some(key * 2)
+//! }
+//! # assert_eq!(lookup(21), Some(42));
+//! ```
+//!
+//! The probes are `lookup__entry` and `lookup__return` of the provider `db`,
+//! and the parameters in `args`, if any, are named arguments of the entry
+//! probe, cloned and turned into an `isize` by [`ProbeArg`](crate::ProbeArg).
+//! The return probe fires whenever the function returns, early or not, and
+//! also when it unwinds, since it's from a guard that's dropped then. For an
+//! `async fn`, that's when its future finishes, or is dropped.
+//!
+//! With `ret`, the return probe has the value the function returns instead, as
+//! its `ret` argument, and with `err`, it has `0` if a function that returns a
+//! `Result` succeeds, or its error if it doesn't, as its `err` argument, which
+//! is often an enum that derives [`ProbeArg`](crate::ProbeArg), so tools can
+//! match outcomes to the arguments of the entry probe:
+//!
+//! ```
+//! # #[derive(Clone, Copy, Debug, probe::ProbeArg)]
+//! # enum Error {
+//! #     Empty = 1,
+//! # }
+//! #[probe::instrument(db, args(len), err)]
+//! fn check(len: usize) -> Result<usize, Error> {
+//!     if len == 0 {
+//!         return Err(Error::Empty);
+//!     }
+//!     Ok(len)
+//! }
+//! # assert!(check(0).is_err());
+//! ```
+//!
+//! The value is cloned for the probe. Either way, the body runs in a closure
+//! to get it, so `ret` and `err` can't be used on an `async fn`, or with an
+//! `impl Trait` return type, and the return probe doesn't fire on unwinding.
+//!
+//! Probes in the body that are named `_`, like `probe!(db, _, key)`, are
+//! named after the function, as they are with [`#[named]`](crate::named),
+//! which only does that, for probes that should follow the function's name
+//! when it's renamed:
+//!
+//! ```
+//! #[probe::named]
+//! fn evict(key: u32) {
+//!     // The probe `evict` of the provider `db`.
+//!     probe::probe!(db, _, key);
+//! }
+//! # evict(1);
+//! ```
+//!
+//! [`#[namespace]`](crate::namespace) on a module suffixes the providers of
+//! the probes in it with the name of the module, and those of the modules in
+//! it, like `db__cache__lru`, so the probes of each module are in a namespace
+//! of their own, without having to name them that way by hand:
+//!
+//! ```
+//! #[probe::namespace]
+//! mod cache {
+//!     pub fn evict(key: u32) {
+//!         // The probe `evict` of the provider `db__cache`.
+//!         probe::probe!(db, evict, key);
+//!     }
+//! }
+//! # cache::evict(1);
+//! ```
+//!
+//! A module has to be inline, with its body in the same file, for an attribute
+//! to see the probes in it, and `namespace` only sees probes written in it,
+//! not those that come from other macros.
@@ -0,0 +1,156 @@
+//! Streaming probes as JSON lines
+//!
+//! With the `jsonl` feature, [`start`] writes every probe that's handled by
+//! the [`callback`] module as a JSON object on its own line, as it fires, so
+//! the probes of a program can go into a log pipeline, like Vector or Fluent
+//! Bit, e.g. while an incident is debugged, without a tracer. [`finish`] stops
+//! writing them.
+//!
+//! ```no_run
+//! probe::jsonl::start(std::io::stdout());
+//! probe::control::enable("server");
+//!
+//! {
+//!     let _span = probe::probe_span!(server, request, id = 1);
+//!     probe::probe!(server, parsed, len = 64);
+//! }
+//!
+//! probe::jsonl::finish().unwrap();
+//! ```
+//!
+//! Each line is like this, with `span` only for the two ends of a span, like
+//! those of [`probe_span!`](crate::probe_span), `message` only for probes with
+//! a format, and `thread_name` only for threads with a name:
+//!
+//! ```json
+//! {"ts":1500,"provider":"server","name":"parsed","level":"trace","thread":1,"thread_name":"main","args":{"len":64},"file":"src/main.rs","line":5}
+//! ```
+//!
+//! The operands are in `args`, by their names where they have one, or by their
+//! index otherwise, and `ts` is from the [`latency`](crate::latency) clock,
+//! which counts nanoseconds by default, since the stream started. Threads are
+//! numbered in the order they first write a line.
+//!
+//! [`callback`]: crate::callback
+
+//
+// DEVELOPER NOTES
+//
+// Each line is built in a buffer first, and written with one `write_all` while
+// the writer is locked, so the lines of different threads aren't mixed, and a
+// line-buffered sink gets a whole line at a time. Errors are kept for
+// `finish`, like those of the `chrome` module.
+//
+
+use crate::callback::{self, ProbeInfo};
+use crate::json::Escaped;
+use crate::latency::Start;
+use crate::span::Span;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::boxed::Box;
+use std::io::{self, Write};
+use std::string::String;
+use std::sync::Mutex;
+use std::thread;
+
+struct Stream {
+    out: Box<dyn Write + Send>,
+    start: Start,
+    error: Option<io::Error>,
+}
+
+static STREAM: Mutex<Option<Stream>> = Mutex::new(None);
+
+static THREADS: AtomicUsize = AtomicUsize::new(1);
+
+std::thread_local! {
+    static THREAD: usize = THREADS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn stream() -> std::sync::MutexGuard<'static, Option<Stream>> {
+    STREAM.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts writing probes to `out`, by adding a `callback` handler, after
+/// finishing any other stream.
+pub fn start<W: Write + Send + 'static>(out: W) {
+    let _ = finish();
+    *stream() = Some(Stream {
+        out: Box::new(out),
+        start: Start::now(),
+        error: None,
+    });
+    callback::add_handler(record);
+}
+
+/// Stops writing probes, by removing its `callback` handler, and flushes what's
+/// been written, with the first error while it was written, if there was one.
+pub fn finish() -> io::Result<()> {
+    callback::remove_handler(record);
+    match stream().take() {
+        Some(mut stream) => match stream.error {
+            Some(error) => Err(error),
+            None => stream.out.flush(),
+        },
+        None => Ok(()),
+    }
+}
+
+/// Writes a probe to the stream, if it's started, as a `callback` handler.
+pub fn record(info: &ProbeInfo, args: &[isize]) {
+    let mut stream = stream();
+    let stream = match stream.as_mut() {
+        Some(stream) => stream,
+        None => return,
+    };
+    if stream.error.is_some() {
+        return;
+    }
+    // Not while the thread's locals are being destroyed.
+    let tid = match THREAD.try_with(|&tid| tid) {
+        Ok(tid) => tid,
+        Err(_) => return,
+    };
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        r#"{{"ts":{},"provider":"{}","name":"{}","level":"{}","thread":{}"#,
+        stream.start.elapsed(),
+        Escaped(info.provider()),
+        Escaped(info.name()),
+        info.level().as_str(),
+        tid
+    );
+    if let Some(name) = thread::current().name() {
+        let _ = write!(line, r#","thread_name":"{}""#, Escaped(name));
+    }
+    match info.span() {
+        Some(Span::Begin) => line.push_str(r#","span":"begin""#),
+        Some(Span::End) => line.push_str(r#","span":"end""#),
+        None => {}
+    }
+    line.push_str(r#","args":{"#);
+    for (i, (name, arg)) in info.operands(args).enumerate() {
+        let comma = if i > 0 { "," } else { "" };
+        let _ = match name {
+            Some(name) => write!(line, r#"{}"{}":{}"#, comma, Escaped(name), arg),
+            None => write!(line, r#"{}"{}":{}"#, comma, i, arg),
+        };
+    }
+    line.push('}');
+    if let Some(format) = info.format() {
+        let mut message = String::new();
+        let _ = crate::format::write(&mut message, format, args);
+        let _ = write!(line, r#","message":"{}""#, Escaped(&message));
+    }
+    let _ = writeln!(
+        line,
+        r#","file":"{}","line":{}}}"#,
+        Escaped(info.file()),
+        info.line()
+    );
+    if let Err(error) = stream.out.write_all(line.as_bytes()) {
+        stream.error = Some(error);
+    }
+}
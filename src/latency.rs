@@ -0,0 +1,117 @@
+//! Timed probes
+//!
+//! [`probe_latency!`](crate::probe_latency) evaluates an expression, and fires
+//! a probe with how long it took as its first argument, `elapsed`, before any
+//! others. It only reads the clock while the probe is enabled, so it costs the
+//! same as a lazy probe otherwise.
+//!
+//! The clock is the system's monotonic clock with the `use_std` feature, in
+//! nanoseconds, or the one the program gives [`set_clock`], in whatever units
+//! it counts, e.g. the TSC, or a cycle counter in firmware. Without either, the
+//! elapsed time is 0.
+//!
+//! A handler of the `callback` feature can tell a timed probe by
+//! `ProbeInfo::timed`, rather than by the name of its first operand.
+
+//
+// DEVELOPER NOTES
+//
+// `Instant` doesn't have a raw value to take, so the start is either a reading
+// of the program's clock, or an `Instant`, and the elapsed time is taken the
+// same way it was started, even if the clock is set in between.
+//
+// The elapsed time is an unmarked argument, so it's an `isize` like the
+// others, which is enough for about 2 seconds of nanoseconds on 32-bit
+// targets.
+//
+
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(all(
+    feature = "use_std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+use std::time::Instant;
+
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+pub enum Start {
+    Clock(u64),
+    #[cfg(all(
+        feature = "use_std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    Instant(Instant),
+    Unknown,
+}
+
+impl Start {
+    #[doc(hidden)]
+    #[inline]
+    pub fn now() -> Self {
+        let clock = CLOCK.load(Ordering::Relaxed);
+        if !clock.is_null() {
+            let clock = unsafe { mem::transmute::<*mut (), fn() -> u64>(clock) };
+            return Start::Clock(clock());
+        }
+        system_start()
+    }
+
+    /// The time since the start, in the units of its clock.
+    #[doc(hidden)]
+    #[inline]
+    pub fn elapsed(self) -> u64 {
+        match self {
+            Start::Clock(start) => {
+                let clock = CLOCK.load(Ordering::Relaxed);
+                if clock.is_null() {
+                    return 0;
+                }
+                let clock = unsafe { mem::transmute::<*mut (), fn() -> u64>(clock) };
+                clock().wrapping_sub(start)
+            }
+            #[cfg(all(
+                feature = "use_std",
+                not(all(target_arch = "wasm32", target_os = "unknown"))
+            ))]
+            Start::Instant(start) => start.elapsed().as_nanos() as u64,
+            Start::Unknown => 0,
+        }
+    }
+}
+
+static CLOCK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Sets the clock for timed probes, as a function that returns a monotonic
+/// time, wrapping at `u64::MAX`, instead of the system clock.
+pub fn set_clock(now: fn() -> u64) {
+    CLOCK.store(now as *mut (), Ordering::Relaxed);
+}
+
+#[cfg(all(
+    feature = "use_std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+fn system_start() -> Start {
+    Start::Instant(Instant::now())
+}
+
+#[cfg(not(all(
+    feature = "use_std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+)))]
+fn system_start() -> Start {
+    Start::Unknown
+}
+
+// Whether a probe is timed, from the hidden `__timed` option that
+// `probe_latency!` gives it.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_timed_value(
+    () => (false);
+    (__timed = true $(, $($rest:tt)*)?) => (true);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::probe_timed_value!($($($rest)*)?));
+);
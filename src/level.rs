@@ -0,0 +1,135 @@
+//! Probe levels
+//!
+//! Probes can have a `level` option, which is `trace` by default, and the
+//! `max_level_*` features compile out every probe that's more verbose than
+//! the chosen level, as with the `log` crate. The `release_max_level_*`
+//! features do the same only in release builds, i.e. without
+//! `debug_assertions`, and take precedence there. If more than one is chosen,
+//! the least verbose one wins.
+//!
+//! ```toml
+//! [dependencies]
+//! probe = { version = "0.5", features = ["max_level_debug", "release_max_level_warn"] }
+//! ```
+//!
+//! Probes that are compiled out are like those of a provider in
+//! `PROBE_DISABLE`: `probe!` still evaluates its arguments, and nothing else
+//! is left of them.
+
+/// The level of a probe, from its `level` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Level {
+    /// `level = critical`
+    Critical = 1,
+    /// `level = error`
+    Error,
+    /// `level = warn`
+    Warn,
+    /// `level = info`
+    Info,
+    /// `level = debug`
+    Debug,
+    /// `level = trace`, the default.
+    Trace,
+}
+
+impl Level {
+    /// The level's name, as in its option, like `warn`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Level::Critical => "critical",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub(crate) const fn from_u8(level: u8) -> Option<Level> {
+        match level {
+            1 => Some(Level::Critical),
+            2 => Some(Level::Error),
+            3 => Some(Level::Warn),
+            4 => Some(Level::Info),
+            5 => Some(Level::Debug),
+            6 => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// The most verbose level of probes that are compiled in, or `None` if every
+/// probe is compiled out, from the `max_level_*` features.
+pub const STATIC_MAX_LEVEL: Option<Level> = Level::from_u8(MAX_LEVEL);
+
+const MAX_LEVEL: u8 = if cfg!(debug_assertions) {
+    max_level()
+} else {
+    release_max_level()
+};
+
+const fn max_level() -> u8 {
+    if cfg!(feature = "max_level_off") {
+        0
+    } else if cfg!(feature = "max_level_critical") {
+        1
+    } else if cfg!(feature = "max_level_error") {
+        2
+    } else if cfg!(feature = "max_level_warn") {
+        3
+    } else if cfg!(feature = "max_level_info") {
+        4
+    } else if cfg!(feature = "max_level_debug") {
+        5
+    } else {
+        6
+    }
+}
+
+const fn release_max_level() -> u8 {
+    if cfg!(feature = "release_max_level_off") {
+        0
+    } else if cfg!(feature = "release_max_level_critical") {
+        1
+    } else if cfg!(feature = "release_max_level_error") {
+        2
+    } else if cfg!(feature = "release_max_level_warn") {
+        3
+    } else if cfg!(feature = "release_max_level_info") {
+        4
+    } else if cfg!(feature = "release_max_level_debug") {
+        5
+    } else if cfg!(feature = "release_max_level_trace") {
+        6
+    } else {
+        max_level()
+    }
+}
+
+/// Whether probes at `level`, as a number from `probe_level_value!`, are
+/// compiled in.
+#[doc(hidden)]
+pub const fn compiled_in(level: u8) -> bool {
+    // Compared with the `Level`, as `level <= 0` under `max_level_off` is an
+    // absurd comparison to clippy.
+    match STATIC_MAX_LEVEL {
+        Some(max) => level <= max as u8,
+        None => false,
+    }
+}
+
+// The level of a probe from its options, as the number of its `Level`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_level_value(
+    () => (6);
+    (level = critical $(, $($rest:tt)*)?) => (1);
+    (level = error $(, $($rest:tt)*)?) => (2);
+    (level = warn $(, $($rest:tt)*)?) => (3);
+    (level = info $(, $($rest:tt)*)?) => (4);
+    (level = debug $(, $($rest:tt)*)?) => (5);
+    (level = $level:ident $(, $($rest:tt)*)?) => (6);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::probe_level_value!($($($rest)*)?));
+);
@@ -2,7 +2,8 @@
 //!
 //! With the `probe!` macro, programmers can place static instrumentation
 //! points in their code to mark events of interest. These are compiled into
-//! platform-specific implementations, e.g. SystemTap SDT on Linux. Probes are
+//! platform-specific implementations, e.g. SystemTap SDT on Linux, DTrace USDT
+//! on macOS, FreeBSD, and illumos, or ETW TraceLogging on Windows. Probes are
 //! designed to have negligible overhead during normal operation, so they can
 //! be present in all builds, and only activated using those external tools.
 //!
@@ -28,6 +29,13 @@
 //!
 //! ## Using probes with SystemTap
 //!
+//! On Linux and Android, SDT probes are supported on x86, x86_64, AArch64,
+//! 32-bit ARM, RISC-V, LoongArch64 (which needs Rust 1.72), and s390x (which
+//! needs Rust 1.84). Other architectures get a no-op implementation, except
+//! that PowerPC64, MIPS, and MIPS64 can have SDT probes with the
+//! `asm_experimental_arch` feature on nightly Rust, where every crate using
+//! probes must also enable `#![feature(asm_experimental_arch)]`.
+//!
 //! For the program above, a SystemTap script could double-check the totals:
 //!
 //! ```notrust
@@ -53,6 +61,22 @@
 //! foo:end
 //! ```
 //!
+//! Each `probe_lazy!` site also has an SDT semaphore, which SystemTap,
+//! bpftrace, and GDB increment while they're attached to the probe, so its
+//! arguments are only evaluated then. For example,
+//! `probe_lazy!(foo, report, summary())` only calls `summary()` while a
+//! script like this is running:
+//!
+//! ```notrust
+//! $ sudo bpftrace -p $(pidof foo) -e 'usdt:*:foo:report { printf("%d\n", arg0); }'
+//! ```
+//!
+//! Each site also has a note with its file and line, in the `.note.probe`
+//! section, with the owner `probe` and the type 4, which has the address of
+//! the site, like its SDT note, and then the file, as `file!()` gives it, and
+//! the line, as NUL-terminated strings, so tools can show where a probe hit
+//! was without debug info, even when several sites have the same probe.
+//!
 //! ## Using probes with GDB
 //!
 //! Starting in version 7.5, GDB can set breakpoints on probes and read arguments.
@@ -77,13 +101,395 @@
 //! (gdb) print $_probe_arg1
 //! $2 = 1035
 //! ```
+//!
+//! ## Using probes with DTrace
+//!
+//! On macOS, FreeBSD, NetBSD, illumos, and Solaris, the same probes are
+//! visible to DTrace as USDT probes. The provider name is suffixed with the
+//! process ID, as usual for USDT. Except on macOS, probes are limited to 6
+//! arguments, and only x86_64 is supported. On NetBSD, the kernel must have
+//! the DTrace modules loaded when the program starts, for its probes to be
+//! registered.
+//!
+//! ```notrust
+//! $ sudo dtrace -n 'foo*:::loop /arg1 > 1000/ { printf("%d %d\n", arg0, arg1); exit(0); }' -c ./foo
+//! dtrace: description 'foo*:::loop ' matched 1 probe
+//! CPU     ID                    FUNCTION:NAME
+//!   2  41096          _ZN3foo4main17h...E:loop 45 1035
+//! ```
+//!
+//! ## Using probes with ETW
+//!
+//! On Windows, each probe is written as a TraceLogging event, named for the
+//! probe, from a provider named for the probe provider. The provider GUID is
+//! derived from its name, so tools can enable it as `*foo`, e.g. in a WPR
+//! profile for recording with `wpr -start foo.wprp`:
+//!
+//! ```notrust
+//! <EventCollector Id="EventCollector_foo" Name="foo">
+//!   <BufferSize Value="64" />
+//!   <Buffers Value="4" />
+//! </EventCollector>
+//! <EventProvider Id="EventProvider_foo" Name="*foo" />
+//! ```
+//!
+//! ## Using probes with LTTng
+//!
+//! With the `lttng` feature on Linux, probes are written as LTTng-UST events
+//! instead of SDT, which needs `liblttng-ust` to build and run. All probes are
+//! the event `rust_probe:probe`, with `provider` and `name` fields and the
+//! arguments as `args`, so a session can filter to the probes it wants:
+//!
+//! ```notrust
+//! $ lttng create foo
+//! $ lttng enable-event -u rust_probe:probe --filter 'provider == "foo" && name == "loop"'
+//! $ lttng start
+//! $ ./foo
+//! $ lttng stop
+//! $ lttng view
+//! [...] rust_probe:probe: { cpu_id = 2 }, { provider = "foo", name = "loop", _args_length = 2, args = [ [0] = 45, [1] = 1035 ] }
+//! ```
+//!
+//! ## Using probes with user_events
+//!
+//! With the `user_events` feature on Linux 6.4 or later, probes are instead
+//! registered with the kernel as user events when the program starts, named
+//! like `foo_loop` with fields `arg0`, `arg1`, etc. They can be recorded with
+//! `perf` or any other tracefs consumer, given write access to
+//! `user_events_data` for the program to register them:
+//!
+//! ```notrust
+//! $ sudo perf record -e user_events:foo_loop -- ./foo
+//! $ sudo perf script
+//!   foo  1234 [002]  96.514318: user_events:foo_loop: arg0=45 arg1=1035
+//! ```
+//!
+//! ## Using probes with Perfetto on Android
+//!
+//! With the `atrace` feature on Android, probes are written as ATrace
+//! sections instead, labelled like `foo:loop 45 1035`, so they appear in
+//! Perfetto or systrace recordings of the app. This needs API level 23.
+//!
+//! ## Using probes with Instruments
+//!
+//! With the `os_signpost` feature on Apple platforms, probes are emitted as
+//! signposts instead of DTrace probes, with the provider as the subsystem in
+//! the `PointsOfInterest` category. Instruments shows them in its os_signpost
+//! and Points of Interest instruments, with probes that have the `span`
+//! option as intervals. This needs macOS 10.14 or iOS 12.
+//!
+//! ## Using probes with Fuchsia tracing
+//!
+//! On Fuchsia, each probe is a trace event in a category named for its
+//! provider, and the program registers as a trace provider at startup, so a
+//! recording just needs to enable that category:
+//!
+//! ```notrust
+//! $ ffx trace start --categories foo --duration 10
+//! ```
+//!
+//! ## Using probes with the QNX System Profiler
+//!
+//! On QNX Neutrino, each probe is inserted into the kernel's trace as a user
+//! string event, like `foo:loop 45 1035`, with a user event code from a hash
+//! of the provider. They're recorded with the kernel's own events while
+//! `tracelogger` runs, for the System Profiler in Momentics to show.
+//!
+//! ## Using probes with ESP-IDF
+//!
+//! With the `esp_log` feature on the ESP32 family with ESP-IDF, probes are
+//! written as log messages, tagged with the provider, at the level given by
+//! their `level` option, or verbose by default. They're only written when
+//! the tag's log level allows, e.g. after this in the application:
+//!
+//! ```notrust
+//! esp_log_level_set("foo", ESP_LOG_VERBOSE);
+//! ```
+//!
+//! ## Using probes with ITM
+//!
+//! With the `itm` feature on bare-metal Cortex-M, probes are written as
+//! compact records to ITM stimulus port 1, or the port in `PROBE_ITM_PORT`
+//! when the crate is built, for an SWO viewer to decode. Each record is a
+//! 32-bit ID of 16-bit hashes of the provider and name, an 8-bit argument
+//! count, and then 32-bit arguments, as described in the `itm` backend.
+//!
+//! ## Using probes with RTT
+//!
+//! With the `rtt` feature on bare-metal ARM or RISC-V, probes are written as
+//! compact records to an RTT up-channel named `probe`, which a J-Link can
+//! stream from the running target, e.g. with `JLinkRTTLogger`. Records are
+//! like those for ITM, except that arguments are zigzag LEB128 numbers. This
+//! crate provides the RTT control block, so the application can't also use
+//! another RTT implementation.
+//!
+//! On the host, the `probe::rtt` module decodes the records, with the
+//! `use_std` feature, e.g. from the channel as read with the `probe-rs`
+//! crate. Its decoder maps the IDs back to the names of known probes.
+//!
+//! ## Using probes with semihosting
+//!
+//! With the `semihosting` feature on bare-metal Cortex-M or RISC-V, probes
+//! are written as lines like `foo:loop 45 1035` to the debugger's console.
+//! This stops the target for every probe, and faults if no debugger is
+//! attached, so it's only meant for early bring-up. The `itm` and `rtt`
+//! features take precedence over it.
+//!
+//! ## Using probes with STM
+//!
+//! With the `stm` feature on bare-metal Cortex-M or AArch64, probes are
+//! written to the extended stimulus ports of a CoreSight System Trace
+//! Macrocell, at the address given by `PROBE_STM_BASE` when the crate is
+//! built. Each provider writes to its own channel, chosen by a hash of its
+//! name, with records like those for ITM, so hardware trace captures include
+//! the probes.
+//!
+//! ## Using probes with Zephyr
+//!
+//! With the `zephyr` feature, for applications built with Zephyr and its
+//! `CONFIG_TRACING`, probes are written as tracing named events, like
+//! `foo:loop`, so they appear in the same trace as the kernel's events, e.g.
+//! in Trace Compass with the CTF backend. Named events only have room for the
+//! first two arguments of a probe. This takes precedence over the other
+//! bare-metal features.
+//!
+//! ## Using probes with FreeRTOS
+//!
+//! With the `freertos` feature, for FreeRTOS applications with Percepio's
+//! TraceRecorder, probes are written as user events on a channel named for
+//! the provider, with a format string like `loop %d %d`, so Tracealyzer shows
+//! them alongside the kernel's events. Probes can have at most 8 arguments.
+//! This takes precedence over the other bare-metal features, except `zephyr`.
+//!
+//! ## Using probes with CTF
+//!
+//! With the `ctf` feature on bare-metal ARM or RISC-V, probes are written as
+//! Common Trace Format packets in a buffer given to `probe::ctf::init`, which
+//! passes each full packet back to the application to store or send. The
+//! matching metadata comes from `probe::ctf::metadata`, so the packets can be
+//! read as a trace with Trace Compass or `babeltrace2`. Events are like those
+//! for LTTng, with `provider`, `name`, and `args` fields.
+//!
+//! ## Using probes with defmt
+//!
+//! With the `defmt` feature on bare-metal targets, probes are logged with the
+//! application's `defmt`, at the level given by their `level` option, or
+//! `trace` by default, so `probe-rs run` or `defmt-print` shows them with the
+//! rest of its logs, like `foo:loop [45, 1035]`. They're filtered by
+//! `DEFMT_LOG` like those logs, too. The other bare-metal features take
+//! precedence over this one.
+//!
+//! ## Probe tables
+//!
+//! With the `table` feature on bare-metal targets, each probe also adds an
+//! entry with its ID, provider, name, and argument count to the
+//! `probe_table` section, as described in the `probe::table` module. Host
+//! tools can then decode the numeric IDs in ITM, RTT, or STM records from the
+//! firmware's ELF file, or from the table sent by the firmware itself.
+//!
+//! ## Using probes with WebAssembly
+//!
+//! With the `wasm` feature on `wasm32`, probes call functions imported from
+//! the host as `probe.fire` and `probe.enabled`, which the host must provide,
+//! and each probe is listed in a custom section named `probe`. For example, a
+//! JavaScript host could log probes like this:
+//!
+//! ```notrust
+//! const text = new TextDecoder();
+//! const { instance } = await WebAssembly.instantiate(bytes, {
+//!   probe: {
+//!     enabled: (provider, provider_len, name, name_len) => 1,
+//!     fire: (provider, provider_len, name, name_len, args, args_len) => {
+//!       const mem = instance.exports.memory.buffer;
+//!       console.log(
+//!         text.decode(new Uint8Array(mem, provider, provider_len)),
+//!         text.decode(new Uint8Array(mem, name, name_len)),
+//!         ...new BigInt64Array(mem, args, args_len));
+//!     },
+//!   },
+//! });
+//! ```
+//!
+//! ## Platforms without a tracer
+//!
+//! Everywhere else, such as OpenBSD, whose `dt(4)` and `btrace` have no
+//! user-level probes, probes compile to nothing, so the same instrumented code
+//! still builds. `probe!` still evaluates its arguments there, and
+//! `probe_lazy!` returns `false` without evaluating them, unless the program
+//! enables the provider itself with the [`control`] module, e.g. on a signal,
+//! or from the `PROBE_ENABLE` environment variable, or from another process
+//! through shared memory.
+//!
+//! ## Handling probes in the process
+//!
+//! With the `callback` feature, on any platform, the program can register a
+//! function with `callback::set_handler`, which is called with each probe
+//! that fires while it's enabled with the [`control`] module, and its operands,
+//! besides the platform's own probe, so a tracer and the program can both see
+//! the same probes at once. Handlers can be added and removed with
+//! `callback::add_handler` and `callback::remove_handler` while the program
+//! runs, and the features below each add their own. See the `callback` module.
+//!
+//! With the `recorder` feature, `recorder::start` adds a flight recorder, which
+//! keeps the most recent probes in a ring buffer in memory for each thread, to
+//! be read back with `recorder::recent` and `recorder::drain`, and the hits,
+//! last operands, and range of the first operand of each probe site, which
+//! `stats` lists.
+//!
+//! With the `chrome` feature, `chrome::start` writes the probes to a trace in
+//! the Chrome Trace Event Format, with the ends of spans as durations, for
+//! `chrome://tracing` or the Perfetto UI. With the `perfetto` feature,
+//! `perfetto::start` writes them to a Perfetto trace in its protobuf format,
+//! with a track for each thread, and the ends of spans as slices.
+//!
+//! With the `jsonl` feature, `jsonl::start` writes each probe as a line of JSON
+//! as it fires, for a log pipeline, and with the `binlog` feature,
+//! `binlog::start` writes them to a compact binary log, which `binlog::decode`
+//! reads back, as `binlog::write_records` does the records of the recorder.
+//! With the `metrics` feature, `metrics::start` counts the probes, and the
+//! times of timed ones, and `metrics::serve` serves those, with the counter and
+//! histogram sites, to Prometheus.
+//!
+//! With the `shm` feature on Unix, `shm::start` writes them to a ring in a
+//! named shared-memory segment, which `shm::Reader` reads from another process.
+//!
+//! With the `stderr` feature, which is meant for development, every probe
+//! that fires is printed to stderr, with its provider, its name, and its
+//! arguments, like `probe foo:alloc size=64 (src/main.rs:10)`, so new
+//! instrumentation can be checked before setting up a tracer. That's every
+//! `probe!`, and lazy probes while they're enabled for a handler. Probes still
+//! go to the platform, and to a handler while the program enables them.
+//!
+//! ## Compiling out providers
+//!
+//! Probes of the providers listed in the `PROBE_DISABLE` environment variable
+//! when a crate is built, separated by commas, like `PROBE_DISABLE=alloc,net`,
+//! are compiled out of it, on every platform. They have no sites, notes, or
+//! table entries, `probe_lazy!` and `probe_enabled!` are always `false`, and
+//! only `probe!` still evaluates its arguments. Cargo rebuilds crates with
+//! probes when the variable changes.
+//!
+//! ## Testing lazy probes
+//!
+//! With the `force_enable` feature, every probe is enabled as if a tracer were
+//! attached to it, on every platform, so tests can cover the arguments of
+//! `probe_lazy!` and the code behind `probe_enabled!` without one. Lazy probes
+//! always evaluate their arguments and fire, and return `true`. It's meant for
+//! a crate's tests, e.g. as a dev-dependency feature, not for release builds.
+//!
+//! With the `testing` feature, `testing::capture` captures the probes of a
+//! provider that fire on the test's thread, so a test can check that they fired
+//! with the right arguments, without a tracer.
 
 #![no_std]
 
 #[cfg(any(test, feature = "use_std"))]
 extern crate std;
 
-mod platform;
+#[doc(hidden)]
+pub mod platform;
+
+pub mod arg;
+
+#[cfg(feature = "binlog")]
+pub mod binlog;
+
+#[cfg(feature = "callback")]
+pub mod callback;
+
+#[cfg(feature = "chrome")]
+pub mod chrome;
+
+pub mod control;
+
+pub mod counter;
+
+pub mod format;
+
+pub mod handle;
+
+pub mod histogram;
+
+#[cfg(feature = "derive")]
+pub mod instrument;
+
+#[cfg(any(feature = "chrome", feature = "jsonl"))]
+mod json;
+
+#[cfg(feature = "jsonl")]
+pub mod jsonl;
+
+pub mod latency;
+
+pub mod level;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "perfetto")]
+pub mod perfetto;
+
+#[cfg(all(feature = "recorder", target_has_atomic = "64"))]
+pub mod recorder;
+
+pub mod rtt;
+
+pub mod sample;
+
+pub mod span;
+
+#[cfg(feature = "use_std")]
+pub mod semaphore;
+
+#[cfg(all(
+    feature = "shm",
+    target_has_atomic = "64",
+    target_pointer_width = "64",
+    any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+        target_os = "illumos",
+        target_os = "solaris",
+    )
+))]
+pub mod shm;
+
+#[cfg(feature = "stderr")]
+mod stderr;
+
+pub mod table;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(all(
+    any(target_arch = "arm", target_arch = "riscv32", target_arch = "riscv64"),
+    target_os = "none",
+    feature = "ctf",
+    not(any(
+        feature = "zephyr",
+        feature = "freertos",
+        all(target_arch = "arm", feature = "itm"),
+        feature = "rtt",
+        feature = "semihosting",
+        all(target_arch = "arm", feature = "stm")
+    )),
+))]
+pub use platform::ctf;
+
+#[cfg(feature = "use_std")]
+pub use arg::ProbePayload;
+pub use arg::{ProbeArg, ProbeArgs};
+pub use handle::ProbeHandle;
+#[cfg(feature = "derive")]
+pub use probe_derive::{instrument, named, namespace, ProbeArg, ProbeArgs};
+#[cfg(all(feature = "recorder", target_has_atomic = "64"))]
+pub use recorder::stats;
 
 /// Define a static probe point.
 ///
@@ -92,13 +498,63 @@ mod platform;
 ///
 /// # Arguments
 ///
-/// * `provider` - An identifier for naming probe groups.
+/// * `provider` - An identifier for naming probe groups, or `_` for the name
+///   of the crate the probe is in, as Cargo gives it in `CARGO_CRATE_NAME`, so
+///   the crates of a workspace each have their own without repeating it.
+///   With the `derive` feature, [`#[namespace]`](macro@namespace) suffixes
+///   the providers in a module with the names of its modules. It can also be
+///   a string literal, like `"my-app.io"`, for a provider that isn't an
+///   identifier. It can't be a `const`, but crates can still share one, as in
+///   [Sharing a provider](#sharing-a-provider). It can have a version, like
+///   `foo@2`, for tools to tell the arguments of one release of a program
+///   from another's, which SDT probes record in a note of type 3 in the
+///   `.note.probe` section, with the provider, the name, and the version,
+///   like the note of the names of the arguments described in the [`arg`]
+///   module. The version isn't part of the provider's name.
 ///
-/// * `name`     - An identifier for this specific probe.
+/// * `name`     - An identifier for this specific probe, or a string literal,
+///   like `"read.done"`. Some platforms only take identifier characters in
+///   providers and names, like DTrace on macOS, whose probes are symbols.
 ///
-/// * `arg`...   - Optional data to provide with the probe. Any expression which
-///   can be cast `as isize` is allowed as an argument. The arguments are always
+/// * `arg`...   - Optional data to provide with the probe. Any expression of a
+///   type that implements [`ProbeArg`], like the integers, is allowed as an
+///   argument. The arguments are always
 ///   evaluated, even on platforms that have a no-op implementation of probes.
+///   Arguments of other kinds, like strings, are marked, as in `@str path`, and
+///   they can be named, as in `total = sum`, as described in the [`arg`] module.
+///
+/// * `option`... - Optional settings after a `;`, as `key = value` pairs, for
+///   platforms that can make use of them:
+///
+///   * `level` - The severity of the probe, one of `critical`, `error`, `warn`,
+///     `info`, `debug`, or `trace`, where the default is `trace`, i.e. verbose.
+///     This is the ETW level on Windows, the LTTng loglevel, and the log level
+///     of ESP-IDF and defmt, and it's recorded in probe tables. Probes that are
+///     more verbose than the `max_level_*` features are compiled out, as
+///     described in [`level`]. The level can also come first, like
+///     `probe!(level: debug, foo, cache_miss, key)`.
+///
+///   * `keyword` - A `u64` constant of ETW keyword bits, for sessions to filter
+///     on. The default is 0, which is enabled by any session.
+///
+///   * `span` - Either `begin` or `end`, to mark this probe as the start or the
+///     finish of an interval, for platforms that trace those, like os_signpost.
+///     Both ends of an interval should use the same provider and name, as
+///     [`probe_span!`] does. SDT probes are named for their end instead, like
+///     `request__begin`.
+///
+///   * `operands` - Either `registers`, the default, or `memory`, for SDT
+///     probes to pass their arguments in words on the stack, from one base
+///     register, rather than each in a register of its own. That's less
+///     register pressure in a hot loop, at the cost of a store for each
+///     argument, and of their sizes, since tools read every one as a whole
+///     word, though still with its signedness. It also lifts the limit of 6
+///     arguments on 32-bit x86.
+///
+///   * `format` - A string literal, like `"alloc size=%d align=%d"`, that's
+///     kept with the probe's metadata for consumers to show its arguments
+///     with, as described in [`format`], and that [`probe_fmt!`] gives ahead
+///     of the arguments instead.
 ///
 /// # Example
 ///
@@ -119,11 +575,55 @@ mod platform;
 /// let mut z = 0;
 /// probe!(foo, inc_z, { z += 1; z });
 /// assert_eq!(z, 1, "arguments are always evaluated");
+///
+/// // Options follow the arguments.
+/// const IO: u64 = 0x1;
+/// probe!(foo, slow_path, z; level = warn, keyword = IO);
+/// probe!(foo, hot_loop, z; operands = memory);
+/// probe!(level: debug, foo, cache_miss, z);
+///
+/// let key = "user:42";
+/// probe!(foo, lookup, @str key, z);
+/// probe!(foo, lookup_named, key = @str key, hits = z);
+///
+/// probe!(foo, request, z; span = begin);
+/// probe!(foo, request, z; span = end);
+///
+/// // The provider is the name of this crate.
+/// probe!(_, startup);
+/// probe!("my-app.io", "read.done", z);
+/// probe!(foo@2, resized, z);
+/// ```
+///
+/// # Sharing a provider
+///
+/// The provider can't be a `const`, like `pub const PROVIDER: &str = "mmtk";`,
+/// since platforms need it as a literal where the probe is, and a macro only
+/// sees the name of a `const`, not its value. A workspace can still name its
+/// provider in one place, with a macro of its own that gives it to each probe,
+/// exported with `#[macro_export]` from a crate that the others depend on:
+///
+/// ```
+/// macro_rules! mmtk_probe {
+///     ($($probe:tt)*) => {
+///         probe::probe!(mmtk, $($probe)*)
+///     };
+/// }
+///
+/// let (bytes, pause) = (4096, 7);
+/// mmtk_probe!(gc_begin, bytes);
+/// mmtk_probe!(gc_end, bytes, pause = pause; level = debug);
 /// ```
 #[macro_export]
 macro_rules! probe(
-    ($provider:ident, $name:ident $(, $arg:expr)* $(,)?)
-    => ($crate::platform_probe!($provider, $name, $($arg,)*));
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_args, [probe_fire, probe_bind],
+            [[$provider $(@ $version)?], $name], [level = $level], [], [], $($args)*)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_args, [probe_fire, probe_bind],
+            [[$provider $(@ $version)?], $name], [], [], [], $($args)*)
+    );
 );
 
 /// Define a static probe point with lazy argument evaluation.
@@ -136,19 +636,1053 @@ macro_rules! probe(
 ///
 /// Returns `true` if the probe is executed (and its arguments evaluated).
 ///
+/// Where the check is a semaphore, as with SystemTap, the arguments are
+/// evaluated out of line, in a closure, so they can't `return` or use `?`
+/// from the enclosing function.
+///
 /// # Example
 ///
 /// ```
 /// # use probe::probe_lazy;
 /// let enabled = probe_lazy!(foo, main);
+/// # #[cfg(not(feature = "force_enable"))]
 /// assert!(!enabled, "lazy probes only return true when they're active");
 ///
 /// let mut z = 0;
 /// probe_lazy!(foo, inc_z, { z += 1; z });
+/// # #[cfg(not(feature = "force_enable"))]
 /// assert_eq!(z, 0, "arguments are not evaluated by default");
+///
+/// probe_lazy!(foo, debug_z, z; level = debug);
 /// ```
 #[macro_export]
 macro_rules! probe_lazy(
-    ($provider:ident, $name:ident $(, $arg:expr)* $(,)?)
-    => ($crate::platform_probe_lazy!($provider, $name, $($arg,)*));
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_args, [probe_lazy_fire, probe_lazy_bind],
+            [[$provider $(@ $version)?], $name], [level = $level], [], [], $($args)*)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_args, [probe_lazy_fire, probe_lazy_bind],
+            [[$provider $(@ $version)?], $name], [], [], [], $($args)*)
+    );
+);
+
+/// Check whether a probe is enabled.
+///
+/// This returns `true` when a debugger or tracing tool is attached to the
+/// probe, like [`probe_lazy!`], but without firing it, so that a whole block
+/// of diagnostic work can be skipped otherwise. The probe's `level` and
+/// `keyword` options can be given in the same way, for platforms that filter
+/// on them. Where a platform can't tell whether a probe is enabled, this is
+/// `true`, like `probe_lazy!`, or `false` if it has no probes at all.
+///
+/// With SystemTap, this reads the probe's semaphore, which is shared with all
+/// of its `probe!` and `probe_lazy!` sites, so it's enabled whenever a tracer
+/// is attached to any of them.
+///
+/// # Example
+///
+/// ```
+/// # use probe::{probe, probe_enabled};
+/// let items = [1, 2, 3];
+/// if probe_enabled!(foo, item) {
+///     for (i, item) in items.iter().enumerate() {
+///         probe!(foo, item, i, *item);
+///     }
+/// }
+///
+/// # #[cfg(not(feature = "force_enable"))]
+/// assert!(!probe_enabled!(foo, slow_path; level = warn));
+/// ```
+#[macro_export]
+macro_rules! probe_enabled(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_enabled!($provider $(@ $version)?, $name; level = $level $(, $($opt)+)?)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt $(; $($opt:tt)+)?)
+    => ({
+        $crate::probe_options!($($($opt)+)?);
+        if $crate::probe_compiled_in!([$provider $(@ $version)?], [$($($opt)+)?]) {
+            $crate::probe_enabled_site!([$provider $(@ $version)?], $name, [$($($opt)+)?])
+        } else {
+            false
+        }
+    });
+);
+
+/// Define a probe site as a value.
+///
+/// This makes a [`ProbeHandle`] for the probe, which can be fired with
+/// [`fire`](ProbeHandle::fire), or checked with
+/// [`is_enabled`](ProbeHandle::is_enabled), from generic code that takes a
+/// `ProbeHandle<impl Site>`. Options can be given as with [`probe!`].
+///
+/// The handle is zero-sized, and the probe is compiled in where the handle is
+/// made, always with [`MAX_ARGS`](handle::MAX_ARGS) arguments.
+///
+/// # Example
+///
+/// ```
+/// use probe::handle::Site;
+/// use probe::{probe_site, ProbeHandle};
+///
+/// fn process<S: Site>(items: &[isize], probe: ProbeHandle<S>) {
+///     for (i, &item) in items.iter().enumerate() {
+///         if probe.is_enabled() {
+///             probe.fire(&[i as isize, item]);
+///         }
+///     }
+/// }
+///
+/// let handle = probe_site!(foo, item);
+/// assert_eq!(std::mem::size_of_val(&handle), 0);
+/// process(&[1, 2, 3], handle);
+/// process(&[4, 5], probe_site!(bar, item; level = debug));
+/// ```
+#[macro_export]
+macro_rules! probe_site(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_site!($provider $(@ $version)?, $name; level = $level $(, $($opt)+)?)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt $(; $($opt:tt)+)?)
+    => ({
+        struct ProbeSite;
+        impl $crate::handle::Site for ProbeSite {
+            #[inline]
+            fn enabled() -> bool {
+                $crate::probe_enabled!($provider $(@ $version)?, $name $(; $($opt)+)?)
+            }
+
+            #[inline]
+            fn fire(args: [isize; $crate::handle::MAX_ARGS]) {
+                $crate::probe!($provider $(@ $version)?, $name,
+                    args[0], args[1], args[2], args[3], args[4], args[5] $(; $($opt)+)?);
+            }
+        }
+        $crate::ProbeHandle::<ProbeSite>::new()
+    });
+);
+
+/// Define a static probe point that only fires on some of its hits.
+///
+/// This works like [`probe_lazy!`], with a `rate` first, and only fires the
+/// probe on one in every `rate` hits of this site while it's enabled, starting
+/// with the first. The arguments are only evaluated when the probe fires.
+/// The `rate` is a `usize`, and 0 or 1 fires every hit.
+///
+/// Returns `true` if the probe is executed (and its arguments evaluated).
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_sampled;
+/// for i in 0..1000 {
+///     // Fired for hits 0, 100, 200, and so on.
+///     probe_sampled!(100, foo, hot_loop, i);
+/// }
+///
+/// probe_sampled!(10, foo, hot_debug; level = debug);
+/// ```
+#[macro_export]
+macro_rules! probe_sampled(
+    ($rate:expr, level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*)
+    => (
+        $crate::probe_parse!(probe_gated, [{
+            static HITS: $crate::sample::Counter = $crate::sample::Counter::new();
+            HITS.sample($rate)
+        }], [[$provider $(@ $version)?], $name], [level = $level], [], [], $($args)*)
+    );
+    ($rate:expr, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_gated, [{
+            static HITS: $crate::sample::Counter = $crate::sample::Counter::new();
+            HITS.sample($rate)
+        }], [[$provider $(@ $version)?], $name], [], [], [], $($args)*)
+    );
+);
+
+/// Define a static probe point that fires at most so many times a second.
+///
+/// This works like [`probe_lazy!`], with a `per_second` limit first, and
+/// fires the probe at most that many times a second at this site while it's
+/// enabled, in bursts of up to that many, and drops the rest. The arguments
+/// are only evaluated when the probe fires. The limit is a `u32`, and 0 never
+/// fires. See the [`sample`] module for the clock it needs.
+///
+/// Returns `true` if the probe is executed (and its arguments evaluated).
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_rate_limited;
+/// # let errno = 5;
+/// // Up to 10 errors a second, however many there are.
+/// probe_rate_limited!(10, foo, io_error, errno; level = error);
+/// ```
+#[macro_export]
+macro_rules! probe_rate_limited(
+    ($per_second:expr, level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt $($args:tt)*)
+    => (
+        $crate::probe_parse!(probe_gated, [{
+            static LIMIT: $crate::sample::Limiter = $crate::sample::Limiter::new();
+            LIMIT.allow($per_second)
+        }], [[$provider $(@ $version)?], $name], [level = $level], [], [], $($args)*)
+    );
+    ($per_second:expr, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_gated, [{
+            static LIMIT: $crate::sample::Limiter = $crate::sample::Limiter::new();
+            LIMIT.allow($per_second)
+        }], [[$provider $(@ $version)?], $name], [], [], [], $($args)*)
+    );
+);
+
+/// Define a static probe point with a format string.
+///
+/// This is [`probe!`] with the `format` option, given as a string literal
+/// before the arguments, for consumers to show a message from them. The
+/// probe's arguments are passed as they are, and the format is only kept in
+/// its metadata, as described in the [`format`] module.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_fmt;
+/// # let (size, align) = (64, 16);
+/// probe_fmt!(alloc, alloc, "alloc size=%d align=%d", size, align);
+/// probe_fmt!(level: debug, alloc, reset, "reset");
+/// ```
+#[macro_export]
+macro_rules! probe_fmt(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt, $format:literal $($args:tt)*)
+    => (
+        $crate::probe_parse!(probe_fmt_fire, [$format],
+            [[$provider $(@ $version)?], $name], [level = $level],
+            [], [], $($args)*)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $format:literal $($args:tt)*) => (
+        $crate::probe_parse!(probe_fmt_fire, [$format],
+            [[$provider $(@ $version)?], $name], [], [], [],
+            $($args)*)
+    );
+);
+
+// Fires a probe with its format as the last of its options.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_fmt_fire(
+    ($format:literal, [$provider:tt, $name:tt, []], $($args:tt)*) => (
+        $crate::probe_args!(probe_fire, probe_bind,
+            [$provider, $name, [format = $format]], $($args)*)
+    );
+    ($format:literal, [$provider:tt, $name:tt, [$($opt:tt)+]], $($args:tt)*) => (
+        $crate::probe_args!(probe_fire, probe_bind,
+            [$provider, $name, [$($opt)+, format = $format]], $($args)*)
+    );
+);
+
+/// Define a static probe point on the value of an expression.
+///
+/// Like `dbg!`, this evaluates the expression, and yields its value, so it can
+/// wrap an expression anywhere. It fires the probe with a clone of the value,
+/// named `value`, like [`probe_lazy!`], so the value is only cloned while the
+/// probe is enabled. Its type has to implement [`ProbeArg`] and `Clone`.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_dbg;
+/// let len = probe_dbg!(foo, len, "hello".len()) * 2;
+/// assert_eq!(len, 10);
+///
+/// let total: u32 = [1, 2, 3].iter().map(|i| probe_dbg!(foo, item, *i; level = debug)).sum();
+/// assert_eq!(total, 6);
+/// ```
+#[macro_export]
+macro_rules! probe_dbg(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt, $value:expr $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_dbg!($provider $(@ $version)?, $name, $value; level = $level $(, $($opt)+)?)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $value:expr $(; $($opt:tt)+)?) => (
+        match $value {
+            value => {
+                $crate::probe_lazy!($provider $(@ $version)?, $name,
+                    value = ::core::clone::Clone::clone(&value) $(; $($opt)+)?);
+                value
+            }
+        }
+    );
+);
+
+/// Define a static probe point for an invariant.
+///
+/// This evaluates the condition, a `bool`, and fires the probe if it's
+/// `false`, with the arguments, which are only evaluated then, so a tracer can
+/// watch for the invariant to be broken in a release build. It yields the
+/// condition, for the program to carry on as it sees fit, and never panics.
+/// [`probe_debug_assert!`] also panics in debug builds.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_assert;
+/// # let (len, capacity) = (3, 4);
+/// if !probe_assert!(queue, overflow, len <= capacity, len, capacity) {
+///     // Drop the item, rather than crash.
+/// }
+///
+/// probe_assert!(queue, empty, len > 0; level = error);
+/// ```
+#[macro_export]
+macro_rules! probe_assert(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt, $cond:expr $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_assert!($provider $(@ $version)?, $name, $cond; level = $level $(, $($opt)+)?)
+    );
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt, $cond:expr, $($args:tt)+)
+    => (
+        if $cond {
+            true
+        } else {
+            $crate::probe!(level: $level, $provider $(@ $version)?, $name, $($args)+);
+            false
+        }
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $cond:expr $(; $($opt:tt)+)?) => (
+        if $cond {
+            true
+        } else {
+            $crate::probe!($provider $(@ $version)?, $name $(; $($opt)+)?);
+            false
+        }
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $cond:expr, $($args:tt)+) => (
+        if $cond {
+            true
+        } else {
+            $crate::probe!($provider $(@ $version)?, $name, $($args)+);
+            false
+        }
+    );
+);
+
+/// Define a static probe point for an invariant that's asserted in debug
+/// builds.
+///
+/// This is [`probe_assert!`], which fires the probe if the condition is
+/// `false`, and then panics if `debug_assertions` are on, like
+/// `debug_assert!`, rather than yielding the condition. In release builds, the
+/// probe is all there is to it.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_debug_assert;
+/// # let (len, capacity) = (3, 4);
+/// probe_debug_assert!(queue, overflow, len <= capacity, len, capacity);
+/// ```
+#[macro_export]
+macro_rules! probe_debug_assert(
+    (@check $cond:expr, $held:expr) => (
+        if !$held && ::core::cfg!(debug_assertions) {
+            ::core::panic!(::core::concat!("probe assertion failed: ", ::core::stringify!($cond)));
+        }
+    );
+    (level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt, $cond:expr $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_debug_assert!(@check $cond,
+            $crate::probe_assert!(level: $level, $provider $(@ $version)?, $name,
+                $cond $(; $($opt)+)?))
+    );
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt, $cond:expr, $($args:tt)+)
+    => (
+        $crate::probe_debug_assert!(@check $cond,
+            $crate::probe_assert!(level: $level, $provider $(@ $version)?, $name, $cond, $($args)+))
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $cond:expr $(; $($opt:tt)+)?) => (
+        $crate::probe_debug_assert!(@check $cond,
+            $crate::probe_assert!($provider $(@ $version)?, $name, $cond $(; $($opt)+)?))
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $cond:expr, $($args:tt)+) => (
+        $crate::probe_debug_assert!(@check $cond,
+            $crate::probe_assert!($provider $(@ $version)?, $name, $cond, $($args)+))
+    );
+);
+
+/// Define a static probe point that times an expression.
+///
+/// This evaluates the expression, and yields its value, and if the probe was
+/// enabled before it, fires the probe with the time it took as its first
+/// argument, named `elapsed`, followed by any others, which are only evaluated
+/// then, like [`probe_lazy!`]. See the [`latency`] module for the clock it
+/// uses.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_latency;
+/// # let buf = [0u8; 64];
+/// let sum = probe_latency!(foo, checksum, buf.iter().map(|&b| b as u32).sum::<u32>(),
+///     len = buf.len());
+/// assert_eq!(sum, 0);
+///
+/// probe_latency!(foo, flush, (); level = debug);
+/// ```
+#[macro_export]
+macro_rules! probe_latency(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt, $expr:expr $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_parse!(probe_latency_fire, [$expr],
+            [[$provider $(@ $version)?], $name], [level = $level],
+            [], [], $(; $($opt)+)?)
+    );
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt, $expr:expr, $($args:tt)+)
+    => (
+        $crate::probe_parse!(probe_latency_fire, [$expr],
+            [[$provider $(@ $version)?], $name], [level = $level],
+            [], [], , $($args)+)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $expr:expr $(; $($opt:tt)+)?) => (
+        $crate::probe_parse!(probe_latency_fire, [$expr],
+            [[$provider $(@ $version)?], $name], [], [], [],
+            $(; $($opt)+)?)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $expr:expr, $($args:tt)+) => (
+        $crate::probe_parse!(probe_latency_fire, [$expr],
+            [[$provider $(@ $version)?], $name], [], [], [],
+            , $($args)+)
+    );
+);
+
+/// Define a static probe point that only fires once.
+///
+/// This works like [`probe_lazy!`], and only fires the probe on the first hit
+/// of this site while it's enabled, even when threads race for it. Later hits
+/// are a relaxed atomic load after the enabled check. The arguments are only
+/// evaluated when the probe fires.
+///
+/// Returns `true` if the probe is executed (and its arguments evaluated).
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_once;
+/// for i in 0..10 {
+///     // Only fired for the first time around.
+///     probe_once!(foo, started, i);
+/// }
+///
+/// probe_once!(foo, ready; level = info);
+/// ```
+#[macro_export]
+macro_rules! probe_once(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_gated, [{
+            static ONCE: $crate::sample::Once = $crate::sample::Once::new();
+            ONCE.first()
+        }], [[$provider $(@ $version)?], $name], [level = $level], [], [], $($args)*)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_gated, [{
+            static ONCE: $crate::sample::Once = $crate::sample::Once::new();
+            ONCE.first()
+        }], [[$provider $(@ $version)?], $name], [], [], [], $($args)*)
+    );
+);
+
+/// Define a static probe point that counts its hits.
+///
+/// This works like [`probe!`], and also counts every hit of this site in an
+/// atomic counter, whether or not the probe is enabled, for a snapshot of the
+/// counts with [`counter::counters`]. See the [`counter`] module for where
+/// they're kept.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_counter;
+/// for i in 0..10 {
+///     probe_counter!(foo, iteration, i);
+/// }
+/// ```
+#[macro_export]
+macro_rules! probe_counter(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_counter_fire, [],
+            [[$provider $(@ $version)?], $name], [level = $level], [], [], $($args)*)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_counter_fire, [],
+            [[$provider $(@ $version)?], $name], [], [], [], $($args)*)
+    );
+);
+
+/// Define a static probe point that keeps a histogram of a value.
+///
+/// This works like [`probe!`], with the value as the first argument, named
+/// `value`, followed by any others, and also counts the value in a log2
+/// histogram of this site, whether or not the probe is enabled, for a snapshot
+/// of its buckets with [`histogram::histograms`]. See the [`histogram`]
+/// module for the buckets.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_histogram;
+/// # let buf = [0u8; 64];
+/// probe_histogram!(foo, read, buf.len(), fd = 3);
+/// probe_histogram!(foo, batch, 7; level = debug);
+/// ```
+#[macro_export]
+macro_rules! probe_histogram(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt, $value:expr $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_parse!(probe_histogram_fire, [$value],
+            [[$provider $(@ $version)?], $name], [level = $level],
+            [], [], $(; $($opt)+)?)
+    );
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt, $value:expr, $($args:tt)+)
+    => (
+        $crate::probe_parse!(probe_histogram_fire, [$value],
+            [[$provider $(@ $version)?], $name], [level = $level],
+            [], [], , $($args)+)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $value:expr $(; $($opt:tt)+)?) => (
+        $crate::probe_parse!(probe_histogram_fire, [$value],
+            [[$provider $(@ $version)?], $name], [], [], [],
+            $(; $($opt)+)?)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $value:expr, $($args:tt)+) => (
+        $crate::probe_parse!(probe_histogram_fire, [$value],
+            [[$provider $(@ $version)?], $name], [], [], [],
+            , $($args)+)
+    );
+);
+
+/// Define a static probe point for the span of a scope.
+///
+/// This fires the probe right away, like [`probe!`], with the `span = begin`
+/// option, and returns a [`SpanGuard`](span::SpanGuard) that fires it again
+/// with `span = end` and without arguments when it's dropped, including when
+/// the scope unwinds. The guard has to be bound to a variable, not `_`, to
+/// last until the end of the scope. See the [`span`] module for how platforms
+/// show them.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_span;
+/// # let (id, len) = (1, 2);
+/// let _span = probe_span!(foo, request, id, len; level = debug);
+/// ```
+#[macro_export]
+macro_rules! probe_span(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_span_begin, [],
+            [[$provider $(@ $version)?], $name], [level = $level], [], [],
+            $($args)*)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt $($args:tt)*) => (
+        $crate::probe_parse!(probe_span_begin, [],
+            [[$provider $(@ $version)?], $name], [], [], [], $($args)*)
+    );
+);
+
+/// Define a pair of static probe points around a block.
+///
+/// This fires the probe with the `span = begin` option, evaluates the block,
+/// fires the probe with `span = end`, and yields the block's value, like
+/// [`probe_span!`] without a guard or arguments. A `return`, `?`, `break`, or
+/// panic out of the block skips the end, so a block that can leave early
+/// should use `probe_span!` instead. Options can follow the block.
+///
+/// # Example
+///
+/// ```
+/// # use probe::probe_block;
+/// let sum = probe_block!(foo, sum, {
+///     (1..=10).sum::<u32>()
+/// });
+/// assert_eq!(sum, 55);
+///
+/// probe_block!(foo, flush, {}; level = debug);
+/// ```
+#[macro_export]
+macro_rules! probe_block(
+    (level: $level:ident, $provider:tt $(@ $version:literal)?,
+        $name:tt, $body:block $(; $($opt:tt)+)?)
+    => (
+        $crate::probe_block!($provider $(@ $version)?, $name, $body; level = $level $(, $($opt)+)?)
+    );
+    ($provider:tt $(@ $version:literal)?, $name:tt, $body:block $(; $($opt:tt)+)?) => ({
+        $crate::probe!($provider $(@ $version)?, $name; span = begin $(, $($opt)+)?);
+        let value = $body;
+        $crate::probe!($provider $(@ $version)?, $name; span = end $(, $($opt)+)?);
+        value
+    });
+);
+
+// Fires the beginning of a span, and returns the guard for its end, which
+// doesn't have the names of the arguments.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_span_begin(
+    (, [$provider:tt, $name:tt, [__names = $names:expr $(, $($opt:tt)*)?]], $($args:tt)*) => ({
+        $crate::probe_args!(probe_fire, probe_bind,
+            [$provider, $name, [__names = $names, span = begin $(, $($opt)*)?]], $($args)*);
+        $crate::span::SpanGuard::new(|| {
+            $crate::probe_fire!($provider, $name, [span = end $(, $($opt)*)?],)
+        })
+    });
+    (, [$provider:tt, $name:tt, [$($opt:tt)*]], $($args:tt)*) => ({
+        $crate::probe_args!(probe_fire, probe_bind,
+            [$provider, $name, [span = begin, $($opt)*]], $($args)*);
+        $crate::span::SpanGuard::new(|| {
+            $crate::probe_fire!($provider, $name, [span = end, $($opt)*],)
+        })
+    });
+);
+
+// Counts a hit of a probe's site, and fires it.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_counter_fire(
+    (, [$provider:tt, $name:tt, [$($opt:tt)*]], $($args:tt)*) => ({
+        $crate::probe_counter_hit!($provider, $name, [$($opt)*]);
+        $crate::probe_args!(probe_fire, probe_bind, [$provider, $name, [$($opt)*]], $($args)*)
+    });
+);
+
+// A lazy probe that's only fired when it's enabled and its `$gate` is true,
+// where the gate is only evaluated while it's enabled.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_gated(
+    ($gate:expr, [$provider:tt, $name:tt, [$($opt:tt)*]], $($args:tt)*) => (
+        if $crate::probe_compiled_in!($provider, [$($opt)*])
+            && $crate::probe_enabled_site!($provider, $name, [$($opt)*])
+            && $gate
+        {
+            $crate::probe_args!(probe_lazy_fire, probe_lazy_bind,
+                [$provider, $name, [$($opt)*]], $($args)*)
+        } else {
+            $crate::probe_options!($($opt)*);
+            false
+        }
+    );
+);
+
+// Times `$expr` while the probe is enabled, and fires it with the elapsed time
+// ahead of the other arguments, and its name ahead of theirs, marked as timed.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_latency_fire(
+    (@time $expr:expr, [$provider:tt, $name:tt, [$($opt:tt)*]], [$($kind:tt)*], $($args:tt)*) => ({
+        let start = if $crate::probe_compiled_in!($provider, [$($opt)*])
+            && $crate::probe_enabled_site!($provider, $name, [$($opt)*])
+        {
+            ::core::option::Option::Some($crate::latency::Start::now())
+        } else {
+            ::core::option::Option::None
+        };
+        let value = $expr;
+        if let ::core::option::Option::Some(start) = start {
+            let elapsed = start.elapsed();
+            $crate::probe_args!(probe_lazy_fire, probe_lazy_bind,
+                [$provider, $name, [$($opt)*]], [[] $($kind)*], [] elapsed, $($args)*);
+        } else {
+            $crate::probe_options!($($opt)*);
+        }
+        value
+    });
+    ($expr:expr, [$provider:tt, $name:tt, [__names = $names:expr $(, $($opt:tt)*)?]],
+        [$($kind:tt)*], $($args:tt)*
+    ) => (
+        $crate::probe_latency_fire!(@time $expr,
+            [$provider, $name, [
+                __names = concat!("elapsed ", $names), __timed = true $(, $($opt)*)?
+            ]],
+            [$($kind)*], $($args)*)
+    );
+    ($expr:expr, [$provider:tt, $name:tt, []], [$([$($kind:tt)*])*], $($args:tt)*) => (
+        $crate::probe_latency_fire!(@time $expr,
+            [$provider, $name, [
+                __names = $crate::probe_names!([] elapsed $([$($kind)*] _)*), __timed = true
+            ]],
+            [$([$($kind)*])*], $($args)*)
+    );
+    ($expr:expr, [$provider:tt, $name:tt, [$($opt:tt)+]], [$([$($kind:tt)*])*], $($args:tt)*) => (
+        $crate::probe_latency_fire!(@time $expr,
+            [$provider, $name, [
+                __names = $crate::probe_names!([] elapsed $([$($kind)*] _)*), __timed = true,
+                $($opt)+
+            ]],
+            [$([$($kind)*])*], $($args)*)
+    );
+);
+
+// Counts the value of a probe in its histogram, and fires it with the value
+// first, named `value`, like the elapsed time of a timed probe.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_histogram_fire(
+    (@record $value:expr, [$provider:tt, $name:tt, [$($opt:tt)*]], [$($kind:tt)*], $($args:tt)*) => (
+        match $crate::arg::ProbeArg::into_probe_arg($value) {
+            value => {
+                $crate::probe_histogram_record!($provider, $name, [$($opt)*], value);
+                $crate::probe_args!(probe_fire, probe_bind,
+                    [$provider, $name, [$($opt)*]], [[] $($kind)*], [] value, $($args)*)
+            }
+        }
+    );
+    ($value:expr, [$provider:tt, $name:tt, [__names = $names:expr $(, $($opt:tt)*)?]],
+        [$($kind:tt)*], $($args:tt)*
+    ) => (
+        $crate::probe_histogram_fire!(@record $value,
+            [$provider, $name, [__names = concat!("value ", $names) $(, $($opt)*)?]],
+            [$($kind)*], $($args)*)
+    );
+    ($value:expr, [$provider:tt, $name:tt, []], [$([$($kind:tt)*])*], $($args:tt)*) => (
+        $crate::probe_histogram_fire!(@record $value,
+            [$provider, $name, [__names = $crate::probe_names!([] value $([$($kind)*] _)*)]],
+            [$([$($kind)*])*], $($args)*)
+    );
+    ($value:expr, [$provider:tt, $name:tt, [$($opt:tt)+]], [$([$($kind:tt)*])*], $($args:tt)*) => (
+        $crate::probe_histogram_fire!(@record $value,
+            [$provider, $name, [
+                __names = $crate::probe_names!([] value $([$($kind)*] _)*), $($opt)+
+            ]],
+            [$([$($kind)*])*], $($args)*)
+    );
+);
+
+/// Get a probe's semaphore, to arm it from the program.
+///
+/// This is `Some` [`Semaphore`](semaphore::Semaphore) for SystemTap probes,
+/// and `None` elsewhere, where probes don't have one. Incrementing it enables
+/// the probe's sites for [`probe_lazy!`] and [`probe_enabled!`] exactly as an
+/// attached tracer does. This needs the `use_std` feature.
+///
+/// # Example
+///
+/// ```
+/// # use probe::{probe_lazy, probe_semaphore};
+/// if let Some(semaphore) = probe_semaphore!(foo, lazy) {
+///     semaphore.increment();
+///     assert!(probe_lazy!(foo, lazy, 42));
+///     semaphore.decrement();
+/// }
+/// ```
+#[cfg(feature = "use_std")]
+#[macro_export]
+macro_rules! probe_semaphore(
+    ($provider:tt $(@ $version:literal)?, $name:tt) => (
+        $crate::platform_probe_semaphore!([$provider $(@ $version)?], $name)
+    );
+);
+
+/// Declare the probes of a provider as functions.
+///
+/// Each probe is a function in a module named for the provider, which fires
+/// it with [`probe!`], with its parameters as named arguments. So a probe's
+/// arguments are declared once, and a call with the wrong number or types of
+/// arguments is an error where it's made. The functions are `#[inline]`, so a
+/// call costs the same as the probe itself, and the module can see the items
+/// of the one it's declared in, for the types of the parameters, which have
+/// to implement [`ProbeArg`]. The provider can have a version, like `foo@2`,
+/// as in [`probe!`].
+///
+/// # Example
+///
+/// ```
+/// use probe::provider;
+///
+/// provider! {
+///     /// The probes of the allocator.
+///     pub foo {
+///         begin(),
+///         /// An allocation of `size` bytes.
+///         alloc(size: usize, align: usize),
+///     }
+/// }
+///
+/// foo::begin();
+/// foo::alloc(64, 16);
+/// ```
+#[macro_export]
+macro_rules! provider(
+    (@probes $provider:tt,
+        $($(#[$attr:meta])* $name:ident($($arg:ident: $ty:ty),* $(,)?)),* $(,)?
+    ) => ($(
+        $(#[$attr])*
+        #[inline]
+        pub fn $name($($arg: $ty),*) {
+            $crate::probe!($provider, $name $(, $arg = $arg)*);
+        }
+    )*);
+    ($($(#[$attr:meta])* $vis:vis $provider:ident $(@ $version:literal)? {
+        $($probes:tt)*
+    })*) => ($(
+        $(#[$attr])*
+        $vis mod $provider {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::provider!(@probes [$provider $(@ $version)?], $($probes)*);
+        }
+    )*);
+);
+
+// The provider of a probe as a string, where `_` is the crate it's in, and a
+// group like `(foo cache)` is from `#[namespace]`, as `foo__cache`. The
+// public macros pass it on in brackets, with its version if it has one, like
+// `[foo @ 2]`.
+//
+// It has to be a literal, not a constant, since it goes into `concat!` for the
+// strings of asm and link names, and a macro can't see the value of a `const`
+// it's given, only its name, which it would take for the provider itself.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_provider(
+    (_) => (::core::env!("CARGO_CRATE_NAME"));
+    ($provider:ident) => (::core::stringify!($provider));
+    ((_ $($module:ident)+)) => (
+        ::core::concat!(::core::env!("CARGO_CRATE_NAME") $(, "__", ::core::stringify!($module))+)
+    );
+    (($provider:ident $($module:ident)+)) => (
+        ::core::concat!(::core::stringify!($provider) $(, "__", ::core::stringify!($module))+)
+    );
+    ($provider:literal) => ($provider);
+    ([$provider:tt $(@ $version:literal)?]) => ($crate::probe_provider!($provider));
+    ($provider:tt) => (
+        ::core::compile_error!(::core::concat!(
+            "the provider of a probe is an identifier, a string, or `_`, not `",
+            ::core::stringify!($provider),
+            "`"
+        ))
+    );
+);
+
+// The name of a probe as a string.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_name(
+    ($name:ident) => (::core::stringify!($name));
+    ($name:literal) => ($name);
+    ($name:tt) => (
+        ::core::compile_error!(::core::concat!(
+            "the name of a probe is an identifier or a string, not `",
+            ::core::stringify!($name),
+            "`"
+        ))
+    );
+);
+
+// Whether a probe isn't compiled out by `PROBE_DISABLE` or its level, as a
+// constant, so the other branch is all that's left of the probe.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_compiled_in(
+    ($provider:tt, [$($opt:tt)*]) => ({
+        const COMPILED_IN: bool = $crate::level::compiled_in($crate::probe_level_value!($($opt)*))
+            && $crate::control::compiled_in(
+                $crate::probe_provider!($provider),
+                ::core::option_env!("PROBE_DISABLE"),
+            );
+        COMPILED_IN
+    });
+);
+
+// The body of `probe!`, once its arguments are plain expressions.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_fire(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::probe_options!($($opt)*);
+        $crate::probe_table!($provider, $name, [$($opt)*], $($arg,)*);
+        if $crate::probe_compiled_in!($provider, [$($opt)*]) {
+            $crate::probe_platform!($provider, $name, [$($opt)*], $($arg,)*)
+        } else {
+            $(let _ = $arg;)*
+        }
+    });
+);
+
+// The body of `probe_lazy!`, once its arguments are plain expressions.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_lazy_fire(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::probe_options!($($opt)*);
+        $crate::probe_table!($provider, $name, [$($opt)*], $($arg,)*);
+        if $crate::probe_compiled_in!($provider, [$($opt)*]) {
+            $crate::probe_lazy_site!($provider, $name, [$($opt)*], $($arg,)*)
+        } else {
+            false
+        }
+    });
+);
+
+// With `callback`, probes go to the program's handler as well as the platform.
+#[cfg(not(feature = "callback"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_platform(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        $crate::platform_probe!($provider, $name, [$($opt)*], $($arg,)*)
+    );
+);
+
+#[cfg(feature = "callback")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_platform(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        $crate::probe_callback!([$provider, $name, [$($opt)*]], [], $($arg,)*)
+    );
+);
+
+// With `force_enable`, lazy probes are plain probes that are always enabled.
+#[cfg(not(any(feature = "force_enable", feature = "callback")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_lazy_site(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        $crate::platform_probe_lazy!($provider, $name, [$($opt)*], $($arg,)*)
+    );
+);
+
+#[cfg(all(feature = "callback", not(feature = "force_enable")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_lazy_site(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        if $crate::probe_callback_enabled!(enabled, $provider, $name) {
+            $crate::probe_platform!($provider, $name, [$($opt)*], $($arg,)*);
+            true
+        } else {
+            $crate::platform_probe_lazy!($provider, $name, [$($opt)*], $($arg,)*)
+        }
+    );
+);
+
+#[cfg(feature = "force_enable")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_lazy_site(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::probe_platform!($provider, $name, [$($opt)*], $($arg,)*);
+        true
+    });
+);
+
+#[cfg(not(any(feature = "force_enable", feature = "callback")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_enabled_site(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::platform_probe_enabled!($provider, $name, [$($opt)*])
+    );
+);
+
+#[cfg(all(feature = "callback", not(feature = "force_enable")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_enabled_site(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::probe_callback_enabled!(enabled, $provider, $name)
+            || $crate::platform_probe_enabled!($provider, $name, [$($opt)*])
+    );
+);
+
+#[cfg(feature = "force_enable")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_enabled_site(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (true);
+);
+
+// Checks the options of a probe, so they're valid on every platform, even
+// where they're ignored.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_options(
+    () => ();
+    (level = $level:ident $(, $($rest:tt)*)?) => (
+        $crate::probe_level!($level);
+        $crate::probe_options!($($($rest)*)?);
+    );
+    (keyword = $keyword:expr $(, $($rest:tt)*)?) => (
+        const _: u64 = $keyword;
+        $crate::probe_options!($($($rest)*)?);
+    );
+    (span = $span:ident $(, $($rest:tt)*)?) => (
+        $crate::probe_span_kind!($span);
+        $crate::probe_options!($($($rest)*)?);
+    );
+    (operands = $operands:ident $(, $($rest:tt)*)?) => (
+        $crate::probe_operands_kind!($operands);
+        $crate::probe_options!($($($rest)*)?);
+    );
+    (__operands = [$($ty:tt),*] $(, $($rest:tt)*)?) => (
+        $crate::probe_options!($($($rest)*)?);
+    );
+    (__names = $names:expr $(, $($rest:tt)*)?) => (
+        $crate::probe_options!($($($rest)*)?);
+    );
+    (__timed = $timed:expr $(, $($rest:tt)*)?) => (
+        $crate::probe_options!($($($rest)*)?);
+    );
+    (format = $format:literal $(, $($rest:tt)*)?) => (
+        const _: () = assert!(
+            $crate::format::valid($format),
+            "a probe's format can't have a `\"`, a `\\`, or braces"
+        );
+        $crate::probe_options!($($($rest)*)?);
+    );
+    ($key:ident = $($rest:tt)*) => (
+        compile_error!(concat!("unknown probe option `", stringify!($key), "`"));
+    );
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_level(
+    (critical) => ();
+    (error) => ();
+    (warn) => ();
+    (info) => ();
+    (debug) => ();
+    (trace) => ();
+    ($level:ident) => (
+        compile_error!(concat!("unknown probe level `", stringify!($level), "`"));
+    );
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_span_kind(
+    (begin) => ();
+    (end) => ();
+    ($span:ident) => (
+        compile_error!(concat!("unknown probe span `", stringify!($span), "`"));
+    );
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_operands_kind(
+    (registers) => ();
+    (memory) => ();
+    ($operands:ident) => (
+        compile_error!(concat!("unknown probe operands `", stringify!($operands), "`"));
+    );
 );
@@ -0,0 +1,257 @@
+//! Serving probe metrics to Prometheus
+//!
+//! With the `metrics` feature, [`start`] counts every probe that's handled by
+//! the [`callback`] module, and sums the elapsed times of those from
+//! [`probe_latency!`](crate::probe_latency), and [`render`] has those, with the
+//! hits of [`counter`](crate::counter) sites and the buckets of
+//! [`histogram`](crate::histogram) sites, in the Prometheus text format.
+//! [`serve`] answers every HTTP request on a TCP listener with them, and on
+//! Unix, [`serve_unix`] writes them to every connection on a Unix socket, so
+//! an observability stack can scrape a program's probes without a tracer.
+//!
+//! ```no_run
+//! use std::net::TcpListener;
+//!
+//! probe::metrics::start();
+//! probe::control::enable("server");
+//! probe::metrics::serve(TcpListener::bind("127.0.0.1:9464").unwrap());
+//!
+//! let sum = probe::probe_latency!(server, request, (1..=100).sum::<u32>());
+//! ```
+//!
+//! Each metric has `provider` and `probe` labels, and the probes of the same
+//! name are added up across their sites:
+//!
+//! ```text
+//! # TYPE probe_hits_total counter
+//! probe_hits_total{provider="server",probe="request"} 1
+//! # TYPE probe_latency summary
+//! probe_latency_sum{provider="server",probe="request"} 1062119
+//! probe_latency_count{provider="server",probe="request"} 1
+//! ```
+//!
+//! The latencies are in the units of the [`latency`](crate::latency) clock,
+//! which are nanoseconds by default. Counter and histogram sites can only be
+//! listed on Linux, Android, and FreeBSD, so they're only there.
+//!
+//! [`callback`]: crate::callback
+
+//
+// DEVELOPER NOTES
+//
+// The handler counts into a map behind a mutex, which is simple, but makes
+// every enabled probe take a lock, so it's for diagnostics rather than for
+// probes on a hot path, which are better as `probe_counter!` sites. The server
+// is a thread that answers one connection at a time, since a scrape is rare,
+// and small, and it doesn't look at what's requested, so any path works.
+//
+
+use crate::callback::{self, ProbeInfo};
+use core::fmt::{self, Write as _};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::string::String;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Stats {
+    hits: u64,
+    latency: Option<(u64, u64)>,
+}
+
+type Key = (&'static str, &'static str);
+
+static STATS: Mutex<BTreeMap<Key, Stats>> = Mutex::new(BTreeMap::new());
+
+fn stats() -> std::sync::MutexGuard<'static, BTreeMap<Key, Stats>> {
+    STATS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts counting probes, by adding a `callback` handler.
+pub fn start() {
+    callback::add_handler(record);
+}
+
+/// Stops counting probes, by removing its `callback` handler, but keeps the
+/// counts.
+pub fn stop() {
+    callback::remove_handler(record);
+}
+
+/// Counts a probe, as a `callback` handler.
+pub fn record(info: &ProbeInfo, args: &[isize]) {
+    let mut stats = stats();
+    let stats = stats.entry((info.provider(), info.name())).or_default();
+    stats.hits += 1;
+    if let (true, Some(&elapsed)) = (info.timed(), args.first()) {
+        let (sum, count) = stats.latency.get_or_insert((0, 0));
+        *sum = sum.wrapping_add(elapsed as usize as u64);
+        *count += 1;
+    }
+}
+
+/// The metrics, in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let stats = stats();
+    out.push_str("# TYPE probe_hits_total counter\n");
+    for (&key, stats) in stats.iter() {
+        let _ = writeln!(out, "probe_hits_total{} {}", Labels(key), stats.hits);
+    }
+    out.push_str("# TYPE probe_latency summary\n");
+    for (&key, stats) in stats.iter() {
+        if let Some((sum, count)) = stats.latency {
+            let _ = writeln!(out, "probe_latency_sum{} {}", Labels(key), sum);
+            let _ = writeln!(out, "probe_latency_count{} {}", Labels(key), count);
+        }
+    }
+    drop(stats);
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    sites(&mut out);
+    out
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn sites(out: &mut String) {
+    use crate::histogram::BUCKETS;
+
+    let mut counters = BTreeMap::<Key, u64>::new();
+    for counter in crate::counter::counters() {
+        *counters
+            .entry((counter.provider(), counter.name()))
+            .or_default() += counter.hits() as u64;
+    }
+    out.push_str("# TYPE probe_counter_hits_total counter\n");
+    for (&key, hits) in &counters {
+        let _ = writeln!(out, "probe_counter_hits_total{} {}", Labels(key), hits);
+    }
+
+    let mut histograms = BTreeMap::<Key, ([u64; BUCKETS], u64)>::new();
+    for histogram in crate::histogram::histograms() {
+        let key = (histogram.provider(), histogram.name());
+        let (sums, sum) = histograms.entry(key).or_insert(([0; BUCKETS], 0));
+        for (total, count) in sums.iter_mut().zip(histogram.buckets()) {
+            *total += count as u64;
+        }
+        *sum = sum.wrapping_add(histogram.sum() as u64);
+    }
+    out.push_str("# TYPE probe_histogram histogram\n");
+    for (&key, (buckets, sum)) in &histograms {
+        // Bucket `k` has the values up to `2^k - 1`, and the empty ones above
+        // the last value are left out.
+        let used = buckets
+            .iter()
+            .rposition(|&count| count > 0)
+            .map_or(0, |k| k + 1);
+        let mut total = 0;
+        for (k, count) in buckets[..used].iter().enumerate() {
+            total += count;
+            let le = if k == 0 { 0 } else { u64::MAX >> (64 - k) };
+            let _ = writeln!(
+                out,
+                "probe_histogram_bucket{{provider=\"{}\",probe=\"{}\",le=\"{}\"}} {}",
+                Escaped(key.0),
+                Escaped(key.1),
+                le,
+                total
+            );
+        }
+        let _ = writeln!(
+            out,
+            "probe_histogram_bucket{{provider=\"{}\",probe=\"{}\",le=\"+Inf\"}} {}",
+            Escaped(key.0),
+            Escaped(key.1),
+            total
+        );
+        let _ = writeln!(out, "probe_histogram_sum{} {}", Labels(key), sum);
+        let _ = writeln!(out, "probe_histogram_count{} {}", Labels(key), total);
+    }
+}
+
+/// Serves the metrics over HTTP, on a thread that answers each connection to
+/// `listener` with them, whatever it asks for, for as long as the program runs.
+pub fn serve(listener: TcpListener) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("probe-metrics".into())
+        .spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                read_request(&mut stream);
+                let body = render();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.0 200 OK\r\n\
+                     Content-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        })
+}
+
+/// Serves the metrics on a Unix socket, on a thread that writes them to each
+/// connection to `listener`, and closes it, for as long as the program runs.
+#[cfg(unix)]
+pub fn serve_unix(listener: std::os::unix::net::UnixListener) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("probe-metrics".into())
+        .spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                let _ = stream.write_all(render().as_bytes());
+            }
+        })
+}
+
+// Reads the head of a request, up to the blank line after it, or a few KiB.
+fn read_request(stream: &mut impl Read) {
+    let mut head = [0; 4096];
+    let mut len = 0;
+    while len < head.len() {
+        match stream.read(&mut head[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+        if head[..len].windows(4).any(|end| end == b"\r\n\r\n") {
+            break;
+        }
+    }
+}
+
+// The labels of a probe's metrics.
+struct Labels(Key);
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (provider, probe) = self.0;
+        write!(
+            f,
+            "{{provider=\"{}\",probe=\"{}\"}}",
+            Escaped(provider),
+            Escaped(probe)
+        )
+    }
+}
+
+// A label value, without its quotes.
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! Exporting probes as a Perfetto trace
+//!
+//! With the `perfetto` feature, [`start`] writes every probe that's handled by
+//! the [`callback`] module as a `TrackEvent` of a Perfetto trace, in its
+//! protobuf format, which the Perfetto UI and `trace_processor` can open, so
+//! a program can be traced where there's no tracer, or without setting one up.
+//! [`finish`] ends the trace.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufWriter;
+//!
+//! let file = File::create("trace.perfetto-trace").unwrap();
+//! probe::perfetto::start(BufWriter::new(file));
+//! probe::control::enable("server");
+//!
+//! {
+//!     let _span = probe::probe_span!(server, request, id = 1);
+//!     probe::probe!(server, parsed, len = 64);
+//! }
+//!
+//! probe::perfetto::finish().unwrap();
+//! ```
+//!
+//! Each thread has a track, under a track of the process, with the thread's
+//! name, and each probe is an event on its thread's track, named by the probe,
+//! in a category of its provider. The two ends of a span, like those of
+//! [`probe_span!`](crate::probe_span), begin and end a slice, and other probes
+//! are instant events. The operands are debug annotations of the event, by
+//! their names where they have one, or by their index otherwise, and the
+//! timestamps are from the [`latency`](crate::latency) clock, which counts
+//! nanoseconds by default, since the trace started.
+//!
+//! [`callback`]: crate::callback
+
+//
+// DEVELOPER NOTES
+//
+// A trace is a `Trace` message, which is only its repeated `packet` field, so
+// each `TracePacket` is written on its own, with the key and the length of the
+// field, and a trace that isn't finished is still valid up to its last whole
+// packet. The messages are encoded by hand, with only the fields used here:
+//
+//   TracePacket: timestamp = 8, trusted_packet_sequence_id = 10,
+//     track_event = 11, track_descriptor = 60
+//   TrackDescriptor: uuid = 1, process = 3, thread = 4, parent_uuid = 5
+//   ProcessDescriptor: pid = 1
+//   ThreadDescriptor: pid = 1, tid = 2, thread_name = 5
+//   TrackEvent: debug_annotations = 4, type = 9, track_uuid = 11,
+//     categories = 22, name = 23
+//   DebugAnnotation: int_value = 4, name = 10
+//
+// Nothing is interned, so every packet stands on its own, in one sequence.
+// Threads are numbered like those of the `chrome` module, and the descriptor
+// of a thread's track is written ahead of its first event in each trace.
+//
+
+use crate::callback::{self, ProbeInfo};
+use crate::latency::Start;
+use crate::span::Span;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::boxed::Box;
+use std::io::{self, Write};
+use std::string::ToString;
+use std::sync::Mutex;
+use std::thread;
+use std::vec::Vec;
+
+const SEQUENCE: u64 = 1;
+
+const TYPE_SLICE_BEGIN: u64 = 1;
+const TYPE_SLICE_END: u64 = 2;
+const TYPE_INSTANT: u64 = 3;
+
+struct Trace {
+    out: Box<dyn Write + Send>,
+    start: Start,
+    number: usize,
+    pid: u32,
+    error: Option<io::Error>,
+}
+
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+// The number of the next trace, and of the next thread.
+static TRACES: AtomicUsize = AtomicUsize::new(1);
+static THREADS: AtomicUsize = AtomicUsize::new(1);
+
+std::thread_local! {
+    static THREAD: usize = THREADS.fetch_add(1, Ordering::Relaxed);
+    // The last trace that has the thread's track.
+    static TRACKED: Cell<usize> = const { Cell::new(0) };
+}
+
+fn trace() -> std::sync::MutexGuard<'static, Option<Trace>> {
+    TRACE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts a trace, written to `out`, by adding a `callback` handler, after
+/// finishing any other.
+pub fn start<W: Write + Send + 'static>(out: W) {
+    let _ = finish();
+    let mut trace = trace();
+    let mut new = Trace {
+        out: Box::new(out),
+        start: Start::now(),
+        number: TRACES.fetch_add(1, Ordering::Relaxed),
+        pid: std::process::id(),
+        error: None,
+    };
+    let pid = new.pid;
+    new.packet(None, |packet| {
+        packet.message(60, |track| {
+            track.uint(1, process_uuid(pid));
+            track.message(3, |process| process.uint(1, pid as u64));
+        });
+    });
+    *trace = Some(new);
+    callback::add_handler(record);
+}
+
+/// Finishes the trace, by removing its `callback` handler, and flushing what's
+/// been written, with the first error while it was written, if there was one.
+pub fn finish() -> io::Result<()> {
+    callback::remove_handler(record);
+    match trace().take() {
+        Some(mut trace) => match trace.error {
+            Some(error) => Err(error),
+            None => trace.out.flush(),
+        },
+        None => Ok(()),
+    }
+}
+
+/// Writes a probe to the trace, if it's started, as a `callback` handler.
+pub fn record(info: &ProbeInfo, args: &[isize]) {
+    let mut trace = trace();
+    let trace = match trace.as_mut() {
+        Some(trace) => trace,
+        None => return,
+    };
+    // Not while the thread's locals are being destroyed.
+    let thread = THREAD.try_with(|&tid| tid).and_then(|tid| {
+        TRACKED.try_with(|tracked| (tid, tracked.replace(trace.number) == trace.number))
+    });
+    let (tid, tracked) = match thread {
+        Ok(thread) => thread,
+        Err(_) => return,
+    };
+    let pid = trace.pid;
+    let uuid = process_uuid(pid) | tid as u64;
+    if !tracked {
+        trace.packet(None, |packet| {
+            packet.message(60, |track| {
+                track.uint(1, uuid);
+                track.uint(5, process_uuid(pid));
+                track.message(4, |thread| {
+                    thread.uint(1, pid as u64);
+                    thread.uint(2, tid as u64);
+                    if let Some(name) = thread::current().name() {
+                        thread.string(5, name);
+                    }
+                });
+            });
+        });
+    }
+    let ts = trace.start.elapsed();
+    trace.packet(Some(ts), |packet| {
+        packet.message(11, |event| {
+            let kind = match info.span() {
+                Some(Span::Begin) => TYPE_SLICE_BEGIN,
+                Some(Span::End) => TYPE_SLICE_END,
+                None => TYPE_INSTANT,
+            };
+            event.uint(9, kind);
+            event.uint(11, uuid);
+            if info.span() != Some(Span::End) {
+                event.string(22, info.provider());
+                event.string(23, info.name());
+            }
+            for (i, (name, arg)) in info.operands(args).enumerate() {
+                event.message(4, |annotation| {
+                    match name {
+                        Some(name) => annotation.string(10, name),
+                        None => annotation.string(10, &i.to_string()),
+                    }
+                    annotation.uint(4, arg as i64 as u64);
+                });
+            }
+        });
+    });
+}
+
+// The UUID of the process's track, and the high half of those of its threads.
+fn process_uuid(pid: u32) -> u64 {
+    (pid as u64) << 32
+}
+
+impl Trace {
+    fn packet(&mut self, timestamp: Option<u64>, fields: impl FnOnce(&mut Message)) {
+        let mut packet = Message(Vec::new());
+        if let Some(timestamp) = timestamp {
+            packet.uint(8, timestamp);
+        }
+        packet.uint(10, SEQUENCE);
+        fields(&mut packet);
+        let mut trace = Message(Vec::new());
+        trace.bytes(1, &packet.0);
+        if self.error.is_none() {
+            if let Err(error) = self.out.write_all(&trace.0) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+// The encoding of a protobuf message.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn uint(&mut self, field: u64, value: u64) {
+        self.varint(field << 3);
+        self.varint(value);
+    }
+
+    fn bytes(&mut self, field: u64, bytes: &[u8]) {
+        self.varint(field << 3 | 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, field: u64, string: &str) {
+        self.bytes(field, string.as_bytes());
+    }
+
+    fn message(&mut self, field: u64, fields: impl FnOnce(&mut Message)) {
+        let mut message = Message(Vec::new());
+        fields(&mut message);
+        self.bytes(field, &message.0);
+    }
+}
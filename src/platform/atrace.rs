@@ -0,0 +1,119 @@
+//! Android ATrace sections
+//!
+//! With the `atrace` feature on Android, each probe is written through the
+//! NDK tracing API as an empty section, i.e. an `ATRACE_BEGIN` immediately
+//! followed by `ATRACE_END`, labelled like `foo:loop 45 1035` with the
+//! provider, the probe name, and its arguments. These show up as instant
+//! slices on the thread's track in Perfetto and systrace, when the app is
+//! traced, e.g. with `-a <package>` for `record_android_trace`.
+//!
+//! The NDK only checks whether tracing is enabled for the app as a whole,
+//! so every probe is enabled while it is, and `probe_lazy!` evaluates its
+//! arguments then too.
+//!
+//! # Links:
+//!
+//! * <https://developer.android.com/ndk/reference/group/tracing>
+//! * <https://perfetto.dev/docs/data-sources/atrace>
+
+//
+// DEVELOPER NOTES
+//
+// The tracing API is in libandroid as of API level 23, so this needs at least
+// that `minSdkVersion`, higher than Rust's default for Android targets.
+//
+// The label is formatted on the stack, and truncated to its buffer, which is
+// still more than atrace keeps for a section name.
+//
+
+use core::ffi::c_char;
+use core::fmt::{self, Write};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args: &[isize] = &[$(($arg) as isize,)*];
+        if $crate::platform::atrace::enabled() {
+            $crate::atrace_write!($provider, $name, args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::platform::atrace::enabled();
+        if enabled {
+            $crate::atrace_write!($provider, $name, &[$(($arg) as isize,)*]);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::platform::atrace::enabled()
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! atrace_write(
+    ($provider:tt, $name:tt, $args:expr) => (
+        $crate::platform::atrace::write(
+            concat!($crate::probe_provider!($provider), ":", $crate::probe_name!($name)),
+            $args,
+        )
+    )
+);
+
+#[link(name = "android")]
+extern "C" {
+    fn ATrace_isEnabled() -> bool;
+    fn ATrace_beginSection(section_name: *const c_char);
+    fn ATrace_endSection();
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    unsafe { ATrace_isEnabled() }
+}
+
+#[cold]
+pub fn write(label: &'static str, args: &[isize]) {
+    let mut section = Section {
+        buf: [0; 256],
+        len: 0,
+    };
+    let _ = section.write_str(label);
+    for arg in args {
+        let _ = write!(section, " {}", arg);
+    }
+    unsafe {
+        ATrace_beginSection(section.buf.as_ptr().cast());
+        ATrace_endSection();
+    }
+}
+
+/// A NUL-terminated section name, truncated to fit.
+struct Section {
+    buf: [u8; 256],
+    len: usize,
+}
+
+impl Write for Section {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len() - 1 - self.len;
+        let n = s.len().min(room);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
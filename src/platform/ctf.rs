@@ -0,0 +1,310 @@
+//! Common Trace Format packets
+//!
+//! With the `ctf` feature on bare-metal ARM or RISC-V, probes are serialized
+//! as CTF events into packets in a buffer that the application provides, in
+//! the style of barectf. Each full packet is handed to the application to
+//! store or send somewhere, and then a new one begins in the same buffer. The
+//! matching CTF metadata comes from [`metadata`], so that Trace Compass or
+//! `babeltrace2` can read the packets as a trace.
+//!
+//! All probes are the same event class, `probe`, with `provider` and `name`
+//! string fields and the arguments as the sequence `args`, like the `lttng`
+//! backend. Nothing is written until [`init`] gives the buffer, and until
+//! then, `probe_lazy!` doesn't evaluate its arguments either.
+//!
+//! # Example
+//!
+//! ```ignore
+//! static mut BUFFER: [u8; 4096] = [0; 4096];
+//!
+//! fn flush(packet: &[u8]) {
+//!     // e.g. write the packet to flash, or send it over a UART
+//! }
+//!
+//! probe::ctf::init(unsafe { &mut *core::ptr::addr_of_mut!(BUFFER) }, cycles, flush);
+//! ```
+//!
+//! # Links:
+//!
+//! * <https://diamon.org/ctf/v1.8.3/>
+//! * <https://barectf.org/docs/barectf/3.1/how-barectf-works/ctf-primer.html>
+
+//
+// DEVELOPER NOTES
+//
+// Every field is byte-aligned, so events are written without any padding,
+// and the packet size is just its content size, so no padding is written at
+// the end of a packet either.
+//
+// The packet header and context are only written when the packet is closed,
+// since they include its end time and size. Interrupts are masked while an
+// event is written, or a packet is closed.
+//
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args: &[isize] = &[$(($arg) as isize,)*];
+        if $crate::platform::ctf::enabled() {
+            $crate::platform::ctf::write($crate::probe_provider!($provider), $crate::probe_name!($name), args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::platform::ctf::enabled();
+        if enabled {
+            $crate::platform::ctf::write(
+                $crate::probe_provider!($provider),
+                $crate::probe_name!($name),
+                &[$(($arg) as isize,)*],
+            );
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::platform::ctf::enabled()
+    )
+);
+
+/// CTF's magic number
+const MAGIC: u32 = 0xc1fc_1fc1;
+
+/// The size of the packet header and context, in bytes.
+const PACKET_HEADER_SIZE: usize = 4 + 8 + 8 + 4 + 4 + 4;
+
+struct Stream {
+    buffer: *mut u8,
+    len: usize,
+    used: usize,
+    clock: fn() -> u64,
+    flush: fn(&[u8]),
+    begin: u64,
+    end: u64,
+    discarded: u32,
+}
+
+struct Shared(UnsafeCell<Option<Stream>>);
+
+// The stream is only used with interrupts masked.
+unsafe impl Sync for Shared {}
+
+static STREAM: Shared = Shared(UnsafeCell::new(None));
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Starts writing probes as CTF packets in `buffer`.
+///
+/// Events are timestamped by `clock`, in the units of the frequency given to
+/// [`metadata`], and each packet is passed to `flush` when the buffer is full.
+/// The buffer must have room for at least the packet header, 32 bytes, and
+/// for most events, or they're discarded, as counted in the packet context.
+///
+/// Calling this again flushes the current packet and then starts over with
+/// the new buffer.
+///
+/// # Panics
+///
+/// Panics if the buffer is smaller than the packet header.
+pub fn init(buffer: &'static mut [u8], clock: fn() -> u64, flush: fn(&[u8])) {
+    assert!(
+        buffer.len() >= PACKET_HEADER_SIZE,
+        "CTF buffer is too small"
+    );
+    critical_section(|| unsafe {
+        let stream = &mut *STREAM.0.get();
+        if let Some(stream) = stream {
+            stream.close();
+        }
+        let begin = clock();
+        *stream = Some(Stream {
+            buffer: buffer.as_mut_ptr(),
+            len: buffer.len(),
+            used: PACKET_HEADER_SIZE,
+            clock,
+            flush,
+            begin,
+            end: begin,
+            discarded: 0,
+        });
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Closes the current packet, if it has any events, and passes it to the
+/// `flush` function given to [`init`].
+pub fn flush() {
+    critical_section(|| unsafe {
+        if let Some(stream) = &mut *STREAM.0.get() {
+            if stream.used > PACKET_HEADER_SIZE {
+                stream.close();
+            }
+        }
+    });
+}
+
+/// Writes the CTF metadata for the packets, given the frequency of the clock
+/// passed to [`init`], in Hz.
+pub fn metadata<W: fmt::Write>(w: &mut W, clock_freq: u64) -> fmt::Result {
+    let byte_order = if cfg!(target_endian = "big") {
+        "be"
+    } else {
+        "le"
+    };
+    let arg_size = isize::BITS;
+    write!(
+        w,
+        r#"/* CTF 1.8 */
+
+typealias integer {{ size = 8; align = 8; signed = false; }} := uint8_t;
+typealias integer {{ size = 32; align = 8; signed = false; }} := uint32_t;
+typealias integer {{ size = 64; align = 8; signed = false; }} := uint64_t;
+typealias integer {{ size = {arg_size}; align = 8; signed = true; }} := arg_t;
+
+trace {{
+    major = 1;
+    minor = 8;
+    byte_order = {byte_order};
+    packet.header := struct {{
+        uint32_t magic;
+    }};
+}};
+
+clock {{
+    name = probe_clock;
+    freq = {clock_freq};
+}};
+
+typealias integer {{
+    size = 64; align = 8; signed = false;
+    map = clock.probe_clock.value;
+}} := probe_clock_t;
+
+stream {{
+    packet.context := struct {{
+        probe_clock_t timestamp_begin;
+        probe_clock_t timestamp_end;
+        uint32_t packet_size;
+        uint32_t content_size;
+        uint32_t events_discarded;
+    }};
+    event.header := struct {{
+        probe_clock_t timestamp;
+    }};
+}};
+
+event {{
+    name = "probe";
+    fields := struct {{
+        string provider;
+        string name;
+        uint8_t _args_length;
+        arg_t args[_args_length];
+    }};
+}};
+"#
+    )
+}
+
+#[doc(hidden)]
+#[inline]
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+#[doc(hidden)]
+#[inline(never)]
+pub fn write(provider: &'static str, name: &'static str, args: &[isize]) {
+    let args = &args[..args.len().min(u8::MAX as usize)];
+    let size =
+        8 + provider.len() + 1 + name.len() + 1 + 1 + args.len() * (isize::BITS as usize / 8);
+    critical_section(|| unsafe {
+        let stream = match &mut *STREAM.0.get() {
+            Some(stream) => stream,
+            None => return,
+        };
+        if stream.used + size > stream.len {
+            if stream.used > PACKET_HEADER_SIZE {
+                stream.close();
+            }
+            if stream.used + size > stream.len {
+                stream.discarded = stream.discarded.wrapping_add(1);
+                return;
+            }
+        }
+        let timestamp = (stream.clock)();
+        stream.end = timestamp;
+        stream.put(&timestamp.to_ne_bytes());
+        stream.put(provider.as_bytes());
+        stream.put(&[0]);
+        stream.put(name.as_bytes());
+        stream.put(&[0]);
+        stream.put(&[args.len() as u8]);
+        for arg in args {
+            stream.put(&arg.to_ne_bytes());
+        }
+    });
+}
+
+impl Stream {
+    unsafe fn put(&mut self, bytes: &[u8]) {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), self.buffer.add(self.used), bytes.len());
+        self.used += bytes.len();
+    }
+
+    /// Writes the packet header and context, flushes it, and starts another.
+    unsafe fn close(&mut self) {
+        let content_size = (self.used as u32) * 8;
+        let used = self.used;
+        self.used = 0;
+        self.put(&MAGIC.to_ne_bytes());
+        self.put(&self.begin.to_ne_bytes());
+        self.put(&self.end.to_ne_bytes());
+        self.put(&content_size.to_ne_bytes());
+        self.put(&content_size.to_ne_bytes());
+        self.put(&self.discarded.to_ne_bytes());
+        (self.flush)(core::slice::from_raw_parts(self.buffer, used));
+
+        self.used = PACKET_HEADER_SIZE;
+        self.begin = (self.clock)();
+        self.end = self.begin;
+    }
+}
+
+#[cfg(target_arch = "arm")]
+fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let primask: u32;
+    unsafe { core::arch::asm!("mrs {}, PRIMASK", "cpsid i", out(reg) primask, options(nostack)) };
+    let result = f();
+    // Only unmask interrupts if they weren't already masked.
+    if primask & 1 == 0 {
+        unsafe { core::arch::asm!("cpsie i", options(nostack)) };
+    }
+    result
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let mstatus: usize;
+    // Clear MIE, and keep its old value.
+    unsafe { core::arch::asm!("csrrci {}, mstatus, 8", out(reg) mstatus, options(nostack)) };
+    let result = f();
+    if mstatus & 8 != 0 {
+        unsafe { core::arch::asm!("csrsi mstatus, 8", options(nostack)) };
+    }
+    result
+}
@@ -1,7 +1,7 @@
 #[doc(hidden)]
 #[macro_export]
 macro_rules! platform_probe(
-    ($provider:ident, $name:ident, $($arg:expr,)*) => ({
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
         // Non-lazy probes always evaluate the arguments.
         let _ = ($($arg,)*);
     })
@@ -10,11 +10,22 @@ macro_rules! platform_probe(
 #[doc(hidden)]
 #[macro_export]
 macro_rules! platform_probe_lazy(
-    ($provider:ident, $name:ident, $($arg:expr,)*) => ({
-        // Expand the arguments so they don't cause unused warnings.
-        if false {
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        // Only evaluate the arguments while the program enables the provider.
+        let enabled = $crate::platform_probe_enabled!($provider, $name, [$($opt)*]);
+        if enabled {
             let _ = ($($arg,)*);
         }
-        false
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        const PROVIDER: u16 = $crate::control::provider($crate::probe_provider!($provider));
+        const PROBE: u16 = $crate::control::probe($crate::probe_provider!($provider), $crate::probe_name!($name));
+        $crate::control::enabled(PROVIDER, PROBE)
     })
 );
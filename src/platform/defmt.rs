@@ -0,0 +1,102 @@
+//! defmt log frames
+//!
+//! With the `defmt` feature on bare-metal targets, each probe is logged with
+//! defmt, at the level given by its `level` option, or `trace` by default, so
+//! it reaches the host through whichever transport the application has set up
+//! for defmt, and is decoded with the rest of its logs, like:
+//!
+//! ```notrust
+//! TRACE foo:loop [45, 1035]
+//! ```
+//!
+//! The application has to depend on `defmt` itself, as it does to log. Probes
+//! are filtered by `DEFMT_LOG` at compile time like any other log in the
+//! module where they are, and `probe_lazy!` only evaluates its arguments when
+//! the probe's level is compiled in, and the program hasn't turned probes off
+//! with `control::kill`.
+//!
+//! # Links:
+//!
+//! * <https://defmt.ferrous-systems.com/>
+//! * <https://defmt.ferrous-systems.com/filtering>
+
+//
+// DEVELOPER NOTES
+//
+// The macros expand to defmt's own logging macros in the caller's crate, so
+// this crate doesn't need to depend on defmt, and probes use the caller's
+// version and filters. Those macros only evaluate their arguments inside the
+// level check, which `probe_lazy!` uses to tell whether the probe is enabled.
+//
+// defmt only interns string literals, so the format string of the frame is
+// interned, but the provider and name are sent as string arguments, since a
+// macro can't make a literal out of them.
+//
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args = $crate::platform::defmt::args([$(($arg) as isize,)*]);
+        if $crate::control::live() {
+            $crate::defmt_log!(
+                [$($opt)*],
+                "{=str}:{=str} {}",
+                $crate::probe_provider!($provider),
+                $crate::probe_name!($name),
+                args,
+            );
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let mut enabled = false;
+        if $crate::control::live() {
+            $crate::defmt_log!(
+                [$($opt)*],
+                "{=str}:{=str} {}",
+                $crate::probe_provider!($provider),
+                $crate::probe_name!($name),
+                {
+                    enabled = true;
+                    $crate::platform::defmt::args([$(($arg) as isize,)*])
+                },
+            );
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::control::live()
+    )
+);
+
+// The defmt logging macro for a probe's `level` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! defmt_log(
+    ([], $($log:tt)*) => (defmt::trace!($($log)*));
+    ([level = critical $(, $($rest:tt)*)?], $($log:tt)*) => (defmt::error!($($log)*));
+    ([level = error $(, $($rest:tt)*)?], $($log:tt)*) => (defmt::error!($($log)*));
+    ([level = warn $(, $($rest:tt)*)?], $($log:tt)*) => (defmt::warn!($($log)*));
+    ([level = info $(, $($rest:tt)*)?], $($log:tt)*) => (defmt::info!($($log)*));
+    ([level = debug $(, $($rest:tt)*)?], $($log:tt)*) => (defmt::debug!($($log)*));
+    ([level = $level:ident $(, $($rest:tt)*)?], $($log:tt)*) => (defmt::trace!($($log)*));
+    ([$key:ident = $value:expr $(, $($rest:tt)*)?], $($log:tt)*) => (
+        $crate::defmt_log!([$($($rest)*)?], $($log)*)
+    );
+);
+
+/// The arguments of a probe, typed even when there are none.
+#[inline(always)]
+pub fn args<const N: usize>(args: [isize; N]) -> [isize; N] {
+    args
+}
@@ -0,0 +1,360 @@
+//! DTrace Object Format (DOF) for USDT providers
+//!
+//! On systems where DTrace doesn't get help from the linker, USDT probes are
+//! described to the kernel by a DOF blob that the process hands over through
+//! the DTrace helper device, normally from an initializer that `dtrace -G`
+//! links into the program. We do the same thing at runtime: each probe site
+//! records itself in a dedicated linker section, and the platform module
+//! turns that table into DOF and loads it during startup.
+//!
+//! # Links:
+//!
+//! * <https://github.com/illumos/illumos-gate/blob/master/usr/src/uts/common/sys/dtrace.h>
+//!   (the `dof_*` structures)
+//! * <https://github.com/illumos/illumos-gate/blob/master/usr/src/lib/libdtrace/common/dt_dof.c>
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::ffi::{c_char, c_int, c_void, CStr};
+
+/// `Site::kind` for a probe site, which is a run of `NOP`s.
+pub const KIND_PROBE: u32 = 1;
+
+/// `Site::kind` for an is-enabled site, which zeroes the return register.
+pub const KIND_IS_ENABLED: u32 = 2;
+
+/// One entry of the `probe_usdt` section, as written by the probe macros.
+#[repr(C)]
+pub struct Site {
+    addr: usize,
+    provider: usize,
+    name: usize,
+    kind: u32,
+    argc: u32,
+}
+
+// Make sure the section exists, even if the program has no probes at all.
+#[used]
+#[link_section = "probe_usdt"]
+static SENTINEL: Site = Site {
+    addr: 0,
+    provider: 0,
+    name: 0,
+    kind: 0,
+    argc: 0,
+};
+
+extern "C" {
+    #[link_name = "__start_probe_usdt"]
+    static START: Site;
+    #[link_name = "__stop_probe_usdt"]
+    static STOP: Site;
+}
+
+fn sites() -> &'static [Site] {
+    unsafe {
+        let start = &START as *const Site;
+        let stop = &STOP as *const Site;
+        let len = (stop as usize - start as usize) / core::mem::size_of::<Site>();
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+#[repr(C)]
+struct DlInfo {
+    fname: *const c_char,
+    fbase: *mut c_void,
+    sname: *const c_char,
+    saddr: *mut c_void,
+}
+
+extern "C" {
+    fn dladdr(addr: *const c_void, info: *mut DlInfo) -> c_int;
+}
+
+fn dlinfo(addr: usize) -> Option<DlInfo> {
+    let mut info = DlInfo {
+        fname: core::ptr::null(),
+        fbase: core::ptr::null_mut(),
+        sname: core::ptr::null(),
+        saddr: core::ptr::null_mut(),
+    };
+    match unsafe { dladdr(addr as *const c_void, &mut info) } {
+        0 => None,
+        _ => Some(info),
+    }
+}
+
+unsafe fn cstr<'a>(ptr: *const c_char) -> &'a [u8] {
+    if ptr.is_null() {
+        b""
+    } else {
+        CStr::from_ptr(ptr).to_bytes()
+    }
+}
+
+/// Returns the file name of the object containing `addr`, for the helper's
+/// module name.
+pub fn module_name(addr: usize) -> &'static [u8] {
+    let path = match dlinfo(addr) {
+        Some(info) => unsafe { cstr(info.fname) },
+        None => b"",
+    };
+    match path.iter().rposition(|&b| b == b'/') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    }
+}
+
+// Kernel limits on string lengths, including the NUL.
+const DTRACE_PROVNAMELEN: usize = 64;
+const DTRACE_FUNCNAMELEN: usize = 128;
+const DTRACE_NAMELEN: usize = 64;
+
+// The provider name also gets the process ID appended.
+const PROVIDER_MAX: usize = DTRACE_PROVNAMELEN - 11;
+
+struct Probe {
+    provider: &'static [u8],
+    name: &'static [u8],
+    func: &'static [u8],
+    base: usize,
+    argc: u32,
+    offs: Vec<u32>,
+    enoffs: Vec<u32>,
+}
+
+fn collect() -> Vec<Probe> {
+    let mut probes: Vec<Probe> = Vec::new();
+    for site in sites() {
+        if site.kind != KIND_PROBE && site.kind != KIND_IS_ENABLED {
+            continue;
+        }
+        let provider = unsafe { cstr(site.provider as *const c_char) };
+        let name = unsafe { cstr(site.name as *const c_char) };
+        if provider.len() > PROVIDER_MAX || name.len() >= DTRACE_NAMELEN {
+            continue;
+        }
+
+        // Probes are named by the function that contains them, and their
+        // offsets are relative to it.
+        let (mut func, mut base) = match dlinfo(site.addr) {
+            Some(info) if !info.saddr.is_null() => {
+                (unsafe { cstr(info.sname) }, info.saddr as usize)
+            }
+            _ => (&b""[..], site.addr),
+        };
+        if func.len() >= DTRACE_FUNCNAMELEN || site.addr - base > u32::MAX as usize {
+            func = b"";
+            base = site.addr;
+        }
+        let offset = (site.addr - base) as u32;
+
+        let i = match probes
+            .iter()
+            .position(|p| p.provider == provider && p.name == name && p.func == func)
+        {
+            Some(i) => i,
+            None => {
+                probes.push(Probe {
+                    provider,
+                    name,
+                    func,
+                    base,
+                    argc: 0,
+                    offs: Vec::new(),
+                    enoffs: Vec::new(),
+                });
+                probes.len() - 1
+            }
+        };
+        let probe = &mut probes[i];
+        if probe.base != base {
+            continue;
+        }
+        probe.argc = probe.argc.max(site.argc);
+        if site.kind == KIND_PROBE {
+            probe.offs.push(offset);
+        } else {
+            probe.enoffs.push(offset);
+        }
+    }
+    probes
+}
+
+const DOF_SECT_STRTAB: u32 = 8;
+const DOF_SECT_PROVIDER: u32 = 15;
+const DOF_SECT_PROBES: u32 = 16;
+const DOF_SECT_PRARGS: u32 = 17;
+const DOF_SECT_PROFFS: u32 = 18;
+const DOF_SECT_PRENOFFS: u32 = 26;
+
+const DOF_SECF_LOAD: u32 = 1;
+
+const DOF_HDR_SIZE: usize = 64;
+const DOF_SEC_SIZE: usize = 32;
+const DOF_PROBE_SIZE: u32 = 48;
+
+// PRIVATE/PRIVATE/UNKNOWN, the default stability for a provider.
+const DOF_ATTR: u32 = 1 << 24 | 1 << 16;
+
+// Every argument is passed as `isize`.
+const ARG_TYPE: &[u8] = b"long\0";
+
+struct Section {
+    kind: u32,
+    align: u32,
+    entsize: u32,
+    data: Vec<u8>,
+}
+
+impl Section {
+    fn new(kind: u32, align: u32, entsize: u32) -> Self {
+        Section {
+            kind,
+            align,
+            entsize,
+            data: Vec::new(),
+        }
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(bytes);
+        offset
+    }
+
+    fn put_str(&mut self, s: &[u8]) -> u32 {
+        let offset = self.put(s);
+        self.put(b"\0");
+        offset
+    }
+}
+
+/// Builds the DOF for all probe sites in the program, or `None` if there
+/// are no probes.
+pub fn build() -> Option<Vec<u8>> {
+    let probes = collect();
+    if probes.is_empty() {
+        return None;
+    }
+
+    let mut sections = Vec::new();
+    let mut providers: Vec<&[u8]> = Vec::new();
+    for probe in &probes {
+        if !providers.contains(&probe.provider) {
+            providers.push(probe.provider);
+        }
+    }
+    for provider in providers {
+        let index = sections.len() as u32;
+        let mut strtab = Section::new(DOF_SECT_STRTAB, 1, 0);
+        let mut dprobes = Section::new(DOF_SECT_PROBES, 8, DOF_PROBE_SIZE);
+        let mut prargs = Section::new(DOF_SECT_PRARGS, 1, 1);
+        let mut proffs = Section::new(DOF_SECT_PROFFS, 4, 4);
+        let mut prenoffs = Section::new(DOF_SECT_PRENOFFS, 4, 4);
+        let mut dprovider = Section::new(DOF_SECT_PROVIDER, 4, 0);
+
+        strtab.put(b"\0");
+        let provider_name = strtab.put_str(provider);
+        for probe in probes.iter().filter(|p| p.provider == provider) {
+            let name = strtab.put_str(probe.name);
+            let func = strtab.put_str(probe.func);
+            let argv = strtab.data.len() as u32;
+            for _ in 0..probe.argc {
+                strtab.put(ARG_TYPE);
+            }
+            let argidx = prargs.data.len() as u32;
+            for i in 0..probe.argc {
+                prargs.put(&[i as u8]);
+            }
+            let offidx = proffs.data.len() as u32 / 4;
+            for off in &probe.offs {
+                proffs.put(&off.to_ne_bytes());
+            }
+            let enoffidx = prenoffs.data.len() as u32 / 4;
+            for off in &probe.enoffs {
+                prenoffs.put(&off.to_ne_bytes());
+            }
+
+            // dof_probe_t
+            dprobes.put(&(probe.base as u64).to_ne_bytes());
+            dprobes.put(&func.to_ne_bytes());
+            dprobes.put(&name.to_ne_bytes());
+            dprobes.put(&argv.to_ne_bytes());
+            dprobes.put(&argv.to_ne_bytes());
+            dprobes.put(&argidx.to_ne_bytes());
+            dprobes.put(&offidx.to_ne_bytes());
+            dprobes.put(&[probe.argc as u8, probe.argc as u8]);
+            dprobes.put(&(probe.offs.len() as u16).to_ne_bytes());
+            dprobes.put(&enoffidx.to_ne_bytes());
+            dprobes.put(&(probe.enoffs.len() as u16).to_ne_bytes());
+            dprobes.put(&[0; 6]);
+        }
+
+        // dof_provider_t
+        for link in 0..4 {
+            dprovider.put(&(index + link).to_ne_bytes());
+        }
+        dprovider.put(&provider_name.to_ne_bytes());
+        for _ in 0..5 {
+            dprovider.put(&DOF_ATTR.to_ne_bytes());
+        }
+        dprovider.put(&(index + 4).to_ne_bytes());
+
+        sections.extend([strtab, dprobes, prargs, proffs, prenoffs, dprovider]);
+    }
+
+    // Lay out the header, then the section headers, then all of the data.
+    let mut offset = DOF_HDR_SIZE + sections.len() * DOF_SEC_SIZE;
+    let mut offsets = Vec::with_capacity(sections.len());
+    for section in &sections {
+        offset = align(offset, section.align as usize);
+        offsets.push(offset);
+        offset += section.data.len();
+    }
+    let size = align(offset, 8);
+
+    let mut dof = Vec::with_capacity(size);
+    let model = match core::mem::size_of::<usize>() {
+        4 => 1, // DOF_MODEL_ILP32
+        _ => 2, // DOF_MODEL_LP64
+    };
+    let encoding = if cfg!(target_endian = "little") {
+        1 // DOF_ENCODE_LSB
+    } else {
+        2 // DOF_ENCODE_MSB
+    };
+    // DOF_MAG, model, encoding, DOF_VERSION_2, DIF_VERSION_2,
+    // DIF_DIR_NREGS, DIF_DTR_NREGS, and padding.
+    dof.extend_from_slice(&[0x7f, b'D', b'O', b'F', model, encoding, 2, 2, 8, 8]);
+    dof.extend_from_slice(&[0; 6]);
+    dof.extend_from_slice(&0u32.to_ne_bytes());
+    dof.extend_from_slice(&(DOF_HDR_SIZE as u32).to_ne_bytes());
+    dof.extend_from_slice(&(DOF_SEC_SIZE as u32).to_ne_bytes());
+    dof.extend_from_slice(&(sections.len() as u32).to_ne_bytes());
+    dof.extend_from_slice(&(DOF_HDR_SIZE as u64).to_ne_bytes());
+    dof.extend_from_slice(&(size as u64).to_ne_bytes());
+    dof.extend_from_slice(&(size as u64).to_ne_bytes());
+    dof.extend_from_slice(&0u64.to_ne_bytes());
+
+    for (section, &offset) in sections.iter().zip(&offsets) {
+        dof.extend_from_slice(&section.kind.to_ne_bytes());
+        dof.extend_from_slice(&section.align.to_ne_bytes());
+        dof.extend_from_slice(&DOF_SECF_LOAD.to_ne_bytes());
+        dof.extend_from_slice(&section.entsize.to_ne_bytes());
+        dof.extend_from_slice(&(offset as u64).to_ne_bytes());
+        dof.extend_from_slice(&(section.data.len() as u64).to_ne_bytes());
+    }
+    for (section, &offset) in sections.iter().zip(&offsets) {
+        dof.resize(offset, 0);
+        dof.extend_from_slice(&section.data);
+    }
+    dof.resize(size, 0);
+    Some(dof)
+}
+
+fn align(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
@@ -0,0 +1,157 @@
+//! ESP-IDF logging
+//!
+//! With the `esp_log` feature on ESP-IDF targets, i.e. the ESP32 family on
+//! either Xtensa or RISC-V, each probe is written as a log message through
+//! `esp_log_write`, with the provider as its tag, like:
+//!
+//! ```notrust
+//! V (1234) foo: loop 45 1035
+//! ```
+//!
+//! These go wherever the application sends its logs, the UART console by
+//! default, or JTAG with the application tracing component. The `level`
+//! option of a probe is its log level, with `critical` as errors and `trace`
+//! as verbose, which is also the default, so the log level of the tag has to
+//! be raised to see most probes, e.g. `esp_log_level_set("foo", ESP_LOG_VERBOSE)`.
+//! Probes below that level are skipped, and `probe_lazy!` only evaluates its
+//! arguments when the level is enabled.
+//!
+//! # Links:
+//!
+//! * <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/log.html>
+//! * <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-guides/app_trace.html>
+
+//
+// DEVELOPER NOTES
+//
+// `ESP_LOGx` are C macros around `esp_log_write`, which check the level
+// against `CONFIG_LOG_MAXIMUM_LEVEL` at compile time, and then let the
+// function check the tag's level at runtime. We only have the latter, with
+// `esp_log_level_get`, which needs ESP-IDF 5.0.
+//
+// The message is formatted on the stack, and truncated to its buffer.
+//
+
+use core::ffi::{c_char, c_uint, c_ulong};
+use core::fmt::{self, Write};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args: &[isize] = &[$(($arg) as isize,)*];
+        const LEVEL: u32 = $crate::esp_log_level!($($opt)*);
+        if $crate::platform::esp_log::enabled(LEVEL, $crate::esp_log_tag!($provider)) {
+            $crate::platform::esp_log::write(
+                LEVEL,
+                $crate::esp_log_tag!($provider),
+                $crate::probe_name!($name),
+                args,
+            );
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const LEVEL: u32 = $crate::esp_log_level!($($opt)*);
+        let enabled = $crate::platform::esp_log::enabled(LEVEL, $crate::esp_log_tag!($provider));
+        if enabled {
+            $crate::platform::esp_log::write(
+                LEVEL,
+                $crate::esp_log_tag!($provider),
+                $crate::probe_name!($name),
+                &[$(($arg) as isize,)*],
+            );
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        const LEVEL: u32 = $crate::esp_log_level!($($opt)*);
+        $crate::platform::esp_log::enabled(LEVEL, $crate::esp_log_tag!($provider))
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! esp_log_tag(
+    ($provider:tt) => (concat!($crate::probe_provider!($provider), "\0"))
+);
+
+// The esp_log_level_t for a probe's `level` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! esp_log_level(
+    () => (5);
+    (level = critical $(, $($rest:tt)*)?) => (1);
+    (level = error $(, $($rest:tt)*)?) => (1);
+    (level = warn $(, $($rest:tt)*)?) => (2);
+    (level = info $(, $($rest:tt)*)?) => (3);
+    (level = debug $(, $($rest:tt)*)?) => (4);
+    (level = $level:ident $(, $($rest:tt)*)?) => (5);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::esp_log_level!($($($rest)*)?));
+);
+
+// The letters that ESP-IDF prints for each level, from ESP_LOG_NONE.
+const LETTERS: [u8; 6] = *b"NEWIDV";
+
+extern "C" {
+    fn esp_log_level_get(tag: *const c_char) -> c_uint;
+    fn esp_log_timestamp() -> u32;
+    fn esp_log_write(level: c_uint, tag: *const c_char, format: *const c_char, ...);
+}
+
+/// Whether the tag's log level includes this probe's level.
+#[inline]
+pub fn enabled(level: u32, tag: &'static str) -> bool {
+    level <= unsafe { esp_log_level_get(tag.as_ptr().cast()) }
+}
+
+#[cold]
+pub fn write(level: u32, tag: &'static str, name: &'static str, args: &[isize]) {
+    let mut message = Message {
+        buf: [0; 128],
+        len: 0,
+    };
+    let _ = message.write_str(name);
+    for arg in args {
+        let _ = write!(message, " {}", arg);
+    }
+    unsafe {
+        esp_log_write(
+            level,
+            tag.as_ptr().cast(),
+            b"%c (%lu) %s: %s\n\0".as_ptr().cast(),
+            LETTERS[level as usize] as c_uint,
+            esp_log_timestamp() as c_ulong,
+            tag.as_ptr(),
+            message.buf.as_ptr(),
+        );
+    }
+}
+
+/// A NUL-terminated log message, truncated to fit.
+struct Message {
+    buf: [u8; 128],
+    len: usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len() - 1 - self.len;
+        let n = s.len().min(room);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
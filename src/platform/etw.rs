@@ -0,0 +1,548 @@
+//! ETW TraceLogging events on Windows
+//!
+//! Each probe is written as a TraceLogging event, which carries its own
+//! metadata, so no manifest needs to be registered for tools to decode it.
+//! The provider is named like the probe provider, with a GUID derived by
+//! hashing that name the same way as .NET `EventSource` and `tracelogging`
+//! do, so tools that understand that convention can enable it by name, e.g.
+//! `*foo` as a provider in a WPR profile. The event is named for the probe,
+//! with `isize` arguments as 64-bit fields `arg0`, `arg1`, etc.
+//!
+//! Providers are registered the first time one of their probes is reached,
+//! and never unregistered, so a DLL that contains probes must not be unloaded.
+//!
+//! # Links:
+//!
+//! * <https://learn.microsoft.com/en-us/windows/win32/tracelogging/trace-logging-about>
+//! * <https://learn.microsoft.com/en-us/windows/win32/api/evntprov/nf-evntprov-eventwritetransfer>
+//! * <https://github.com/microsoft/tracelogging/blob/main/etw/traceloggingdynamic/include/TraceLoggingDynamic.h>
+//!   (the metadata encoding)
+
+//
+// DEVELOPER NOTES
+//
+// The event and provider metadata blobs are computed at compile time for
+// each probe site, as is the provider GUID. The `level` and `keyword` probe
+// options go in the event descriptor, with levels mapped to the standard
+// `WINEVENT_LEVEL_*` values, and `debug` and `trace` both being verbose.
+// While a provider isn't enabled, a probe costs a load and compare of the
+// level from the enable callback, plus a load of the registered provider
+// pointer once it has been resolved.
+//
+// We don't have per-provider statics across probe sites, so each site embeds
+// a complete `Provider`, and the first site that's reached for a name
+// registers its own and links it into a global list. Other sites of that
+// provider find it in the list and point to it from then on.
+//
+
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, Ordering};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args = [$(($arg) as isize as i64,)*];
+        $crate::etw_site!(SITE, $provider, $name, [$($opt)*], $($arg,)*);
+        if SITE.enabled() {
+            SITE.write(&args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::etw_site!(SITE, $provider, $name, [$($opt)*], $($arg,)*);
+        let enabled = SITE.enabled();
+        if enabled {
+            SITE.write(&[$(($arg) as isize as i64,)*]);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        $crate::etw_site!(SITE, $provider, $name, [$($opt)*],);
+        SITE.enabled()
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! etw_site(
+    ($site:ident, $provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        const ARGC: usize = 0 $(+ $crate::etw_argc!($arg))*;
+        const TRAITS_LEN: usize =
+            $crate::platform::etw::traits_len($crate::probe_provider!($provider));
+        static TRAITS: [u8; TRAITS_LEN] =
+            $crate::platform::etw::traits($crate::probe_provider!($provider));
+        const EVENT_LEN: usize =
+            $crate::platform::etw::event_len($crate::probe_name!($name), ARGC);
+        static EVENT: [u8; EVENT_LEN] =
+            $crate::platform::etw::event($crate::probe_name!($name), ARGC);
+        static $site: $crate::platform::etw::Site = $crate::platform::etw::Site::new(
+            $crate::probe_provider!($provider),
+            $crate::platform::etw::guid($crate::probe_provider!($provider)),
+            &TRAITS,
+            &EVENT,
+            $crate::etw_level!($($opt)*),
+            $crate::etw_keyword!($($opt)*),
+        );
+    )
+);
+
+// The ETW level for a probe's `level` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! etw_level(
+    () => (5);
+    (level = critical $(, $($rest:tt)*)?) => (1);
+    (level = error $(, $($rest:tt)*)?) => (2);
+    (level = warn $(, $($rest:tt)*)?) => (3);
+    (level = info $(, $($rest:tt)*)?) => (4);
+    (level = $level:ident $(, $($rest:tt)*)?) => (5);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::etw_level!($($($rest)*)?));
+);
+
+// The ETW keyword bits for a probe's `keyword` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! etw_keyword(
+    () => (0);
+    (keyword = $keyword:expr $(, $($rest:tt)*)?) => ($keyword);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::etw_keyword!($($($rest)*)?));
+);
+
+// Counts one argument, without evaluating it.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! etw_argc(
+    ($arg:expr) => (1);
+);
+
+type Guid = [u8; 16];
+
+// EVENT_DESCRIPTOR
+#[repr(C)]
+struct EventDescriptor {
+    id: u16,
+    version: u8,
+    channel: u8,
+    level: u8,
+    opcode: u8,
+    task: u16,
+    keyword: u64,
+}
+
+// EVENT_DATA_DESCRIPTOR
+#[repr(C)]
+struct EventDataDescriptor {
+    ptr: u64,
+    size: u32,
+    kind: u32,
+}
+
+type EnableCallback = unsafe extern "system" fn(
+    source_id: *const Guid,
+    is_enabled: u32,
+    level: u8,
+    match_any_keyword: u64,
+    match_all_keyword: u64,
+    filter_data: *const c_void,
+    context: *mut c_void,
+);
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn EventRegister(
+        provider_id: *const Guid,
+        enable_callback: Option<EnableCallback>,
+        context: *const c_void,
+        handle: *mut u64,
+    ) -> u32;
+
+    fn EventSetInformation(
+        handle: u64,
+        information_class: u32,
+        information: *const c_void,
+        length: u32,
+    ) -> u32;
+
+    fn EventWriteTransfer(
+        handle: u64,
+        descriptor: *const EventDescriptor,
+        activity_id: *const Guid,
+        related_activity_id: *const Guid,
+        count: u32,
+        data: *const EventDataDescriptor,
+    ) -> u32;
+}
+
+const EVENT_CONTROL_CODE_ENABLE_PROVIDER: u32 = 1;
+const EVENT_CONTROL_CODE_DISABLE_PROVIDER: u32 = 0;
+const EVENT_PROVIDER_SET_TRAITS: u32 = 2;
+const EVENT_DATA_DESCRIPTOR_TYPE_EVENT_METADATA: u32 = 1;
+const EVENT_DATA_DESCRIPTOR_TYPE_PROVIDER_METADATA: u32 = 2;
+
+// The channel that marks an event as TraceLogging.
+const TRACELOGGING_CHANNEL: u8 = 11;
+
+// TlgInINT64
+const TLG_IN_INT64: u8 = 9;
+
+#[doc(hidden)]
+pub struct Provider {
+    name: &'static str,
+    guid: Guid,
+    traits: &'static [u8],
+    handle: AtomicU64,
+    // The enabled level plus one, or zero while disabled.
+    level: AtomicU8,
+    any: AtomicU64,
+    all: AtomicU64,
+    next: AtomicPtr<Provider>,
+}
+
+static PROVIDERS: AtomicPtr<Provider> = AtomicPtr::new(core::ptr::null_mut());
+static LOCK: AtomicBool = AtomicBool::new(false);
+
+impl Provider {
+    fn enabled(&self, level: u8, keyword: u64) -> bool {
+        level < self.level.load(Ordering::Relaxed)
+            && (keyword == 0
+                || (keyword & self.any.load(Ordering::Relaxed) != 0
+                    && keyword & self.all.load(Ordering::Relaxed)
+                        == self.all.load(Ordering::Relaxed)))
+    }
+
+    unsafe fn register(&'static self) {
+        let mut handle = 0;
+        let context = self as *const Provider as *const c_void;
+        if EventRegister(&self.guid, Some(callback), context, &mut handle) == 0 {
+            EventSetInformation(
+                handle,
+                EVENT_PROVIDER_SET_TRAITS,
+                self.traits.as_ptr().cast(),
+                self.traits.len() as u32,
+            );
+            self.handle.store(handle, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe extern "system" fn callback(
+    _source_id: *const Guid,
+    is_enabled: u32,
+    level: u8,
+    match_any_keyword: u64,
+    match_all_keyword: u64,
+    _filter_data: *const c_void,
+    context: *mut c_void,
+) {
+    let provider = &*(context as *const Provider);
+    match is_enabled {
+        EVENT_CONTROL_CODE_ENABLE_PROVIDER => {
+            provider.any.store(match_any_keyword, Ordering::Relaxed);
+            provider.all.store(match_all_keyword, Ordering::Relaxed);
+            // Level 0 means that all levels are enabled.
+            let level = if level == 0 { u8::MAX } else { level };
+            provider
+                .level
+                .store(level.saturating_add(1), Ordering::Release);
+        }
+        EVENT_CONTROL_CODE_DISABLE_PROVIDER => {
+            provider.level.store(0, Ordering::Release);
+        }
+        _ => {}
+    }
+}
+
+#[doc(hidden)]
+pub struct Site {
+    provider: Provider,
+    resolved: AtomicPtr<Provider>,
+    event: &'static [u8],
+    descriptor: EventDescriptor,
+}
+
+impl Site {
+    pub const fn new(
+        provider: &'static str,
+        guid: Guid,
+        traits: &'static [u8],
+        event: &'static [u8],
+        level: u8,
+        keyword: u64,
+    ) -> Site {
+        Site {
+            provider: Provider {
+                name: provider,
+                guid,
+                traits,
+                handle: AtomicU64::new(0),
+                level: AtomicU8::new(0),
+                any: AtomicU64::new(0),
+                all: AtomicU64::new(0),
+                next: AtomicPtr::new(core::ptr::null_mut()),
+            },
+            resolved: AtomicPtr::new(core::ptr::null_mut()),
+            event,
+            descriptor: EventDescriptor {
+                id: 0,
+                version: 0,
+                channel: TRACELOGGING_CHANNEL,
+                level,
+                opcode: 0,
+                task: 0,
+                keyword,
+            },
+        }
+    }
+
+    #[inline]
+    fn provider(&'static self) -> &'static Provider {
+        let provider = self.resolved.load(Ordering::Acquire);
+        if provider.is_null() {
+            self.resolve()
+        } else {
+            unsafe { &*provider }
+        }
+    }
+
+    #[cold]
+    fn resolve(&'static self) -> &'static Provider {
+        while LOCK
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+
+        let mut provider = PROVIDERS.load(Ordering::Relaxed);
+        while !provider.is_null() && unsafe { (*provider).name } != self.provider.name {
+            provider = unsafe { (*provider).next.load(Ordering::Relaxed) };
+        }
+        if provider.is_null() {
+            unsafe { self.provider.register() };
+            let this = &self.provider as *const Provider as *mut Provider;
+            let next = PROVIDERS.load(Ordering::Relaxed);
+            self.provider.next.store(next, Ordering::Relaxed);
+            PROVIDERS.store(this, Ordering::Relaxed);
+            provider = this;
+        }
+        self.resolved.store(provider, Ordering::Release);
+
+        LOCK.store(false, Ordering::Release);
+        unsafe { &*provider }
+    }
+
+    #[inline]
+    pub fn enabled(&'static self) -> bool {
+        let descriptor = &self.descriptor;
+        self.provider()
+            .enabled(descriptor.level, descriptor.keyword)
+    }
+
+    pub fn write(&'static self, args: &[i64]) {
+        let provider = self.provider();
+        let data = [
+            EventDataDescriptor {
+                ptr: provider.traits.as_ptr() as u64,
+                size: provider.traits.len() as u32,
+                kind: EVENT_DATA_DESCRIPTOR_TYPE_PROVIDER_METADATA,
+            },
+            EventDataDescriptor {
+                ptr: self.event.as_ptr() as u64,
+                size: self.event.len() as u32,
+                kind: EVENT_DATA_DESCRIPTOR_TYPE_EVENT_METADATA,
+            },
+            EventDataDescriptor {
+                ptr: args.as_ptr() as u64,
+                size: core::mem::size_of_val(args) as u32,
+                kind: 0,
+            },
+        ];
+        let count = if args.is_empty() { 2 } else { 3 };
+        unsafe {
+            EventWriteTransfer(
+                provider.handle.load(Ordering::Relaxed),
+                &self.descriptor,
+                core::ptr::null(),
+                core::ptr::null(),
+                count,
+                data.as_ptr(),
+            );
+        }
+    }
+}
+
+/// The length of the provider traits for `name`.
+pub const fn traits_len(name: &str) -> usize {
+    2 + name.len() + 1
+}
+
+/// The provider traits: the total size, and the provider name.
+pub const fn traits<const N: usize>(name: &str) -> [u8; N] {
+    let mut traits = [0; N];
+    let size = (N as u16).to_le_bytes();
+    traits[0] = size[0];
+    traits[1] = size[1];
+    let name = name.as_bytes();
+    let mut i = 0;
+    while i < name.len() {
+        traits[2 + i] = name[i];
+        i += 1;
+    }
+    traits
+}
+
+/// The length of the event metadata for `name` with `argc` arguments.
+pub const fn event_len(name: &str, argc: usize) -> usize {
+    let mut len = 2 + 1 + name.len() + 1;
+    let mut i = 0;
+    while i < argc {
+        len += "arg".len() + digits(i) + 1 + 1;
+        i += 1;
+    }
+    len
+}
+
+/// The event metadata: the total size, an empty tags byte, the event name,
+/// and then each argument's field name and type.
+pub const fn event<const N: usize>(name: &str, argc: usize) -> [u8; N] {
+    let mut event = [0; N];
+    let size = (N as u16).to_le_bytes();
+    event[0] = size[0];
+    event[1] = size[1];
+    let name = name.as_bytes();
+    let mut pos = 3;
+    while pos - 3 < name.len() {
+        event[pos] = name[pos - 3];
+        pos += 1;
+    }
+    pos += 1;
+    let mut i = 0;
+    while i < argc {
+        event[pos] = b'a';
+        event[pos + 1] = b'r';
+        event[pos + 2] = b'g';
+        pos += 3;
+        let mut digit = digits(i);
+        while digit > 0 {
+            digit -= 1;
+            event[pos] = b'0' + (i / 10usize.pow(digit as u32) % 10) as u8;
+            pos += 1;
+        }
+        event[pos + 1] = TLG_IN_INT64;
+        pos += 2;
+        i += 1;
+    }
+    event
+}
+
+const fn digits(mut n: usize) -> usize {
+    let mut digits = 1;
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+    digits
+}
+
+/// The provider GUID for `name`, as generated by `EventSource`: the SHA-1
+/// hash of a namespace and the upper-cased name in UTF-16BE, made into a
+/// version 5 GUID. Only ASCII names are hashed correctly.
+pub const fn guid(name: &str) -> Guid {
+    const NAMESPACE: Guid = [
+        0x48, 0x2c, 0x2d, 0xb2, 0xc3, 0x90, 0x47, 0xc8, 0x87, 0xf8, 0x1a, 0x15, 0xbf, 0xc1, 0x30,
+        0xfb,
+    ];
+    let name = name.as_bytes();
+    let len = NAMESPACE.len() + 2 * name.len();
+    let blocks = (len + 1 + 8 + 63) / 64;
+
+    // The padded message, one byte at a time.
+    const fn byte(name: &[u8], len: usize, blocks: usize, i: usize) -> u8 {
+        if i < NAMESPACE.len() {
+            NAMESPACE[i]
+        } else if i < len {
+            let j = i - NAMESPACE.len();
+            if j % 2 == 0 {
+                0
+            } else {
+                name[j / 2].to_ascii_uppercase()
+            }
+        } else if i == len {
+            0x80
+        } else if i >= blocks * 64 - 8 {
+            let bits = (len as u64 * 8).to_be_bytes();
+            bits[i - (blocks * 64 - 8)]
+        } else {
+            0
+        }
+    }
+
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut block = 0;
+    while block < blocks {
+        let mut w = [0u32; 80];
+        let mut t = 0;
+        while t < 16 {
+            let i = block * 64 + t * 4;
+            w[t] = u32::from_be_bytes([
+                byte(name, len, blocks, i),
+                byte(name, len, blocks, i + 1),
+                byte(name, len, blocks, i + 2),
+                byte(name, len, blocks, i + 3),
+            ]);
+            t += 1;
+        }
+        while t < 80 {
+            w[t] = (w[t - 3] ^ w[t - 8] ^ w[t - 14] ^ w[t - 16]).rotate_left(1);
+            t += 1;
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        t = 0;
+        while t < 80 {
+            let (f, k) = match t {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w[t]);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+            t += 1;
+        }
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+        block += 1;
+    }
+
+    let mut guid = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        guid[i] = h[i / 4].to_be_bytes()[i % 4];
+        i += 1;
+    }
+    guid[7] = (guid[7] & 0x0f) | 0x50;
+    guid
+}
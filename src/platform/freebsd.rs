@@ -0,0 +1,57 @@
+//! DTrace helper for FreeBSD
+//!
+//! FreeBSD's `dof_helper_t` also carries the target process ID and a
+//! generation number, and the ioctl is encoded the BSD way.
+//!
+//! # Links:
+//!
+//! * <https://man.freebsd.org/cgi/man.cgi?query=dtrace_usdt>
+//! * <https://github.com/freebsd/freebsd-src/blob/main/cddl/contrib/opensolaris/lib/libdtrace/common/drti.c>
+
+use core::ffi::{c_char, c_int, c_ulong};
+
+// dof_helper_t
+#[repr(C)]
+struct DofHelper {
+    module: [c_char; 64],
+    addr: u64,
+    dof: u64,
+    pid: c_int,
+    gen: c_int,
+}
+
+// _IOWR('z', 3, dof_helper_t)
+const DTRACEHIOC_ADDDOF: c_ulong = 0xc000_0000
+    | ((core::mem::size_of::<DofHelper>() as c_ulong) << 16)
+    | (b'z' as c_ulong) << 8
+    | 3;
+
+const O_RDWR: c_int = 2;
+
+extern "C" {
+    fn open(path: *const c_char, flags: c_int, ...) -> c_int;
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    fn close(fd: c_int) -> c_int;
+    fn getpid() -> c_int;
+}
+
+pub(super) fn load(module: &[u8], dof: &[u8]) {
+    let mut helper = DofHelper {
+        module: [0; 64],
+        addr: 0,
+        dof: dof.as_ptr() as u64,
+        pid: unsafe { getpid() },
+        gen: 0,
+    };
+    for (dst, &src) in helper.module[..63].iter_mut().zip(module) {
+        *dst = src as c_char;
+    }
+
+    unsafe {
+        let fd = open(b"/dev/dtrace/helper\0".as_ptr().cast(), O_RDWR);
+        if fd >= 0 {
+            ioctl(fd, DTRACEHIOC_ADDDOF, &mut helper as *mut DofHelper);
+            close(fd);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! FreeRTOS trace recorder user events
+//!
+//! With the `freertos` feature on bare-metal targets, for FreeRTOS
+//! applications with Percepio's TraceRecorder, each probe is written as a
+//! user event with `xTracePrintF`, on a channel named for the provider, so it
+//! appears in Tracealyzer with the kernel's events. The event's format string
+//! is the probe name followed by `%d` for each argument, like `loop %d %d`,
+//! and probes can have at most 8 arguments.
+//!
+//! The recorder drops events while it isn't recording, and that can't be
+//! checked here, so every probe is written, and `probe_lazy!` always evaluates
+//! its arguments, unless the program turns probes off with `control::kill`.
+//!
+//! # Links:
+//!
+//! * <https://percepio.com/tracealyzer/freertostrace/>
+//! * <https://github.com/percepio/TraceRecorderSource/blob/main/include/trcPrint.h>
+
+//
+// DEVELOPER NOTES
+//
+// FreeRTOS only has C macros as trace hooks for its own objects, so user
+// events go straight to TraceRecorder. This uses its 4.6 API, where channels
+// are registered strings, and the handle is cached at each probe site, since
+// registering is much slower than printing.
+//
+// `xTracePrintF` is variadic, so each argument count is its own call, and the
+// arguments are `int`, which is `isize` on the 32-bit targets FreeRTOS runs on.
+//
+
+use core::ffi::{c_char, c_int, c_void};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const _: () = assert!(
+            <[&str]>::len(&[$(stringify!($arg),)*]) <= $crate::platform::freertos::MAX_ARGS,
+            concat!("too many arguments for FreeRTOS probe ",
+                $crate::probe_provider!($provider), ":", $crate::probe_name!($name), ", the limit is 8"),
+        );
+        static CHANNEL: $crate::platform::freertos::Channel =
+            $crate::platform::freertos::Channel::new(concat!($crate::probe_provider!($provider), "\0"));
+        let args = [$(($arg) as isize,)*];
+        if $crate::control::live() {
+            CHANNEL.print(
+                concat!($crate::probe_name!($name), $($crate::freertos_format!($arg),)* "\0"),
+                &args,
+            );
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::control::live();
+        if enabled {
+            $crate::platform_probe!($provider, $name, [$($opt)*], $($arg,)*);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::control::live()
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! freertos_format(
+    ($arg:expr) => (" %d")
+);
+
+/// The most arguments a probe can have.
+pub const MAX_ARGS: usize = 8;
+
+extern "C" {
+    fn xTraceStringRegister(string: *const c_char, handle: *mut *mut c_void) -> c_int;
+    fn xTracePrintF(channel: *mut c_void, format: *const c_char, ...) -> c_int;
+}
+
+/// TRC_SUCCESS
+const TRC_SUCCESS: c_int = 0;
+
+/// A user event channel, registered the first time it's used.
+pub struct Channel {
+    name: &'static str,
+    handle: AtomicPtr<c_void>,
+}
+
+impl Channel {
+    pub const fn new(name: &'static str) -> Self {
+        Channel {
+            name,
+            handle: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn handle(&self) -> *mut c_void {
+        let mut handle = self.handle.load(Ordering::Relaxed);
+        if handle.is_null() {
+            // Racing to register twice only wastes a string in the recorder.
+            let result = unsafe { xTraceStringRegister(self.name.as_ptr().cast(), &mut handle) };
+            if result != TRC_SUCCESS {
+                return ptr::null_mut();
+            }
+            self.handle.store(handle, Ordering::Relaxed);
+        }
+        handle
+    }
+
+    #[inline(never)]
+    pub fn print(&self, format: &'static str, args: &[isize]) {
+        let channel = self.handle();
+        if channel.is_null() {
+            return;
+        }
+        let format: *const c_char = format.as_ptr().cast();
+        let a = |i: usize| args[i] as c_int;
+        unsafe {
+            match args.len() {
+                0 => xTracePrintF(channel, format),
+                1 => xTracePrintF(channel, format, a(0)),
+                2 => xTracePrintF(channel, format, a(0), a(1)),
+                3 => xTracePrintF(channel, format, a(0), a(1), a(2)),
+                4 => xTracePrintF(channel, format, a(0), a(1), a(2), a(3)),
+                5 => xTracePrintF(channel, format, a(0), a(1), a(2), a(3), a(4)),
+                6 => xTracePrintF(channel, format, a(0), a(1), a(2), a(3), a(4), a(5)),
+                7 => xTracePrintF(channel, format, a(0), a(1), a(2), a(3), a(4), a(5), a(6)),
+                _ => xTracePrintF(
+                    channel,
+                    format,
+                    a(0),
+                    a(1),
+                    a(2),
+                    a(3),
+                    a(4),
+                    a(5),
+                    a(6),
+                    a(7),
+                ),
+            };
+        }
+    }
+}
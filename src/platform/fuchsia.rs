@@ -0,0 +1,363 @@
+//! Fuchsia trace events
+//!
+//! Each probe is written as a trace event through the trace engine, in a
+//! category named for the provider, so it shows up in `ffx trace` recordings
+//! and the Perfetto UI, e.g. with `ffx trace start --categories foo`. The
+//! event is named for the probe, with `isize` arguments as 64-bit fields
+//! `arg0`, `arg1`, etc., up to the trace format's limit of 15. Probes are
+//! instant events, or duration events with the `span = begin` and
+//! `span = end` options.
+//!
+//! The program registers itself as a trace provider when it starts, with its
+//! own thread to handle the trace manager's requests. Probes are only written
+//! while their category is enabled, and `probe_lazy!` only evaluates its
+//! arguments then.
+//!
+//! # Links:
+//!
+//! * <https://fuchsia.dev/fuchsia-src/development/tracing>
+//! * <https://fuchsia.dev/fuchsia-src/reference/tracing/trace-format>
+//! * <https://fuchsia.googlesource.com/fuchsia/+/refs/heads/main/zircon/system/ulib/trace-engine/include/lib/trace-engine/instrumentation.h>
+
+//
+// DEVELOPER NOTES
+//
+// This is what the C `TRACE_INSTANT` macro expands to: acquire the trace
+// context for the category, caching its state in a `trace_site_t` for the
+// site, then register the thread and strings, write the record, and release
+// the context.
+//
+// The provider needs an async dispatcher, which C programs usually have
+// anyway. We can't assume there's one to share, so an `.init_array` entry
+// from each site starts a loop on a thread of its own, like `user_events`
+// registers its sites. That loop is `async-loop-default`, which is only a
+// static library in the SDK, and others are shared libraries, all of which
+// need to be on the library path.
+//
+
+use core::ffi::{c_char, c_int, c_void};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args = [$(($arg) as isize as i64,)*];
+        $crate::fuchsia_site!(SITE, $provider, $name, [$($opt)*]);
+        if let Some(context) = SITE.acquire() {
+            context.write(&args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::fuchsia_site!(SITE, $provider, $name, [$($opt)*]);
+        match SITE.acquire() {
+            Some(context) => {
+                context.write(&[$(($arg) as isize as i64,)*]);
+                true
+            }
+            None => false,
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        $crate::fuchsia_site!(SITE, $provider, $name, [$($opt)*]);
+        SITE.acquire().is_some()
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! fuchsia_site(
+    ($site:ident, $provider:tt, $name:tt, [$($opt:tt)*]) => (
+        static $site: $crate::platform::fuchsia::Site = $crate::platform::fuchsia::Site::new(
+            concat!($crate::probe_provider!($provider), "\0"),
+            concat!($crate::probe_name!($name), "\0"),
+            $crate::fuchsia_kind!($($opt)*),
+        );
+
+        #[used]
+        #[link_section = ".init_array"]
+        static REGISTER: extern "C" fn() = $crate::platform::fuchsia::register;
+    )
+);
+
+// The kind of event for the `span` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! fuchsia_kind(
+    () => ($crate::platform::fuchsia::KIND_INSTANT);
+    (span = begin $(, $($rest:tt)*)?) => ($crate::platform::fuchsia::KIND_BEGIN);
+    (span = end $(, $($rest:tt)*)?) => ($crate::platform::fuchsia::KIND_END);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::fuchsia_kind!($($($rest)*)?));
+);
+
+pub const KIND_INSTANT: u8 = 0;
+pub const KIND_BEGIN: u8 = 1;
+pub const KIND_END: u8 = 2;
+
+/// TRACE_MAX_ARGS
+const MAX_ARGS: usize = 15;
+
+// Field names, as string literals for the trace engine to register.
+static ARG_NAMES: [&str; MAX_ARGS] = [
+    "arg0\0", "arg1\0", "arg2\0", "arg3\0", "arg4\0", "arg5\0", "arg6\0", "arg7\0", "arg8\0",
+    "arg9\0", "arg10\0", "arg11\0", "arg12\0", "arg13\0", "arg14\0",
+];
+
+// trace_string_ref_t
+#[repr(C)]
+struct StringRef {
+    encoded_value: u16,
+    inline_string: *const c_char,
+}
+
+// trace_thread_ref_t
+#[repr(C)]
+struct ThreadRef {
+    encoded_value: u32,
+    inline_process_koid: u64,
+    inline_thread_koid: u64,
+}
+
+// trace_arg_t, with an int64 trace_arg_value_t
+#[repr(C)]
+struct Arg {
+    name_ref: StringRef,
+    kind: u32,
+    value: [u64; 4],
+}
+
+/// TRACE_ARG_INT64
+const ARG_INT64: u32 = 3;
+
+/// TRACE_SCOPE_THREAD
+const SCOPE_THREAD: c_int = 0;
+
+enum Context {}
+enum Loop {}
+
+#[link(name = "zircon")]
+extern "C" {
+    fn zx_ticks_get() -> u64;
+}
+
+#[link(name = "trace-engine")]
+extern "C" {
+    fn trace_acquire_context_for_category_cached(
+        category_literal: *const c_char,
+        site: *mut usize,
+        out_ref: *mut StringRef,
+    ) -> *mut Context;
+    fn trace_release_context(context: *mut Context);
+    fn trace_context_register_current_thread(context: *mut Context, out_ref: *mut ThreadRef);
+    fn trace_context_register_string_literal(
+        context: *mut Context,
+        string_literal: *const c_char,
+        out_ref: *mut StringRef,
+    );
+    fn trace_context_write_instant_event_record(
+        context: *mut Context,
+        event_time: u64,
+        thread_ref: *const ThreadRef,
+        category_ref: *const StringRef,
+        name_ref: *const StringRef,
+        scope: c_int,
+        args: *const Arg,
+        num_args: usize,
+    );
+    fn trace_context_write_duration_begin_event_record(
+        context: *mut Context,
+        event_time: u64,
+        thread_ref: *const ThreadRef,
+        category_ref: *const StringRef,
+        name_ref: *const StringRef,
+        args: *const Arg,
+        num_args: usize,
+    );
+    fn trace_context_write_duration_end_event_record(
+        context: *mut Context,
+        event_time: u64,
+        thread_ref: *const ThreadRef,
+        category_ref: *const StringRef,
+        name_ref: *const StringRef,
+        args: *const Arg,
+        num_args: usize,
+    );
+}
+
+#[link(name = "trace-provider-so")]
+extern "C" {
+    fn trace_provider_create_with_fdio(dispatcher: *mut c_void) -> *mut c_void;
+}
+
+#[link(name = "async-loop-default")]
+#[link(name = "async-default")]
+extern "C" {
+    static kAsyncLoopConfigNoAttachToCurrentThread: c_void;
+    fn async_loop_create(config: *const c_void, out_loop: *mut *mut Loop) -> i32;
+    fn async_loop_get_dispatcher(event_loop: *mut Loop) -> *mut c_void;
+    fn async_loop_start_thread(
+        event_loop: *mut Loop,
+        name: *const c_char,
+        out_thread: *mut c_void,
+    ) -> i32;
+}
+
+pub struct Site {
+    category: &'static str,
+    name: &'static str,
+    kind: u8,
+    state: AtomicUsize,
+}
+
+impl Site {
+    pub const fn new(category: &'static str, name: &'static str, kind: u8) -> Self {
+        Site {
+            category,
+            name,
+            kind,
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    /// The trace context, if this probe's category is being traced.
+    #[inline]
+    pub fn acquire(&'static self) -> Option<Acquired> {
+        let mut category_ref = StringRef {
+            encoded_value: 0,
+            inline_string: ptr::null(),
+        };
+        let context = unsafe {
+            trace_acquire_context_for_category_cached(
+                self.category.as_ptr().cast(),
+                &self.state as *const AtomicUsize as *mut usize,
+                &mut category_ref,
+            )
+        };
+        if context.is_null() {
+            return None;
+        }
+        Some(Acquired {
+            site: self,
+            context,
+            category_ref,
+        })
+    }
+}
+
+/// An acquired trace context, released on drop.
+pub struct Acquired {
+    site: &'static Site,
+    context: *mut Context,
+    category_ref: StringRef,
+}
+
+impl Acquired {
+    #[cold]
+    pub fn write(&self, args: &[i64]) {
+        let args = &args[..args.len().min(MAX_ARGS)];
+        unsafe {
+            let ticks = zx_ticks_get();
+            let mut thread_ref = ThreadRef {
+                encoded_value: 0,
+                inline_process_koid: 0,
+                inline_thread_koid: 0,
+            };
+            trace_context_register_current_thread(self.context, &mut thread_ref);
+            let name_ref = self.string_ref(self.site.name);
+
+            let mut records: [Arg; MAX_ARGS] = core::mem::zeroed();
+            for ((record, &arg), name) in records.iter_mut().zip(args).zip(&ARG_NAMES) {
+                record.name_ref = self.string_ref(name);
+                record.kind = ARG_INT64;
+                record.value[0] = arg as u64;
+            }
+
+            let (context, thread_ref, category_ref) =
+                (self.context, &thread_ref, &self.category_ref);
+            let (records, num_args) = (records.as_ptr(), args.len());
+            match self.site.kind {
+                KIND_BEGIN => trace_context_write_duration_begin_event_record(
+                    context,
+                    ticks,
+                    thread_ref,
+                    category_ref,
+                    &name_ref,
+                    records,
+                    num_args,
+                ),
+                KIND_END => trace_context_write_duration_end_event_record(
+                    context,
+                    ticks,
+                    thread_ref,
+                    category_ref,
+                    &name_ref,
+                    records,
+                    num_args,
+                ),
+                _ => trace_context_write_instant_event_record(
+                    context,
+                    ticks,
+                    thread_ref,
+                    category_ref,
+                    &name_ref,
+                    SCOPE_THREAD,
+                    records,
+                    num_args,
+                ),
+            }
+        }
+    }
+
+    fn string_ref(&self, literal: &'static str) -> StringRef {
+        let mut string_ref = StringRef {
+            encoded_value: 0,
+            inline_string: ptr::null(),
+        };
+        unsafe {
+            trace_context_register_string_literal(
+                self.context,
+                literal.as_ptr().cast(),
+                &mut string_ref,
+            );
+        }
+        string_ref
+    }
+}
+
+impl Drop for Acquired {
+    fn drop(&mut self) {
+        unsafe { trace_release_context(self.context) }
+    }
+}
+
+/// Registers the program as a trace provider, run from `.init_array`.
+#[doc(hidden)]
+pub extern "C" fn register() {
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+    if REGISTERED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    unsafe {
+        let mut event_loop = ptr::null_mut();
+        if async_loop_create(&kAsyncLoopConfigNoAttachToCurrentThread, &mut event_loop) != 0 {
+            return;
+        }
+        let name = b"probe-trace-provider\0";
+        if async_loop_start_thread(event_loop, name.as_ptr().cast(), ptr::null_mut()) != 0 {
+            return;
+        }
+        // The provider lives as long as the program.
+        trace_provider_create_with_fdio(async_loop_get_dispatcher(event_loop));
+    }
+}
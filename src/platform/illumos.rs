@@ -0,0 +1,50 @@
+//! DTrace helper for illumos and Solaris
+//!
+//! This is the original `dof_helper_t` and helper ioctl, as used by the
+//! `drti.o` initializer that `dtrace -G` links into C programs.
+//!
+//! # Links:
+//!
+//! * <https://github.com/illumos/illumos-gate/blob/master/usr/src/lib/libdtrace/common/drti.c>
+//! * <https://github.com/illumos/illumos-gate/blob/master/usr/src/uts/common/sys/dtrace.h>
+//!   (`DTRACEHIOC_ADDDOF`)
+
+use core::ffi::{c_char, c_int};
+
+// dof_helper_t
+#[repr(C)]
+struct DofHelper {
+    module: [c_char; 64],
+    addr: u64,
+    dof: u64,
+}
+
+const DTRACEHIOC: c_int = (b'd' as c_int) << 24 | (b't' as c_int) << 16 | (b'h' as c_int) << 8;
+const DTRACEHIOC_ADDDOF: c_int = DTRACEHIOC | 3;
+
+const O_RDWR: c_int = 2;
+
+extern "C" {
+    fn open(path: *const c_char, flags: c_int, ...) -> c_int;
+    fn ioctl(fd: c_int, request: c_int, ...) -> c_int;
+    fn close(fd: c_int) -> c_int;
+}
+
+pub(super) fn load(module: &[u8], dof: &[u8]) {
+    let mut helper = DofHelper {
+        module: [0; 64],
+        addr: 0,
+        dof: dof.as_ptr() as u64,
+    };
+    for (dst, &src) in helper.module[..63].iter_mut().zip(module) {
+        *dst = src as c_char;
+    }
+
+    unsafe {
+        let fd = open(b"/dev/dtrace/helper\0".as_ptr().cast(), O_RDWR);
+        if fd >= 0 {
+            ioctl(fd, DTRACEHIOC_ADDDOF, &mut helper as *mut DofHelper);
+            close(fd);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! ARM Cortex-M ITM stimulus records
+//!
+//! With the `itm` feature on bare-metal ARM, each probe writes a compact
+//! record to an ITM stimulus port, so it's traced over SWO with the rest of
+//! the instrumentation trace, e.g. with `itmdump` or an IDE's SWO viewer. The
+//! port is 1 by default, or `PROBE_ITM_PORT` from the environment of the
+//! build, up to 31.
+//!
+//! Each record is a 32-bit ID, with a 16-bit hash of the provider in the high
+//! half and a 16-bit hash of the name in the low half, followed by an 8-bit
+//! count of the arguments, and then each argument as a 32-bit word. The hashes
+//! are the 32-bit FNV-1a hash of the string, folded to 16 bits by XOR of its
+//! halves, so a decoder can map them back to names from a list of probes.
+//!
+//! A probe is skipped unless the ITM and its port are enabled, by the
+//! debugger or the application, and then `probe_lazy!` doesn't evaluate its
+//! arguments either. This needs ARMv7-M or ARMv8-M Mainline, as ARMv6-M has
+//! no ITM.
+//!
+//! # Links:
+//!
+//! * <https://developer.arm.com/documentation/ddi0403/latest/> (see "Instrumentation Trace Macrocell")
+//! * <https://developer.arm.com/documentation/ddi0314/h/Instrumentation-Trace-Macrocell-Unit>
+
+//
+// DEVELOPER NOTES
+//
+// A record takes several writes to the port, so interrupts are masked while
+// it's written, to keep the records of a probe in an interrupt handler from
+// splitting another. Each write waits for the port's FIFO to be ready.
+//
+
+use core::arch::asm;
+use core::ptr;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args: &[isize] = &[$(($arg) as isize,)*];
+        const ID: u32 = $crate::platform::itm::id($crate::probe_provider!($provider), $crate::probe_name!($name));
+        if $crate::platform::itm::enabled() {
+            $crate::platform::itm::write(ID, args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const ID: u32 = $crate::platform::itm::id($crate::probe_provider!($provider), $crate::probe_name!($name));
+        let enabled = $crate::platform::itm::enabled();
+        if enabled {
+            $crate::platform::itm::write(ID, &[$(($arg) as isize,)*]);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::platform::itm::enabled()
+    )
+);
+
+/// The stimulus port for probes, from `PROBE_ITM_PORT` at build time.
+const PORT: usize = parse_port(env!("PROBE_ITM_PORT"));
+
+/// ITM_STIM0, with a register for each port after it.
+const ITM_STIM: *mut u32 = 0xe000_0000 as *mut u32;
+
+/// ITM_TER0, with an enable bit for each port.
+const ITM_TER: *const u32 = 0xe000_0e00 as *const u32;
+
+/// ITM_TCR, and its ITMENA bit.
+const ITM_TCR: *const u32 = 0xe000_0e80 as *const u32;
+const ITMENA: u32 = 1;
+
+const fn parse_port(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut port = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        port = port * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    port
+}
+
+/// The record ID for a probe, from the hashes of its provider and name.
+pub const fn id(provider: &str, name: &str) -> u32 {
+    (hash(provider) as u32) << 16 | hash(name) as u32
+}
+
+/// FNV-1a, folded to 16 bits.
+const fn hash(s: &str) -> u16 {
+    let bytes = s.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    (hash >> 16) as u16 ^ hash as u16
+}
+
+/// Whether the ITM and the probe port are enabled.
+#[inline]
+pub fn enabled() -> bool {
+    unsafe {
+        ptr::read_volatile(ITM_TCR) & ITMENA != 0 && ptr::read_volatile(ITM_TER) & 1 << PORT != 0
+    }
+}
+
+#[cold]
+pub fn write(id: u32, args: &[isize]) {
+    let args = &args[..args.len().min(u8::MAX as usize)];
+    let primask: u32;
+    unsafe {
+        asm!("mrs {}, PRIMASK", "cpsid i", out(reg) primask, options(nomem, nostack));
+        let stim = ITM_STIM.add(PORT);
+        write_port(stim, id);
+        write_port(stim.cast::<u8>(), args.len() as u8);
+        for &arg in args {
+            write_port(stim, arg as u32);
+        }
+        // Only unmask interrupts if they weren't already masked.
+        if primask & 1 == 0 {
+            asm!("cpsie i", options(nomem, nostack));
+        }
+    }
+}
+
+/// Writes to a port once its FIFO is ready, which the port reads as 1.
+#[inline]
+unsafe fn write_port<T>(stim: *mut T, value: T) {
+    while ptr::read_volatile(stim.cast::<u32>()) & 1 == 0 {}
+    ptr::write_volatile(stim, value);
+}
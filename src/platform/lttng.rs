@@ -0,0 +1,109 @@
+//! LTTng-UST tracepoints
+//!
+//! With the `lttng` feature on Linux, probes are written as events of a
+//! single LTTng-UST tracepoint provider, `rust_probe`, which the build script
+//! compiles from `lttng/tp.h`. Each event records the probe's `provider` and
+//! `name` as strings, and its arguments as the sequence `args`, so sessions
+//! can select probes with a filter expression on those fields. There's an
+//! event for each probe level, like `rust_probe:probe_warn`, with the matching
+//! loglevel, e.g. `TRACE_WARNING`, so sessions can also filter on that, and
+//! `rust_probe:probe` is for `trace` probes, which is the default.
+//!
+//! The events end up in CTF traces, readable with `babeltrace2` or Trace
+//! Compass. The tracepoint state is checked before each event is written, so
+//! `probe_lazy!` only evaluates its arguments while a session enables it.
+//!
+//! # Links:
+//!
+//! * <https://lttng.org/docs/v2.13/#doc-tracing-your-own-user-application>
+//! * <https://lttng.org/man/3/lttng-ust/v2.13/>
+
+//
+// DEVELOPER NOTES
+//
+// LTTng normally expects a tracepoint provider to be generated for every
+// event, with static types for each field. We can't generate C per probe from
+// a macro, so there's only one generic event, and the probe's identity is
+// part of its payload instead. The events for each level are instances of
+// one event class, and the shim picks one by the level's number.
+//
+// Unlike SDT, the enabled check is a real call into the C shim, because the
+// layout of the tracepoint state isn't stable across LTTng-UST versions.
+//
+
+use core::ffi::{c_char, c_int, c_long};
+
+extern "C" {
+    fn probe_lttng_enabled(level: c_int) -> c_int;
+    fn probe_lttng_write(
+        level: c_int,
+        provider: *const c_char,
+        name: *const c_char,
+        args: *const c_long,
+        argc: usize,
+    );
+}
+
+#[inline]
+pub fn enabled(level: u8) -> bool {
+    unsafe { probe_lttng_enabled(level.into()) != 0 }
+}
+
+#[cold]
+pub fn write(level: u8, provider: &'static str, name: &'static str, args: &[isize]) {
+    unsafe {
+        probe_lttng_write(
+            level.into(),
+            provider.as_ptr().cast(),
+            name.as_ptr().cast(),
+            args.as_ptr().cast(),
+            args.len(),
+        );
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const LEVEL: u8 = $crate::probe_level_value!($($opt)*);
+        let args: &[isize] = &[$(($arg) as isize,)*];
+        if $crate::platform::lttng::enabled(LEVEL) {
+            $crate::lttng_write!(LEVEL, $provider, $name, args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const LEVEL: u8 = $crate::probe_level_value!($($opt)*);
+        let enabled = $crate::platform::lttng::enabled(LEVEL);
+        if enabled {
+            $crate::lttng_write!(LEVEL, $provider, $name, &[$(($arg) as isize,)*]);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::platform::lttng::enabled($crate::probe_level_value!($($opt)*))
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! lttng_write(
+    ($level:expr, $provider:tt, $name:tt, $args:expr) => (
+        $crate::platform::lttng::write(
+            $level,
+            concat!($crate::probe_provider!($provider), "\0"),
+            concat!($crate::probe_name!($name), "\0"),
+            $args,
+        )
+    )
+);
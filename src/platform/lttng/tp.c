@@ -0,0 +1,42 @@
+#define TRACEPOINT_CREATE_PROBES
+#define TRACEPOINT_DEFINE
+#include "tp.h"
+
+/* The level is the number of the Rust `Level`, from 1 for critical. */
+
+int probe_lttng_enabled(int level)
+{
+	switch (level) {
+	case 1: return tracepoint_enabled(rust_probe, probe_critical);
+	case 2: return tracepoint_enabled(rust_probe, probe_error);
+	case 3: return tracepoint_enabled(rust_probe, probe_warn);
+	case 4: return tracepoint_enabled(rust_probe, probe_info);
+	case 5: return tracepoint_enabled(rust_probe, probe_debug);
+	default: return tracepoint_enabled(rust_probe, probe);
+	}
+}
+
+void probe_lttng_write(int level, const char *provider, const char *name,
+		const long *args, size_t argc)
+{
+	switch (level) {
+	case 1:
+		do_tracepoint(rust_probe, probe_critical, provider, name, args, argc);
+		break;
+	case 2:
+		do_tracepoint(rust_probe, probe_error, provider, name, args, argc);
+		break;
+	case 3:
+		do_tracepoint(rust_probe, probe_warn, provider, name, args, argc);
+		break;
+	case 4:
+		do_tracepoint(rust_probe, probe_info, provider, name, args, argc);
+		break;
+	case 5:
+		do_tracepoint(rust_probe, probe_debug, provider, name, args, argc);
+		break;
+	default:
+		do_tracepoint(rust_probe, probe, provider, name, args, argc);
+		break;
+	}
+}
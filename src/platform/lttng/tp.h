@@ -0,0 +1,62 @@
+/*
+ * The LTTng-UST tracepoint provider behind the `lttng` feature.
+ *
+ * Every Rust probe is an instance of the same event class, with its own
+ * provider and name recorded as string fields, and its arguments as a
+ * sequence of longs, like the `isize` casts on every other platform. There's
+ * an event for each probe level, with the matching loglevel, and `probe` is
+ * the default `trace` level.
+ */
+
+#undef TRACEPOINT_PROVIDER
+#define TRACEPOINT_PROVIDER rust_probe
+
+#undef TRACEPOINT_INCLUDE
+#define TRACEPOINT_INCLUDE "./tp.h"
+
+#if !defined(PROBE_LTTNG_TP_H) || defined(TRACEPOINT_HEADER_MULTI_READ)
+#define PROBE_LTTNG_TP_H
+
+#include <stddef.h>
+#include <lttng/tracepoint.h>
+
+TRACEPOINT_EVENT_CLASS(
+	rust_probe,
+	probe_class,
+	TP_ARGS(
+		const char *, provider,
+		const char *, name,
+		const long *, args,
+		size_t, argc
+	),
+	TP_FIELDS(
+		ctf_string(provider, provider)
+		ctf_string(name, name)
+		ctf_sequence(long, args, args, size_t, argc)
+	)
+)
+
+#define PROBE_LTTNG_EVENT(event, loglevel)				\
+	TRACEPOINT_EVENT_INSTANCE(					\
+		rust_probe,						\
+		probe_class,						\
+		event,							\
+		TP_ARGS(						\
+			const char *, provider,				\
+			const char *, name,				\
+			const long *, args,				\
+			size_t, argc					\
+		)							\
+	)								\
+	TRACEPOINT_LOGLEVEL(rust_probe, event, loglevel)
+
+PROBE_LTTNG_EVENT(probe_critical, TRACE_CRIT)
+PROBE_LTTNG_EVENT(probe_error, TRACE_ERR)
+PROBE_LTTNG_EVENT(probe_warn, TRACE_WARNING)
+PROBE_LTTNG_EVENT(probe_info, TRACE_INFO)
+PROBE_LTTNG_EVENT(probe_debug, TRACE_DEBUG_LINE)
+PROBE_LTTNG_EVENT(probe, TRACE_DEBUG)
+
+#endif /* PROBE_LTTNG_TP_H */
+
+#include <lttng/tracepoint-event.h>
@@ -0,0 +1,125 @@
+//! DTrace USDT probes on macOS
+//!
+//! Apple's linker has built-in support for user-level statically defined
+//! tracing. A probe site is just a call to a specially-named external symbol,
+//! and when `ld64` sees a relocation against such a symbol it replaces the
+//! call with `NOP`s and records the location in a DOF section of the output.
+//! DTrace can then find these probes with `dtrace -l`, e.g. `foo*:::`, and
+//! read their arguments as `arg0`, `arg1`, etc.
+//!
+//! Lazy probes use the matching "is-enabled" symbol, which the linker turns
+//! into a zeroing instruction that DTrace patches while the probe is active.
+//!
+//! # Links:
+//!
+//! * <https://www.illumos.org/books/dtrace/chp-usdt.html>
+//! * <https://github.com/apple-oss-distributions/ld64/blob/main/src/ld/parsers/macho_relocatable_file.cpp>
+//!   (search for `___dtrace_probe$`)
+
+//
+// DEVELOPER NOTES
+//
+// This mirrors what `dtrace -h` generates in a C header on Darwin:
+//
+//   #define FOO_BAR(arg0) \
+//   do { \
+//       __asm__ volatile(".reference " FOO_TYPEDEFS); \
+//       __dtrace_probe$foo$bar$v1$6c6f6e67(arg0); \
+//       __asm__ volatile(".reference " FOO_STABILITY); \
+//   } while (0)
+//   #define FOO_BAR_ENABLED() \
+//       ({ int _r = __dtrace_isenabled$foo$bar$v1(); \
+//           __asm__ volatile(""); \
+//           _r; })
+//
+// where FOO_TYPEDEFS and FOO_STABILITY are the provider-wide symbols below.
+// The suffix of the probe symbol is the hex-encoded C type of each argument,
+// joined with `$`. Arguments are all passed as `long` ("6c6f6e67"), matching
+// the `isize` casts on every other platform.
+//
+// The calls are real calls as far as rustc knows, so arguments go through
+// the normal C ABI, which is exactly where DTrace expects to read them.
+//
+// DTrace converts a double underscore in probe names to a dash, so Rust's
+// `foo__bar` is seen as `foo-bar`, just as in C.
+//
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::dtrace_probe!($provider, $name, $($arg,)*);
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::platform_probe_enabled!($provider, $name, [$($opt)*]);
+        if enabled {
+            $crate::dtrace_probe!($provider, $name, $($arg,)*);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        extern "C" {
+            #[link_name = concat!(
+                "__dtrace_isenabled$", $crate::probe_provider!($provider), "$", $crate::probe_name!($name), "$v1"
+            )]
+            fn is_enabled() -> i32;
+        }
+        unsafe { is_enabled() != 0 }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! dtrace_probe(
+    ($provider:tt, $name:tt, $($arg:expr,)*) => ({
+        extern "C" {
+            #[link_name = concat!("__dtrace_typedefs$", $crate::probe_provider!($provider), "$v2")]
+            fn typedefs();
+
+            #[link_name = concat!(
+                "__dtrace_stability$", $crate::probe_provider!($provider),
+                "$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0"
+            )]
+            fn stability();
+
+            #[link_name = concat!(
+                "__dtrace_probe$", $crate::probe_provider!($provider), "$", $crate::probe_name!($name),
+                "$v1$", $crate::dtrace_types!($($arg,)*)
+            )]
+            fn probe($(_: $crate::dtrace_long!($arg)),*);
+        }
+        unsafe {
+            ::core::arch::asm!(".reference {}", sym typedefs,
+                options(nomem, nostack, preserves_flags));
+            probe($(($arg) as isize),*);
+            ::core::arch::asm!(".reference {}", sym stability,
+                options(nomem, nostack, preserves_flags));
+        }
+    })
+);
+
+// The hex-encoded argument types for the probe symbol, "long" for each.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! dtrace_types(
+    () => ("");
+    ($arg:expr,) => ("6c6f6e67");
+    ($arg:expr, $($rest:expr,)+) => (concat!("6c6f6e67$", $crate::dtrace_types!($($rest,)+)));
+);
+
+// The parameter type for each argument, in the extern probe declaration.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! dtrace_long(
+    ($arg:expr) => (isize);
+);
@@ -1,5 +1,265 @@
-#[cfg(any(target_os = "linux", target_os = "android"))]
+// Backends from features take precedence over SDT, in this order on Linux.
+#[cfg(all(target_os = "linux", feature = "lttng"))]
+pub mod lttng;
+
+#[cfg(all(target_os = "linux", feature = "user_events", not(feature = "lttng")))]
+pub mod user_events;
+
+#[cfg(all(target_os = "android", feature = "atrace"))]
+pub mod atrace;
+
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64",
+        target_arch = "riscv32",
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+        all(target_arch = "powerpc64", feature = "asm_experimental_arch"),
+        all(target_arch = "mips", feature = "asm_experimental_arch"),
+        all(target_arch = "mips64", feature = "asm_experimental_arch"),
+    ),
+    not(any(
+        all(target_os = "linux", any(feature = "lttng", feature = "user_events")),
+        all(target_os = "android", feature = "atrace")
+    ))
+))]
 mod systemtap;
 
-#[cfg(not(any(target_os = "linux", target_os = "android")))]
+// Only SDT probes have a semaphore, for `probe_semaphore!`.
+#[cfg(all(
+    feature = "use_std",
+    not(all(
+        any(target_os = "linux", target_os = "android"),
+        any(
+            target_arch = "x86_64",
+            target_arch = "x86",
+            target_arch = "aarch64",
+            target_arch = "arm",
+            target_arch = "riscv64",
+            target_arch = "riscv32",
+            target_arch = "s390x",
+            target_arch = "loongarch64",
+            all(target_arch = "powerpc64", feature = "asm_experimental_arch"),
+            all(target_arch = "mips", feature = "asm_experimental_arch"),
+            all(target_arch = "mips64", feature = "asm_experimental_arch"),
+        ),
+        not(any(
+            all(target_os = "linux", any(feature = "lttng", feature = "user_events")),
+            all(target_os = "android", feature = "atrace")
+        ))
+    ))
+))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_semaphore(
+    ($provider:tt, $name:tt) => (
+        ::core::option::Option::None::<$crate::semaphore::Semaphore>
+    )
+);
+
+/// Runs a probe site out of line, so a lazy probe's caller only has to check
+/// whether it's enabled.
+#[doc(hidden)]
+#[cold]
+#[inline(never)]
+pub fn cold<F: FnOnce()>(site: F) {
+    site()
+}
+
+#[cfg(all(target_vendor = "apple", feature = "os_signpost"))]
+pub mod os_signpost;
+
+#[cfg(all(target_os = "macos", not(feature = "os_signpost")))]
+mod macos;
+
+#[cfg(all(
+    any(
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "illumos",
+        target_os = "solaris"
+    ),
+    target_arch = "x86_64",
+))]
+mod dof;
+#[cfg(all(
+    any(
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "illumos",
+        target_os = "solaris"
+    ),
+    target_arch = "x86_64",
+))]
+pub mod usdt;
+
+#[cfg(all(target_os = "freebsd", target_arch = "x86_64"))]
+mod freebsd;
+
+#[cfg(all(target_os = "netbsd", target_arch = "x86_64"))]
+mod netbsd;
+
+#[cfg(all(
+    any(target_os = "illumos", target_os = "solaris"),
+    target_arch = "x86_64"
+))]
+mod illumos;
+
+#[cfg(windows)]
+pub mod etw;
+
+#[cfg(target_os = "fuchsia")]
+pub mod fuchsia;
+
+#[cfg(target_os = "nto")]
+pub mod qnx;
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+pub mod wasm;
+
+#[cfg(all(target_os = "espidf", feature = "esp_log"))]
+pub mod esp_log;
+
+// Backends from features for bare metal, in this order, where an RTOS comes
+// before anything that writes to the hardware directly.
+#[cfg(all(target_os = "none", feature = "zephyr"))]
+pub mod zephyr;
+
+#[cfg(all(target_os = "none", feature = "freertos", not(feature = "zephyr")))]
+pub mod freertos;
+
+#[cfg(all(
+    target_arch = "arm",
+    target_os = "none",
+    feature = "itm",
+    not(any(feature = "zephyr", feature = "freertos")),
+))]
+pub mod itm;
+
+#[cfg(all(
+    any(target_arch = "arm", target_arch = "riscv32", target_arch = "riscv64"),
+    target_os = "none",
+    feature = "rtt",
+    not(any(
+        feature = "zephyr",
+        feature = "freertos",
+        all(target_arch = "arm", feature = "itm")
+    )),
+))]
+pub mod rtt;
+
+#[cfg(all(
+    any(target_arch = "arm", target_arch = "riscv32", target_arch = "riscv64"),
+    target_os = "none",
+    feature = "semihosting",
+    not(any(
+        feature = "zephyr",
+        feature = "freertos",
+        all(target_arch = "arm", feature = "itm"),
+        feature = "rtt"
+    )),
+))]
+pub mod semihosting;
+
+#[cfg(all(
+    any(target_arch = "arm", target_arch = "aarch64"),
+    target_os = "none",
+    feature = "stm",
+    not(any(
+        feature = "zephyr",
+        feature = "freertos",
+        all(target_arch = "arm", feature = "itm"),
+        feature = "rtt",
+        feature = "semihosting"
+    )),
+))]
+pub mod stm;
+
+#[cfg(all(
+    any(target_arch = "arm", target_arch = "riscv32", target_arch = "riscv64"),
+    target_os = "none",
+    feature = "ctf",
+    not(any(
+        feature = "zephyr",
+        feature = "freertos",
+        all(target_arch = "arm", feature = "itm"),
+        feature = "rtt",
+        feature = "semihosting",
+        all(target_arch = "arm", feature = "stm")
+    )),
+))]
+pub mod ctf;
+
+#[cfg(all(
+    target_os = "none",
+    feature = "defmt",
+    not(any(
+        feature = "zephyr",
+        feature = "freertos",
+        all(target_arch = "arm", feature = "itm"),
+        all(
+            any(target_arch = "arm", target_arch = "riscv32", target_arch = "riscv64"),
+            any(feature = "rtt", feature = "semihosting", feature = "ctf"),
+        ),
+        all(any(target_arch = "arm", target_arch = "aarch64"), feature = "stm"),
+    )),
+))]
+pub mod defmt;
+
+#[cfg(not(any(
+    windows,
+    target_os = "fuchsia",
+    target_os = "nto",
+    all(target_arch = "wasm32", feature = "wasm"),
+    all(target_os = "espidf", feature = "esp_log"),
+    all(
+        target_os = "none",
+        any(feature = "zephyr", feature = "freertos", feature = "defmt"),
+    ),
+    all(target_arch = "arm", target_os = "none", feature = "itm"),
+    all(
+        any(target_arch = "arm", target_arch = "riscv32", target_arch = "riscv64"),
+        target_os = "none",
+        any(feature = "rtt", feature = "semihosting", feature = "ctf"),
+    ),
+    all(
+        any(target_arch = "arm", target_arch = "aarch64"),
+        target_os = "none",
+        feature = "stm",
+    ),
+    all(
+        any(target_os = "linux", target_os = "android"),
+        any(
+            target_arch = "x86_64",
+            target_arch = "x86",
+            target_arch = "aarch64",
+            target_arch = "arm",
+            target_arch = "riscv64",
+            target_arch = "riscv32",
+            target_arch = "s390x",
+            target_arch = "loongarch64",
+            all(target_arch = "powerpc64", feature = "asm_experimental_arch"),
+            all(target_arch = "mips", feature = "asm_experimental_arch"),
+            all(target_arch = "mips64", feature = "asm_experimental_arch"),
+        ),
+    ),
+    all(target_os = "linux", any(feature = "lttng", feature = "user_events")),
+    all(target_os = "android", feature = "atrace"),
+    target_os = "macos",
+    all(target_vendor = "apple", feature = "os_signpost"),
+    all(
+        any(
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "illumos",
+            target_os = "solaris"
+        ),
+        target_arch = "x86_64",
+    ),
+)))]
 mod default;
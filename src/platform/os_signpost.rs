@@ -0,0 +1,218 @@
+//! os_signpost events on Apple platforms
+//!
+//! With the `os_signpost` feature on macOS, iOS, and the other Apple targets,
+//! each probe is emitted as a signpost, so Instruments can show it on a
+//! timeline. The provider is the log subsystem, in the `PointsOfInterest`
+//! category, the signpost is named for the probe, and its message holds the
+//! arguments. Probes with the `span = begin` and `span = end` options are the
+//! beginning and end of an interval instead, which Instruments pairs up by
+//! the probe name. These use the exclusive signpost ID, so only one interval
+//! of a given name can be open at a time for each provider.
+//!
+//! # Links:
+//!
+//! * <https://developer.apple.com/documentation/os/logging/recording_performance_data>
+//! * <https://github.com/apple-oss-distributions/libtrace/blob/main/os/signpost.h>
+
+//
+// DEVELOPER NOTES
+//
+// The `os_signpost_*` functions in <os/signpost.h> are macros around
+// `_os_signpost_emit_with_name_impl`, with the arguments packed like
+// `__builtin_os_log_format` does: a flags byte, a count byte, and then a
+// descriptor byte, size byte, and value for each argument. We only have
+// `isize` scalars, formatted with `%ld`.
+//
+// Both the name and the format have to be string literals in C, since
+// they're put in the `__oslogstring` section of the image, where the logging
+// system keeps them, and only their offsets are recorded with each signpost.
+// We do the same with statics.
+//
+// `os_signpost_enabled` needs macOS 10.14 or iOS 12.
+//
+
+use core::ffi::{c_char, c_void};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args: &[isize] = &[$(($arg) as isize,)*];
+        $crate::os_signpost_site!(SITE, $provider, $name, [$($opt)*], $($arg,)*);
+        if SITE.enabled() {
+            SITE.emit(args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::os_signpost_site!(SITE, $provider, $name, [$($opt)*], $($arg,)*);
+        let enabled = SITE.enabled();
+        if enabled {
+            SITE.emit(&[$(($arg) as isize,)*]);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        $crate::os_signpost_site!(SITE, $provider, $name, [$($opt)*],);
+        SITE.enabled()
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_signpost_site(
+    ($site:ident, $provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        const NAME: &str = concat!($crate::probe_name!($name), "\0");
+        const FORMAT: &str = concat!($crate::os_signpost_format!($($arg,)*), "\0");
+
+        #[link_section = "__TEXT,__oslogstring,cstring_literals"]
+        static NAME_STR: [u8; NAME.len()] = $crate::platform::os_signpost::cstr(NAME);
+        #[link_section = "__TEXT,__oslogstring,cstring_literals"]
+        static FORMAT_STR: [u8; FORMAT.len()] = $crate::platform::os_signpost::cstr(FORMAT);
+
+        static $site: $crate::platform::os_signpost::Site =
+            $crate::platform::os_signpost::Site::new(
+                concat!($crate::probe_provider!($provider), "\0"),
+                &NAME_STR,
+                &FORMAT_STR,
+                $crate::os_signpost_type!($($opt)*),
+            );
+    )
+);
+
+// The message format, e.g. "%ld %ld".
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_signpost_format(
+    (@ $arg:expr) => (" %ld");
+    () => ("");
+    ($arg:expr, $($rest:expr,)*) => (concat!("%ld" $(, $crate::os_signpost_format!(@ $rest))*));
+);
+
+// The `os_signpost_type_t` for the `span` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! os_signpost_type(
+    () => (0);
+    (span = begin $(, $($rest:tt)*)?) => (1);
+    (span = end $(, $($rest:tt)*)?) => (2);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::os_signpost_type!($($($rest)*)?));
+);
+
+/// Copies a string constant into an array for a `__oslogstring` static.
+pub const fn cstr<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut out = [0; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+/// OS_SIGNPOST_ID_EXCLUSIVE
+const ID_EXCLUSIVE: u64 = 0xeeee_b0b5_b2b2_eeee;
+
+/// The most arguments that are recorded, like `os_log`.
+const MAX_ARGS: usize = 32;
+
+extern "C" {
+    static __dso_handle: u8;
+
+    fn os_log_create(subsystem: *const c_char, category: *const c_char) -> *mut c_void;
+    fn os_signpost_enabled(log: *mut c_void) -> bool;
+    fn _os_signpost_emit_with_name_impl(
+        dso: *const c_void,
+        log: *mut c_void,
+        kind: u8,
+        id: u64,
+        name: *const c_char,
+        format: *const c_char,
+        buf: *mut u8,
+        size: u32,
+    );
+}
+
+pub struct Site {
+    provider: &'static str,
+    name: &'static [u8],
+    format: &'static [u8],
+    kind: u8,
+    log: AtomicPtr<c_void>,
+}
+
+impl Site {
+    pub const fn new(
+        provider: &'static str,
+        name: &'static [u8],
+        format: &'static [u8],
+        kind: u8,
+    ) -> Self {
+        Site {
+            provider,
+            name,
+            format,
+            kind,
+            log: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// The provider's log, created the first time the site is reached.
+    fn log(&self) -> *mut c_void {
+        let log = self.log.load(Ordering::Acquire);
+        if !log.is_null() {
+            return log;
+        }
+        let log = unsafe {
+            os_log_create(
+                self.provider.as_ptr().cast(),
+                b"PointsOfInterest\0".as_ptr().cast(),
+            )
+        };
+        self.log.store(log, Ordering::Release);
+        log
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        unsafe { os_signpost_enabled(self.log()) }
+    }
+
+    #[cold]
+    pub fn emit(&self, args: &[isize]) {
+        let args = &args[..args.len().min(MAX_ARGS)];
+        let mut buf = [0u8; 2 + MAX_ARGS * (2 + 8)];
+        let mut len = 2;
+        buf[1] = args.len() as u8;
+        for arg in args {
+            buf[len] = 0; // scalar
+            buf[len + 1] = 8;
+            buf[len + 2..len + 10].copy_from_slice(&(*arg as i64).to_ne_bytes());
+            len += 10;
+        }
+        unsafe {
+            _os_signpost_emit_with_name_impl(
+                &__dso_handle as *const u8 as *const c_void,
+                self.log(),
+                self.kind,
+                ID_EXCLUSIVE,
+                self.name.as_ptr().cast(),
+                self.format.as_ptr().cast(),
+                buf.as_mut_ptr(),
+                len as u32,
+            );
+        }
+    }
+}
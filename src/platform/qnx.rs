@@ -0,0 +1,111 @@
+//! QNX Neutrino user events
+//!
+//! On QNX, each probe is inserted into the instrumented kernel's trace as a
+//! user string event, with `trace_logf`, so it shows up in the System
+//! Profiler in Momentics, or `traceprinter`, with the kernel's events while
+//! `tracelogger` runs. The string is like `foo:loop 45 1035`, and the user
+//! event code is a 16-bit hash of the provider, modulo the 1024 user event
+//! codes, so a whole provider can be filtered on its code.
+//!
+//! The kernel drops user events unless it's logging them, which can't be
+//! checked here, so every probe is written, and `probe_lazy!` always evaluates
+//! its arguments, unless the program turns probes off with `control::kill`.
+//!
+//! # Links:
+//!
+//! * <https://www.qnx.com/developers/docs/8.0/com.qnx.doc.neutrino.sys_arch/topic/trace.html>
+//! * <https://www.qnx.com/developers/docs/8.0/com.qnx.doc.neutrino.lib_ref/topic/t/trace_logf.html>
+
+//
+// DEVELOPER NOTES
+//
+// `trace_logf` is a wrapper around `TraceEvent(_NTO_TRACE_INSERTUSRSTREVENT)`
+// in libc. The string is formatted on the stack, and truncated to its buffer,
+// so it's a single `%s` for the C formatter.
+//
+// The code hash is the same as the high half of the ID that bare-metal
+// backends use, from `rtt::id`.
+//
+
+use core::ffi::{c_char, c_int};
+use core::fmt::{self, Write};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const CODE: i32 = $crate::platform::qnx::code($crate::probe_provider!($provider));
+        let args = [$(($arg) as isize,)*];
+        if $crate::control::live() {
+            $crate::platform::qnx::write(
+                CODE,
+                concat!($crate::probe_provider!($provider), ":", $crate::probe_name!($name)),
+                &args,
+            );
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::control::live();
+        if enabled {
+            $crate::platform_probe!($provider, $name, [$($opt)*], $($arg,)*);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::control::live()
+    )
+);
+
+/// _NTO_TRACE_USERLAST + 1
+const USER_CODES: i32 = 1024;
+
+extern "C" {
+    fn trace_logf(code: c_int, format: *const c_char, ...) -> c_int;
+}
+
+/// The user event code for a provider.
+pub const fn code(provider: &str) -> i32 {
+    (crate::rtt::id(provider, "") >> 16) as i32 % USER_CODES
+}
+
+#[inline(never)]
+pub fn write(code: i32, label: &'static str, args: &[isize]) {
+    let mut event = Event {
+        buf: [0; 128],
+        len: 0,
+    };
+    let _ = event.write_str(label);
+    for arg in args {
+        let _ = write!(event, " {}", arg);
+    }
+    unsafe { trace_logf(code, b"%s\0".as_ptr().cast(), event.buf.as_ptr()) };
+}
+
+/// A NUL-terminated event string, truncated to fit.
+struct Event {
+    buf: [u8; 128],
+    len: usize,
+}
+
+impl Write for Event {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len() - 1 - self.len;
+        let n = s.len().min(room);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+//! SEGGER RTT up-channel records
+//!
+//! With the `rtt` feature on bare-metal ARM or RISC-V, each probe writes a
+//! compact binary record to an RTT up-channel named `probe`, so a J-Link, or
+//! any other debug probe that speaks RTT, can stream them out of the target's
+//! memory while it runs, without stalling it like semihosting would.
+//!
+//! Each record is a 32-bit little-endian ID, with a 16-bit hash of the
+//! provider in the high half and a 16-bit hash of the name in the low half,
+//! followed by an 8-bit count of the arguments, and then each argument as a
+//! zigzag-encoded LEB128 number. The hashes are the 32-bit FNV-1a hash of the
+//! string, folded to 16 bits by XOR of its halves. When the channel doesn't
+//! have room for a whole record, the record is dropped.
+//!
+//! This crate defines the RTT control block, `_SEGGER_RTT`, with only this
+//! channel, so it can't be combined with another RTT implementation. The
+//! channel's buffer is 1024 bytes by default, or `PROBE_RTT_BUFFER_SIZE` from
+//! the environment of the build. RTT has no way to tell if anything is
+//! reading, so every probe is written, and `probe_lazy!` always evaluates its
+//! arguments, unless the program turns probes off with `control::kill`.
+//!
+//! # Links:
+//!
+//! * <https://www.segger.com/products/debug-probes/j-link/technology/about-real-time-transfer/>
+//! * <https://wiki.segger.com/RTT>
+
+//
+// DEVELOPER NOTES
+//
+// The control block layout is what the host searches RAM for: the ID string,
+// the number of up and down channels, and then their descriptors. The host
+// only reads from the up-channel at its read offset, and we only write at the
+// write offset, so the buffer is a single-producer ring as long as interrupts
+// are masked while a record is written.
+//
+// The ID is written when the first probe fires, not in the static initializer,
+// so the host can't find a copy of it in flash, or in stale RAM after a reset.
+//
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const ID: u32 = $crate::rtt::id($crate::probe_provider!($provider), $crate::probe_name!($name));
+        let args = [$(($arg) as isize,)*];
+        if $crate::control::live() {
+            $crate::platform::rtt::write(ID, &args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::control::live();
+        if enabled {
+            $crate::platform_probe!($provider, $name, [$($opt)*], $($arg,)*);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::control::live()
+    )
+);
+
+/// The channel's buffer size, from `PROBE_RTT_BUFFER_SIZE` at build time.
+const BUFFER_SIZE: usize = parse_size(env!("PROBE_RTT_BUFFER_SIZE"));
+
+/// The channel's name, `rtt::CHANNEL` with a NUL.
+const NAME: &[u8] = b"probe\0";
+
+/// SEGGER_RTT_MODE_NO_BLOCK_SKIP
+const MODE_NO_BLOCK_SKIP: u32 = 0;
+
+// SEGGER_RTT_BUFFER_UP
+#[repr(C)]
+struct Channel {
+    name: *const u8,
+    buffer: *mut u8,
+    size: u32,
+    write: u32,
+    read: u32,
+    flags: u32,
+}
+
+// SEGGER_RTT_CB
+#[repr(C)]
+struct ControlBlock {
+    id: [u8; 16],
+    max_up: i32,
+    max_down: i32,
+    up: Channel,
+}
+
+struct Rtt(UnsafeCell<ControlBlock>);
+
+// The control block is only changed with interrupts masked.
+unsafe impl Sync for Rtt {}
+
+struct Buffer(UnsafeCell<[u8; BUFFER_SIZE]>);
+
+unsafe impl Sync for Buffer {}
+
+static BUFFER: Buffer = Buffer(UnsafeCell::new([0; BUFFER_SIZE]));
+
+#[no_mangle]
+static _SEGGER_RTT: Rtt = Rtt(UnsafeCell::new(ControlBlock {
+    id: [0; 16],
+    max_up: 1,
+    max_down: 0,
+    up: Channel {
+        name: NAME.as_ptr(),
+        buffer: BUFFER.0.get().cast(),
+        size: BUFFER_SIZE as u32,
+        write: 0,
+        read: 0,
+        flags: MODE_NO_BLOCK_SKIP,
+    },
+}));
+
+const fn parse_size(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut size = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        size = size * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    size
+}
+
+#[inline(never)]
+pub fn write(id: u32, args: &[isize]) {
+    let args = &args[..args.len().min(u8::MAX as usize)];
+    let len = 5 + args.iter().map(|&arg| varint(arg).count()).sum::<usize>();
+    critical_section(|| unsafe {
+        let cb = _SEGGER_RTT.0.get();
+        if (*cb).id[0] == 0 {
+            init(cb);
+        }
+        let channel = ptr::addr_of_mut!((*cb).up);
+        let write = ptr::read_volatile(ptr::addr_of!((*channel).write)) as usize;
+        let read = ptr::read_volatile(ptr::addr_of!((*channel).read)) as usize;
+        let free = if read > write {
+            read - write - 1
+        } else {
+            BUFFER_SIZE - write + read - 1
+        };
+        if len > free {
+            return;
+        }
+
+        let buffer = BUFFER.0.get().cast::<u8>();
+        let mut offset = write;
+        let bytes = id.to_le_bytes().into_iter().chain([args.len() as u8]);
+        for byte in bytes.chain(args.iter().flat_map(|&arg| varint(arg))) {
+            ptr::write_volatile(buffer.add(offset), byte);
+            offset = (offset + 1) % BUFFER_SIZE;
+        }
+        // The data must be in memory before the host can see the new offset.
+        fence(Ordering::SeqCst);
+        ptr::write_volatile(ptr::addr_of_mut!((*channel).write), offset as u32);
+    });
+}
+
+/// The zigzag LEB128 bytes of an argument.
+fn varint(arg: isize) -> impl Iterator<Item = u8> {
+    let mut value = ((arg << 1) ^ (arg >> (isize::BITS - 1))) as usize;
+    let mut done = false;
+    core::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let byte = value as u8 & 0x7f;
+        value >>= 7;
+        done = value == 0;
+        Some(if done { byte } else { byte | 0x80 })
+    })
+}
+
+/// Writes the control block ID, back to front so the host never finds a
+/// partial match, as SEGGER's implementation does.
+#[cold]
+unsafe fn init(cb: *mut ControlBlock) {
+    let id = b"SEGGER RTT\0\0\0\0\0\0";
+    for i in (0..id.len()).rev() {
+        ptr::write_volatile(ptr::addr_of_mut!((*cb).id[i]), id[i]);
+    }
+    fence(Ordering::SeqCst);
+}
+
+#[cfg(target_arch = "arm")]
+fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let primask: u32;
+    unsafe { core::arch::asm!("mrs {}, PRIMASK", "cpsid i", out(reg) primask, options(nostack)) };
+    let result = f();
+    // Only unmask interrupts if they weren't already masked.
+    if primask & 1 == 0 {
+        unsafe { core::arch::asm!("cpsie i", options(nostack)) };
+    }
+    result
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let mstatus: usize;
+    // Clear MIE, and keep its old value.
+    unsafe { core::arch::asm!("csrrci {}, mstatus, 8", out(reg) mstatus, options(nostack)) };
+    let result = f();
+    if mstatus & 8 != 0 {
+        unsafe { core::arch::asm!("csrsi mstatus, 8", options(nostack)) };
+    }
+    result
+}
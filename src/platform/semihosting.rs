@@ -0,0 +1,133 @@
+//! Semihosting console output
+//!
+//! With the `semihosting` feature on bare-metal Cortex-M or RISC-V, each probe
+//! is written to the debugger's console through a semihosting `SYS_WRITE0`
+//! call, as a line like `foo:loop 45 1035`, for early bring-up when neither
+//! ITM nor RTT is available.
+//!
+//! Each call halts the target while the debugger handles it, so this is slow,
+//! and without a debugger attached to handle it, the call is a fault instead.
+//! Every probe is written, and `probe_lazy!` always evaluates its arguments,
+//! unless the program turns probes off with `control::kill`.
+//!
+//! # Links:
+//!
+//! * <https://github.com/ARM-software/abi-aa/blob/main/semihosting/semihosting.rst>
+//! * <https://github.com/riscv-non-isa/riscv-semihosting/blob/main/riscv-semihosting.adoc>
+
+//
+// DEVELOPER NOTES
+//
+// The line is formatted on the stack, and truncated to its buffer, so the
+// debugger only has to be stopped once per probe.
+//
+// On Cortex-M, the semihosting trap is `bkpt 0xab`, unlike the `svc` of the
+// A and R profiles, which aren't supported here.
+//
+// On RISC-V, the semihosting trap is an `ebreak` between two particular
+// shifts of `x0`, which must all be uncompressed and in the same page, hence
+// the `norvc` and alignment.
+//
+
+use core::fmt::{self, Write};
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args = [$(($arg) as isize,)*];
+        if $crate::control::live() {
+            $crate::platform::semihosting::write(
+                concat!($crate::probe_provider!($provider), ":", $crate::probe_name!($name)),
+                &args,
+            );
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::control::live();
+        if enabled {
+            $crate::platform_probe!($provider, $name, [$($opt)*], $($arg,)*);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::control::live()
+    )
+);
+
+/// SYS_WRITE0
+const SYS_WRITE0: usize = 0x04;
+
+#[inline(never)]
+pub fn write(label: &'static str, args: &[isize]) {
+    let mut line = Line {
+        buf: [0; 128],
+        len: 0,
+    };
+    let _ = line.write_str(label);
+    for arg in args {
+        let _ = write!(line, " {}", arg);
+    }
+    // There's always room for the newline, before the NUL.
+    line.buf[line.len] = b'\n';
+    unsafe { syscall(SYS_WRITE0, line.buf.as_ptr() as usize) };
+}
+
+/// A NUL-terminated line, truncated to fit with its newline.
+struct Line {
+    buf: [u8; 128],
+    len: usize,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len() - 2 - self.len;
+        let n = s.len().min(room);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "arm")]
+unsafe fn syscall(op: usize, param: usize) -> usize {
+    let result;
+    core::arch::asm!(
+        "bkpt #0xab",
+        inout("r0") op => result,
+        in("r1") param,
+        options(nostack, preserves_flags),
+    );
+    result
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+unsafe fn syscall(op: usize, param: usize) -> usize {
+    let result;
+    core::arch::asm!(
+        ".balign 16",
+        ".option push",
+        ".option norvc",
+        "slli x0, x0, 0x1f",
+        "ebreak",
+        "srai x0, x0, 7",
+        ".option pop",
+        inout("a0") op => result,
+        in("a1") param,
+        options(nostack, preserves_flags),
+    );
+    result
+}
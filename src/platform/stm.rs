@@ -0,0 +1,148 @@
+//! ARM CoreSight STM stimulus records
+//!
+//! With the `stm` feature on bare-metal Cortex-M or AArch64, each probe writes
+//! a record to the extended stimulus ports of a System Trace Macrocell, so the
+//! probes are in the hardware trace with everything else the SoC traces. The
+//! address of the stimulus ports has to be given in `PROBE_STM_BASE` when the
+//! crate is built, since it depends on the SoC.
+//!
+//! Each provider gets a channel of its own, the 16-bit hash of its name
+//! modulo the number of channels, which is 65536 by default, or
+//! `PROBE_STM_CHANNELS` from the environment of the build. A record starts
+//! with a 32-bit ID as timestamped, marked data, with the hash of the provider
+//! in the high half and a hash of the name in the low half, as for ITM. Then
+//! each argument follows as data of the target's pointer size. The hashes are
+//! the 32-bit FNV-1a hash of the string, folded to 16 bits by XOR of its
+//! halves.
+//!
+//! Writes to a channel are dropped by the STM unless the channel is enabled,
+//! which is up to the debugger, so every probe is written, and `probe_lazy!`
+//! always evaluates its arguments, unless the program turns probes off with
+//! `control::kill`.
+//!
+//! # Links:
+//!
+//! * <https://developer.arm.com/documentation/ddi0444/b/> (see "Programmers model")
+//! * <https://developer.arm.com/documentation/ihi0054/b/> (STPv2)
+
+//
+// DEVELOPER NOTES
+//
+// Each channel has 256 bytes of stimulus registers, where the offset picks
+// the kind of packet the STM generates. We use guaranteed transactions, which
+// stall rather than drop data when the STM is busy, so a record is never
+// missing arguments, and interrupts are masked so that records on a channel
+// don't interleave. For 32-bit ARM, that's only written for Cortex-M, since
+// we can't tell it apart from the A and R profiles with `cfg`.
+//
+
+use core::ptr;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        const ID: u32 = $crate::platform::stm::id($crate::probe_provider!($provider), $crate::probe_name!($name));
+        let args = [$(($arg) as isize,)*];
+        if $crate::control::live() {
+            $crate::platform::stm::write(ID, &args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::control::live();
+        if enabled {
+            $crate::platform_probe!($provider, $name, [$($opt)*], $($arg,)*);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::control::live()
+    )
+);
+
+/// The extended stimulus ports, from `PROBE_STM_BASE` at build time.
+const BASE: usize = parse(env!("PROBE_STM_BASE"));
+
+/// The number of channels, from `PROBE_STM_CHANNELS` at build time.
+const CHANNELS: usize = parse(env!("PROBE_STM_CHANNELS"));
+
+/// The size of each channel's stimulus registers.
+const CHANNEL_SIZE: usize = 0x100;
+
+/// Guaranteed data, marked and timestamped.
+const G_DMTS: usize = 0x00;
+
+/// Guaranteed data.
+const G_D: usize = 0x18;
+
+const fn parse(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    value
+}
+
+/// The record ID for a probe, from the hashes of its provider and name.
+pub const fn id(provider: &str, name: &str) -> u32 {
+    (hash(provider) as u32) << 16 | hash(name) as u32
+}
+
+/// FNV-1a, folded to 16 bits.
+const fn hash(s: &str) -> u16 {
+    let bytes = s.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    (hash >> 16) as u16 ^ hash as u16
+}
+
+#[inline(never)]
+pub fn write(id: u32, args: &[isize]) {
+    let channel = BASE + (id >> 16) as usize % CHANNELS * CHANNEL_SIZE;
+    critical_section(|| unsafe {
+        ptr::write_volatile((channel + G_DMTS) as *mut u32, id);
+        for &arg in args {
+            ptr::write_volatile((channel + G_D) as *mut isize, arg);
+        }
+    });
+}
+
+#[cfg(target_arch = "arm")]
+fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let primask: u32;
+    unsafe { core::arch::asm!("mrs {}, PRIMASK", "cpsid i", out(reg) primask, options(nostack)) };
+    let result = f();
+    // Only unmask interrupts if they weren't already masked.
+    if primask & 1 == 0 {
+        unsafe { core::arch::asm!("cpsie i", options(nostack)) };
+    }
+    result
+}
+
+#[cfg(target_arch = "aarch64")]
+fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let daif: u64;
+    unsafe { core::arch::asm!("mrs {}, DAIF", "msr DAIFSet, #2", out(reg) daif, options(nostack)) };
+    let result = f();
+    // Restore the IRQ mask as it was.
+    unsafe { core::arch::asm!("msr DAIF, {}", in(reg) daif, options(nostack)) };
+    result
+}
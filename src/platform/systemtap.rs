@@ -20,12 +20,13 @@
 //
 // DEVELOPER NOTES
 //
-// Arguments are currently type-casted as isize for the supposed maximum
-// register size, whereas SystemTap's long is i64 no matter the architecture.
-// However, if we could figure out types here, they could be annotated more
-// specifically, for example an argstr of "4@$0 -2@$1" indicates u32 and i16
-// respectively. Any pointer would be fine too, like *c_char, simply 4@ or 8@
-// for target_word_size.
+// Arguments are type-casted as isize for the supposed maximum register size,
+// whereas SystemTap's long is i64 no matter the architecture, unless they're
+// marked with an integer kind like `@u32`, which comes here in the hidden
+// `__operands` option. Those are annotated more specifically, for example an
+// argstr of "4@%rdi -2@%rsi" indicates u32 and i16 respectively, and tools
+// only read that much of the register. Pointers, like `@str` and `@cstr`, are
+// simply 4@ or 8@ for target_word_size.
 //
 // The macros in sdt.h don't know types either, so they split each argument
 // into two asm inputs, roughly:
@@ -46,71 +47,539 @@
 // to use positional `{}@{}` with a `const` operand for the size, but calling
 // things like `mem::size_of::<T>()` is still hard when we don't know `T`.
 //
-// FIXME semaphores - SDT can define a short* that debuggers will increment when
-// they attach, and decrement on detach. Thus a `probe_enabled!(provider,name)`
-// could return if that value != 0, to be used similarly to log_enabled!(). It
-// is difficult with mangling and macro hygene to connect two `probe!` and
-// `probe_enabled!` calls to the same symbol, unless we forced `#[no_mangle]`.
-// For now, we only use semaphores in `probe_lazy!` to skip argument evaluation
-// when there's nobody attached to see the probe.
+// Each argument is a register operand, so its argstr is just the size and the
+// register name as the assembler prints it. The names are what SystemTap and
+// libbpf expect to parse for each architecture: AT&T `%rdi` on x86, `x0` on
+// AArch64, and ABI names like `a0` on RISC-V. RISC-V probes are fine with
+// linker relaxation, since the note only refers to the probe address with an
+// absolute relocation, and a compressed `c.nop` is still a valid uprobe site.
+//
+// PowerPC prints registers as bare numbers, which would be ambiguous with an
+// immediate, so they get an explicit `%r` prefix, e.g. `-8@%r3`. Both
+// SystemTap and libbpf accept that, as GCC's `-mregnames` would write it. The
+// s390x assembler already names registers like `%r2`, and MIPS registers are
+// numbered like `$4`, just as GCC writes them for <sys/sdt.h>. LoongArch uses
+// ABI names with a `$` prefix, like `$a0`, which is also what its SystemTap
+// port parses. The MIPS probe `nop` can't be moved into a delay slot, because
+// LLVM's assembler doesn't reorder instructions.
+//
+// On 32-bit ARM, the probe is a wide `nop.w`, so in Thumb mode the site is
+// one Thumb-2 instruction of the same size as in ARM mode, and a tracer is
+// free to patch it with either a 16-bit or 32-bit breakpoint. Stable Rust
+// can't `cfg` on Thumb mode, but that's fine since the assembler ignores the
+// `.w` qualifier in ARM mode. The probe address is a plain label, not a
+// function symbol, so it never gets the Thumb bit set, and debuggers rely on
+// the `$a`/`$t` mapping symbols to know which mode to use.
+//
+// The note isn't loaded, so its addresses are always absolute link-time
+// addresses, even for position-independent code. That matters for the
+// semaphore on i686, where code can only reach it relative to the GOT, but the
+// `sym` operand in the note is still a plain `R_386_32` relocation, and
+// arguments are 4-byte registers like `%eax`.
+//
+// Each probe has a `u16` semaphore in the `.probes` section, and every note
+// for the probe records its address. Tracers increment the semaphores of a
+// probe while they're attached to it, and decrement them when they detach,
+// so `probe_lazy!` only evaluates its arguments and reaches the `NOP` while
+// some tracer is watching. This is the `_ENABLED()` check of <sys/sdt.h>, and
+// SystemTap, bpftrace, and GDB all maintain it.
+//
+// Every operand needs a register of its own, so a probe with more than 12 is
+// a compile error that names it, rather than a register allocation failure,
+// though `probe!` already puts the rest in a record. 32-bit x86 only has the
+// registers for 6, or 7 without a frame pointer, so its limit is 6, and the
+// record doesn't help there.
+//
+// An operand with a raw descriptor from `@raw` isn't an asm input at all, so
+// its descriptor can name any register or location, and it doesn't take up a
+// register, though it still counts towards the limit. The descriptors are
+// split from the register operands before the asm, so the positional `{}` in
+// the argstr still line up with the inputs.
+//
+// With `operands = memory`, the operands are cast `as isize` into an array
+// on the stack, and the asm only has a pointer to it, as the named `base`
+// operand. Each argstr is then a word at an offset from it, in the syntax of
+// a memory operand, like `-8@16(%rax)`, or `[x0, 16]` on AArch64, which the
+// tools read from memory after they've read the register. Since every value
+// fills its word, sign- or zero-extended by the cast, a word with the type's
+// signedness reads back the same value on either endianness, though a narrow
+// size wouldn't on big-endian targets.
+//
+// SDT doesn't have spans, so a probe with the `span` option is named for its
+// end, like `loop__begin`, with a semaphore of its own. The name is a token
+// tree like `(loop __begin)` from there on, which is only ever stringified.
+//
+// Named arguments add a second note, of type 1 with the owner "probe", in
+// `.note.probe`, with the provider, the name, and the names of the operands,
+// separated by spaces, where unnamed ones are `_`. Tools that don't know the
+// note skip it, and those that do match it to the SDT notes by the provider
+// and name. Each site has its own, like the SDT notes, but they're only
+// strings, so they don't need to be at any address. A probe's format is in a
+// note of type 2, and its provider's version in one of type 3, in the same
+// way.
+//
+// The file and line of each site are in a note of type 4, in the same asm as
+// its SDT note, with the same address, so tools match them by that, then the
+// file and the line as strings. It's always there, since it's only in the
+// file, not loaded with the program.
+//
+// The semaphore is defined in the asm, named for the probe like
+// `_.stapsdt.sem.foo.loop`, in a COMDAT group like `_.stapsdt.base`, so all
+// the sites of a probe share it, including `probe!` sites and the
+// `probe_enabled!` checks, which define it without a note of their own. Rust
+// reads it as an extern static, since it's written from outside the process.
+// `probe_semaphore!` hands out the same address, so the program can also set
+// it, with atomic updates, since it might race with its own threads.
+//
+// Behind the semaphore, a lazy probe's arguments and site are in a closure
+// that's called through `platform::cold`, which is never inlined, so the
+// caller only has the load and branch, and doesn't keep the arguments live in
+// registers around it. The site is still in the closure's own copy of the
+// code, with its note, which is all a tracer needs.
 //
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! platform_probe(
-    ($provider:ident, $name:ident, $($arg:expr,)*) => ({
-        $crate::sdt!([sym 0], $provider, $name, $($arg,)*);
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        $crate::sdt_span!(sdt_probe, [$($opt)*], [$($opt)*], $provider, $name, $($arg,)*)
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::sdt_operands!([$($opt)*], $provider, $name, $($arg,)*);
+        $crate::sdt_names!([$($opt)*], $provider, $name);
+        $crate::sdt_format!([$($opt)*], $provider, $name);
+        $crate::sdt_version!($provider, $name);
     })
 );
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! platform_probe_lazy(
-    ($provider:ident, $name:ident, $($arg:expr,)*) => ({
-        #[link_section = ".probes"]
-        static mut SEMAPHORE: u16 = 0;
-        let enabled = unsafe { ::core::ptr::read_volatile(&SEMAPHORE) } != 0;
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        $crate::sdt_span!(sdt_probe_lazy, [$($opt)*], [$($opt)*], $provider, $name, $($arg,)*)
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::sdt_enabled!($provider, $name);
         if enabled {
-            $crate::sdt!([sym "{}" SEMAPHORE], $provider, $name, $($arg,)*);
+            $crate::platform::cold(|| {
+                $crate::sdt_operands!([$($opt)*], $provider, $name, $($arg,)*);
+                $crate::sdt_names!([$($opt)*], $provider, $name);
+                $crate::sdt_format!([$($opt)*], $provider, $name);
+                $crate::sdt_version!($provider, $name);
+            });
         }
         enabled
     })
 );
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::sdt_span!(sdt_probe_enabled, [$($opt)*], [$($opt)*], $provider, $name)
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        // Define the semaphore, in case there are no sites of this probe.
+        unsafe {
+            ::core::arch::asm!(
+                $crate::sdt_semaphore!($provider, $name),
+                options(nomem, nostack, preserves_flags),
+            )
+        };
+        $crate::sdt_enabled!($provider, $name)
+    })
+);
+
+#[cfg(feature = "use_std")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_semaphore(
+    ($provider:tt, $name:tt) => ({
+        // Define the semaphore, in case there are no sites of this probe.
+        unsafe {
+            ::core::arch::asm!(
+                $crate::sdt_semaphore!($provider, $name),
+                options(nomem, nostack, preserves_flags),
+            )
+        };
+        ::core::option::Option::Some(unsafe { $crate::semaphore::Semaphore::new($crate::sdt_semaphore_addr!($provider, $name)) })
+    })
+);
+
+// The name of a probe, or of the end of a span, from the `span` option, which
+// is a probe of its own, like `loop__begin`, since SDT doesn't have spans.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_span(
+    ($target:ident, [span = begin $(, $($rest:tt)*)?], $opts:tt,
+        $provider:tt, $name:tt $($probe:tt)*
+    ) => (
+        $crate::$target!($provider, ($name __begin), $opts $($probe)*)
+    );
+    ($target:ident, [span = end $(, $($rest:tt)*)?], $opts:tt,
+        $provider:tt, $name:tt $($probe:tt)*
+    ) => (
+        $crate::$target!($provider, ($name __end), $opts $($probe)*)
+    );
+    ($target:ident, [$key:ident = $value:expr $(, $($rest:tt)*)?], $($probe:tt)*) => (
+        $crate::sdt_span!($target, [$($($rest)*)?], $($probe)*)
+    );
+    ($target:ident, [], $opts:tt, $provider:tt, $name:tt $($probe:tt)*) => (
+        $crate::$target!($provider, $name, $opts $($probe)*)
+    );
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_name(
+    (($name:tt $suffix:ident)) => (concat!($crate::probe_name!($name), stringify!($suffix)));
+    ($name:tt) => ($crate::probe_name!($name));
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_enabled(
+    ($provider:tt, $name:tt) => (unsafe {
+        ::core::ptr::read_volatile($crate::sdt_semaphore_addr!($provider, $name)) != 0
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_semaphore_addr(
+    ($provider:tt, $name:tt) => ({
+        extern "C" {
+            #[link_name = concat!(
+                "_.stapsdt.sem.", $crate::probe_provider!($provider), ".", $crate::sdt_name!($name)
+            )]
+            static SEMAPHORE: u16;
+        }
+        unsafe { ::core::ptr::addr_of!(SEMAPHORE) }
+    })
+);
+
+// The definition of a probe's semaphore, once per object.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_semaphore(
+    ($provider:tt, $name:tt) => (concat!(r#"
+.ifndef "_.stapsdt.sem."#, $crate::probe_provider!($provider), ".", $crate::sdt_name!($name), r#""
+        .pushsection .probes,"awG","progbits","_.stapsdt.sem."#,
+            $crate::probe_provider!($provider), ".", $crate::sdt_name!($name), r#"",comdat
+        .balign 2
+        .weak "_.stapsdt.sem."#, $crate::probe_provider!($provider), ".", $crate::sdt_name!($name), r#""
+        .hidden "_.stapsdt.sem."#, $crate::probe_provider!($provider), ".", $crate::sdt_name!($name), r#""
+"_.stapsdt.sem."#, $crate::probe_provider!($provider), ".", $crate::sdt_name!($name), r#"": .2byte 0
+        .size "_.stapsdt.sem."#, $crate::probe_provider!($provider), ".", $crate::sdt_name!($name), r#"", 2
+        .popsection
+.endif"#));
+);
+
+// The types of the operands, from the `__operands` option of arguments with
+// kinds, or `isize` for all of them, and where they're passed, from the
+// `operands` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_operands(
+    ([$($opt:tt)*], $provider:tt, $name:tt, $($arg:expr,)*) => (
+        $crate::sdt_operands!(@scan [$($opt)*], [], registers, $provider, $name, $($arg,)*)
+    );
+    (@scan [__operands = [$($ty:tt),*] $(, $($rest:tt)*)?], [], $mode:ident, $($probe:tt)*) => (
+        $crate::sdt_operands!(@scan [$($($rest)*)?], [$($ty)*], $mode, $($probe)*)
+    );
+    (@scan [operands = $mode:ident $(, $($rest:tt)*)?], $types:tt, $default:ident, $($probe:tt)*) => (
+        $crate::sdt_operands!(@scan [$($($rest)*)?], $types, $mode, $($probe)*)
+    );
+    (@scan [$key:ident = $value:expr $(, $($rest:tt)*)?], $($probe:tt)*) => (
+        $crate::sdt_operands!(@scan [$($($rest)*)?], $($probe)*)
+    );
+    (@scan [], [], $mode:ident, $provider:tt, $name:tt, $($arg:expr,)*) => (
+        $crate::sdt!($mode, $provider, $name, $([isize] $arg,)*)
+    );
+    (@scan [], [$($ty:tt)*], $mode:ident, $provider:tt, $name:tt, $($arg:expr,)*) => (
+        $crate::sdt!($mode, $provider, $name, $([$ty] $arg,)*)
+    );
+);
+
+// A note with the names of the operands, from the `__names` option of named
+// arguments, if there are any.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_names(
+    ([__names = $names:expr $(, $($rest:tt)*)?], $provider:tt, $name:tt) => (unsafe {
+        ::core::arch::asm!(concat!(r#"
+        .pushsection .note.probe,"?","note"
+        .balign 4
+        .4byte 992f-991f, 994f-993f, 1
+991:    .asciz "probe"
+992:    .balign 4
+993:    .asciz ""#, $crate::probe_provider!($provider), r#""
+        .asciz ""#, $crate::sdt_name!($name), r#""
+        .asciz ""#, $names, r#""
+994:    .balign 4
+        .popsection"#),
+            options(nomem, nostack, preserves_flags),
+        )
+    });
+    ([$key:ident = $value:expr $(, $($rest:tt)*)?], $($probe:tt)*) => (
+        $crate::sdt_names!([$($($rest)*)?], $($probe)*)
+    );
+    ([], $provider:tt, $name:tt) => ();
+);
+
+// A note with the format of the probe, from its `format` option, if any.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_format(
+    ([format = $format:literal $(, $($rest:tt)*)?], $provider:tt, $name:tt) => (unsafe {
+        ::core::arch::asm!(concat!(r#"
+        .pushsection .note.probe,"?","note"
+        .balign 4
+        .4byte 992f-991f, 994f-993f, 2
+991:    .asciz "probe"
+992:    .balign 4
+993:    .asciz ""#, $crate::probe_provider!($provider), r#""
+        .asciz ""#, $crate::sdt_name!($name), r#""
+        .asciz ""#, $format, r#""
+994:    .balign 4
+        .popsection"#),
+            options(nomem, nostack, preserves_flags),
+        )
+    });
+    ([$key:ident = $value:expr $(, $($rest:tt)*)?], $($probe:tt)*) => (
+        $crate::sdt_format!([$($($rest)*)?], $($probe)*)
+    );
+    ([], $provider:tt, $name:tt) => ();
+);
+
+// A note with the version of the provider, from a provider like `foo@2`, if
+// it has one.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_version(
+    ([$provider:tt @ $version:literal], $name:tt) => (unsafe {
+        ::core::arch::asm!(concat!(r#"
+        .pushsection .note.probe,"?","note"
+        .balign 4
+        .4byte 992f-991f, 994f-993f, 3
+991:    .asciz "probe"
+992:    .balign 4
+993:    .asciz ""#, $crate::probe_provider!($provider), r#""
+        .asciz ""#, $crate::sdt_name!($name), r#""
+        .asciz ""#, $version, r#""
+994:    .balign 4
+        .popsection"#),
+            options(nomem, nostack, preserves_flags),
+        )
+    });
+    ([$provider:tt], $name:tt) => ($crate::sdt_version!($provider, $name));
+    ($provider:tt, $name:tt) => ();
+);
+
+// The argstr prefix of an operand of a type, in a register of a size.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_argstr(
+    ($size:literal, u8) => ("1@");
+    ($size:literal, i8) => ("-1@");
+    ($size:literal, u16) => ("2@");
+    ($size:literal, i16) => ("-2@");
+    ($size:literal, u32) => ("4@");
+    ($size:literal, i32) => ("-4@");
+    ($size:literal, f32) => ("4@");
+    (8, f64) => ("8@");
+    (8, u64) => ("8@");
+    (8, i64) => ("-8@");
+    (8, usize) => ("8@");
+    (8, isize) => ("-8@");
+    (4, usize) => ("4@");
+    (4, u64) => ("4@");
+    (4, f64) => ("4@");
+    (4, $ty:ident) => ("-4@");
+);
+
+// The argstr prefix of an operand of a type, in a word of memory of a size,
+// which it was cast to as `isize`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_mem_argstr(
+    (4, i8) => ("-4@");
+    (4, i16) => ("-4@");
+    (4, i32) => ("-4@");
+    (4, isize) => ("-4@");
+    (4, $ty:ident) => ("4@");
+    (8, i8) => ("-8@");
+    (8, i16) => ("-8@");
+    (8, i32) => ("-8@");
+    (8, i64) => ("-8@");
+    (8, isize) => ("-8@");
+    (8, $ty:ident) => ("8@");
+);
+
+// The location of an operand in memory, at an offset from the `base` register,
+// as `off(base)` for `()`, or as `[base, off]` with a prefix for the offset.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_mem(
+    ((), $reg:literal, $off:tt) => (concat!($off, "(", $reg, "{base})"));
+    ([$prefix:literal], $reg:literal, $off:tt) => (concat!("[", $reg, "{base}, ", $prefix, $off, "]"));
+);
+
+// A compile error for a probe with more operands than there are registers for.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! sdt_limit(
+    (12, $provider:tt, $name:tt,
+        [$a:tt $b:tt $c:tt $d:tt $e:tt $f:tt $g:tt $h:tt $i:tt $j:tt $k:tt $l:tt $m:tt $($n:tt)*]
+    ) => (
+        compile_error!(concat!("too many arguments for SDT probe ",
+            $crate::probe_provider!($provider), ":", $crate::sdt_name!($name), ", the limit is 12"));
+    );
+    (6, $provider:tt, $name:tt, [$a:tt $b:tt $c:tt $d:tt $e:tt $f:tt $g:tt $($n:tt)*]) => (
+        compile_error!(concat!("too many arguments for SDT probe ",
+            $crate::probe_provider!($provider), ":", $crate::sdt_name!($name), ", the limit is 6 on 32-bit x86"));
+    );
+    ($limit:literal, $provider:tt, $name:tt, $types:tt) => ();
+);
+
 // Since we can't #include <sys/sdt.h>, we have to reinvent it...
 // but once you take out the C/C++ type handling, there's not a lot to it.
 #[doc(hidden)]
 #[macro_export]
 macro_rules! sdt(
-    ([sym $symstr:literal $($sym:ident)?],
-        $provider:ident, $name:ident, $($arg:expr,)*
-    ) => (
+    ($mode:ident, $provider:tt, $name:tt, $([$ty:tt] $arg:expr,)*) => (
         #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
-        $crate::sdt!([sym $symstr $($sym)?, opt att_syntax],
-            $provider, $name, $($arg,)*);
+        $crate::sdt!([nop "nop", reg "", mem (), opt att_syntax],
+            $mode, $provider, $name, $([$ty] $arg,)*);
+
+        #[cfg(target_arch = "aarch64")]
+        $crate::sdt!([nop "nop", reg "", mem [""], opt],
+            $mode, $provider, $name, $([$ty] $arg,)*);
+
+        #[cfg(target_arch = "arm")]
+        $crate::sdt!([nop "nop.w", reg "", mem ["#"], opt],
+            $mode, $provider, $name, $([$ty] $arg,)*);
+
+        #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+        $crate::sdt!([nop "nop", reg "", mem (), opt],
+            $mode, $provider, $name, $([$ty] $arg,)*);
+
+        #[cfg(target_arch = "powerpc64")]
+        $crate::sdt!([nop "nop", reg "%r", mem (), opt],
+            $mode, $provider, $name, $([$ty] $arg,)*);
+
+        #[cfg(target_arch = "s390x")]
+        $crate::sdt!([nop "nop", reg "", mem (), opt],
+            $mode, $provider, $name, $([$ty] $arg,)*);
+
+        #[cfg(target_arch = "loongarch64")]
+        $crate::sdt!([nop "nop", reg "", mem (), opt],
+            $mode, $provider, $name, $([$ty] $arg,)*);
 
-        #[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
-        $crate::sdt!([sym $symstr $($sym)?, opt],
-            $provider, $name, $($arg,)*);
+        #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+        $crate::sdt!([nop "nop", reg "", mem (), opt],
+            $mode, $provider, $name, $([$ty] $arg,)*);
     );
 
-    ([sym $symstr:literal $($sym:ident)?, opt $($opt:ident)?],
-        $provider:ident, $name:ident, $($arg1:expr, $($arg:expr,)*)?
+    ([nop $nop:literal, reg $reg:literal, mem $mem:tt, opt $($opt:ident)?],
+        registers, $provider:tt, $name:tt, $([$ty:tt] $arg:expr,)*
     ) => (
+        #[cfg(target_arch = "x86")]
+        $crate::sdt_limit!(6, $provider, $name, [$($ty)*]);
+
+        #[cfg(not(target_arch = "x86"))]
+        $crate::sdt_limit!(12, $provider, $name, [$($ty)*]);
+
+        #[cfg(target_pointer_width = "32")]
+        $crate::sdt!(@split [nop $nop, reg $reg, mem $mem, opt $($opt)?, size 4],
+            (registers), $provider, $name, [], [], [], $([$ty] $arg,)*);
+
+        #[cfg(target_pointer_width = "64")]
+        $crate::sdt!(@split [nop $nop, reg $reg, mem $mem, opt $($opt)?, size 8],
+            (registers), $provider, $name, [], [], [], $([$ty] $arg,)*);
+    );
+    ([nop $nop:literal, reg $reg:literal, mem $mem:tt, opt $($opt:ident)?],
+        memory, $provider:tt, $name:tt, $([$ty:tt] $arg:expr,)*
+    ) => (
+        $crate::sdt_limit!(12, $provider, $name, [$($ty)*]);
+
         #[cfg(target_pointer_width = "32")]
-        $crate::sdt!([sym $symstr $($sym)?, opt $($opt)?, size 4],
-            $provider, $name, $("-4@{}", $arg1, $(" -4@{}", $arg,)*)?);
+        $crate::sdt!(@split [nop $nop, reg $reg, mem $mem, opt $($opt)?, size 4],
+            (memory 0 4 8 12 16 20 24 28 32 36 40 44), $provider, $name, [], [], [],
+            $([$ty] $arg,)*);
 
         #[cfg(target_pointer_width = "64")]
-        $crate::sdt!([sym $symstr $($sym)?, opt $($opt)?, size 8],
-            $provider, $name, $("-8@{}", $arg1, $(" -8@{}", $arg,)*)?);
+        $crate::sdt!(@split [nop $nop, reg $reg, mem $mem, opt $($opt)?, size 8],
+            (memory 0 8 16 24 32 40 48 56 64 72 80 88), $provider, $name, [], [], [],
+            $([$ty] $arg,)*);
     );
 
-    ([sym $symstr:literal $($sym:ident)?, opt $($opt:ident)?, size $size:literal],
-        $provider:ident, $name:ident, $($argstr:literal, $arg:expr,)*
+    // Splits the operands into the pieces of the argstr, each after a space,
+    // and the arguments that are passed to the asm, apart from those with a
+    // raw descriptor. In memory, each one takes the next offset.
+    (@split $cfg:tt, $mode:tt, $provider:tt, $name:tt,
+        [$($piece:expr,)*], [$($in:expr,)*], [$($raw:expr,)*], [$desc:literal] $arg:expr, $($rest:tt)*
+    ) => (
+        $crate::sdt!(@split $cfg, $mode, $provider, $name,
+            [$($piece,)* " ", $desc,], [$($in,)*], [$($raw,)* $arg,], $($rest)*)
+    );
+    (@split [nop $nop:literal, reg $reg:literal, mem $mem:tt, opt $($opt:ident)?, size $size:tt],
+        (registers), $provider:tt, $name:tt,
+        [$($piece:expr,)*], [$($in:expr,)*], [$($raw:expr,)*], [$ty:ident] $arg:expr, $($rest:tt)*
+    ) => (
+        $crate::sdt!(@split [nop $nop, reg $reg, mem $mem, opt $($opt)?, size $size],
+            (registers), $provider, $name,
+            [$($piece,)* " ", concat!($crate::sdt_argstr!($size, $ty), $reg, "{}"),],
+            [$($in,)* $arg,], [$($raw,)*], $($rest)*)
+    );
+    (@split [nop $nop:literal, reg $reg:literal, mem $mem:tt, opt $($opt:ident)?, size $size:tt],
+        (memory $off:tt $($offs:tt)*), $provider:tt, $name:tt,
+        [$($piece:expr,)*], [$($in:expr,)*], [$($raw:expr,)*], [$ty:ident] $arg:expr, $($rest:tt)*
+    ) => (
+        $crate::sdt!(@split [nop $nop, reg $reg, mem $mem, opt $($opt)?, size $size],
+            (memory $($offs)*), $provider, $name,
+            [$($piece,)* " ", concat!($crate::sdt_mem_argstr!($size, $ty),
+                $crate::sdt_mem!($mem, $reg, $off)),],
+            [$($in,)* $arg,], [$($raw,)*], $($rest)*)
+    );
+    (@split [nop $nop:literal, reg $reg:literal, mem $mem:tt, opt $($opt:ident)?, size $size:tt],
+        (memory $($offs:tt)*), $provider:tt, $name:tt,
+        [$($space:expr, $($piece:expr,)*)?], [$($in:expr,)+], [$($raw:expr,)*],
+    ) => ({
+        $(let _ = $raw;)*
+        let args = [$(($in) as isize,)+];
+        $crate::sdt!([nop $nop, reg $reg, opt $($opt)?, size $size],
+            $provider, $name, [$($($piece,)*)?], base = in(reg) args.as_ptr(),)
+    });
+    (@split [nop $nop:literal, reg $reg:literal, mem $mem:tt, opt $($opt:ident)?, size $size:tt],
+        $mode:tt, $provider:tt, $name:tt,
+        [$($space:expr, $($piece:expr,)*)?], [$($in:expr,)*], [$($raw:expr,)*],
+    ) => ({
+        $(let _ = $raw;)*
+        $crate::sdt!([nop $nop, reg $reg, opt $($opt)?, size $size],
+            $provider, $name, [$($($piece,)*)?], $(in(reg) ($in) as isize,)*)
+    });
+
+    ([nop $nop:literal, reg $reg:literal, opt $($opt:ident)?, size $size:literal],
+        $provider:tt, $name:tt, [$($piece:expr,)*], $($operand:tt)*
     ) => (unsafe {
         ::core::arch::asm!(concat!(r#"
-990:    nop
+990:    "#, $nop, r#"
         .pushsection .note.stapsdt,"?","note"
         .balign 4
         .4byte 992f-991f, 994f-993f, 3
@@ -118,12 +587,22 @@ macro_rules! sdt(
 992:    .balign 4
 993:    ."#, $size, r#"byte 990b
         ."#, $size, r#"byte _.stapsdt.base
-        ."#, $size, r#"byte "#, $symstr, r#"
-        .asciz ""#, stringify!($provider), r#""
-        .asciz ""#, stringify!($name), r#""
-        .asciz ""#, $($argstr,)* r#""
+        ."#, $size, r#"byte "_.stapsdt.sem."#, $crate::probe_provider!($provider), ".", $crate::sdt_name!($name), r#""
+        .asciz ""#, $crate::probe_provider!($provider), r#""
+        .asciz ""#, $crate::sdt_name!($name), r#""
+        .asciz ""#, $($piece,)* r#""
 994:    .balign 4
         .popsection
+        .pushsection .note.probe,"?","note"
+        .balign 4
+        .4byte 996f-995f, 998f-997f, 4
+995:    .asciz "probe"
+996:    .balign 4
+997:    ."#, $size, r#"byte 990b
+        .asciz ""#, file!(), r#""
+        .asciz ""#, line!(), r#""
+998:    .balign 4
+        .popsection
 .ifndef _.stapsdt.base
         .pushsection .stapsdt.base,"aG","progbits",.stapsdt.base,comdat
         .weak _.stapsdt.base
@@ -131,9 +610,8 @@ macro_rules! sdt(
 _.stapsdt.base: .space 1
         .size _.stapsdt.base, 1
         .popsection
-.endif"#),
-            $(sym $sym,)?
-            $(in(reg) ($arg) as isize,)*
+.endif"#, $crate::sdt_semaphore!($provider, $name)),
+            $($operand)*
             options(readonly, nostack, preserves_flags $(, $opt)?),
         )
     });
@@ -0,0 +1,181 @@
+//! USDT probe sites for DTrace on illumos, Solaris, FreeBSD, and NetBSD
+//!
+//! These systems find user-level probes through the DOF that a process loads
+//! into the kernel through `/dev/dtrace/helper`. With C, that's done by
+//! running `dtrace -G` over the object files, which rewrites calls to probe
+//! stubs into `NOP`s and links in an initializer that loads the generated
+//! DOF. Here the probe macros emit the rewritten instructions directly, and
+//! record their addresses in a table from which the DOF is built at startup.
+//! Providers then show up in DTrace with the process ID appended, e.g.
+//! `dtrace -ln 'foo*:::'`, with arguments in `arg0`, `arg1`, etc.
+//!
+//! # Links:
+//!
+//! * <https://illumos.org/books/dtrace/chp-usdt.html>
+//! * <https://github.com/illumos/illumos-gate/blob/master/usr/src/lib/libdtrace/common/dt_link.c>
+//! * <https://github.com/illumos/illumos-gate/blob/master/usr/src/lib/libdtrace/common/drti.c>
+
+//
+// DEVELOPER NOTES
+//
+// On amd64, `dtrace -G` replaces the 5-byte `call` of a probe stub with five
+// `NOP`s, and the call of an is-enabled stub with `xor %rax,%rax` followed by
+// two `NOP`s. The kernel instruments those same sequences, reading probe
+// arguments from the C argument registers, and setting `%rax` to 1 at an
+// is-enabled site while the probe is active. We only pass arguments in
+// registers, which limits probes to 6 arguments.
+//
+// Each site appends a `dof::Site` to the `probe_usdt` section: its address,
+// the provider and name strings, its kind, and the number of arguments. Every
+// object also gets a single `.init_array` entry for `register`, deduplicated
+// across objects with a COMDAT group, like `_.stapsdt.base` for SystemTap.
+//
+// `probe_enabled!` has no arguments for its probe site, which is never
+// reached, so it counts as a site with no arguments, and the probe's
+// argument count is the largest of its sites.
+//
+// Loading the DOF is the only part that differs between systems, since the
+// helper ioctl and its `dof_helper_t` argument aren't quite the same.
+//
+
+use super::dof;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(target_os = "freebsd")]
+use super::freebsd as helper;
+
+#[cfg(target_os = "netbsd")]
+use super::netbsd as helper;
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+use super::illumos as helper;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::usdt!([probe], $provider, $name, $($arg,)*);
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled: usize;
+        $crate::usdt!([is_enabled enabled], $provider, $name, $($arg,)*);
+        let enabled = enabled != 0;
+        if enabled {
+            $crate::usdt!([probe], $provider, $name, $($arg,)*);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        let enabled: usize;
+        $crate::usdt!([enabled enabled], $provider, $name);
+        enabled != 0
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! usdt(
+    ([probe], $provider:tt, $name:tt, $($arg:expr,)*) => (
+        $crate::usdt!(@regs [$provider, $name, $crate::usdt_argc!($($arg,)*)] []
+            ["rdi" "rsi" "rdx" "rcx" "r8" "r9"] $($arg,)*)
+    );
+
+    ([is_enabled $enabled:ident], $provider:tt, $name:tt, $($arg:expr,)*) => (unsafe {
+        ::core::arch::asm!(
+            $crate::usdt!(@asm ".byte 0x48, 0x33, 0xc0, 0x90, 0x90", "2",
+                $provider, $name, $crate::usdt_argc!($($arg,)*)),
+            register = sym $crate::platform::usdt::register,
+            out("rax") $enabled,
+            options(nomem, nostack),
+        )
+    });
+
+    // An is-enabled site, with a probe site that's jumped over, since the
+    // kernel won't take a probe without any.
+    ([enabled $enabled:ident], $provider:tt, $name:tt) => (unsafe {
+        ::core::arch::asm!(
+            $crate::usdt!(@asm ".byte 0x48, 0x33, 0xc0, 0x90, 0x90", "2", $provider, $name, "0"),
+            "jmp 993f",
+            $crate::usdt!(@asm ".byte 0x90, 0x90, 0x90, 0x90, 0x90", "1", $provider, $name, "0"),
+            "993:",
+            register = sym $crate::platform::usdt::register,
+            out("rax") $enabled,
+            options(nomem, nostack),
+        )
+    });
+
+    (@regs [$provider:tt, $name:tt, $argc:expr] [$($ops:tt)*] [$reg:tt $($regs:tt)*]
+        $arg:expr, $($rest:expr,)*
+    ) => (
+        $crate::usdt!(@regs [$provider, $name, $argc] [$($ops)* in($reg) ($arg) as isize,]
+            [$($regs)*] $($rest,)*)
+    );
+
+    (@regs [$provider:tt, $name:tt, $argc:expr] $ops:tt [] $arg:expr, $($rest:expr,)*) => (
+        compile_error!(concat!("too many arguments for USDT probe ",
+            $crate::probe_provider!($provider), ":", $crate::probe_name!($name), ", the limit is 6"))
+    );
+
+    (@regs [$provider:tt, $name:tt, $argc:expr] [$($ops:tt)*] [$($regs:tt)*]) => (unsafe {
+        ::core::arch::asm!(
+            $crate::usdt!(@asm ".byte 0x90, 0x90, 0x90, 0x90, 0x90", "1",
+                $provider, $name, $argc),
+            register = sym $crate::platform::usdt::register,
+            $($ops)*
+            options(readonly, nostack, preserves_flags),
+        )
+    });
+
+    // The kind is `dof::KIND_PROBE` or `dof::KIND_IS_ENABLED`.
+    (@asm $insn:literal, $kind:literal, $provider:tt, $name:tt, $argc:expr) => (concat!(r#"
+990:    "#, $insn, r#"
+        .pushsection probe_usdt,"aw","progbits"
+        .balign 8
+        .8byte 990b, 991f, 992f
+        .4byte "#, $kind, ", ", $argc, r#"
+        .popsection
+        .pushsection .rodata.probe_usdt,"a","progbits"
+991:    .asciz ""#, $crate::probe_provider!($provider), r#""
+992:    .asciz ""#, $crate::probe_name!($name), r#""
+        .popsection
+.ifndef _.probe_usdt.register
+        .pushsection .init_array,"awG","init_array",_.probe_usdt.register,comdat
+        .balign 8
+        .weak _.probe_usdt.register
+        .hidden _.probe_usdt.register
+_.probe_usdt.register:
+        .8byte {register}
+        .popsection
+.endif"#));
+);
+
+// The number of arguments, as an expression for the assembler.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! usdt_argc(
+    () => ("0");
+    ($arg:expr, $($rest:expr,)*) => (concat!("1+", $crate::usdt_argc!($($rest,)*)));
+);
+
+/// Loads the DOF for this program's probes, run from `.init_array`.
+#[doc(hidden)]
+pub extern "C" fn register() {
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+    if REGISTERED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    if let Some(dof) = dof::build() {
+        let module = dof::module_name(register as *const () as usize);
+        helper::load(module, &dof);
+    }
+}
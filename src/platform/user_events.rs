@@ -0,0 +1,310 @@
+//! Linux user_events
+//!
+//! With the `user_events` feature on Linux, each probe is registered with
+//! the kernel as a user event through tracefs, named for the provider and
+//! probe joined by an underscore, with `isize` arguments as `s64` fields
+//! `arg0`, `arg1`, etc. They're listed in `user_events_status`, and can be
+//! enabled like any other trace event, e.g. `perf record -e user_events:foo_loop`
+//! or through `events/user_events/foo_loop/enable`.
+//!
+//! The kernel sets a bit in each site while its event is enabled, so a
+//! disabled probe only costs a load and compare, and `probe_lazy!` only
+//! evaluates its arguments while it's enabled.
+//!
+//! # Links:
+//!
+//! * <https://docs.kernel.org/trace/user_events.html>
+//! * <https://github.com/torvalds/linux/blob/master/include/uapi/linux/user_events.h>
+
+//
+// DEVELOPER NOTES
+//
+// Each site puts a `Site` in the `probe_user_events` section, and an
+// `.init_array` entry for `register`, which registers every site in the table
+// once, before `main`. Each registration gets its own enable bit, even when
+// several sites share an event, and the kernel tracks them all. A site whose
+// registration failed, e.g. without access to tracefs, is never enabled.
+//
+// `probe_enabled!` doesn't have a site of its own, since an event can only be
+// registered with one set of fields, and it doesn't know how many arguments
+// the probe has. It checks the first site of the event instead, since the
+// kernel enables every registration of an event together.
+//
+// This needs Linux 6.4 for the current `user_reg` ABI, with the enable
+// address, and `CONFIG_USER_EVENTS`.
+//
+
+use core::ffi::{c_int, c_ulong, c_void};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicU32, Ordering};
+use std::fs::OpenOptions;
+use std::os::unix::io::IntoRawFd;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args = [$(($arg) as isize as i64,)*];
+        $crate::user_events_site!(SITE, $provider, $name, $($arg,)*);
+        if SITE.enabled() {
+            SITE.write(&args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::user_events_site!(SITE, $provider, $name, $($arg,)*);
+        let enabled = SITE.enabled();
+        if enabled {
+            SITE.write(&[$(($arg) as isize as i64,)*]);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => ({
+        static LOOKUP: $crate::platform::user_events::Lookup =
+            $crate::platform::user_events::Lookup::new(
+                concat!($crate::probe_provider!($provider), "_", $crate::probe_name!($name)),
+            );
+        LOOKUP.enabled()
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! user_events_site(
+    ($site:ident, $provider:tt, $name:tt, $($arg:expr,)*) => (
+        #[used]
+        #[link_section = "probe_user_events"]
+        static $site: $crate::platform::user_events::Site =
+            $crate::platform::user_events::Site::new(concat!(
+                $crate::probe_provider!($provider), "_", $crate::probe_name!($name),
+                $crate::user_events_fields!([$provider, $name] [" "] [arg0 arg1 arg2 arg3 arg4
+                    arg5 arg6 arg7 arg8 arg9 arg10 arg11] $($arg,)*),
+                "\0"
+            ));
+
+        #[used]
+        #[link_section = ".init_array"]
+        static REGISTER: extern "C" fn() = $crate::platform::user_events::register;
+    )
+);
+
+// The field list of the event, e.g. " s64 arg0;s64 arg1".
+#[doc(hidden)]
+#[macro_export]
+macro_rules! user_events_fields(
+    ($probe:tt [$sep:literal] [$($names:tt)*]) => ("");
+    ($probe:tt [$sep:literal] [$field:tt $($names:tt)*] $arg:expr, $($rest:expr,)*) => (concat!(
+        $sep, "s64 ", stringify!($field),
+        $crate::user_events_fields!($probe [";"] [$($names)*] $($rest,)*)
+    ));
+    ([$provider:tt, $name:tt] [$sep:literal] [] $arg:expr, $($rest:expr,)*) => (
+        compile_error!(concat!("too many arguments for user_events probe ",
+            $crate::probe_provider!($provider), ":", $crate::probe_name!($name), ", the limit is 12"))
+    );
+);
+
+/// One entry of the `probe_user_events` section, as written by the probe macros.
+#[repr(C)]
+pub struct Site {
+    enabled: AtomicU32,
+    write_index: AtomicU32,
+    event: &'static str,
+}
+
+impl Site {
+    /// A site for `event`, which is the NUL-terminated user_events command
+    /// with the event name and fields.
+    pub const fn new(event: &'static str) -> Self {
+        Site {
+            enabled: AtomicU32::new(0),
+            write_index: AtomicU32::new(0),
+            event,
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed) != 0
+    }
+
+    #[cold]
+    pub fn write(&self, args: &[i64]) {
+        let write_index = self.write_index.load(Ordering::Relaxed);
+        let iov = [
+            IoVec {
+                base: &write_index as *const u32 as *const c_void,
+                len: core::mem::size_of::<u32>(),
+            },
+            IoVec {
+                base: args.as_ptr() as *const c_void,
+                len: core::mem::size_of_val(args),
+            },
+        ];
+        unsafe {
+            writev(
+                DATA.load(Ordering::Relaxed),
+                iov.as_ptr(),
+                iov.len() as c_int,
+            );
+        }
+    }
+}
+
+/// The first site of an event, for `probe_enabled!`, found on first use.
+pub struct Lookup {
+    site: AtomicPtr<Site>,
+    name: &'static str,
+}
+
+impl Lookup {
+    /// A lookup for the event called `name`.
+    pub const fn new(name: &'static str) -> Self {
+        Lookup {
+            site: AtomicPtr::new(ptr::null_mut()),
+            name,
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        let mut site = self.site.load(Ordering::Relaxed);
+        if site.is_null() {
+            site = find(self.name) as *const Site as *mut Site;
+            self.site.store(site, Ordering::Relaxed);
+        }
+        unsafe { (*site).enabled() }
+    }
+}
+
+/// The first site of the event called `name`, or the sentinel, which is never
+/// enabled, if there isn't one.
+#[cold]
+fn find(name: &str) -> &'static Site {
+    sites()
+        .iter()
+        .find(|site| match site.event.strip_prefix(name) {
+            Some(rest) => rest.starts_with(' ') || rest.starts_with('\0'),
+            None => false,
+        })
+        .unwrap_or(&SENTINEL)
+}
+
+// Make sure the section exists, even if the program has no probes at all.
+#[used]
+#[link_section = "probe_user_events"]
+static SENTINEL: Site = Site::new("");
+
+// These are only the bounds of the table, typed as bytes since `Site` isn't
+// FFI-safe.
+extern "C" {
+    #[link_name = "__start_probe_user_events"]
+    static START: u8;
+    #[link_name = "__stop_probe_user_events"]
+    static STOP: u8;
+}
+
+fn sites() -> &'static [Site] {
+    unsafe {
+        let start = &START as *const u8 as *const Site;
+        let stop = &STOP as *const u8 as *const Site;
+        let len = (stop as usize - start as usize) / core::mem::size_of::<Site>();
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+// struct user_reg
+#[repr(C, packed)]
+struct UserReg {
+    size: u32,
+    enable_bit: u8,
+    enable_size: u8,
+    flags: u16,
+    enable_addr: u64,
+    name_args: u64,
+    write_index: u32,
+}
+
+#[repr(C)]
+struct IoVec {
+    base: *const c_void,
+    len: usize,
+}
+
+#[cfg(not(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "sparc64",
+)))]
+const IOC_READ_WRITE: c_ulong = 3 << 30;
+
+#[cfg(any(
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "sparc64",
+))]
+const IOC_READ_WRITE: c_ulong = 6 << 29;
+
+// _IOWR('*', 0, struct user_reg *)
+const DIAG_IOCSREG: c_ulong = IOC_READ_WRITE
+    | ((core::mem::size_of::<*mut UserReg>() as c_ulong) << 16)
+    | (b'*' as c_ulong) << 8;
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    fn writev(fd: c_int, iov: *const IoVec, iovcnt: c_int) -> isize;
+}
+
+/// The open `user_events_data` file, for registering and writing events.
+static DATA: AtomicI32 = AtomicI32::new(-1);
+
+/// Registers all of the program's probes as user events, run from `.init_array`.
+#[doc(hidden)]
+pub extern "C" fn register() {
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+    if REGISTERED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    let file = [
+        "/sys/kernel/tracing/user_events_data",
+        "/sys/kernel/debug/tracing/user_events_data",
+    ]
+    .iter()
+    .find_map(|path| OpenOptions::new().write(true).open(path).ok());
+    let fd = match file {
+        Some(file) => file.into_raw_fd(),
+        None => return,
+    };
+    DATA.store(fd, Ordering::Relaxed);
+
+    for site in sites() {
+        if site.event.is_empty() {
+            continue;
+        }
+        let mut reg = UserReg {
+            size: core::mem::size_of::<UserReg>() as u32,
+            enable_bit: 0,
+            enable_size: core::mem::size_of::<AtomicU32>() as u8,
+            flags: 0,
+            enable_addr: &site.enabled as *const AtomicU32 as u64,
+            name_args: site.event.as_ptr() as u64,
+            write_index: 0,
+        };
+        if unsafe { ioctl(fd, DIAG_IOCSREG, &mut reg as *mut UserReg) } == 0 {
+            site.write_index.store(reg.write_index, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! WebAssembly host imports
+//!
+//! With the `wasm` feature on `wasm32`, each probe calls the function
+//! `probe.fire`, which the module imports from its host, so a runtime or its
+//! devtools can observe probe events. The host gets pointers into linear
+//! memory for the UTF-8 provider and name, and for the arguments as `i64`s:
+//!
+//! ```notrust
+//! (import "probe" "fire" (func (param i32 i32 i32 i32 i32 i32)))
+//! (import "probe" "enabled" (func (param i32 i32 i32 i32) (result i32)))
+//! ```
+//!
+//! `probe_lazy!` first asks the host whether a probe is `enabled`, and only
+//! evaluates its arguments and fires it if the result is non-zero.
+//!
+//! Every probe is also recorded in a custom section named `probe`, so tools
+//! can list the probes of a module without running it. The section is a
+//! sequence of records, each with the provider, the name, the number of
+//! arguments, the level, the format, and the file and line of the site, where
+//! strings are a LEB128 length followed by UTF-8 bytes, the argument count and
+//! the line are LEB128 numbers, the level is a byte from 1 for `critical` to 6
+//! for `trace`, and the format is empty for a probe without one.
+
+//
+// DEVELOPER NOTES
+//
+// The linker concatenates custom sections of the same name, so each site only
+// has to contribute its own record, as a byte array built by a `const fn`.
+// The array length must be known up front, hence the separate `record_len`.
+// These are the entries of the `table` module without their IDs.
+//
+// The imports are unconditional, so a host that doesn't provide them will
+// fail to instantiate the module, which is why this is opt-in.
+//
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::wasm_record!($provider, $name, [$($opt)*], $($arg,)*);
+        $crate::platform::wasm::fire(
+            $crate::probe_provider!($provider),
+            $crate::probe_name!($name),
+            &[$(($arg) as isize as i64,)*],
+        );
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        $crate::wasm_record!($provider, $name, [$($opt)*], $($arg,)*);
+        let enabled = $crate::platform::wasm::enabled(
+            $crate::probe_provider!($provider),
+            $crate::probe_name!($name),
+        );
+        if enabled {
+            $crate::platform::wasm::fire(
+                $crate::probe_provider!($provider),
+                $crate::probe_name!($name),
+                &[$(($arg) as isize as i64,)*],
+            );
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::platform::wasm::enabled($crate::probe_provider!($provider), $crate::probe_name!($name))
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! wasm_record(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        const PROVIDER: &str = $crate::probe_provider!($provider);
+        const NAME: &str = $crate::probe_name!($name);
+        const ARGS: usize = <[&str]>::len(&[$(stringify!($arg),)*]);
+        const LEVEL: u8 = $crate::probe_level_value!($($opt)*);
+        const FORMAT: &str = $crate::probe_format_value!($($opt)*);
+        const FILE: &str = file!();
+        const LINE: u32 = line!();
+        const LEN: usize = $crate::table::record_len(PROVIDER, NAME, ARGS, FORMAT, FILE, LINE);
+        const RECORD: [u8; LEN] =
+            $crate::table::record(PROVIDER, NAME, ARGS, LEVEL, FORMAT, FILE, LINE);
+
+        #[used]
+        #[link_section = "probe"]
+        static RECORDS: [[u8; LEN]; $crate::probe_compiled_in!($provider, [$($opt)*]) as usize] =
+            [RECORD; $crate::probe_compiled_in!($provider, [$($opt)*]) as usize];
+    )
+);
+
+#[link(wasm_import_module = "probe")]
+extern "C" {
+    #[link_name = "fire"]
+    fn probe_fire(
+        provider: *const u8,
+        provider_len: usize,
+        name: *const u8,
+        name_len: usize,
+        args: *const i64,
+        args_len: usize,
+    );
+
+    #[link_name = "enabled"]
+    fn probe_enabled(
+        provider: *const u8,
+        provider_len: usize,
+        name: *const u8,
+        name_len: usize,
+    ) -> bool;
+}
+
+#[inline]
+pub fn fire(provider: &'static str, name: &'static str, args: &[i64]) {
+    unsafe {
+        probe_fire(
+            provider.as_ptr(),
+            provider.len(),
+            name.as_ptr(),
+            name.len(),
+            args.as_ptr(),
+            args.len(),
+        )
+    }
+}
+
+#[inline]
+pub fn enabled(provider: &'static str, name: &'static str) -> bool {
+    unsafe { probe_enabled(provider.as_ptr(), provider.len(), name.as_ptr(), name.len()) }
+}
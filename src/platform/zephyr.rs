@@ -0,0 +1,85 @@
+//! Zephyr tracing named events
+//!
+//! With the `zephyr` feature on bare-metal targets, for applications built
+//! with Zephyr and `CONFIG_TRACING`, each probe is written as a named event
+//! through `sys_trace_named_event`, named like `foo:loop`, so it lands in the
+//! same timeline as the kernel's own events, with whichever tracing backend
+//! Zephyr is using, e.g. CTF over UART or USB.
+//!
+//! Named events only have two 32-bit arguments, so the first two arguments of
+//! a probe are recorded, and any others are dropped. The CTF backend also
+//! truncates the name to 20 bytes. Probes are written while Zephyr's tracing
+//! is enabled, and `probe_lazy!` only evaluates its arguments then.
+//!
+//! # Links:
+//!
+//! * <https://docs.zephyrproject.org/latest/services/tracing/index.html>
+//! * <https://github.com/zephyrproject-rtos/zephyr/blob/main/include/zephyr/tracing/tracing.h>
+
+//
+// DEVELOPER NOTES
+//
+// The rest of Zephyr's tracing hooks are macros for its own objects, but
+// named events are a plain function that every backend implements, for
+// exactly this kind of application event. That needs Zephyr 3.2.
+//
+
+use core::ffi::c_char;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let args: &[isize] = &[$(($arg) as isize,)*];
+        if $crate::platform::zephyr::enabled() {
+            $crate::zephyr_write!($provider, $name, args);
+        }
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_lazy(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ({
+        let enabled = $crate::platform::zephyr::enabled();
+        if enabled {
+            $crate::zephyr_write!($provider, $name, &[$(($arg) as isize,)*]);
+        }
+        enabled
+    })
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! platform_probe_enabled(
+    ($provider:tt, $name:tt, [$($opt:tt)*]) => (
+        $crate::platform::zephyr::enabled()
+    )
+);
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! zephyr_write(
+    ($provider:tt, $name:tt, $args:expr) => (
+        $crate::platform::zephyr::write(
+            concat!($crate::probe_provider!($provider), ":", $crate::probe_name!($name), "\0"),
+            $args,
+        )
+    )
+);
+
+extern "C" {
+    fn is_tracing_enabled() -> bool;
+    fn sys_trace_named_event(name: *const c_char, arg0: u32, arg1: u32);
+}
+
+#[inline]
+pub fn enabled() -> bool {
+    unsafe { is_tracing_enabled() }
+}
+
+#[inline(never)]
+pub fn write(name: &'static str, args: &[isize]) {
+    let arg = |i| args.get(i).map_or(0, |&arg: &isize| arg as u32);
+    unsafe { sys_trace_named_event(name.as_ptr().cast(), arg(0), arg(1)) }
+}
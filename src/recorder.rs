@@ -0,0 +1,620 @@
+//! A flight recorder of probes
+//!
+//! With the `recorder` feature, [`start`] keeps the most recent probes that
+//! fire in a ring buffer in memory for each thread, as they're handled by the
+//! [`callback`] module, so a service can see what its probes were doing just before
+//! something went wrong, e.g. from a panic hook or a debug endpoint, without
+//! a tracer. Each [`Record`] has the probe's ID, the time it fired, and the
+//! first [`ARGS`] of its operands.
+//!
+//! [`recent`] lists the records in the buffers, merged oldest first, and
+//! [`drain`] takes the ones since the last drain, so they can be written out
+//! as they come. Records are overwritten once a thread's buffer is full, so a
+//! drain that falls behind misses some. Threads never share a buffer while
+//! they're running, so the probes of one don't contend with another's, but a
+//! thread that exits leaves its buffer, and its records, to the next new one.
+//!
+//! The recorder is a handler of the `callback` module, so only the probes that
+//! are enabled with the [`control`] module are recorded, alongside any other
+//! handlers, and it can be started and stopped while the program runs, e.g.
+//! only while a diagnostics mode is on.
+//!
+//! ```
+//! use probe::{control, probe, recorder};
+//!
+//! recorder::start(1024);
+//! control::enable("foo");
+//! probe!(foo, bar, 1, 2);
+//!
+//! for record in recorder::drain() {
+//!     assert_eq!(record.id, probe::rtt::id("foo", "bar"));
+//!     assert_eq!(record.args(), [1, 2]);
+//! }
+//! ```
+//!
+//! The recorder also keeps [`stats`] of each probe site, with how often it's
+//! fired, the last time it did, with its operands, and the least, greatest,
+//! and mean values of its first operand, so a program can show what its probes
+//! are doing, e.g. on its own diagnostics page:
+//!
+//! ```
+//! use probe::{control, probe, recorder};
+//!
+//! recorder::start(1024);
+//! control::enable("server");
+//! for len in [64, 256] {
+//!     probe!(server, parsed, len);
+//! }
+//!
+//! for stats in probe::stats() {
+//!     println!(
+//!         "{}:{} at {}:{}: {} hits, {:?} last, {:?} mean",
+//!         stats.provider,
+//!         stats.name,
+//!         stats.file,
+//!         stats.line,
+//!         stats.hits,
+//!         stats.last.map(|last| last.args()[0]),
+//!         stats.mean,
+//!     );
+//! }
+//! ```
+//!
+//! A child that's forked while the recorder is there starts over with empty
+//! buffers, of the same capacity, and on the same clock, and with no stats, so
+//! it doesn't report the parent's records a second time, or lose the buffers
+//! of threads that aren't in it.
+//!
+//! A thread's first probe may allocate its buffer, and a site's first hit
+//! allocates its stats, so a probe in a signal handler only stays clear of the
+//! allocator once its thread and its site have fired outside of one.
+//!
+//! The recorder needs 64-bit atomics, so it's only on targets that have them.
+//!
+//! [`callback`]: crate::callback
+//! [`control`]: crate::control
+
+//
+// DEVELOPER NOTES
+//
+// Each buffer is a fixed array of slots, and each probe takes the next
+// position with a `fetch_add`, so writers never wait for each other. Each slot
+// is a seqlock of atomics: its sequence is odd while a position is written to
+// it, and even once it's done, and a writer claims a slot with a CAS from an
+// even sequence of an older position, so a writer that's lapped by another
+// drops its record, rather than tearing the other's. Readers check that the
+// sequence is the one they expect for the position, before and after reading
+// it, and skip positions that are overwritten or still being written.
+//
+// The buffers are in a list that's only ever pushed to, with a CAS, and
+// they're never freed, so the probes never have to check whether theirs is
+// still there, and readers can walk the list without a lock. A thread takes a
+// buffer on its first probe, and marks it as free again from the destructor
+// of its thread-local, so the list only grows to the most threads that have
+// been recording at once. Records of different threads are merged by their
+// times, from the same clock.
+//
+// That isn't signal-safe: a thread's first probe initializes its thread-local
+// and may allocate its buffer, and a site's first hit allocates its stats, so
+// a probe in a signal handler can only count on not allocating once its
+// thread and its site have both fired outside of one. After that, a probe
+// only takes atomics.
+//
+// The stats of each site are in a hash table of lists like that one, by the
+// site's ID, with a site for each `ProbeInfo`, by its address, since that's a
+// static at each site. A site is only ever pushed to its list, with a CAS,
+// after checking the sites that were pushed since it looked, so each site has
+// one. Its counts are atomics, updated by every thread that fires it, and its
+// last probe is a slot like those of the buffers, at the position of its hit,
+// so a probe that's overtaken by a later one of the same site doesn't replace
+// it. A reader tries it a few times while it's being written, and then gives
+// up, like `drain` skips a slot that's being written, rather than waiting for
+// a writer it may have interrupted. The count of the first operands is raised
+// last, with a release store, so a reader that sees it sees the values it
+// counts.
+//
+// On Unix, the child handler of `pthread_atfork` moves the recorder aside, so
+// the child makes a new one on its next probe, or read. It only does that with
+// atomics, since a forked child of a threaded program shouldn't allocate, or
+// touch a thread-local for the first time, before it runs on. The forking
+// thread's buffer belongs to the old recorder, which its thread-local keeps,
+// so the thread takes a new one too. The old buffers stay in the child as
+// they were, and aren't read again.
+//
+
+use crate::callback::{self, ProbeInfo};
+use crate::latency::Start;
+use core::cell::Cell;
+use core::ptr;
+use core::sync::atomic::{
+    fence, AtomicBool, AtomicI64, AtomicIsize, AtomicPtr, AtomicU32, AtomicU64, Ordering,
+};
+use std::boxed::Box;
+use std::vec::Vec;
+
+/// The number of operands kept in each record.
+pub const ARGS: usize = 6;
+
+/// A probe in the recorder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The time the probe fired, since the recorder started, in the units of
+    /// the [`latency`](crate::latency) clock, which are nanoseconds by default.
+    pub time: u64,
+    /// The probe's ID, from [`rtt::id`](crate::rtt::id).
+    pub id: u32,
+    /// The number of operands the probe had, which can be more than [`ARGS`].
+    pub len: usize,
+    args: [isize; ARGS],
+}
+
+impl Record {
+    /// The operands of the probe, up to [`ARGS`] of them.
+    pub fn args(&self) -> &[isize] {
+        &self.args[..self.len.min(ARGS)]
+    }
+}
+
+struct Slot {
+    seq: AtomicU64,
+    time: AtomicU64,
+    id: AtomicU32,
+    len: AtomicU64,
+    args: [AtomicIsize; ARGS],
+}
+
+impl Slot {
+    fn new() -> Self {
+        Slot {
+            seq: AtomicU64::new(0),
+            time: AtomicU64::new(0),
+            id: AtomicU32::new(0),
+            len: AtomicU64::new(0),
+            args: Default::default(),
+        }
+    }
+
+    fn write(&self, pos: u64, time: u64, info: &ProbeInfo, args: &[isize]) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        if seq & 1 != 0
+            || seq > pos * 2
+            || self
+                .seq
+                .compare_exchange(seq, pos * 2 + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        fence(Ordering::Release);
+        self.time.store(time, Ordering::Relaxed);
+        self.id.store(info.id(), Ordering::Relaxed);
+        self.len.store(args.len() as u64, Ordering::Relaxed);
+        for (slot, &arg) in self.args.iter().zip(args) {
+            slot.store(arg, Ordering::Relaxed);
+        }
+        self.seq.store(pos * 2 + 2, Ordering::Release);
+    }
+
+    fn read(&self, pos: u64) -> Option<Record> {
+        let seq = self.seq.load(Ordering::Acquire);
+        if seq != pos * 2 + 2 {
+            return None;
+        }
+        let mut record = Record {
+            time: self.time.load(Ordering::Relaxed),
+            id: self.id.load(Ordering::Relaxed),
+            len: self.len.load(Ordering::Relaxed) as usize,
+            args: [0; ARGS],
+        };
+        for (arg, slot) in record.args.iter_mut().zip(&self.args) {
+            *arg = slot.load(Ordering::Relaxed);
+        }
+        fence(Ordering::Acquire);
+        if self.seq.load(Ordering::Relaxed) != seq {
+            return None;
+        }
+        Some(record)
+    }
+}
+
+/// The stats of a probe site, from [`stats`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// The probe's ID, from [`rtt::id`](crate::rtt::id).
+    pub id: u32,
+    /// The probe's provider.
+    pub provider: &'static str,
+    /// The probe's name.
+    pub name: &'static str,
+    /// The file of the probe's site.
+    pub file: &'static str,
+    /// The line of the probe's site.
+    pub line: u32,
+    /// The number of times the probe has fired.
+    pub hits: u64,
+    /// The last time the probe fired, with its operands, unless it hasn't, or
+    /// another probe of the site was being written over it.
+    pub last: Option<Record>,
+    /// The least value of the probe's first operand, if it has one.
+    pub min: Option<isize>,
+    /// The greatest value of the probe's first operand, if it has one.
+    pub max: Option<isize>,
+    /// The mean value of the probe's first operand, if it has one, from a sum
+    /// that stops at the bounds of an `i64`.
+    pub mean: Option<f64>,
+}
+
+// The stats of a site, in the list of its bucket.
+struct Site {
+    next: AtomicPtr<Site>,
+    key: usize,
+    info: ProbeInfo,
+    hits: AtomicU64,
+    last: Slot,
+    count: AtomicU64,
+    sum: AtomicI64,
+    min: AtomicIsize,
+    max: AtomicIsize,
+}
+
+impl Site {
+    fn new(key: usize, info: &ProbeInfo) -> Self {
+        Site {
+            next: AtomicPtr::new(ptr::null_mut()),
+            key,
+            info: info.clone(),
+            hits: AtomicU64::new(0),
+            last: Slot::new(),
+            count: AtomicU64::new(0),
+            sum: AtomicI64::new(0),
+            min: AtomicIsize::new(isize::MAX),
+            max: AtomicIsize::new(isize::MIN),
+        }
+    }
+
+    fn write(&self, time: u64, info: &ProbeInfo, args: &[isize]) {
+        let hit = self.hits.fetch_add(1, Ordering::Relaxed);
+        self.last.write(hit, time, info, args);
+        if let Some(&first) = args.first() {
+            self.min.fetch_min(first, Ordering::Relaxed);
+            self.max.fetch_max(first, Ordering::Relaxed);
+            // Saturating, so a sum past the bounds doesn't wrap to the other
+            // sign.
+            let _ = self
+                .sum
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                    Some(sum.saturating_add(first as i64))
+                });
+            self.count.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let count = self.count.load(Ordering::Acquire);
+        Stats {
+            id: self.info.id(),
+            provider: self.info.provider(),
+            name: self.info.name(),
+            file: self.info.file(),
+            line: self.info.line(),
+            hits: self.hits.load(Ordering::Relaxed),
+            last: self.last(),
+            min: Some(self.min.load(Ordering::Relaxed)).filter(|_| count > 0),
+            max: Some(self.max.load(Ordering::Relaxed)).filter(|_| count > 0),
+            mean: Some(self.sum.load(Ordering::Relaxed) as f64 / count as f64)
+                .filter(|_| count > 0),
+        }
+    }
+
+    // The last probe, trying a few times while another is being written over
+    // it, but not waiting for that writer, which may be interrupted, or be
+    // interrupted by this reader.
+    fn last(&self) -> Option<Record> {
+        for _ in 0..LAST_TRIES {
+            let seq = self.last.seq.load(Ordering::Acquire);
+            if seq == 0 {
+                return None;
+            }
+            if seq & 1 == 0 {
+                if let Some(record) = self.last.read(seq / 2 - 1) {
+                    return Some(record);
+                }
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+}
+
+// The number of buckets of the sites.
+const BUCKETS: usize = 64;
+
+// How many times the last probe of a site is read while it's being written.
+const LAST_TRIES: usize = 16;
+
+fn sites(mut site: *mut Site) -> impl Iterator<Item = &'static Site> {
+    core::iter::from_fn(move || {
+        let next = unsafe { site.as_ref()? };
+        site = next.next.load(Ordering::Acquire);
+        Some(next)
+    })
+}
+
+// A thread's buffer, which is only written by the thread that owns it.
+struct Ring {
+    next: AtomicPtr<Ring>,
+    owned: AtomicBool,
+    head: AtomicU64,
+    drained: AtomicU64,
+    slots: Box<[Slot]>,
+}
+
+impl Ring {
+    fn slot(&self, pos: u64) -> &Slot {
+        &self.slots[(pos % self.slots.len() as u64) as usize]
+    }
+
+    // The records from `from` up to the head, oldest first, if they're still
+    // there.
+    fn records(&self, from: u64, head: u64) -> impl Iterator<Item = Record> + '_ {
+        let from = from.max(head.saturating_sub(self.slots.len() as u64));
+        (from..head).filter_map(move |pos| self.slot(pos).read(pos))
+    }
+}
+
+struct Recorder {
+    start: Start,
+    capacity: usize,
+    rings: AtomicPtr<Ring>,
+    sites: [AtomicPtr<Site>; BUCKETS],
+}
+
+impl Recorder {
+    fn rings(&self) -> impl Iterator<Item = &'static Ring> {
+        let mut ring = unsafe { self.rings.load(Ordering::Acquire).as_ref() };
+        core::iter::from_fn(move || {
+            let next = ring?;
+            ring = unsafe { next.next.load(Ordering::Acquire).as_ref() };
+            Some(next)
+        })
+    }
+
+    // A buffer for this thread, which is one that another thread has left if
+    // there is one, or a new one.
+    fn claim(&self) -> &'static Ring {
+        for ring in self.rings() {
+            let claimed =
+                ring.owned
+                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed);
+            if claimed.is_ok() {
+                return ring;
+            }
+        }
+        let ring = Box::leak(Box::new(Ring {
+            next: AtomicPtr::new(ptr::null_mut()),
+            owned: AtomicBool::new(true),
+            head: AtomicU64::new(0),
+            drained: AtomicU64::new(0),
+            slots: (0..self.capacity).map(|_| Slot::new()).collect(),
+        }));
+        let mut head = self.rings.load(Ordering::Relaxed);
+        loop {
+            ring.next.store(head, Ordering::Relaxed);
+            match self
+                .rings
+                .compare_exchange_weak(head, ring, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return ring,
+                Err(next) => head = next,
+            }
+        }
+    }
+
+    // The stats of a site, which are new if it hasn't fired yet.
+    fn site(&self, info: &ProbeInfo) -> &'static Site {
+        let key = info as *const ProbeInfo as usize;
+        let bucket = &self.sites[info.id() as usize % BUCKETS];
+        let mut checked = ptr::null_mut();
+        let mut new = None;
+        loop {
+            let head = bucket.load(Ordering::Acquire);
+            let found = sites(head)
+                .take_while(|&site| !ptr::eq(site, checked))
+                .find(|site| site.key == key);
+            if let Some(site) = found {
+                if let Some(new) = new {
+                    drop(unsafe { Box::from_raw(new) });
+                }
+                return site;
+            }
+            checked = head;
+            let site = *new.get_or_insert_with(|| Box::into_raw(Box::new(Site::new(key, info))));
+            unsafe { (*site).next.store(head, Ordering::Relaxed) };
+            if bucket
+                .compare_exchange(head, site, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                return unsafe { &*site };
+            }
+        }
+    }
+
+    // The records of every thread, oldest first, with those of each buffer from
+    // `from` up to its head.
+    fn records(&self, range: impl Fn(&Ring) -> (u64, u64)) -> Vec<Record> {
+        let mut records = Vec::new();
+        for ring in self.rings() {
+            let (from, head) = range(ring);
+            records.extend(ring.records(from, head));
+        }
+        records.sort_by_key(|record| record.time);
+        records
+    }
+}
+
+static RECORDER: AtomicPtr<Recorder> = AtomicPtr::new(ptr::null_mut());
+
+// The parent's recorder, in a child that's forked while it's there, until the
+// child makes its own.
+static FORKED: AtomicPtr<Recorder> = AtomicPtr::new(ptr::null_mut());
+
+fn recorder() -> Option<&'static Recorder> {
+    let recorder = RECORDER.load(Ordering::Acquire);
+    if recorder.is_null() {
+        let parent = unsafe { FORKED.swap(ptr::null_mut(), Ordering::AcqRel).as_ref()? };
+        return Some(install(parent.start, parent.capacity));
+    }
+    unsafe { recorder.as_ref() }
+}
+
+// Installs a new recorder, unless there's one already, and returns the one
+// that's there.
+fn install(start: Start, capacity: usize) -> &'static Recorder {
+    let recorder = Box::into_raw(Box::new(Recorder {
+        start,
+        capacity,
+        rings: AtomicPtr::new(ptr::null_mut()),
+        sites: [(); BUCKETS].map(|_| AtomicPtr::new(ptr::null_mut())),
+    }));
+    let installed = RECORDER.compare_exchange(
+        ptr::null_mut(),
+        recorder,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    match installed {
+        Ok(_) => unsafe { &*recorder },
+        Err(current) => {
+            drop(unsafe { Box::from_raw(recorder) });
+            unsafe { &*current }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn handle_fork() {
+    extern "C" {
+        fn pthread_atfork(
+            prepare: Option<extern "C" fn()>,
+            parent: Option<extern "C" fn()>,
+            child: Option<extern "C" fn()>,
+        ) -> core::ffi::c_int;
+    }
+
+    extern "C" fn child() {
+        let parent = RECORDER.swap(ptr::null_mut(), Ordering::AcqRel);
+        if !parent.is_null() {
+            FORKED.store(parent, Ordering::Release);
+        }
+    }
+
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+    if !REGISTERED.swap(true, Ordering::Relaxed) {
+        unsafe { pthread_atfork(None, None, Some(child)) };
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_fork() {}
+
+// The thread's buffer, with the recorder it's from, which it leaves for
+// another thread when it exits.
+struct Local(Cell<Option<(&'static Ring, *const Recorder)>>);
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        if let Some((ring, _)) = self.0.get() {
+            ring.owned.store(false, Ordering::Release);
+        }
+    }
+}
+
+std::thread_local! {
+    static LOCAL: Local = const { Local(Cell::new(None)) };
+}
+
+/// Starts recording probes, in a buffer of `capacity` records for each thread,
+/// if they aren't there already, by adding a `callback` handler.
+pub fn start(capacity: usize) {
+    if recorder().is_none() {
+        install(Start::now(), capacity.max(1));
+    }
+    handle_fork();
+    callback::add_handler(record);
+}
+
+/// Stops recording probes, by removing its `callback` handler, but keeps the
+/// records, for a later [`start`] to go on from.
+pub fn stop() {
+    callback::remove_handler(record);
+}
+
+/// Records a probe in the thread's buffer, and its site's stats, if the
+/// recorder has been started, as a `callback` handler.
+pub fn record(info: &ProbeInfo, args: &[isize]) {
+    if let Some(recorder) = recorder() {
+        let time = recorder.start.elapsed();
+        recorder.site(info).write(time, info, args);
+        // Not while the thread's locals are being destroyed.
+        let _ = LOCAL.try_with(|local| {
+            let ring = match local.0.get() {
+                Some((ring, from)) if ptr::eq(from, recorder) => ring,
+                _ => {
+                    let ring = recorder.claim();
+                    local.0.set(Some((ring, recorder)));
+                    ring
+                }
+            };
+            let pos = ring.head.fetch_add(1, Ordering::Relaxed);
+            ring.slot(pos).write(pos, time, info, args);
+        });
+    }
+}
+
+/// The records in the buffers, oldest first.
+pub fn recent() -> Vec<Record> {
+    match recorder() {
+        Some(recorder) => recorder.records(|ring| (0, ring.head.load(Ordering::Acquire))),
+        None => Vec::new(),
+    }
+}
+
+/// Takes the records since the last drain, oldest first, without those that
+/// were overwritten since, or are still being written.
+pub fn drain() -> Vec<Record> {
+    match recorder() {
+        Some(recorder) => recorder.records(|ring| {
+            let head = ring.head.load(Ordering::Acquire);
+            let from = ring.drained.fetch_max(head, Ordering::AcqRel);
+            (from, head.max(from))
+        }),
+        None => Vec::new(),
+    }
+}
+
+/// The stats of every probe site that's been recorded, by provider, name, and
+/// site.
+pub fn stats() -> Vec<Stats> {
+    let mut stats: Vec<_> = match recorder() {
+        Some(recorder) => recorder
+            .sites
+            .iter()
+            .flat_map(|bucket| sites(bucket.load(Ordering::Acquire)))
+            .map(Site::stats)
+            .collect(),
+        None => Vec::new(),
+    };
+    stats.sort_by_key(|stats| (stats.provider, stats.name, stats.file, stats.line));
+    stats
+}
+
+// A site of a probe with this ID that's been recorded, for the `binlog` module
+// to describe records with.
+#[cfg(feature = "binlog")]
+pub(crate) fn info(id: u32) -> Option<ProbeInfo> {
+    let recorder = recorder()?;
+    let bucket = &recorder.sites[id as usize % BUCKETS];
+    sites(bucket.load(Ordering::Acquire))
+        .find(|site| site.info.id() == id)
+        .map(|site| site.info.clone())
+}
@@ -0,0 +1,146 @@
+//! Decoding probes from RTT on the host
+//!
+//! Firmware built with the `rtt` feature writes its probes to an RTT
+//! up-channel named [`CHANNEL`], in the mode that skips whole records when
+//! the channel is full, so the channel only ever holds whole records. Each
+//! record is:
+//!
+//! * the probe's 32-bit ID, little-endian, from [`id`],
+//! * an 8-bit count of the arguments,
+//! * each argument as a zigzag-encoded LEB128 number.
+//!
+//! With the `use_std` feature, a [`Decoder`] turns the bytes read from that
+//! channel back into [`Event`]s, e.g. with the `probe-rs` crate:
+//!
+//! ```ignore
+//! let mut rtt = probe_rs::rtt::Rtt::attach(&mut core)?;
+//! let channel = rtt
+//!     .up_channels()
+//!     .iter_mut()
+//!     .find(|channel| channel.name() == Some(probe::rtt::CHANNEL))
+//!     .unwrap();
+//!
+//! let mut decoder = probe::rtt::Decoder::new();
+//! decoder.register("foo", "loop");
+//! let mut buf = [0; 1024];
+//! loop {
+//!     let n = channel.read(&mut core, &mut buf)?;
+//!     decoder.push(&buf[..n]);
+//!     while let Some(event) = decoder.next_event() {
+//!         match decoder.lookup(event.id) {
+//!             Some((provider, name)) => println!("{}:{} {:?}", provider, name, event.args),
+//!             None => println!("{:#010x} {:?}", event.id, event.args),
+//!         }
+//!     }
+//! }
+//! ```
+
+#[cfg(feature = "use_std")]
+use std::collections::HashMap;
+#[cfg(feature = "use_std")]
+use std::string::{String, ToString};
+#[cfg(feature = "use_std")]
+use std::vec::Vec;
+
+/// The name of the RTT up-channel for probes.
+pub const CHANNEL: &str = "probe";
+
+/// The ID of a probe in RTT records, with a 16-bit hash of the provider in
+/// the high half and a 16-bit hash of the name in the low half.
+///
+/// The hashes are the 32-bit FNV-1a hash of the string, folded to 16 bits by
+/// XOR of its halves.
+pub const fn id(provider: &str, name: &str) -> u32 {
+    (hash(provider) as u32) << 16 | hash(name) as u32
+}
+
+/// FNV-1a, folded to 16 bits.
+const fn hash(s: &str) -> u16 {
+    let bytes = s.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    (hash >> 16) as u16 ^ hash as u16
+}
+
+/// A probe that fired, decoded from its RTT record.
+#[cfg(feature = "use_std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// The probe's ID, from [`id`].
+    pub id: u32,
+    /// The probe's arguments, sign-extended from the target's pointer size.
+    pub args: Vec<i64>,
+}
+
+/// Decodes [`Event`]s from the bytes of the RTT channel.
+///
+/// Records can be split across reads of the channel, so the decoder keeps
+/// any partial record until the rest of it is pushed.
+#[cfg(feature = "use_std")]
+#[derive(Clone, Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    names: HashMap<u32, (String, String)>,
+}
+
+#[cfg(feature = "use_std")]
+impl Decoder {
+    /// Creates a decoder that doesn't know any probe names yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a probe's names, so that [`lookup`](Self::lookup) can find them
+    /// from its ID.
+    pub fn register(&mut self, provider: &str, name: &str) {
+        self.names
+            .insert(id(provider, name), (provider.to_string(), name.to_string()));
+    }
+
+    /// Finds the provider and name of a registered probe from its ID.
+    pub fn lookup(&self, id: u32) -> Option<(&str, &str)> {
+        let (provider, name) = self.names.get(&id)?;
+        Some((provider, name))
+    }
+
+    /// Adds bytes read from the channel.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Takes the next whole record that was pushed, if there is one.
+    pub fn next_event(&mut self) -> Option<Event> {
+        let header = self.buf.get(..5)?;
+        let id = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let mut args = Vec::with_capacity(header[4] as usize);
+        let mut offset = 5;
+        for _ in 0..header[4] {
+            let (arg, len) = varint(&self.buf[offset..])?;
+            args.push(arg);
+            offset += len;
+        }
+        self.buf.drain(..offset);
+        Some(Event { id, args })
+    }
+}
+
+/// Decodes a zigzag LEB128 number, and its length, if it's all there.
+#[cfg(feature = "use_std")]
+fn varint(bytes: &[u8]) -> Option<(i64, usize)> {
+    let mut value: u64 = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        value |= u64::from(byte & 0x7f)
+            .checked_shl(7 * i as u32)
+            .unwrap_or(0);
+        if byte & 0x80 == 0 {
+            let arg = (value >> 1) as i64 ^ -((value & 1) as i64);
+            return Some((arg, i + 1));
+        }
+    }
+    None
+}
@@ -0,0 +1,174 @@
+//! Sampled and rate-limited probes
+//!
+//! [`probe_sampled!`](crate::probe_sampled) fires a probe on only one in
+//! every `rate` hits of its site while it's enabled, so a hot path can stay
+//! instrumented without a tracer having to keep up with every hit. Each site
+//! counts its own hits, so the first hit is always fired, and then every
+//! `rate`th one after it.
+//!
+//! [`probe_rate_limited!`](crate::probe_rate_limited) fires a probe at most
+//! `per_second` times a second at each site, in bursts of up to that many, so
+//! a probe on an error path can't flood a tracer when the errors storm. It
+//! needs a clock, which is the system clock with the `use_std` feature, or
+//! the one the program gives [`set_clock`], e.g. a cycle counter in firmware.
+//! Without either, rate-limited probes fire every time, like lazy probes.
+//!
+//! [`probe_once!`](crate::probe_once) fires a probe on only the first hit of
+//! its site while it's enabled, e.g. to mark that something was initialized
+//! without a probe for every time around a loop.
+
+//
+// DEVELOPER NOTES
+//
+// The counter is a static in the macro expansion, so it's per site, and it's
+// only touched after the probe's enabled check, so a site costs the same as a
+// lazy probe while nothing is attached. Threads share the counter with a
+// relaxed `fetch_add`, so the sampling is exact even when they race, though
+// which thread's hit is the one that fires isn't.
+//
+// The rate limit is a token bucket, kept as the "theoretical arrival time" of
+// GCRA, so it's a single `AtomicU32` of microseconds per site, which wraps
+// every 71 minutes. Each hit that fires moves it on by the interval between
+// hits at the rate, and a hit is dropped while it's more than a burst of
+// intervals ahead of the clock. Anything further ahead than that can only be
+// from before the clock wrapped, or jumped back, so it starts over from now.
+//
+// The system clock is read through the vDSO on Linux, which is nearly as cheap
+// as reading the TSC directly, without having to find out its frequency, and
+// it's only read for enabled probes.
+//
+
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+
+#[doc(hidden)]
+pub struct Counter {
+    hits: AtomicUsize,
+}
+
+impl Counter {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        Counter {
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a hit, and returns `true` for one in every `rate` of them, or
+    /// for every hit if `rate` is 0 or 1.
+    #[doc(hidden)]
+    #[inline]
+    pub fn sample(&self, rate: usize) -> bool {
+        let hit = self.hits.fetch_add(1, Ordering::Relaxed);
+        rate <= 1 || hit % rate == 0
+    }
+}
+
+#[doc(hidden)]
+pub struct Once {
+    fired: AtomicBool,
+}
+
+impl Once {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        Once {
+            fired: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns `true` the first time, and `false` after that.
+    #[doc(hidden)]
+    #[inline]
+    pub fn first(&self) -> bool {
+        // A load first, so later hits don't keep writing to the cache line.
+        !self.fired.load(Ordering::Relaxed) && !self.fired.swap(true, Ordering::Relaxed)
+    }
+}
+
+#[doc(hidden)]
+pub struct Limiter {
+    /// The time in microseconds when the bucket is full again.
+    full: AtomicU32,
+}
+
+impl Limiter {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        Limiter {
+            full: AtomicU32::new(0),
+        }
+    }
+
+    /// Takes a token, if there's one left at `per_second`, or if there's no
+    /// clock to tell.
+    #[doc(hidden)]
+    pub fn allow(&self, per_second: u32) -> bool {
+        if per_second == 0 {
+            return false;
+        }
+        let now = match clock() {
+            Some(now) => now,
+            None => return true,
+        };
+        let interval = 1_000_000 / per_second;
+        let burst = interval * (per_second - 1);
+        let mut full = self.full.load(Ordering::Relaxed);
+        loop {
+            let ahead = full.wrapping_sub(now) as i32;
+            let from = if ahead <= 0 || ahead as u32 > burst + interval {
+                now
+            } else if ahead as u32 > burst {
+                return false;
+            } else {
+                full
+            };
+            match self.full.compare_exchange_weak(
+                full,
+                from.wrapping_add(interval),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => full = current,
+            }
+        }
+    }
+}
+
+static CLOCK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Sets the clock for rate-limited probes, as a function that returns the
+/// time in microseconds, wrapping at `u32::MAX`, instead of the system clock.
+pub fn set_clock(micros: fn() -> u32) {
+    CLOCK.store(micros as *mut (), Ordering::Relaxed);
+}
+
+fn clock() -> Option<u32> {
+    let micros = CLOCK.load(Ordering::Relaxed);
+    if !micros.is_null() {
+        let micros = unsafe { mem::transmute::<*mut (), fn() -> u32>(micros) };
+        return Some(micros());
+    }
+    system_clock()
+}
+
+#[cfg(all(
+    feature = "use_std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+fn system_clock() -> Option<u32> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(now.as_micros() as u32)
+}
+
+#[cfg(not(all(
+    feature = "use_std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+)))]
+fn system_clock() -> Option<u32> {
+    None
+}
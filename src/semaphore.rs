@@ -0,0 +1,67 @@
+//! Setting probe semaphores from the program
+//!
+//! SystemTap probes have a semaphore that tracers increment while they're
+//! attached, which is what `probe_lazy!` and `probe_enabled!` check. With the
+//! `use_std` feature, [`probe_semaphore!`](crate::probe_semaphore) gets a
+//! probe's own [`Semaphore`], so the program can arm it in the same way, e.g.
+//! for consumers of its own that are only interested in probes while they're
+//! enabled.
+//!
+//! ```
+//! use probe::{probe_enabled, probe_semaphore};
+//!
+//! if let Some(semaphore) = probe_semaphore!(foo, loop) {
+//!     semaphore.increment();
+//!     assert!(probe_enabled!(foo, loop));
+//!     semaphore.decrement();
+//! }
+//! ```
+//!
+//! Other platforms don't have semaphores, so there's no `Semaphore` for them.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// A probe's semaphore, from [`probe_semaphore!`](crate::probe_semaphore).
+///
+/// Each shared object has its own semaphore for a probe, like tracers see
+/// them, so this only arms the probe's sites in the same object.
+#[derive(Clone, Copy, Debug)]
+pub struct Semaphore {
+    count: &'static AtomicU16,
+}
+
+impl Semaphore {
+    /// The semaphore at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// `addr` has to be a probe's semaphore, which is aligned and lives for
+    /// the rest of the program.
+    #[doc(hidden)]
+    pub unsafe fn new(addr: *const u16) -> Self {
+        Semaphore {
+            count: &*(addr as *const AtomicU16),
+        }
+    }
+
+    /// The number of tracers and callers that have armed the probe.
+    pub fn count(&self) -> u16 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Arms the probe, as a tracer does when it attaches.
+    pub fn increment(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Disarms the probe, after [`increment`](Self::increment), as a tracer
+    /// does when it detaches. The probe stays enabled while anything else has
+    /// it armed.
+    pub fn decrement(&self) {
+        let _ = self
+            .count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            });
+    }
+}
@@ -0,0 +1,456 @@
+//! Exporting probes through shared memory
+//!
+//! With the `shm` feature on Unix, [`start`] writes every probe that's handled
+//! by the [`callback`] module to a ring of records in a named shared-memory
+//! segment, and a [`Reader`] in another process reads them from it as they
+//! come, without ptrace, uprobes, or any privileges, e.g. in a container where
+//! a tracer can't run. The reader only needs to run as the same user.
+//!
+//! ```no_run
+//! // In the traced program:
+//! probe::shm::start("/myapp-probes", 4096).unwrap();
+//! probe::control::enable("server");
+//! probe::probe!(server, parsed, 64);
+//!
+//! // In the observer:
+//! let mut reader = probe::shm::Reader::open("/myapp-probes").unwrap();
+//! loop {
+//!     for event in reader.read() {
+//!         println!("{}:{} {:?}", event.provider, event.name, event.args);
+//!     }
+//!     std::thread::sleep(std::time::Duration::from_millis(100));
+//! }
+//! ```
+//!
+//! Each record has the probe's ID, from [`rtt::id`](crate::rtt::id), the time
+//! it fired, as nanoseconds since the Unix epoch, so the times of different
+//! processes can be compared, and the first [`ARGS`] of its operands. The names
+//! of each probe are written once, in a table of [`SITES`] of them, which the
+//! reader looks the IDs up in. Records are overwritten once the ring is full,
+//! and a reader that falls behind skips them, and counts them as
+//! [`missed`](Reader::missed).
+//!
+//! The segment is left behind when the program exits, until it's removed, e.g.
+//! from `/dev/shm` on Linux, like the flags of
+//! [`control::share`](crate::control::share). There should only be one
+//! program writing to a segment at a time.
+//!
+//! The exporter needs 64-bit atomics, which a reader loads from a read-only
+//! mapping, so it's only on 64-bit targets.
+//!
+//! [`callback`]: crate::callback
+
+//
+// DEVELOPER NOTES
+//
+// The segment is a header, the site table, and then the ring, all of 64-bit
+// atomics, or bytes, in `repr(C)` structs, so its layout is the same in every
+// process, whatever its pointer size:
+//
+//   header: magic `probeshm`, version, capacity, sites, head, published, 2 spare
+//   site: ID, provider, name, each name NUL-padded to `NAME` bytes
+//   slot: sequence, time, ID, operand count, `ARGS` operands
+//
+// The slots are seqlocks, like those of the recorder, but with one ring for
+// the whole process, since the reader can't follow a list of them, so writers
+// take positions from the shared head. Sites are added under a lock, and then
+// `published` is raised with a release store, so a reader that sees a site
+// in the count sees its names. Each thread remembers which IDs it's seen
+// published, so only a probe's first hit on a thread takes the lock.
+//
+
+use crate::callback::{self, ProbeInfo};
+use core::cell::RefCell;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicPtr, AtomicU64, AtomicU8, Ordering};
+use std::boxed::Box;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::string::String;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::vec::Vec;
+
+/// The number of operands kept in each record.
+pub const ARGS: usize = 6;
+
+/// The number of probes whose names can be in a segment.
+pub const SITES: usize = 1024;
+
+/// The most bytes of a provider's or a probe's name that are kept.
+pub const NAME: usize = 64;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"probeshm");
+const VERSION: u64 = 1;
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    version: AtomicU64,
+    capacity: AtomicU64,
+    sites: AtomicU64,
+    head: AtomicU64,
+    published: AtomicU64,
+    spare: [AtomicU64; 2],
+}
+
+#[repr(C)]
+struct Site {
+    id: AtomicU64,
+    provider: [AtomicU8; NAME],
+    name: [AtomicU8; NAME],
+}
+
+#[repr(C)]
+struct Slot {
+    seq: AtomicU64,
+    time: AtomicU64,
+    id: AtomicU64,
+    len: AtomicU64,
+    args: [AtomicU64; ARGS],
+}
+
+// A mapped segment, which is only borrowed for as long as it's mapped.
+struct Segment {
+    addr: *mut u8,
+    capacity: usize,
+    sites: usize,
+}
+
+// The mapping is only atomics, which any thread can use.
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Segment {
+    // The length of a segment, or `None` if it's too long to be mapped.
+    fn len(capacity: usize, sites: usize) -> Option<usize> {
+        let sites = sites.checked_mul(mem::size_of::<Site>())?;
+        let slots = capacity.checked_mul(mem::size_of::<Slot>())?;
+        mem::size_of::<Header>()
+            .checked_add(sites)?
+            .checked_add(slots)
+    }
+
+    // The parts of a mapping, which is at least as long as they need.
+    unsafe fn new(addr: *mut u8, capacity: usize, sites: usize) -> Segment {
+        Segment {
+            addr,
+            capacity,
+            sites,
+        }
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.addr as *const Header) }
+    }
+
+    fn sites(&self) -> &[Site] {
+        unsafe {
+            let sites = self.addr.add(mem::size_of::<Header>()) as *const Site;
+            core::slice::from_raw_parts(sites, self.sites)
+        }
+    }
+
+    fn slots(&self) -> &[Slot] {
+        unsafe {
+            let sites = self.addr.add(mem::size_of::<Header>()) as *const Site;
+            let slots = sites.add(self.sites) as *const Slot;
+            core::slice::from_raw_parts(slots, self.capacity)
+        }
+    }
+
+    fn published(&self) -> &[Site] {
+        let published = self.header().published.load(Ordering::Acquire) as usize;
+        let sites = self.sites();
+        &sites[..published.min(sites.len())]
+    }
+}
+
+static SEGMENT: AtomicPtr<Segment> = AtomicPtr::new(ptr::null_mut());
+
+// Serializes adding sites to the table.
+static PUBLISH: Mutex<()> = Mutex::new(());
+
+std::thread_local! {
+    // The segment this thread has seen sites published in, and their IDs.
+    static PUBLISHED: RefCell<(usize, HashSet<u32>)> = RefCell::new((0, HashSet::new()));
+}
+
+fn segment() -> Option<&'static Segment> {
+    unsafe { SEGMENT.load(Ordering::Acquire).as_ref() }
+}
+
+/// Starts writing probes to the shared-memory segment called `name`, like
+/// `/myapp-probes`, in a ring of `capacity` records, by adding a `callback`
+/// handler.
+///
+/// The segment is created if it doesn't exist yet, and emptied if it does.
+pub fn start(name: &str, capacity: usize) -> io::Result<()> {
+    let capacity = capacity.max(1);
+    let len = Segment::len(capacity, SITES)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "capacity is too large"))?;
+    let (addr, _) = crate::control::map(name, Some((len, 0o600)))?;
+    let segment = unsafe { Segment::new(addr, capacity, SITES) };
+    let header = segment.header();
+    header.magic.store(0, Ordering::Relaxed);
+    header.capacity.store(capacity as u64, Ordering::Relaxed);
+    header.sites.store(SITES as u64, Ordering::Relaxed);
+    header.head.store(0, Ordering::Relaxed);
+    header.published.store(0, Ordering::Relaxed);
+    for slot in segment.slots() {
+        slot.seq.store(0, Ordering::Relaxed);
+    }
+    header.version.store(VERSION, Ordering::Relaxed);
+    header.magic.store(MAGIC, Ordering::Release);
+    // The old segment stays mapped, for probes that are still writing to it.
+    SEGMENT.store(Box::into_raw(Box::new(segment)), Ordering::Release);
+    callback::add_handler(record);
+    Ok(())
+}
+
+/// Stops writing probes, by removing its `callback` handler. The segment stays,
+/// with the records in it.
+pub fn stop() {
+    callback::remove_handler(record);
+}
+
+/// Writes a probe to the segment, if it's started, as a `callback` handler.
+pub fn record(info: &ProbeInfo, args: &[isize]) {
+    let segment = match segment() {
+        Some(segment) => segment,
+        None => return,
+    };
+    publish(segment, info);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    let pos = segment.header().head.fetch_add(1, Ordering::Relaxed);
+    let slot = &segment.slots()[(pos % segment.capacity as u64) as usize];
+    let seq = slot.seq.load(Ordering::Relaxed);
+    if seq & 1 != 0
+        || seq > pos * 2
+        || slot
+            .seq
+            .compare_exchange(seq, pos * 2 + 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+    {
+        return;
+    }
+    fence(Ordering::Release);
+    slot.time.store(time, Ordering::Relaxed);
+    slot.id.store(info.id() as u64, Ordering::Relaxed);
+    slot.len.store(args.len() as u64, Ordering::Relaxed);
+    for (slot, &arg) in slot.args.iter().zip(args) {
+        slot.store(arg as i64 as u64, Ordering::Relaxed);
+    }
+    slot.seq.store(pos * 2 + 2, Ordering::Release);
+}
+
+// Adds the probe's names to the site table, unless they're there already.
+fn publish(segment: &'static Segment, info: &ProbeInfo) {
+    let key = segment as *const Segment as usize;
+    let seen = PUBLISHED.try_with(|published| {
+        let mut published = published.borrow_mut();
+        if published.0 != key {
+            *published = (key, HashSet::new());
+        }
+        !published.1.insert(info.id())
+    });
+    if seen.unwrap_or(true) {
+        return;
+    }
+    let _lock = PUBLISH.lock().unwrap_or_else(|e| e.into_inner());
+    let id = info.id() as u64;
+    if segment
+        .published()
+        .iter()
+        .any(|site| site.id.load(Ordering::Relaxed) == id)
+    {
+        return;
+    }
+    let index = segment.header().published.load(Ordering::Relaxed) as usize;
+    if let Some(site) = segment.sites().get(index) {
+        site.id.store(id, Ordering::Relaxed);
+        fill(&site.provider, info.provider());
+        fill(&site.name, info.name());
+        segment
+            .header()
+            .published
+            .store(index as u64 + 1, Ordering::Release);
+    }
+}
+
+fn fill(bytes: &[AtomicU8; NAME], name: &str) {
+    let name = name.as_bytes();
+    for (i, byte) in bytes.iter().enumerate() {
+        byte.store(name.get(i).copied().unwrap_or(0), Ordering::Relaxed);
+    }
+}
+
+fn read_name(bytes: &[AtomicU8; NAME]) -> String {
+    let bytes: Vec<u8> = bytes
+        .iter()
+        .map(|byte| byte.load(Ordering::Relaxed))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// A probe that fired, read from a segment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// The time the probe fired, in nanoseconds since the Unix epoch.
+    pub time: u64,
+    /// The probe's ID, from [`rtt::id`](crate::rtt::id).
+    pub id: u32,
+    /// The probe's provider, or empty if it isn't in the site table.
+    pub provider: String,
+    /// The probe's name, or empty if it isn't in the site table.
+    pub name: String,
+    /// The number of operands the probe had, which can be more than [`ARGS`].
+    pub len: usize,
+    /// The operands of the probe, up to [`ARGS`] of them.
+    pub args: Vec<i64>,
+}
+
+/// Reads the probes that a program writes to a segment, from another process.
+///
+/// A segment that's started again, with another capacity, isn't read any more,
+/// and has to be opened again.
+pub struct Reader {
+    segment: Segment,
+    len: usize,
+    next: u64,
+    missed: u64,
+    names: HashMap<u32, (String, String)>,
+}
+
+impl Reader {
+    /// Opens the shared-memory segment called `name`, for reading, from the
+    /// probes that fire after it's opened.
+    pub fn open(name: &str) -> io::Result<Reader> {
+        let (addr, len) = crate::control::map(name, None)?;
+        let segment = match unsafe { Reader::segment(addr, len) } {
+            Ok(segment) => segment,
+            Err(error) => {
+                unsafe { crate::control::unmap(addr, len) };
+                return Err(error);
+            }
+        };
+        let next = segment.header().head.load(Ordering::Acquire);
+        Ok(Reader {
+            segment,
+            len,
+            next,
+            missed: 0,
+            names: HashMap::new(),
+        })
+    }
+
+    // The segment of a mapping, if it's a started one.
+    unsafe fn segment(addr: *mut u8, len: usize) -> io::Result<Segment> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        if len < mem::size_of::<Header>() {
+            return Err(invalid("segment is too short"));
+        }
+        let header = &*(addr as *const Header);
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(invalid("segment isn't started"));
+        }
+        if header.version.load(Ordering::Relaxed) != VERSION {
+            return Err(invalid("segment is of an unknown version"));
+        }
+        let capacity = usize::try_from(header.capacity.load(Ordering::Relaxed));
+        let sites = usize::try_from(header.sites.load(Ordering::Relaxed));
+        let (capacity, sites) = match (capacity, sites) {
+            (Ok(capacity), Ok(sites)) if capacity > 0 => (capacity, sites),
+            _ => return Err(invalid("segment is of an unknown size")),
+        };
+        match Segment::len(capacity, sites) {
+            Some(needed) if needed <= len => Ok(Segment::new(addr, capacity, sites)),
+            _ => Err(invalid("segment is too short")),
+        }
+    }
+
+    /// The probes that have fired since the last read, in the order they took
+    /// their records, up to the first that's still being written.
+    pub fn read(&mut self) -> Vec<Event> {
+        let header = self.segment.header();
+        if header.capacity.load(Ordering::Relaxed) != self.segment.capacity as u64 {
+            return Vec::new();
+        }
+        let head = header.head.load(Ordering::Acquire);
+        let capacity = self.segment.capacity as u64;
+        // A segment that was started again starts over.
+        if head < self.next {
+            self.next = 0;
+        }
+        let from = self.next.max(head.saturating_sub(capacity));
+        self.missed += from - self.next;
+        self.next = from;
+        let mut events = Vec::new();
+        while self.next < head {
+            let pos = self.next;
+            let slot = &self.segment.slots()[(pos % capacity) as usize];
+            let seq = slot.seq.load(Ordering::Acquire);
+            // Not written yet, so it's read next time, unless it's been
+            // overwritten by then.
+            if seq < pos * 2 + 2 {
+                break;
+            }
+            if seq > pos * 2 + 2 {
+                self.missed += 1;
+                self.next += 1;
+                continue;
+            }
+            let time = slot.time.load(Ordering::Relaxed);
+            let id = slot.id.load(Ordering::Relaxed) as u32;
+            let len = slot.len.load(Ordering::Relaxed) as usize;
+            let args: Vec<_> = slot.args[..len.min(ARGS)]
+                .iter()
+                .map(|arg| arg.load(Ordering::Relaxed) as i64)
+                .collect();
+            fence(Ordering::Acquire);
+            self.next += 1;
+            if slot.seq.load(Ordering::Relaxed) != pos * 2 + 2 {
+                self.missed += 1;
+                continue;
+            }
+            let (provider, name) = self.names(id);
+            events.push(Event {
+                time,
+                id,
+                provider,
+                name,
+                len,
+                args,
+            });
+        }
+        events
+    }
+
+    /// The number of probes that were overwritten, or dropped, before they
+    /// could be read.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    fn names(&mut self, id: u32) -> (String, String) {
+        if !self.names.contains_key(&id) {
+            for site in self.segment.published() {
+                let site_id = site.id.load(Ordering::Relaxed) as u32;
+                self.names
+                    .entry(site_id)
+                    .or_insert_with(|| (read_name(&site.provider), read_name(&site.name)));
+            }
+        }
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        unsafe { crate::control::unmap(self.segment.addr, self.len) };
+    }
+}
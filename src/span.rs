@@ -0,0 +1,70 @@
+//! Spans
+//!
+//! [`probe_span!`](crate::probe_span) fires a probe with the `span = begin`
+//! option right away, and returns a [`SpanGuard`] that fires the same probe
+//! with `span = end` when it's dropped, so the ends of a span stay paired
+//! however the scope is left, by an early return, a `?`, or a panic that
+//! unwinds.
+//!
+//! ```
+//! # use probe::probe_span;
+//! fn handle(request: u32) -> Result<(), ()> {
+//!     let _span = probe_span!(server, request, request);
+//!     if request == 0 {
+//!         return Err(());
+//!     }
+//!     Ok(())
+//! }
+//! # handle(0).unwrap_err();
+//! ```
+//!
+//! [`probe_block!`](crate::probe_block) fires the two ends around a block,
+//! and yields its value, without a guard, for a block that doesn't leave
+//! early.
+//!
+//! Platforms with spans of their own, like os_signpost, show these as
+//! intervals. SDT probes don't have spans, so the ends are probes of their
+//! own, named like `request__begin` and `request__end`. Only the beginning
+//! has the arguments, and the end has the same options, like its `level`.
+
+/// The end of a span that a probe is, from its `span` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Span {
+    /// `span = begin`
+    Begin,
+    /// `span = end`
+    End,
+}
+
+// The end of a span from a probe's options, as the number of its `Span`, or 0
+// without one.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_span_value(
+    () => (0);
+    (span = begin $(, $($rest:tt)*)?) => (1);
+    (span = end $(, $($rest:tt)*)?) => (2);
+    ($key:ident = $value:expr $(, $($rest:tt)*)?) => ($crate::probe_span_value!($($($rest)*)?));
+);
+
+/// A guard that fires the end of a span when it's dropped, from
+/// [`probe_span!`](crate::probe_span).
+#[must_use = "the span ends when the guard is dropped"]
+pub struct SpanGuard<F: FnMut()> {
+    end: F,
+}
+
+impl<F: FnMut()> SpanGuard<F> {
+    #[doc(hidden)]
+    #[inline]
+    pub fn new(end: F) -> Self {
+        SpanGuard { end }
+    }
+}
+
+impl<F: FnMut()> Drop for SpanGuard<F> {
+    #[inline]
+    fn drop(&mut self) {
+        (self.end)()
+    }
+}
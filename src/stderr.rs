@@ -0,0 +1,48 @@
+// Printing probes to stderr, with the `stderr` feature.
+//
+// DEVELOPER NOTES
+//
+// This goes through the `callback` module, which handles every probe that
+// fires with the feature, unless they're killed, without a handler or any
+// flags, but doesn't enable them, so lazy probes are only printed when they're
+// enabled for some other reason.
+//
+// Each probe is a line, written while stderr is locked, so the lines of
+// different threads aren't mixed, and errors are ignored, since there's
+// nowhere else to report them.
+//
+
+use crate::callback::ProbeInfo;
+use core::fmt::Write as _;
+use std::io::{self, Write};
+
+// A probe's line, e.g. `probe foo:alloc size=64 align=16 (src/main.rs:10)`,
+// with its format, if it has one, or its operands, by name where they have
+// one.
+pub(crate) fn print(info: &ProbeInfo, args: &[isize]) {
+    let mut line = std::string::String::from("probe ");
+    line.push_str(info.provider());
+    line.push(':');
+    line.push_str(info.name());
+    match info.format() {
+        Some(format) => {
+            line.push(' ');
+            let _ = crate::format::write(&mut line, format, args);
+        }
+        None => {
+            for (name, arg) in info.operands(args) {
+                let _ = match name {
+                    Some(name) => write!(line, " {}={}", name, arg),
+                    None => write!(line, " {}", arg),
+                };
+            }
+        }
+    }
+    let _ = writeln!(
+        io::stderr().lock(),
+        "{} ({}:{})",
+        line,
+        info.file(),
+        info.line()
+    );
+}
@@ -0,0 +1,286 @@
+//! Probe tables for firmware
+//!
+//! Bare-metal targets don't have notes for tools to find probes, and the ITM,
+//! RTT, and STM records only have a numeric ID for each probe, from
+//! [`rtt::id`](crate::rtt::id). With the `table` feature on bare-metal
+//! targets, every probe site also adds an entry to a table in the read-only
+//! `probe_table` section, which ends up in flash, so host tools can decode
+//! those IDs from the ELF file, whichever transport the records came over.
+//!
+//! The section is a sequence of entries, each with:
+//!
+//! * the probe's 32-bit ID, little-endian,
+//! * the provider, as a LEB128 length followed by UTF-8 bytes,
+//! * the name, in the same way,
+//! * the number of arguments, as a LEB128 number,
+//! * the probe's [`Level`], as a byte from 1 for `critical` to 6 for `trace`,
+//! * its [format](crate::format), like the provider, and empty without one,
+//! * the file of its site, as `file!()` gives it, like the provider,
+//! * and the line of its site, as a LEB128 number.
+//!
+//! There's an entry for each probe site, so a probe that's used in several
+//! places has an entry for each of them, and none for probes that are
+//! compiled out, with `PROBE_DISABLE` or their [`level`](crate::level).
+//! [`entries`] reads them from the contents of the section, and on the
+//! target, [`section`] has the table itself, e.g. for the firmware to send it
+//! to the host at startup.
+
+//
+// DEVELOPER NOTES
+//
+// The linker concatenates the entries, which are byte arrays built by a
+// `const fn`, with no alignment padding. The section name is a C identifier,
+// so the linker defines `__start_probe_table` and `__stop_probe_table` for it,
+// and `#[used]` makes it retained, so `--gc-sections` doesn't drop entries
+// just because nothing refers to them.
+//
+// The records in the `probe` custom section for WebAssembly are the same as
+// these entries without the ID, so they're built by the same functions.
+//
+
+use crate::level::Level;
+
+#[cfg(all(target_os = "none", feature = "table"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_table(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => (
+        // In its own scope, so these don't shadow anything in the arguments.
+        const _: () = {
+            const PROVIDER: &str = $crate::probe_provider!($provider);
+            const NAME: &str = $crate::probe_name!($name);
+            const ARGS: usize = <[&str]>::len(&[$(stringify!($arg),)*]);
+            const LEVEL: u8 = $crate::probe_level_value!($($opt)*);
+            const FORMAT: &str = $crate::probe_format_value!($($opt)*);
+            const FILE: &str = file!();
+            const LINE: u32 = line!();
+            const LEN: usize = $crate::table::record_len(PROVIDER, NAME, ARGS, FORMAT, FILE, LINE);
+            const ENTRY: $crate::table::Entry<LEN> = $crate::table::Entry {
+                id: $crate::rtt::id(PROVIDER, NAME).to_le_bytes(),
+                record: $crate::table::record(PROVIDER, NAME, ARGS, LEVEL, FORMAT, FILE, LINE),
+            };
+
+            // No entry for a probe that's compiled out.
+            #[used]
+            #[link_section = "probe_table"]
+            static ENTRIES: [$crate::table::Entry<LEN>; $crate::probe_compiled_in!($provider, [$($opt)*]) as usize] =
+                [ENTRY; $crate::probe_compiled_in!($provider, [$($opt)*]) as usize];
+        };
+    )
+);
+
+#[cfg(not(all(target_os = "none", feature = "table")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! probe_table(
+    ($provider:tt, $name:tt, [$($opt:tt)*], $($arg:expr,)*) => ()
+);
+
+/// A probe in the table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Probe<'a> {
+    /// The probe's ID, from [`rtt::id`](crate::rtt::id).
+    pub id: u32,
+    /// The probe's provider.
+    pub provider: &'a str,
+    /// The probe's name.
+    pub name: &'a str,
+    /// The number of arguments the probe has.
+    pub args: usize,
+    /// The probe's level.
+    pub level: Level,
+    /// The probe's format, if it has one.
+    pub format: Option<&'a str>,
+    /// The file of the probe's site.
+    pub file: &'a str,
+    /// The line of the probe's site.
+    pub line: u32,
+}
+
+/// An iterator over the probes in a table, from [`entries`].
+#[derive(Clone, Debug)]
+pub struct Entries<'a> {
+    bytes: &'a [u8],
+}
+
+/// Reads the probes in a table, from the contents of its section.
+///
+/// The iterator stops early at an entry that isn't valid.
+pub fn entries(section: &[u8]) -> Entries<'_> {
+    Entries { bytes: section }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Probe<'a>;
+
+    fn next(&mut self) -> Option<Probe<'a>> {
+        let mut bytes = self.bytes;
+        let probe = Probe {
+            id: u32::from_le_bytes(take(&mut bytes, 4)?.try_into().ok()?),
+            provider: string(&mut bytes)?,
+            name: string(&mut bytes)?,
+            args: leb128_read(&mut bytes)?,
+            level: Level::from_u8(take(&mut bytes, 1)?[0])?,
+            format: Some(string(&mut bytes)?).filter(|format| !format.is_empty()),
+            file: string(&mut bytes)?,
+            line: leb128_read(&mut bytes)?.try_into().ok()?,
+        };
+        self.bytes = bytes;
+        Some(probe)
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if bytes.len() < len {
+        return None;
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Some(head)
+}
+
+fn string<'a>(bytes: &mut &'a [u8]) -> Option<&'a str> {
+    let len = leb128_read(bytes)?;
+    core::str::from_utf8(take(bytes, len)?).ok()
+}
+
+fn leb128_read(bytes: &mut &[u8]) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = take(bytes, 1)?[0];
+        value |= ((byte & 0x7f) as usize).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+/// The table of probes in this program's `probe_table` section.
+#[cfg(all(target_os = "none", feature = "table"))]
+pub fn section() -> &'static [u8] {
+    extern "C" {
+        static __start_probe_table: u8;
+        static __stop_probe_table: u8;
+    }
+    unsafe {
+        let start = core::ptr::addr_of!(__start_probe_table);
+        let stop = core::ptr::addr_of!(__stop_probe_table);
+        core::slice::from_raw_parts(start, stop as usize - start as usize)
+    }
+}
+
+#[doc(hidden)]
+#[repr(C)]
+pub struct Entry<const LEN: usize> {
+    pub id: [u8; 4],
+    pub record: [u8; LEN],
+}
+
+/// The size of a probe's record, after its ID.
+#[doc(hidden)]
+pub const fn record_len(
+    provider: &str,
+    name: &str,
+    args: usize,
+    format: &str,
+    file: &str,
+    line: u32,
+) -> usize {
+    leb128_len(provider.len())
+        + provider.len()
+        + leb128_len(name.len())
+        + name.len()
+        + leb128_len(args)
+        + 1
+        + leb128_len(format.len())
+        + format.len()
+        + leb128_len(file.len())
+        + file.len()
+        + leb128_len(line as usize)
+}
+
+/// A probe's record, after its ID, of size `record_len`.
+#[doc(hidden)]
+pub const fn record<const LEN: usize>(
+    provider: &str,
+    name: &str,
+    args: usize,
+    level: u8,
+    format: &str,
+    file: &str,
+    line: u32,
+) -> [u8; LEN] {
+    let mut record = [0; LEN];
+    let mut offset = 0;
+    let strings = [provider.as_bytes(), name.as_bytes(), &[]];
+    let mut i = 0;
+    while i < strings.len() {
+        let bytes = strings[i];
+        // The last field is just the argument count, without any bytes.
+        let value = if i < strings.len() - 1 {
+            bytes.len()
+        } else {
+            args
+        };
+        let (leb128, len) = leb128(value);
+        let mut j = 0;
+        while j < len {
+            record[offset] = leb128[j];
+            offset += 1;
+            j += 1;
+        }
+        j = 0;
+        while j < bytes.len() {
+            record[offset] = bytes[j];
+            offset += 1;
+            j += 1;
+        }
+        i += 1;
+    }
+    record[offset] = level;
+    offset += 1;
+    let strings = [format.as_bytes(), file.as_bytes(), &[]];
+    i = 0;
+    while i < strings.len() {
+        let bytes = strings[i];
+        // The last field is just the line, without any bytes.
+        let value = if i < strings.len() - 1 {
+            bytes.len()
+        } else {
+            line as usize
+        };
+        let (leb128, len) = leb128(value);
+        let mut j = 0;
+        while j < len {
+            record[offset] = leb128[j];
+            offset += 1;
+            j += 1;
+        }
+        j = 0;
+        while j < bytes.len() {
+            record[offset] = bytes[j];
+            offset += 1;
+            j += 1;
+        }
+        i += 1;
+    }
+    record
+}
+
+const fn leb128_len(value: usize) -> usize {
+    leb128(value).1
+}
+
+const fn leb128(mut value: usize) -> ([u8; 10], usize) {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    while value >= 0x80 {
+        bytes[len] = (value & 0x7f) as u8 | 0x80;
+        value >>= 7;
+        len += 1;
+    }
+    bytes[len] = value as u8;
+    (bytes, len + 1)
+}
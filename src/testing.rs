@@ -0,0 +1,230 @@
+//! Testing that probes fire
+//!
+//! With the `testing` feature, a test can [`capture`] the probes of a provider
+//! that fire on its thread, run the code under test, and then check which of
+//! them fired, with which operands, without a tracer. It's meant for a crate's
+//! tests, e.g. as a dev-dependency feature, like `force_enable`.
+//!
+//! ```
+//! use probe::probe;
+//!
+//! fn resize(len: usize) {
+//!     probe!(cache, resized, len);
+//! }
+//!
+//! let capture = probe::testing::capture("cache");
+//! resize(64);
+//! capture.assert_fired("resized", &[64]);
+//! capture.assert_not_fired("evicted");
+//! ```
+//!
+//! A capture enables its provider with the [`control`] module while it's
+//! there, and takes the probes from the [`callback`] module, with a handler of
+//! its own. The operands are as a tracer would see them, so a `@str` argument
+//! is its address and its length. Only probes that fire on the capture's
+//! thread are captured, so the tests of a crate can run in parallel, but
+//! probes on threads that the code under test spawns aren't.
+//!
+//! [`callback`]: crate::callback
+//! [`control`]: crate::control
+
+//
+// DEVELOPER NOTES
+//
+// Each thread has a list of its captures, so they can nest, e.g. of different
+// providers. The providers they enable are counted, so one test's capture
+// doesn't disable a provider while another's is still capturing it, and one
+// that was enabled before any capture stays enabled after them. The handler
+// is there while any thread has a capture, and removed with the last one, so
+// it doesn't keep a slot of the `callback` module after the tests.
+//
+
+use crate::callback::{self, ProbeInfo};
+use crate::control;
+use core::cell::RefCell;
+use core::fmt;
+use std::rc::Rc;
+use std::string::String;
+use std::sync::Mutex;
+use std::vec::Vec;
+
+/// A probe that fired during a [`Capture`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fired {
+    /// The probe's provider.
+    pub provider: &'static str,
+    /// The probe's name.
+    pub name: &'static str,
+    /// The names of the probe's operands, as in
+    /// [`ProbeInfo::names`](crate::callback::ProbeInfo::names).
+    pub names: Option<&'static str>,
+    /// The probe's operands.
+    pub args: Vec<isize>,
+}
+
+impl fmt::Display for Fired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{} {:?}", self.provider, self.name, self.args)
+    }
+}
+
+type Fires = Rc<RefCell<Vec<Fired>>>;
+
+std::thread_local! {
+    static CAPTURES: RefCell<Vec<(&'static str, Fires)>> = const { RefCell::new(Vec::new()) };
+}
+
+// The providers that captures have enabled, and how many captures of each
+// there are.
+static ENABLED: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+
+// How many captures there are, on any thread, while the handler is there.
+static CAPTURING: Mutex<usize> = Mutex::new(0);
+
+fn capturing() -> std::sync::MutexGuard<'static, usize> {
+    CAPTURING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn handler(info: &ProbeInfo, args: &[isize]) {
+    // Not while the thread's locals are being destroyed, or from a probe that
+    // fires while a capture is being changed.
+    let _ = CAPTURES.try_with(|captures| {
+        if let Ok(captures) = captures.try_borrow() {
+            for (provider, fired) in captures.iter() {
+                if *provider == info.provider() {
+                    fired.borrow_mut().push(Fired {
+                        provider: info.provider(),
+                        name: info.name(),
+                        names: info.names(),
+                        args: args.to_vec(),
+                    });
+                }
+            }
+        }
+    });
+}
+
+/// Captures the probes of a provider that fire on this thread, until it's
+/// dropped.
+pub fn capture(provider: &'static str) -> Capture {
+    {
+        let mut enabled = ENABLED.lock().unwrap_or_else(|e| e.into_inner());
+        match enabled.iter_mut().find(|(name, _)| name == provider) {
+            Some((_, count)) => *count += 1,
+            None if !control::is_enabled(provider) => {
+                control::enable(provider);
+                enabled.push((provider.into(), 1));
+            }
+            None => {}
+        }
+    }
+    {
+        let mut capturing = capturing();
+        if *capturing == 0 {
+            callback::add_handler(handler);
+        }
+        *capturing += 1;
+    }
+    let fired = Fires::default();
+    CAPTURES.with(|captures| captures.borrow_mut().push((provider, fired.clone())));
+    Capture { provider, fired }
+}
+
+/// The probes of a provider that fired on a thread, from [`capture`].
+#[derive(Debug)]
+pub struct Capture {
+    provider: &'static str,
+    fired: Fires,
+}
+
+impl Capture {
+    /// The probes that have fired so far, in the order they fired.
+    pub fn fired(&self) -> Vec<Fired> {
+        self.fired.borrow().clone()
+    }
+
+    /// How many times a probe has fired so far.
+    pub fn count(&self, name: &str) -> usize {
+        self.fired
+            .borrow()
+            .iter()
+            .filter(|fired| fired.name == name)
+            .count()
+    }
+
+    /// Takes the probes that have fired so far, so the next checks only see
+    /// those that fire after.
+    pub fn clear(&self) -> Vec<Fired> {
+        self.fired.take()
+    }
+
+    /// Panics, with the probes that fired, unless a probe has fired with these
+    /// operands.
+    #[track_caller]
+    pub fn assert_fired(&self, name: &str, args: &[isize]) {
+        let fired = self.fired.borrow();
+        if !fired
+            .iter()
+            .any(|fired| fired.name == name && fired.args == args)
+        {
+            panic!(
+                "probe {}:{} {:?} didn't fire, but these did:\n{}",
+                self.provider,
+                name,
+                args,
+                List(&fired)
+            );
+        }
+    }
+
+    /// Panics, with the probes that fired, if a probe has fired.
+    #[track_caller]
+    pub fn assert_not_fired(&self, name: &str) {
+        let fired = self.fired.borrow();
+        if fired.iter().any(|fired| fired.name == name) {
+            panic!("probe {}:{} fired:\n{}", self.provider, name, List(&fired));
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = CAPTURES.try_with(|captures| {
+            captures
+                .borrow_mut()
+                .retain(|(_, fired)| !Rc::ptr_eq(fired, &self.fired))
+        });
+        let mut enabled = ENABLED.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(i) = enabled.iter().position(|(name, _)| name == self.provider) {
+            enabled[i].1 -= 1;
+            if enabled[i].1 == 0 {
+                control::disable(self.provider);
+                enabled.swap_remove(i);
+            }
+        }
+        drop(enabled);
+        let mut capturing = capturing();
+        *capturing -= 1;
+        if *capturing == 0 {
+            callback::remove_handler(handler);
+        }
+    }
+}
+
+// The probes that fired, a line each.
+struct List<'a>(&'a [Fired]);
+
+impl fmt::Display for List<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("  (none)");
+        }
+        for (i, fired) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "  {}", fired)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use probe::arg::{f32_from_arg, f64_from_arg};
+use probe::{probe, probe_lazy, probe_rate_limited, probe_sampled, ProbeArg};
+use std::ptr::NonNull;
+use std::time::{Duration, Instant};
+
+#[test]
+fn floats() {
+    assert_eq!(f32_from_arg(1.5f32.to_bits() as isize), 1.5);
+    assert_eq!(f32_from_arg((-0.25f32).to_bits() as isize), -0.25);
+    // An `f64` only fits in 64-bit arguments.
+    let bits = (-2.5f64).to_bits() as isize;
+    if cfg!(target_pointer_width = "64") {
+        assert_eq!(f64_from_arg(bits), -2.5);
+    }
+}
+
+#[test]
+fn halves() {
+    use probe::arg::{f64_from_args, i64_from_args, u64_from_args};
+
+    let n = 0x1234_5678_9abc_def0u64;
+    assert_eq!(u64_from_args(n as u32 as isize, (n >> 32) as isize), n);
+    let n = -(1i64 << 40) - 7;
+    assert_eq!(i64_from_args(n as u32 as isize, (n >> 32) as isize), n);
+    let bits = (-2.5f64).to_bits();
+    assert_eq!(
+        f64_from_args(bits as u32 as isize, (bits >> 32) as isize),
+        -2.5
+    );
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn wide() {
+    use probe::arg::{i128_from_args, u128_from_args};
+
+    let id = 0x1234_5678_9abc_def0_0fed_cba9_8765_4321u128;
+    assert_eq!(u128_from_args(id as isize, (id >> 64) as isize), id);
+    let n = -(1i128 << 100) - 7;
+    assert_eq!(i128_from_args(n as isize, (n >> 64) as isize), n);
+}
+
+#[test]
+fn named() {
+    let (i, total, path) = (1, 2, "/tmp");
+    probe!(named, plain, i = i, total = total);
+    probe!(named, mixed, i, total = total, path = @str path,);
+    probe!(level: debug, named, options, i = i; keyword = 1);
+    let forced = cfg!(feature = "force_enable");
+    let lazy = probe_lazy!(named, lazy, i = i, total = { total + 1 }; level = info);
+    assert_eq!(lazy, forced);
+    assert_eq!(probe_sampled!(2, named, sampled, i = i), forced);
+    assert_eq!(
+        probe_rate_limited!(10, level: warn, named, limited, i = i, @u8 3),
+        forced
+    );
+}
+
+#[test]
+fn custom() {
+    struct Fd(i32);
+
+    impl ProbeArg for Fd {
+        fn into_probe_arg(self) -> isize {
+            self.0 as isize
+        }
+    }
+
+    assert_eq!(Fd(-3).into_probe_arg(), -3);
+    assert_eq!(true.into_probe_arg(), 1);
+    assert_eq!('a'.into_probe_arg(), 97);
+    assert_eq!((8 as *const u8).into_probe_arg(), 8);
+
+    // Pointers and references are their addresses.
+    let mut buf = [0u8; 4];
+    let addr = buf.as_ptr() as isize;
+    assert_eq!((&buf).into_probe_arg(), addr);
+    assert_eq!((&buf[..]).into_probe_arg(), addr);
+    assert_eq!((&mut buf).into_probe_arg(), addr);
+    assert_eq!(buf.as_mut_ptr().into_probe_arg(), addr);
+    assert_eq!(NonNull::from(&buf).into_probe_arg(), addr);
+    assert_eq!(Some(&buf).into_probe_arg(), addr);
+    assert_eq!(Some(NonNull::from(&buf)).into_probe_arg(), addr);
+    assert_eq!(None::<&[u8]>.into_probe_arg(), 0);
+    assert_eq!(None::<&mut u8>.into_probe_arg(), 0);
+    assert_eq!(None::<NonNull<u8>>.into_probe_arg(), 0);
+    probe!(custom, pointers, &buf, buf.as_ptr(), &mut buf, "str");
+    let cache = [(1, "one")];
+    let hit = cache.iter().find(|entry| entry.0 == 2);
+    probe!(custom, lookup, 2, hit);
+    probe!(custom, fd, Fd(3), 4, fd = Fd(5), @u8 6);
+    let mut evaluated = false;
+    probe_lazy!(custom, lazy, {
+        evaluated = true;
+        Fd(7)
+    });
+    assert_eq!(evaluated, cfg!(feature = "force_enable"));
+}
+
+#[test]
+fn durations() {
+    assert_eq!(Duration::from_micros(1500).into_probe_arg(), 1_500_000);
+    assert_eq!(Duration::MAX.into_probe_arg(), isize::MAX);
+    let start = Instant::now();
+    let elapsed = start.elapsed();
+    assert!(elapsed.into_probe_arg() >= 0);
+    probe!(custom, timed, start.elapsed(), Instant::now() - start);
+}
+
+#[test]
+fn formatted() {
+    use probe::arg::{FmtArg, FMT_CAPACITY};
+    use std::cell::Cell;
+    use std::fmt;
+
+    struct Counted<'a>(&'a Cell<u32>);
+
+    impl fmt::Debug for Counted<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.set(self.0.get() + 1);
+            f.write_str("counted")
+        }
+    }
+
+    // Lazy probes only format their arguments while they're enabled.
+    let count = Cell::new(0);
+    probe!(formatted, eager, @debug Counted(&count), @display 42);
+    assert_eq!(count.get(), 1);
+    let lazy = probe_lazy!(formatted, lazy, value = @debug Counted(&count));
+    assert_eq!(count.get(), 1 + lazy as u32);
+
+    // Long values are cut off at a character boundary.
+    let long = "é".repeat(FMT_CAPACITY);
+    assert_eq!(FmtArg::display(&long).as_str().len(), FMT_CAPACITY);
+    assert_eq!(FmtArg::debug(&long).as_str().len(), FMT_CAPACITY - 1);
+    assert_eq!(FmtArg::debug(&(1, "a")).as_str(), "(1, \"a\")");
+}
+
+#[cfg(feature = "use_std")]
+#[test]
+fn payload() {
+    use probe::arg::PayloadArg;
+    use probe::ProbePayload;
+    use std::cell::Cell;
+
+    struct Point<'a> {
+        x: u8,
+        y: u8,
+        encoded: &'a Cell<u32>,
+    }
+
+    impl ProbePayload for Point<'_> {
+        fn encode_payload(&self, buf: &mut Vec<u8>) {
+            self.encoded.set(self.encoded.get() + 1);
+            buf.extend_from_slice(&[self.x, self.y]);
+        }
+    }
+
+    // Lazy probes only encode their payloads while they're enabled.
+    let encoded = Cell::new(0);
+    let point = Point {
+        x: 1,
+        y: 2,
+        encoded: &encoded,
+    };
+    probe!(payload, eager, @payload point);
+    assert_eq!(encoded.get(), 1);
+    let lazy = probe_lazy!(payload, lazy, point = @payload &point);
+    assert_eq!(encoded.get(), 1 + lazy as u32);
+
+    // The buffer is reused, but each payload starts out empty.
+    assert_eq!(PayloadArg::encode(&point).as_bytes(), [1, 2]);
+    assert_eq!(PayloadArg::encode(&point).as_bytes(), [1, 2]);
+}
@@ -0,0 +1,28 @@
+use probe::{probe_assert, probe_debug_assert};
+
+#[test]
+fn held() {
+    let mut evaluated = false;
+    assert!(probe_assert!(assert, held, 1 + 1 == 2, {
+        evaluated = true;
+        1
+    }));
+    assert!(!evaluated, "arguments are only evaluated when it fails");
+    assert!(probe_assert!(level: warn, assert, held, true));
+    probe_debug_assert!(assert, held, !evaluated, 2; level = error);
+}
+
+#[test]
+fn failed() {
+    let len = 5;
+    assert!(!probe_assert!(assert, failed, len < 4, len));
+    assert!(!probe_assert!(assert, failed, len < 4; level = error));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "probe assertion failed: len < 4")]
+fn debug_failed() {
+    let len = 5;
+    probe_debug_assert!(level: error, assert, failed, len < 4, len);
+}
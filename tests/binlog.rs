@@ -0,0 +1,157 @@
+#![cfg(feature = "binlog")]
+
+mod common;
+
+use common::Shared;
+use probe::binlog::{self, decode::Reader};
+use probe::level::Level;
+use probe::span::Span;
+use probe::{control, probe, probe_fmt, probe_span};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn logged() {
+    let out = Shared::default();
+    binlog::start(out.clone());
+    control::enable("logged");
+
+    for i in 0..3 {
+        probe!(logged, looped, i, -i, len = isize::MIN; level = warn);
+    }
+    thread::Builder::new()
+        .name("worker".into())
+        .spawn(|| {
+            let _span = probe_span!(logged, request, id = 7);
+            probe_fmt!(logged, formatted, "size=%d", 8);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    probe!(unlogged, other);
+    binlog::finish().unwrap();
+    control::disable("logged");
+
+    let log = out.0.lock().unwrap().clone();
+    let mut reader = Reader::new(&log[..]).unwrap();
+    let events: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+    assert!(events.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    let fired: Vec<_> = events
+        .iter()
+        .map(|event| {
+            (
+                event.site.name.as_str(),
+                reader.thread_name(event.thread),
+                event.args.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        fired,
+        [
+            ("looped", Some("logged"), vec![0, 0, isize::MIN as i64]),
+            ("looped", Some("logged"), vec![1, -1, isize::MIN as i64]),
+            ("looped", Some("logged"), vec![2, -2, isize::MIN as i64]),
+            ("request", Some("worker"), vec![7]),
+            ("formatted", Some("worker"), vec![8]),
+            ("request", Some("worker"), vec![]),
+        ]
+    );
+
+    // Each site is only in the log once.
+    let sites = reader.sites();
+    assert_eq!(sites.len(), 4);
+    assert_eq!(sites[0].provider, "logged");
+    assert_eq!(sites[0].names.as_deref(), Some("_ _ len"));
+    assert_eq!(sites[0].level, Level::Warn);
+    assert_eq!(sites[0].file, file!());
+    assert_eq!(sites[1].span, Some(Span::Begin));
+    assert_eq!(sites[2].format.as_deref(), Some("size=%d"));
+    assert_eq!(sites[3].span, Some(Span::End));
+    assert!(Arc::ptr_eq(&events[0].site, &events[2].site));
+
+    // Each string is only in the log once, whatever its sites, besides the
+    // thread's name, which is the test's.
+    let count = |string: &str| {
+        log.windows(string.len())
+            .filter(|window| *window == string.as_bytes())
+            .count()
+    };
+    assert_eq!(count("logged"), 2);
+    assert_eq!(count(file!()), 1);
+
+    // A log that's cut short is read up to its last whole record.
+    for len in 0..log.len() {
+        match Reader::new(&log[..len]) {
+            Ok(reader) => {
+                let mut read = reader.collect::<Vec<_>>();
+                if let Some(Err(error)) = read.last() {
+                    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+                    read.pop();
+                }
+                let read: Vec<_> = read.into_iter().map(Result::unwrap).collect();
+                assert_eq!(read, events[..read.len()]);
+            }
+            Err(error) => assert_eq!(error.kind(), ErrorKind::UnexpectedEof),
+        }
+    }
+}
+
+#[test]
+fn not_a_log() {
+    let error = Reader::new(&b"not a probe log"[..]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[cfg(all(feature = "recorder", target_has_atomic = "64"))]
+#[test]
+fn recorded() {
+    use probe::recorder;
+    use probe::rtt::id;
+
+    recorder::start(16);
+    control::enable("dumped");
+    for i in 0..3 {
+        probe!(dumped, looped, i, -i);
+    }
+    probe!(dumped, wide, 1, 2, 3, 4, 5, 6, 7; level = debug);
+    recorder::stop();
+    control::disable("dumped");
+    let records: Vec<_> = recorder::drain()
+        .into_iter()
+        .filter(|record| [id("dumped", "looped"), id("dumped", "wide")].contains(&record.id))
+        .collect();
+
+    let mut log = Vec::new();
+    binlog::write_records(&mut log, &records).unwrap();
+    let mut reader = Reader::new(&log[..]).unwrap();
+    let events: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+    let fired: Vec<_> = events
+        .iter()
+        .map(|event| {
+            (
+                event.site.provider.as_str(),
+                event.site.name.as_str(),
+                event.thread,
+                event.time,
+                event.args.clone(),
+            )
+        })
+        .collect();
+    let expected: Vec<_> = records
+        .iter()
+        .zip([
+            ("looped", vec![0, 0]),
+            ("looped", vec![1, -1]),
+            ("looped", vec![2, -2]),
+            ("wide", vec![1, 2, 3, 4, 5, 6]),
+        ])
+        .map(|(record, (name, args))| ("dumped", name, 0, record.time, args))
+        .collect();
+    assert_eq!(fired, expected);
+    let sites = reader.sites();
+    assert_eq!(sites.len(), 2);
+    assert_eq!(sites[0].file, file!());
+    assert_eq!(sites[1].level, Level::Debug);
+}
@@ -0,0 +1,155 @@
+#![cfg(feature = "callback")]
+
+use probe::callback::{self, ProbeInfo};
+use probe::level::Level;
+use probe::{control, probe, probe_enabled, probe_lazy};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+
+type Fired = (
+    &'static str,
+    &'static str,
+    Option<&'static str>,
+    Level,
+    Vec<isize>,
+);
+
+static FIRED: Mutex<Vec<Fired>> = Mutex::new(Vec::new());
+
+// The handlers are global, so the tests take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn handler(info: &ProbeInfo, args: &[isize]) {
+    if info.provider() == "handled" {
+        let fired = (
+            info.provider(),
+            info.name(),
+            info.names(),
+            info.level(),
+            args.to_vec(),
+        );
+        FIRED.lock().unwrap().push(fired);
+    }
+}
+
+fn fired() -> Vec<Fired> {
+    std::mem::take(&mut *FIRED.lock().unwrap())
+}
+
+#[test]
+fn handled() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    callback::set_handler(handler);
+
+    // Only enabled probes go to the handler.
+    probe!(handled, before, 1);
+    assert!(!probe_enabled!(handled, before) || cfg!(feature = "force_enable"));
+    assert_eq!(fired(), []);
+
+    control::enable("handled");
+    assert!(probe_enabled!(handled, plain));
+    probe!(handled, plain, 1, -2; level = warn);
+    let text = "text";
+    probe!(handled, named, len = 3, @str text);
+    assert!(probe_lazy!(handled, lazy, 7));
+    assert_eq!(
+        fired(),
+        [
+            ("handled", "plain", None, Level::Warn, vec![1, -2]),
+            (
+                "handled",
+                "named",
+                Some("len _ _"),
+                Level::Trace,
+                vec![3, text.as_ptr() as isize, 4]
+            ),
+            ("handled", "lazy", None, Level::Trace, vec![7]),
+        ]
+    );
+
+    callback::clear_handler();
+    probe!(handled, after, 1);
+    assert_eq!(fired(), []);
+    control::disable("handled");
+}
+
+static REGISTERED: Mutex<Vec<(char, &str)>> = Mutex::new(Vec::new());
+
+fn first(info: &ProbeInfo, _: &[isize]) {
+    if info.provider() == "registered" {
+        REGISTERED.lock().unwrap().push(('a', info.name()));
+    }
+}
+
+fn second(info: &ProbeInfo, _: &[isize]) {
+    if info.provider() == "registered" {
+        REGISTERED.lock().unwrap().push(('b', info.name()));
+    }
+}
+
+fn registered() -> Vec<(char, &'static str)> {
+    let mut registered = std::mem::take(&mut *REGISTERED.lock().unwrap());
+    registered.sort();
+    registered
+}
+
+#[test]
+fn added() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    control::enable("registered");
+
+    // Handlers run alongside each other, and each is only added once.
+    assert!(callback::add_handler(first));
+    assert!(callback::add_handler(second));
+    assert!(callback::add_handler(first));
+    probe!(registered, both);
+    assert_eq!(registered(), [('a', "both"), ('b', "both")]);
+
+    callback::remove_handler(first);
+    assert!(!callback::has_handler(first));
+    assert!(callback::has_handler(second));
+    probe!(registered, second);
+    assert_eq!(registered(), [('b', "second")]);
+
+    // Setting a handler replaces the others.
+    callback::set_handler(first);
+    assert!(!callback::has_handler(second));
+    probe!(registered, first);
+    assert_eq!(registered(), [('a', "first")]);
+
+    callback::clear_handler();
+    assert!(!probe_enabled!(registered, none) || cfg!(feature = "force_enable"));
+    probe!(registered, none);
+    assert_eq!(registered(), []);
+
+    // A handler that's added by several threads at once is only added once,
+    // and one that's removed while it's added is either there or not.
+    for removing in [false, true] {
+        for _ in 0..1000 {
+            let barrier = Arc::new(Barrier::new(4));
+            let threads: Vec<_> = (0..4)
+                .map(|t| {
+                    let barrier = Arc::clone(&barrier);
+                    thread::spawn(move || {
+                        barrier.wait();
+                        if removing && t == 3 {
+                            callback::remove_handler(first);
+                        } else {
+                            assert!(callback::add_handler(first));
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let added = callback::has_handler(first);
+            assert!(added || removing);
+            probe!(registered, raced);
+            let expected: &[_] = if added { &[('a', "raced")] } else { &[] };
+            assert_eq!(registered(), expected);
+            callback::clear_handler();
+        }
+    }
+    control::disable("registered");
+}
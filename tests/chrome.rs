@@ -0,0 +1,58 @@
+#![cfg(feature = "chrome")]
+
+mod common;
+
+use common::Shared;
+use probe::{chrome, control, probe, probe_span};
+use std::thread;
+
+// The fields of an event, without the ones that change.
+fn fields(event: &str) -> String {
+    event
+        .trim_end_matches(',')
+        .split(',')
+        .filter(|field| !field.starts_with(r#""ts":"#) && !field.starts_with(r#""pid":"#))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[test]
+fn traced() {
+    let out = Shared::default();
+    chrome::start(out.clone());
+    control::enable("traced");
+
+    {
+        let _span = probe_span!(traced, request, id = 7);
+        probe!(traced, parsed, 1, len = 64);
+    }
+    thread::Builder::new()
+        .name("\"worker\"".into())
+        .spawn(|| probe!("traced", "dotted.name"))
+        .unwrap()
+        .join()
+        .unwrap();
+    probe!(untraced, other);
+    chrome::finish().unwrap();
+    control::disable("traced");
+
+    let trace = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<_> = trace.lines().collect();
+    assert_eq!(lines.first(), Some(&"["));
+    assert_eq!(lines.last(), Some(&"]"));
+    let events: Vec<_> = lines[1..lines.len() - 1]
+        .iter()
+        .map(|line| fields(line))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            r#"{"name":"thread_name","ph":"M","tid":1,"args":{"name":"traced"}}"#,
+            r#"{"name":"request","cat":"traced","ph":"B","tid":1,"args":{"id":7}}"#,
+            r#"{"name":"parsed","cat":"traced","ph":"i","s":"t","tid":1,"args":{"0":1,"len":64}}"#,
+            r#"{"name":"request","cat":"traced","ph":"E","tid":1,"args":{}}"#,
+            r#"{"name":"thread_name","ph":"M","tid":2,"args":{"name":"\"worker\""}}"#,
+            r#"{"name":"dotted.name","cat":"traced","ph":"i","s":"t","tid":2,"args":{}}"#,
+        ]
+    );
+}
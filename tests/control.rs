@@ -0,0 +1,69 @@
+use probe::control;
+
+#[test]
+fn flags() {
+    assert!(!control::is_enabled("flags"));
+    control::enable("flags");
+    control::enable("flags");
+    assert!(control::is_enabled("flags"));
+    assert!(!control::is_enabled("other"));
+    control::toggle("flags");
+    assert!(!control::is_enabled("flags"));
+    control::toggle("flags");
+    control::disable("flags");
+    assert!(!control::is_enabled("flags"));
+}
+
+#[test]
+fn configure() {
+    control::configure(" listed , single:probe,,");
+    assert!(control::is_enabled("listed"));
+    assert!(!control::is_enabled("single"));
+    assert!(control::is_probe_enabled("single", "probe"));
+    assert!(!control::is_probe_enabled("single", "other"));
+    control::disable_probe("single", "probe");
+    assert!(!control::is_probe_enabled("single", "probe"));
+}
+
+#[cfg(feature = "use_std")]
+#[test]
+fn configure_from_env() {
+    std::env::set_var("PROBE_ENABLE", "env,env_single:probe");
+    control::configure_from_env();
+    assert!(control::is_enabled("env"));
+    assert!(control::is_probe_enabled("env_single", "probe"));
+}
+
+#[cfg(all(feature = "use_std", target_os = "linux"))]
+#[test]
+fn signal() {
+    extern "C" {
+        fn raise(signum: std::ffi::c_int) -> std::ffi::c_int;
+    }
+
+    control::toggle_on_signal("signal", control::SIGUSR2).unwrap();
+    control::toggle_on_signal("signaled", control::SIGUSR2).unwrap();
+    unsafe { raise(control::SIGUSR2) };
+    assert!(control::is_enabled("signal"));
+    assert!(control::is_enabled("signaled"));
+    unsafe { raise(control::SIGUSR2) };
+    assert!(!control::is_enabled("signal"));
+}
+
+#[test]
+fn kill_switch() {
+    control::kill();
+    assert!(control::is_killed());
+    control::revive();
+    assert!(!control::is_killed());
+}
+
+#[test]
+fn compiled_in() {
+    assert!(control::compiled_in("alloc", None));
+    assert!(control::compiled_in("alloc", Some("")));
+    assert!(!control::compiled_in("alloc", Some("alloc")));
+    assert!(!control::compiled_in("alloc", Some("gc, alloc ,net")));
+    assert!(control::compiled_in("alloc", Some("gc,allocator,loc")));
+    assert!(control::compiled_in("gc", Some("alloc,")));
+}
@@ -0,0 +1,36 @@
+#![cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+
+use probe::counter::counters;
+use probe::level::{Level, STATIC_MAX_LEVEL};
+use probe::probe_counter;
+
+fn hits(name: &str) -> Vec<usize> {
+    counters()
+        .iter()
+        .filter(|counter| counter.provider() == "counted" && counter.name() == name)
+        .map(|counter| counter.hits())
+        .collect()
+}
+
+#[test]
+fn counted() {
+    for i in 0..3 {
+        probe_counter!(counted, looped, i, @str "x"; level = info);
+    }
+    probe_counter!(level: warn, counted, twice);
+    probe_counter!(counted, twice; level = error);
+
+    // Each site has its own counter, whether or not the probe is enabled.
+    assert_eq!(hits("looped"), [3]);
+    let mut twice = hits("twice");
+    twice.sort_unstable();
+    assert_eq!(twice, [1, 1]);
+}
+
+#[test]
+fn compiled_out() {
+    // A probe that's compiled out doesn't have a counter.
+    probe_counter!(counted, hidden; level = trace);
+    let compiled_in = STATIC_MAX_LEVEL == Some(Level::Trace);
+    assert_eq!(hits("hidden").len(), compiled_in as usize);
+}
@@ -0,0 +1,172 @@
+#![cfg(feature = "derive")]
+
+use probe::{probe, probe_lazy, ProbeArg, ProbeArgs};
+
+#[derive(Clone, Copy, ProbeArg)]
+enum State {
+    /// Waiting for a request.
+    Idle = 1,
+    Busy = (1 << 1),
+}
+
+#[derive(ProbeArg)]
+#[repr(u8)]
+enum Transition {
+    Start,
+    Stop,
+    Abort = 9,
+}
+
+#[derive(ProbeArgs)]
+struct Request {
+    /// The request's id.
+    pub id: u64,
+    pub(crate) len: usize,
+    state: State,
+    flags: Flags<u8>,
+}
+
+#[derive(Clone)]
+struct Flags<T>(Option<T>);
+
+impl<T: Into<isize>> ProbeArg for Flags<T> {
+    fn into_probe_arg(self) -> isize {
+        self.0.map_or(-1, Into::into)
+    }
+}
+
+#[derive(ProbeArgs)]
+struct Pair(i32, i8);
+
+#[derive(ProbeArgs)]
+struct Unit;
+
+#[test]
+fn fields() {
+    let request = Request {
+        id: 7,
+        len: 512,
+        state: State::Busy,
+        flags: Flags(None),
+    };
+    assert_eq!(request.probe_args(), [7, 512, 2, -1]);
+    assert_eq!(Pair(-3, 4).probe_args(), [-3, 4]);
+    assert_eq!(Unit.probe_args(), []);
+    assert_eq!(State::Idle.into_probe_arg(), 1);
+    assert_eq!(Transition::Start.into_probe_arg(), 0);
+    assert_eq!(Transition::Stop.into_probe_arg(), 1);
+    assert_eq!(Transition::Abort.into_probe_arg(), 9);
+    probe!(derive, transition, State::Busy, Transition::Stop);
+
+    probe!(derive, request, @fields request, 1);
+    let lazy = probe_lazy!(derive, pair, pair = @fields Pair(1, 2));
+    assert_eq!(lazy, cfg!(feature = "force_enable"));
+}
+
+#[probe::instrument(_)]
+fn parse(input: &str) -> Result<u32, std::num::ParseIntError> {
+    #![allow(clippy::needless_question_mark)]
+    Ok(input.trim().parse()?)
+}
+
+struct Cache {
+    hits: u32,
+}
+
+impl Cache {
+    #[probe::instrument(derived, args(self))]
+    fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    #[probe::instrument(derived, args(key))]
+    fn lookup(&mut self, key: u32) -> Option<u32> {
+        if key == 0 {
+            return None;
+        }
+        self.hits += 1;
+        Some(key * 2)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ProbeArg)]
+enum Error {
+    Empty = 1,
+    TooLong,
+}
+
+#[probe::instrument(derived, args(len), err)]
+fn check(len: usize) -> Result<usize, Error> {
+    if len == 0 {
+        return Err(Error::Empty);
+    }
+    if len > 16 {
+        Err(Error::TooLong)?;
+    }
+    Ok(len)
+}
+
+impl Cache {
+    #[probe::instrument(derived, ret)]
+    fn first<'a, T>(&self, items: &'a [T]) -> Option<&'a T>
+    where
+        T: Clone,
+    {
+        items.first()
+    }
+
+    #[probe::instrument(derived, ret)]
+    fn take(self) -> u32 {
+        self.hits
+    }
+}
+
+#[probe::instrument("derived.io"@2, args(len))]
+fn resize(len: usize) -> usize {
+    len.next_power_of_two()
+}
+
+#[probe::named]
+fn evict(key: u32) -> bool {
+    probe!(derived, _, key);
+    probe!(level: debug, _, _);
+    fn inner() -> bool {
+        probe_lazy!(derived, inner)
+    }
+    probe::probe_sampled!(2, level: warn, derived, _, key) | inner()
+}
+
+#[probe::namespace]
+mod cache {
+    pub fn evict(key: u32) {
+        probe::probe!(derived, evict, key);
+        probe::probe_sampled!(2, level: debug, _, evict_sampled, key);
+        lru::evict(key);
+    }
+
+    mod lru {
+        #[probe::instrument(derived, args(key))]
+        pub fn evict(key: u32) {
+            probe::probe!(derived, evict, key);
+        }
+    }
+}
+
+#[test]
+fn instrumented() {
+    assert_eq!(parse(" 42 "), Ok(42));
+    assert!(parse("x").is_err());
+    let mut cache = Cache { hits: 0 };
+    assert_eq!(cache.lookup(0), None);
+    assert_eq!(cache.lookup(21), Some(42));
+    assert_eq!(cache.hits(), 1);
+    assert_eq!(cache.first(&[3, 4]), Some(&3));
+    assert_eq!(cache.first::<u8>(&[]), None);
+    assert_eq!(cache.take(), 1);
+    assert_eq!(check(0), Err(Error::Empty));
+    assert_eq!(check(17), Err(Error::TooLong));
+    assert_eq!(check(5), Ok(5));
+    assert_eq!(resize(5), 8);
+    assert_eq!(evict(1), cfg!(feature = "force_enable"));
+    cache::evict(2);
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "force_enable")]
+
+use probe::{
+    probe_dbg, probe_enabled, probe_latency, probe_lazy, probe_once, probe_rate_limited,
+    probe_sampled, probe_site,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[test]
+fn lazy_arguments() {
+    let mut evaluated = 0;
+    assert!(probe_lazy!(forced, lazy, {
+        evaluated += 1;
+        evaluated
+    }));
+    assert!(probe_lazy!(forced, lazy, evaluated; level = debug));
+    assert_eq!(evaluated, 1, "lazy arguments are evaluated once");
+}
+
+#[test]
+fn enabled() {
+    let enabled = [
+        probe_enabled!(forced, enabled),
+        probe_enabled!(forced, enabled; level = trace),
+    ];
+    assert_eq!(enabled, [true, true]);
+    assert!(probe_site!(forced, handle).is_enabled());
+}
+
+#[test]
+fn sampled() {
+    let mut evaluated = 0;
+    let fired = (0..10)
+        .filter(|_| {
+            probe_sampled!(3, forced, sampled, {
+                evaluated += 1;
+                evaluated
+            })
+        })
+        .count();
+    assert_eq!((fired, evaluated), (4, 4), "hits 0, 3, 6, and 9 are fired");
+    assert!(probe_sampled!(0, forced, every; level = debug));
+}
+
+#[test]
+fn once() {
+    let mut evaluated = 0;
+    let fired = (0..10)
+        .filter(|_| {
+            probe_once!(forced, once, {
+                evaluated += 1;
+                evaluated
+            })
+        })
+        .count();
+    assert_eq!((fired, evaluated), (1, 1));
+    assert!(probe_once!(level: debug, forced, other));
+}
+
+#[test]
+fn rate_limited() {
+    // Without `use_std` there's no clock, so nothing is limited.
+    let fired = (0..10)
+        .filter(|_| probe_rate_limited!(3, forced, limited))
+        .count();
+    assert_eq!(fired, if cfg!(feature = "use_std") { 3 } else { 10 });
+    assert!(!probe_rate_limited!(0, forced, never));
+}
+
+#[test]
+fn latency() {
+    static TICKS: AtomicU64 = AtomicU64::new(0);
+    let mut evaluated = 0;
+    let value = probe_latency!(forced, timed, TICKS.fetch_add(5, Ordering::Relaxed), {
+        evaluated += 1;
+        evaluated
+    });
+    assert_eq!((value, evaluated), (0, 1));
+    probe_latency!(level: debug, forced, timed, ());
+
+    probe::latency::set_clock(|| TICKS.load(Ordering::Relaxed));
+    let start = probe::latency::Start::now();
+    TICKS.fetch_add(7, Ordering::Relaxed);
+    assert_eq!(start.elapsed(), 7);
+}
+
+#[test]
+fn dbg() {
+    let mut evaluated = 0;
+    let value = probe_dbg!(forced, value, {
+        evaluated += 1;
+        evaluated * 2
+    });
+    assert_eq!((value, evaluated), (2, 1), "the value is evaluated once");
+    assert_eq!(probe_dbg!(level: debug, forced, value, value + 1), 3);
+}
@@ -0,0 +1,47 @@
+#![cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+
+use probe::histogram::{bucket, histograms, BUCKETS};
+use probe::probe_histogram;
+use std::time::Duration;
+
+fn buckets(name: &str) -> Vec<[usize; BUCKETS]> {
+    histograms()
+        .iter()
+        .filter(|histogram| histogram.provider() == "measured" && histogram.name() == name)
+        .map(|histogram| histogram.buckets())
+        .collect()
+}
+
+#[test]
+fn buckets_are_log2() {
+    assert_eq!(bucket(0), 0);
+    assert_eq!(bucket(1), 1);
+    assert_eq!(bucket(2), 2);
+    assert_eq!(bucket(3), 2);
+    assert_eq!(bucket(4), 3);
+    assert_eq!(bucket(1023), 10);
+    assert_eq!(bucket(1024), 11);
+    assert_eq!(bucket(-1), BUCKETS - 1);
+}
+
+#[test]
+fn recorded() {
+    for size in [0usize, 1, 5, 6, 7, 100] {
+        probe_histogram!(measured, sizes, size, @str "x"; level = info);
+    }
+    probe_histogram!(level: warn, measured, waits, Duration::from_nanos(1500), tries = 2);
+
+    let sizes = buckets("sizes");
+    assert_eq!(sizes.len(), 1);
+    let mut expected = [0; BUCKETS];
+    expected[0] = 1;
+    expected[1] = 1;
+    expected[3] = 3;
+    expected[7] = 1;
+    assert_eq!(sizes[0], expected);
+
+    let waits = buckets("waits");
+    assert_eq!(waits.len(), 1);
+    assert_eq!(waits[0][bucket(1500)], 1);
+    assert_eq!(waits[0].iter().sum::<usize>(), 1);
+}
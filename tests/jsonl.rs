@@ -0,0 +1,51 @@
+#![cfg(feature = "jsonl")]
+
+mod common;
+
+use common::Shared;
+use probe::{control, jsonl, probe, probe_fmt, probe_span};
+use std::thread;
+
+#[test]
+fn streamed() {
+    let out = Shared::default();
+    jsonl::start(out.clone());
+    control::enable("streamed");
+
+    thread::Builder::new()
+        .name("\"worker\"".into())
+        .spawn(|| {
+            let _span = probe_span!(streamed, request, id = 7);
+            probe!(streamed, parsed, -1, len = 64; level = warn);
+            probe_fmt!(streamed, formatted, "size=%d", 8);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    probe!(unstreamed, other);
+    jsonl::finish().unwrap();
+    control::disable("streamed");
+
+    let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    let file = format!(r#","file":"{}","line":"#, file!());
+    // The lines, without their times and lines.
+    let lines: Vec<_> = out
+        .lines()
+        .map(|line| {
+            assert!(line.starts_with(r#"{"ts":"#));
+            assert!(line.contains(&file), "{}", line);
+            let line = &line[line.find(',').unwrap()..line.find(&file).unwrap()];
+            line.replace(r#","thread":1,"#, ",")
+        })
+        .collect();
+    let worker = r#""level":"trace","thread_name":"\"worker\"""#;
+    assert_eq!(
+        lines,
+        [
+            format!(r#","provider":"streamed","name":"request",{},"span":"begin","args":{{"id":7}}"#, worker),
+            r#","provider":"streamed","name":"parsed","level":"warn","thread_name":"\"worker\"","args":{"0":-1,"len":64}"#.to_owned(),
+            format!(r#","provider":"streamed","name":"formatted",{},"args":{{"0":8}},"message":"size=8""#, worker),
+            format!(r#","provider":"streamed","name":"request",{},"span":"end","args":{{}}"#, worker),
+        ]
+    );
+}
@@ -0,0 +1,21 @@
+#![cfg(all(target_os = "linux", feature = "lttng"))]
+
+use probe::{probe, probe_lazy};
+
+#[test]
+fn no_session() {
+    // Without an LTTng session enabling `rust_probe:probe`, probes are skipped.
+    let mut x = 0;
+    probe!(test, foo);
+    probe!(test, bar, {
+        x += 1;
+        x
+    });
+    assert_eq!(x, 1, "arguments are always evaluated");
+
+    assert!(!probe_lazy!(test, baz, {
+        x += 1;
+        x
+    }));
+    assert_eq!(x, 1, "lazy arguments are not evaluated");
+}
@@ -0,0 +1,26 @@
+#![cfg(all(feature = "force_enable", feature = "max_level_info"))]
+
+use probe::level::{Level, STATIC_MAX_LEVEL};
+use probe::{probe, probe_enabled, probe_lazy};
+
+#[test]
+fn compiled_out() {
+    assert_eq!(STATIC_MAX_LEVEL, Some(Level::Info));
+    assert!(probe_enabled!(max_level, info; level = info));
+    assert!(probe_enabled!(max_level, warn; level = warn));
+    assert!(!probe_enabled!(max_level, debug; level = debug));
+    assert!(!probe_enabled!(max_level, trace));
+    assert!(probe_enabled!(level: warn, max_level, prefix));
+    assert!(!probe_enabled!(level: debug, max_level, prefix));
+
+    let mut evaluated = 0;
+    probe!(max_level, fire, {
+        evaluated += 1;
+        evaluated
+    }; level = debug);
+    assert!(!probe_lazy!(max_level, lazy, {
+        evaluated += 1;
+        evaluated
+    }; level = trace));
+    assert_eq!(evaluated, 1, "only probe! evaluates its arguments");
+}
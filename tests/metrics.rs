@@ -0,0 +1,95 @@
+#![cfg(feature = "metrics")]
+
+use probe::{control, metrics, probe, probe_latency};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+#[test]
+fn served() {
+    metrics::start();
+    control::enable("scraped");
+
+    for _ in 0..3 {
+        probe!(scraped, plain, 1);
+    }
+    assert_eq!(probe_latency!(scraped, timed, 2 + 2), 4);
+    // Only probe_latency! is timed, not another probe with the same name.
+    probe!(scraped, untimed, elapsed = 5);
+    probe!(unscraped, other);
+
+    // Counters and histograms count whether or not they're enabled.
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    {
+        probe::probe_counter!(sited, counted);
+        for value in [0, 1, 5] {
+            probe::probe_histogram!(sited, sized, value as usize);
+        }
+    }
+    metrics::stop();
+    probe!(scraped, plain, 1);
+    control::disable("scraped");
+
+    let text = metrics::render();
+    let lines: Vec<_> = text
+        .lines()
+        .filter(|line| line.contains("scraped") || line.contains("sited"))
+        .filter(|line| !line.starts_with("probe_latency_sum"))
+        .collect();
+    let mut expected = vec![
+        r#"probe_hits_total{provider="scraped",probe="plain"} 3"#,
+        r#"probe_hits_total{provider="scraped",probe="timed"} 1"#,
+        r#"probe_hits_total{provider="scraped",probe="untimed"} 1"#,
+        r#"probe_latency_count{provider="scraped",probe="timed"} 1"#,
+    ];
+    if cfg!(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd"
+    )) {
+        expected.extend([
+            r#"probe_counter_hits_total{provider="sited",probe="counted"} 1"#,
+            r#"probe_histogram_bucket{provider="sited",probe="sized",le="0"} 1"#,
+            r#"probe_histogram_bucket{provider="sited",probe="sized",le="1"} 2"#,
+            r#"probe_histogram_bucket{provider="sited",probe="sized",le="3"} 2"#,
+            r#"probe_histogram_bucket{provider="sited",probe="sized",le="7"} 3"#,
+            r#"probe_histogram_bucket{provider="sited",probe="sized",le="+Inf"} 3"#,
+            r#"probe_histogram_sum{provider="sited",probe="sized"} 6"#,
+            r#"probe_histogram_count{provider="sited",probe="sized"} 3"#,
+        ]);
+    }
+    assert_eq!(lines, expected);
+    assert!(text.contains(r#"probe_latency_sum{provider="scraped",probe="timed"} "#));
+
+    // Over HTTP.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    metrics::serve(listener).unwrap();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.0 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+    assert!(head.contains(&format!("Content-Length: {}", body.len())));
+    assert!(body.contains(expected[0]));
+
+    // Over a Unix socket.
+    #[cfg(unix)]
+    {
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let path = std::env::temp_dir().join(format!("probe-metrics-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        metrics::serve_unix(UnixListener::bind(&path).unwrap()).unwrap();
+        let mut body = String::new();
+        UnixStream::connect(&path)
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(body.contains(expected[0]));
+    }
+}
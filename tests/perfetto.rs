@@ -0,0 +1,166 @@
+#![cfg(feature = "perfetto")]
+
+mod common;
+
+use common::Shared;
+use probe::{control, perfetto, probe, probe_span};
+use std::thread;
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Int(u64),
+    Bytes(Vec<u8>),
+}
+
+fn varint(bytes: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    for shift in (0..).step_by(7) {
+        let byte = bytes[0];
+        *bytes = &bytes[1..];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            break;
+        }
+    }
+    value
+}
+
+// The fields of a message, with only the wire types used.
+fn fields(mut bytes: &[u8]) -> Vec<(u64, Value)> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let key = varint(&mut bytes);
+        let value = match key & 7 {
+            0 => Value::Int(varint(&mut bytes)),
+            2 => {
+                let len = varint(&mut bytes) as usize;
+                let (value, rest) = bytes.split_at(len);
+                bytes = rest;
+                Value::Bytes(value.to_vec())
+            }
+            wire => panic!("unexpected wire type {}", wire),
+        };
+        fields.push((key >> 3, value));
+    }
+    fields
+}
+
+fn field(fields: &[(u64, Value)], number: u64) -> Option<&Value> {
+    fields
+        .iter()
+        .find(|(n, _)| *n == number)
+        .map(|(_, value)| value)
+}
+
+fn int(fields: &[(u64, Value)], number: u64) -> u64 {
+    match field(fields, number) {
+        Some(Value::Int(value)) => *value,
+        value => panic!("field {} is {:?}", number, value),
+    }
+}
+
+fn message(fields: &[(u64, Value)], number: u64) -> Vec<(u64, Value)> {
+    match field(fields, number) {
+        Some(Value::Bytes(bytes)) => self::fields(bytes),
+        value => panic!("field {} is {:?}", number, value),
+    }
+}
+
+fn string(fields: &[(u64, Value)], number: u64) -> Option<String> {
+    match field(fields, number) {
+        Some(Value::Bytes(bytes)) => Some(String::from_utf8(bytes.clone()).unwrap()),
+        _ => None,
+    }
+}
+
+#[test]
+fn traced() {
+    let out = Shared::default();
+    perfetto::start(out.clone());
+    control::enable("traced");
+
+    {
+        let _span = probe_span!(traced, request, id = 7);
+        probe!(traced, parsed, -1, len = 64);
+    }
+    thread::Builder::new()
+        .name("worker".into())
+        .spawn(|| probe!(traced, threaded))
+        .unwrap()
+        .join()
+        .unwrap();
+    probe!(untraced, other);
+    perfetto::finish().unwrap();
+    control::disable("traced");
+
+    let trace = out.0.lock().unwrap().clone();
+    let packets: Vec<_> = fields(&trace)
+        .into_iter()
+        .map(|(number, packet)| match (number, packet) {
+            (1, Value::Bytes(packet)) => fields(&packet),
+            packet => panic!("unexpected {:?}", packet),
+        })
+        .collect();
+    assert!(packets.iter().all(|packet| int(packet, 10) == 1));
+
+    // The process, and then each thread, before its events.
+    let process = message(&packets[0], 60);
+    let process_uuid = int(&process, 1);
+    assert_eq!(int(&message(&process, 3), 1), std::process::id() as u64);
+    let mut threads = Vec::new();
+    let mut events = Vec::new();
+    for packet in &packets[1..] {
+        if field(packet, 60).is_some() {
+            let track = message(packet, 60);
+            assert_eq!(int(&track, 5), process_uuid);
+            threads.push((int(&track, 1), string(&message(&track, 4), 5).unwrap()));
+        } else {
+            let event = message(packet, 11);
+            let track = threads
+                .iter()
+                .position(|&(uuid, _)| uuid == int(&event, 11));
+            let annotations: Vec<_> = event
+                .iter()
+                .filter(|(number, _)| *number == 4)
+                .map(|(_, annotation)| match annotation {
+                    Value::Bytes(bytes) => {
+                        let annotation = fields(bytes);
+                        (string(&annotation, 10).unwrap(), int(&annotation, 4) as i64)
+                    }
+                    value => panic!("unexpected {:?}", value),
+                })
+                .collect();
+            events.push((
+                track.unwrap(),
+                int(&event, 9),
+                string(&event, 22),
+                string(&event, 23),
+                annotations,
+            ));
+        }
+    }
+    assert_eq!(
+        threads
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>(),
+        ["traced", "worker"]
+    );
+    let traced = || Some("traced".to_owned());
+    let name = |name: &str| Some(name.to_owned());
+    assert_eq!(
+        events,
+        [
+            (0, 1, traced(), name("request"), vec![("id".to_owned(), 7)]),
+            (
+                0,
+                3,
+                traced(),
+                name("parsed"),
+                vec![("0".to_owned(), -1), ("len".to_owned(), 64)]
+            ),
+            (0, 2, None, None, vec![]),
+            (1, 3, traced(), name("threaded"), vec![]),
+        ]
+    );
+}
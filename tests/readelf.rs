@@ -1,16 +1,36 @@
-#![cfg(any(target_os = "linux", target_os = "android"))]
+#![cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64",
+        target_arch = "riscv32",
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+        all(target_arch = "powerpc64", feature = "asm_experimental_arch"),
+        all(target_arch = "mips", feature = "asm_experimental_arch"),
+        all(target_arch = "mips64", feature = "asm_experimental_arch"),
+    ),
+    not(any(
+        all(target_os = "linux", any(feature = "lttng", feature = "user_events")),
+        all(target_os = "android", feature = "atrace")
+    ))
+))]
+#![cfg_attr(feature = "asm_experimental_arch", feature(asm_experimental_arch))]
 
-use probe::probe;
+use probe::{
+    probe, probe_block, probe_enabled, probe_fmt, probe_latency, probe_lazy, probe_site,
+    probe_span, provider,
+};
 use std::env;
+use std::mem;
 use std::process::Command;
+use std::time::Duration;
 
-#[test]
-fn check_notes() {
-    // First let's create probes with and without arguments
-    probe!(test, foo);
-    probe!(test, bar, 42);
-
-    // Now make sure readelf can find "stapsdt" ELF notes in this test executable
+// Reads the ELF notes in this test executable
+fn readelf_notes() -> String {
     let test_exe = env::current_exe().unwrap();
     let output = Command::new("readelf")
         .arg("-n")
@@ -26,9 +46,499 @@ fn check_notes() {
         panic!("{}", error);
     }
 
-    let count = String::from_utf8_lossy(&output.stdout)
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn check_notes() {
+    // First let's create probes with and without arguments
+    probe!(test, foo);
+    probe!(test, bar, 42);
+
+    // Now make sure readelf can find "stapsdt" ELF notes for them
+    let count = readelf_notes()
         .lines()
-        .filter(|line| line.contains("NT_STAPSDT"))
+        .filter(|line| line.contains("Provider: test"))
         .count();
     assert_eq!(count, 2);
 }
+
+#[test]
+fn check_semaphore() {
+    // Nothing is attached to increment the semaphore.
+    let mut evaluated = 0;
+    let enabled = probe_lazy!(lazy, baz, {
+        evaluated += 1;
+        42
+    });
+    assert_eq!(enabled, cfg!(feature = "force_enable"));
+    assert_eq!(
+        evaluated == 1,
+        enabled,
+        "lazy arguments are only evaluated when enabled"
+    );
+
+    let notes = readelf_notes();
+    let mut note = notes
+        .lines()
+        .skip_while(|line| !line.contains("Provider: lazy"))
+        .skip(2);
+
+    // The semaphore address is absolute, even on i686 where code has to
+    // reach it relative to the GOT. With `force_enable`, nothing reads the
+    // semaphore, so the linker drops it.
+    let location = note.next().unwrap();
+    let semaphore = location.rsplit("Semaphore: 0x").next().unwrap();
+    let semaphore = u64::from_str_radix(semaphore, 16).unwrap();
+    assert_eq!(semaphore == 0, cfg!(feature = "force_enable"));
+
+    // Arguments are the size of a pointer, like `-4@%eax` on i686.
+    let arguments = note.next().unwrap().trim();
+    let size = mem::size_of::<isize>();
+    assert!(
+        arguments.starts_with(&format!("Arguments: -{}@", size)),
+        "{}",
+        arguments
+    );
+}
+
+#[test]
+fn check_shared_semaphore() {
+    probe!(shared, qux, 1);
+    probe_lazy!(shared, qux, 2);
+    assert_eq!(probe_enabled!(shared, qux), cfg!(feature = "force_enable"));
+
+    // Every site of a probe records the same semaphore.
+    let notes = readelf_notes();
+    let lines: Vec<_> = notes.lines().collect();
+    let mut semaphores = lines
+        .windows(3)
+        .filter(|note| note[0].contains("Provider: shared"))
+        .take(2)
+        .map(|note| note[2].rsplit("Semaphore: ").next().unwrap().to_owned());
+    let first = semaphores.next().unwrap();
+    let semaphore = u64::from_str_radix(&first[2..], 16).unwrap();
+    assert_eq!(semaphore == 0, cfg!(feature = "force_enable"));
+    assert_eq!(semaphores.next(), Some(first));
+}
+
+#[test]
+fn check_handle() {
+    let handle = probe_site!(handle, quux);
+    assert_eq!(handle.is_enabled(), cfg!(feature = "force_enable"));
+    handle.fire(&[1, 2]);
+
+    // The handle's site always has every argument.
+    let notes = readelf_notes();
+    let arguments = notes
+        .lines()
+        .skip_while(|line| !line.contains("Provider: handle"))
+        .find(|line| line.contains("Arguments: "))
+        .unwrap();
+    assert_eq!(arguments.matches('@').count(), probe::handle::MAX_ARGS);
+}
+
+#[test]
+fn check_str() {
+    let name = String::from("probe");
+    probe!(strings, open, @str name, 3);
+    let name = std::ffi::CString::new(name).unwrap();
+    probe!(strings, cstr, @cstr name.as_c_str(), @cstr name.as_ptr());
+    probe!(strings, bytes, @bytes name.as_bytes(), @bytes b"probe", @bytes vec![0u8; 4]);
+    let mut evaluated = false;
+    let lazy = probe_lazy!(strings, lazy, @str {
+        evaluated = true;
+        "lazy"
+    });
+    assert_eq!((lazy, evaluated), (cfg!(feature = "force_enable"), lazy));
+
+    // A string is a pointer and a length.
+    let notes = readelf_notes();
+    let arguments = notes
+        .lines()
+        .skip_while(|line| !line.contains("Provider: strings"))
+        .find(|line| line.contains("Arguments: "))
+        .unwrap();
+    assert_eq!(arguments.matches('@').count(), 3);
+    let arguments = notes
+        .lines()
+        .skip_while(|line| !line.contains("Name: bytes"))
+        .find(|line| line.contains("Arguments: "))
+        .unwrap();
+    assert_eq!(arguments.matches('@').count(), 6);
+}
+
+#[test]
+fn check_typed() {
+    let (byte, short, long) = (0xffu8, -2i16, -4i64);
+    probe!(typed, ints, @u8 byte, @i16 short, @u32 3, @i64 long, 5);
+    probe!(typed, wide, @str "s", @f32 0.5);
+    probe!(typed, huge, @i128 -1);
+
+    // Each operand has the size and signedness of its type.
+    let notes = readelf_notes();
+    let sizes = |name: &str| -> Vec<String> {
+        let arguments = notes
+            .lines()
+            .skip_while(|line| !line.contains("Provider: typed"))
+            .skip_while(|line| !line.ends_with(&format!("Name: {}", name)))
+            .find(|line| line.contains("Arguments: "))
+            .unwrap();
+        arguments
+            .rsplit("Arguments: ")
+            .next()
+            .unwrap()
+            .split(' ')
+            .map(|operand| operand.split('@').next().unwrap().to_owned())
+            .collect()
+    };
+    // 64-bit integers are two operands on 32-bit targets.
+    if mem::size_of::<isize>() == 8 {
+        assert_eq!(sizes("ints"), ["1", "-2", "4", "-8", "-8"]);
+        assert_eq!(sizes("wide"), ["8", "8", "4"]);
+        assert_eq!(sizes("huge"), ["8", "-8"]);
+    } else {
+        assert_eq!(sizes("ints"), ["1", "-2", "4", "4", "-4", "-4"]);
+        assert_eq!(sizes("wide"), ["4", "4", "4"]);
+        assert_eq!(sizes("huge"), ["4", "4", "4", "-4"]);
+    }
+}
+
+#[test]
+fn check_raw() {
+    let (fd, len) = (3, 4u32);
+    probe!(raw, read, @raw("8@%rdi") fd, len = @u32 len);
+
+    // The raw descriptor is used as it is, in place of a register operand.
+    let notes = readelf_notes();
+    let arguments = notes
+        .lines()
+        .skip_while(|line| !line.contains("Provider: raw"))
+        .find(|line| line.contains("Arguments: "))
+        .unwrap();
+    let mut operands = arguments.rsplit("Arguments: ").next().unwrap().split(' ');
+    assert_eq!(operands.next(), Some("8@%rdi"));
+    assert!(operands.next().unwrap().starts_with("4@"));
+    assert_eq!(operands.next(), None);
+}
+
+#[test]
+fn check_memory() {
+    let (byte, short) = (0xffu8, -2i16);
+    probe!(memory, hot, @u8 byte, @i16 short, @raw("8@%rdi") 3, 4; operands = memory);
+    probe!(memory, many, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12; operands = memory);
+
+    // The operands are words at offsets from one register, in order, apart
+    // from the raw one.
+    let notes = readelf_notes();
+    let mut arguments = notes
+        .lines()
+        .skip_while(|line| !line.contains("Provider: memory"))
+        .filter(|line| line.contains("Arguments: "))
+        .map(|line| line.rsplit("Arguments: ").next().unwrap().to_owned());
+    let hot = arguments.next().unwrap();
+    let operands: Vec<_> = hot.split(' ').collect();
+    let word = mem::size_of::<isize>();
+    let offset = |operand: &str| -> usize {
+        let location = operand.split('@').nth(1).unwrap();
+        let digits = location.trim_start_matches(|c: char| !c.is_ascii_digit());
+        let end = digits.find(|c: char| !c.is_ascii_digit()).unwrap();
+        digits[..end].parse().unwrap()
+    };
+    assert_eq!(operands.len(), 4);
+    assert!(operands[0].starts_with(&format!("{}@", word)));
+    assert!(operands[1].starts_with(&format!("-{}@", word)));
+    assert_eq!(operands[2], "8@%rdi");
+    assert!(operands[3].starts_with(&format!("-{}@", word)));
+    let offsets: Vec<_> = [0, 1, 3].iter().map(|&i| offset(operands[i])).collect();
+    assert_eq!(offsets, [0, word, 2 * word]);
+
+    let many = arguments.next().unwrap();
+    assert_eq!(many.split(' ').count(), 12);
+}
+
+#[test]
+fn check_default_provider() {
+    probe!(_, defaulted, 1);
+    let _span = probe_span!(_, defaulted_span);
+
+    // The provider is the name of this test's crate.
+    let notes = readelf_notes();
+    let lines: Vec<_> = notes.lines().collect();
+    for name in ["Name: defaulted", "Name: defaulted_span__begin"] {
+        assert!(
+            lines
+                .windows(2)
+                .any(|note| note[0].contains("Provider: readelf") && note[1].ends_with(name)),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn check_quoted() {
+    let n = 3;
+    probe!("my-app.io", "read.done", n);
+    probe_lazy!("my-app.io", "read.done", n);
+    let _span = probe_span!("my-app.io", "read.span");
+    assert_eq!(
+        probe_enabled!("my-app.io", "read.done"),
+        cfg!(feature = "force_enable")
+    );
+
+    // The strings are the provider and the name as they are, and the probe
+    // has a semaphore, since it's lazy, unless `force_enable` drops it.
+    let notes = readelf_notes();
+    let lines: Vec<_> = notes.lines().collect();
+    for name in ["Name: read.done", "Name: read.span__begin"] {
+        assert!(
+            lines
+                .windows(2)
+                .any(|note| note[0].contains("Provider: my-app.io") && note[1].ends_with(name)),
+            "{}",
+            name
+        );
+    }
+    let semaphore = lines.windows(3).any(|note| {
+        let semaphore = note[2].rsplit("Semaphore: 0x").next().unwrap();
+        note[1].ends_with("Name: read.done") && u64::from_str_radix(semaphore, 16) != Ok(0)
+    });
+    assert_eq!(semaphore, !cfg!(feature = "force_enable"));
+}
+
+provider! {
+    declared@3 {
+        begin(),
+        alloc(size: usize, align: usize,),
+        wait(timeout: Duration),
+    }
+}
+
+#[test]
+fn check_provider() {
+    declared::begin();
+    declared::alloc(64, 16);
+    declared::wait(Duration::from_millis(1));
+
+    let notes = readelf_notes();
+    let lines: Vec<_> = notes.lines().collect();
+    let arguments = |name: &str| -> usize {
+        let note = lines
+            .windows(4)
+            .find(|note| note[0].contains("Provider: declared") && note[1].ends_with(name))
+            .unwrap();
+        note[3].matches('@').count()
+    };
+    assert_eq!(arguments("Name: begin"), 0);
+    assert_eq!(arguments("Name: alloc"), 2);
+    assert_eq!(arguments("Name: wait"), 1);
+}
+
+#[test]
+fn check_span() {
+    fn step(i: u32) -> Option<u32> {
+        let _span = probe_span!(span, step, i = i; level = debug);
+        i.checked_sub(1)
+    }
+    assert_eq!(step(0), None);
+    assert_eq!(step(2), Some(1));
+    assert_eq!(probe_block!(span, block, { step(3) }), Some(2));
+
+    // The ends of a span are probes of their own, and only the first has the
+    // arguments.
+    let notes = readelf_notes();
+    let lines: Vec<_> = notes.lines().collect();
+    let arguments = |name: &str| -> usize {
+        let note = lines
+            .windows(4)
+            .find(|note| note[0].contains("Provider: span") && note[1].ends_with(name))
+            .unwrap();
+        note[3].matches('@').count()
+    };
+    assert_eq!(arguments("Name: step__begin"), 1);
+    assert_eq!(arguments("Name: step__end"), 0);
+    assert_eq!(arguments("Name: block__begin"), 0);
+    assert_eq!(arguments("Name: block__end"), 0);
+}
+
+#[test]
+fn check_names() {
+    let (i, total, path) = (1, 2, "/tmp");
+    probe!(named, loop, i = i, total = total, @str path, len = @str path);
+
+    // The names of the operands are in a note of their own.
+    let test_exe = env::current_exe().unwrap();
+    let output = Command::new("readelf")
+        .arg("-p")
+        .arg(".note.probe")
+        .arg(&test_exe)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let strings = String::from_utf8_lossy(&output.stdout);
+    let names = strings
+        .lines()
+        .skip_while(|line| !line.ends_with("  named"))
+        .nth(2)
+        .unwrap();
+    assert!(names.ends_with("  i total _ _ len len_len"), "{}", names);
+
+    // A timed probe's elapsed time comes first, named whether or not the
+    // others are.
+    assert_eq!(probe_latency!(timed, plain, 1 + 1, 3, @str path), 2);
+    probe_latency!(timed, sized, (), len = path.len());
+    for (name, names) in [("plain", "elapsed _ _ _"), ("sized", "elapsed len")] {
+        let names_of = strings
+            .lines()
+            .skip_while(|line| !line.ends_with("  timed"))
+            .skip_while(|line| !line.ends_with(&format!("  {}", name)))
+            .nth(1)
+            .unwrap();
+        assert!(names_of.ends_with(&format!("  {}", names)), "{}", names_of);
+    }
+
+    // So is the format of a probe that has one.
+    probe_fmt!(formatted, alloc, "alloc size=%d", 64; level = debug);
+    let format = strings
+        .lines()
+        .skip_while(|line| !line.ends_with("  formatted"))
+        .nth(2)
+        .unwrap();
+    assert!(format.ends_with("  alloc size=%d"), "{}", format);
+
+    // And the version of a provider that has one, which isn't in its name.
+    probe!(versioned@2, resized, 1);
+    probe_lazy!(level: debug, versioned@2, evicted, 2);
+    let version = strings
+        .lines()
+        .skip_while(|line| !line.ends_with("  versioned"))
+        .nth(2)
+        .unwrap();
+    assert!(version.ends_with("  2"), "{}", version);
+    assert!(readelf_notes().contains("Provider: versioned\n"));
+
+    // A caller's location is three operands, with the line as a `u32`.
+    #[track_caller]
+    fn lookup() {
+        probe!(located, lookup, caller = @location std::panic::Location::caller());
+    }
+    lookup();
+    let names = strings
+        .lines()
+        .skip_while(|line| !line.ends_with("  lookup"))
+        .nth(1)
+        .unwrap();
+    assert!(
+        names.ends_with("  caller caller_len caller_line"),
+        "{}",
+        names
+    );
+    let notes = readelf_notes();
+    let arguments = notes
+        .lines()
+        .skip_while(|line| !line.ends_with("Name: lookup"))
+        .nth(2)
+        .unwrap();
+    assert!(arguments.contains(" 4@"), "{}", arguments);
+
+    // And each site's file and line, after its address.
+    probe!(located, here);
+    let line = format!("  {}", line!() - 1);
+    let lines: Vec<_> = strings.lines().collect();
+    assert!(lines
+        .windows(2)
+        .any(|note| note[0].ends_with("  tests/readelf.rs") && note[1].ends_with(&line)));
+}
+
+// 32-bit x86 doesn't have the registers for this many operands.
+#[cfg(not(target_arch = "x86"))]
+#[test]
+fn check_record() {
+    probe!(record, wide, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13);
+    let mut evaluated = false;
+    let lazy = probe_lazy!(record, lazy, @u8 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, @str {
+        evaluated = true;
+        "lazy"
+    });
+    assert_eq!((lazy, evaluated), (cfg!(feature = "force_enable"), lazy));
+
+    // The operands past the 10th are a pointer and a length.
+    let notes = readelf_notes();
+    let mut arguments = notes
+        .lines()
+        .skip_while(|line| !line.contains("Provider: record"))
+        .filter(|line| line.contains("Arguments: "));
+    let word = if mem::size_of::<isize>() == 8 {
+        " 8@"
+    } else {
+        " 4@"
+    };
+    for arguments in [arguments.next().unwrap(), arguments.next().unwrap()] {
+        assert_eq!(arguments.matches('@').count(), 12);
+        assert_eq!(arguments.matches(word).count(), 2);
+    }
+}
+
+#[cfg(feature = "use_std")]
+#[test]
+fn check_self_armed() {
+    use probe::probe_semaphore;
+
+    let semaphore = probe_semaphore!(armed, quux).unwrap();
+    assert_eq!(semaphore.count(), 0);
+    semaphore.increment();
+    let mut evaluated = false;
+    assert!(probe_lazy!(armed, quux, {
+        evaluated = true;
+        1
+    }));
+    assert!(evaluated);
+    assert!(probe_enabled!(armed, quux));
+
+    semaphore.decrement();
+    semaphore.decrement();
+    assert_eq!(semaphore.count(), 0);
+}
+
+#[cfg(feature = "callback")]
+#[test]
+fn check_handled() {
+    use probe::callback::{self, ProbeInfo};
+    use std::sync::Mutex;
+
+    static HANDLED: Mutex<Vec<(&str, Vec<isize>)>> = Mutex::new(Vec::new());
+    fn handler(info: &ProbeInfo, args: &[isize]) {
+        if info.provider() == "both" {
+            HANDLED.lock().unwrap().push((info.name(), args.to_vec()));
+        }
+    }
+
+    callback::set_handler(handler);
+    probe::control::enable("both");
+    probe!(both, plain, 1);
+    let mut evaluated = 0;
+    assert!(probe_lazy!(both, lazy, {
+        evaluated += 1;
+        2
+    }));
+    probe::control::disable("both");
+    callback::clear_handler();
+
+    // The handler gets the probes, with their arguments evaluated once, and
+    // they're still SDT probes.
+    assert_eq!(evaluated, 1);
+    assert_eq!(
+        *HANDLED.lock().unwrap(),
+        [("plain", vec![1]), ("lazy", vec![2])]
+    );
+    let notes = readelf_notes();
+    let lines: Vec<_> = notes.lines().collect();
+    let names: Vec<_> = lines
+        .windows(2)
+        .filter(|note| note[0].contains("Provider: both"))
+        .map(|note| note[1].rsplit("Name: ").next().unwrap())
+        .collect();
+    assert!(names.contains(&"plain"), "{:?}", names);
+    assert!(names.contains(&"lazy"), "{:?}", names);
+}
@@ -0,0 +1,211 @@
+#![cfg(all(feature = "recorder", target_has_atomic = "64"))]
+
+use probe::rtt::id;
+use probe::{control, probe, recorder};
+use std::sync::{Arc, Barrier, Mutex, MutexGuard};
+use std::thread;
+
+// The recorder is global, so the tests take turns, and each starts with the
+// records of the others drained.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn serial() -> MutexGuard<'static, ()> {
+    let serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    recorder::start(4);
+    control::enable("recorded");
+    recorder::drain();
+    serial
+}
+
+fn recent(name: &str) -> Vec<recorder::Record> {
+    let id = id("recorded", name);
+    recorder::recent()
+        .into_iter()
+        .filter(|record| record.id == id)
+        .collect()
+}
+
+#[test]
+fn recorded() {
+    let _serial = serial();
+
+    // Only the last 4 are kept.
+    for i in 0..6 {
+        probe!(recorded, looped, i);
+    }
+    let recent = recent("looped");
+    let args: Vec<_> = recent.iter().map(|record| record.args()[0]).collect();
+    assert_eq!(args, [2, 3, 4, 5]);
+    assert!(recent.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+    assert_eq!(recorder::drain(), recent);
+    assert_eq!(recorder::drain(), []);
+
+    // Only the first operands are kept.
+    probe!(recorded, wide, 1, 2, 3, 4, 5, 6, 7, 8; operands = memory);
+    let drained = recorder::drain();
+    assert_eq!(drained.len(), 1);
+    assert_eq!(drained[0].len, 8);
+    assert_eq!(drained[0].args(), [1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn threaded() {
+    let _serial = serial();
+
+    // Each thread has its own buffer, while they're all running, and no record
+    // is torn.
+    let barrier = Arc::new(Barrier::new(4));
+    let threads: Vec<_> = (1..=4)
+        .map(|t| {
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    probe!(recorded, threaded, t, i, i * t);
+                }
+                barrier.wait();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let recent = recent("threaded");
+    assert!(recent.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    for t in 1..=4 {
+        let last: Vec<_> = recent
+            .iter()
+            .filter(|record| record.args()[0] == t)
+            .map(|record| record.args()[1])
+            .collect();
+        assert_eq!(last, [996, 997, 998, 999]);
+    }
+    for record in &recent {
+        let args = record.args();
+        assert_eq!(args[0] * args[1], args[2]);
+    }
+}
+
+#[test]
+fn reused() {
+    let _serial = serial();
+
+    // A new thread takes over the buffer of one that's exited, and the oldest of
+    // its records.
+    thread::spawn(|| {
+        for i in 0..4 {
+            probe!(recorded, filled, i);
+        }
+    })
+    .join()
+    .unwrap();
+    thread::spawn(|| probe!(recorded, reused)).join().unwrap();
+    let filled: Vec<_> = recent("filled")
+        .iter()
+        .map(|record| record.args()[0])
+        .collect();
+    assert_eq!(filled, [1, 2, 3]);
+    assert_eq!(recent("reused").len(), 1);
+}
+
+#[test]
+fn counted() {
+    let _serial = serial();
+
+    // Each site has its stats, whether or not its records are still there.
+    for len in [4, -2, 10, 0, 3] {
+        probe!(recorded, measured, len, 1);
+    }
+    assert_eq!(recent("measured").len(), 4);
+    probe!(recorded, unmeasured);
+    let stats = probe::stats();
+    assert!(stats
+        .windows(2)
+        .all(|pair| (pair[0].provider, pair[0].name) <= (pair[1].provider, pair[1].name)));
+    let named = |name| {
+        let mut sites = stats
+            .iter()
+            .filter(move |stats| stats.provider == "recorded" && stats.name == name);
+        let site = sites.next().unwrap();
+        assert!(sites.next().is_none());
+        site
+    };
+    let measured = named("measured");
+    assert_eq!(measured.id, id("recorded", "measured"));
+    assert!(measured.file.ends_with("recorder.rs"));
+    assert_eq!(measured.hits, 5);
+    assert_eq!(measured.last.unwrap().args(), [3, 1]);
+    assert_eq!(
+        (measured.min, measured.max, measured.mean),
+        (Some(-2), Some(10), Some(3.0))
+    );
+    let unmeasured = named("unmeasured");
+    assert_eq!(unmeasured.hits, 1);
+    assert_eq!(unmeasured.last.unwrap().len, 0);
+    assert_eq!((unmeasured.min, unmeasured.mean), (None, None));
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn saturated() {
+    let _serial = serial();
+
+    // The sum stops at the bounds, rather than wrapping to the other sign.
+    for _ in 0..2 {
+        probe!(recorded, huge, isize::MAX);
+        probe!(recorded, tiny, isize::MIN);
+    }
+    let stats = probe::stats();
+    let mean = |name| {
+        stats
+            .iter()
+            .find(|stats| stats.provider == "recorded" && stats.name == name)
+            .unwrap()
+            .mean
+    };
+    assert_eq!(mean("huge"), Some(i64::MAX as f64 / 2.0));
+    assert_eq!(mean("tiny"), Some(i64::MIN as f64 / 2.0));
+}
+
+#[cfg(unix)]
+#[test]
+fn forked() {
+    extern "C" {
+        fn fork() -> i32;
+        fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+        fn _exit(status: i32) -> !;
+    }
+
+    let _serial = serial();
+
+    // A forked child starts over, without the parent's records, or stats.
+    probe!(recorded, parent);
+    let pid = unsafe { fork() };
+    assert!(pid >= 0);
+    if pid == 0 {
+        probe!(recorded, child, 1);
+        let recent = recorder::recent();
+        let stats = probe::stats();
+        let ok = recent.len() == 1
+            && recent[0].id == id("recorded", "child")
+            && stats.len() == 1
+            && stats[0].hits == 1;
+        unsafe { _exit(if ok { 0 } else { 1 }) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { waitpid(pid, &mut status, 0) }, pid);
+    assert_eq!(status, 0);
+    let drained = recorder::drain();
+    assert_eq!(drained.len(), 1);
+    assert_eq!(drained[0].id, id("recorded", "parent"));
+}
+
+#[test]
+fn stopped() {
+    let _serial = serial();
+
+    recorder::stop();
+    probe!(recorded, stopped);
+    assert_eq!(recent("stopped"), []);
+    control::disable("recorded");
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "use_std")]
+
+use probe::rtt::{id, Decoder, Event};
+
+#[test]
+fn decode() {
+    let mut decoder = Decoder::new();
+    decoder.register("foo", "loop");
+
+    // foo:loop(45, -1035), then foo:begin(), split across pushes.
+    let mut bytes = Vec::new();
+    bytes.extend(id("foo", "loop").to_le_bytes());
+    bytes.extend([2, 90, 0x95, 0x10]);
+    bytes.extend(id("foo", "begin").to_le_bytes());
+    bytes.push(0);
+
+    decoder.push(&bytes[..7]);
+    assert_eq!(decoder.next_event(), None, "the record is incomplete");
+    decoder.push(&bytes[7..]);
+
+    let event = decoder.next_event().unwrap();
+    assert_eq!(
+        event,
+        Event {
+            id: id("foo", "loop"),
+            args: vec![45, -1035],
+        }
+    );
+    assert_eq!(decoder.lookup(event.id), Some(("foo", "loop")));
+
+    let event = decoder.next_event().unwrap();
+    assert_eq!(event.args, []);
+    assert_eq!(decoder.lookup(event.id), None, "foo:begin isn't registered");
+    assert_eq!(decoder.next_event(), None);
+}
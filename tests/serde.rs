@@ -0,0 +1,39 @@
+#![cfg(all(feature = "serde", feature = "callback"))]
+
+use probe::callback::{self, ProbeInfo};
+use probe::{control, probe, probe_lazy};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+type Request = (u32, String, BTreeMap<String, i64>);
+
+static DECODED: Mutex<Vec<(&'static str, Request)>> = Mutex::new(Vec::new());
+
+// Decodes the payload while the probe fires, since its buffer is reused after.
+fn handler(info: &ProbeInfo, args: &[isize]) {
+    if info.provider() != "serialized" {
+        return;
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(args[0] as *const u8, args[1] as usize) };
+    let request = postcard::from_bytes(bytes).unwrap();
+    DECODED.lock().unwrap().push((info.name(), request));
+}
+
+#[test]
+fn decoded() {
+    let headers = BTreeMap::from([("len".to_string(), 64)]);
+    let request = (7u32, "GET".to_string(), headers);
+
+    callback::add_handler(handler);
+    control::enable("serialized");
+    probe!(serialized, eager, @serde request);
+    assert!(probe_lazy!(serialized, lazy, request = @serde &request, 1));
+    control::disable("serialized");
+    callback::remove_handler(handler);
+
+    let decoded = DECODED.lock().unwrap();
+    assert_eq!(
+        *decoded,
+        [("eager", request.clone()), ("lazy", request.clone())]
+    );
+}
@@ -0,0 +1,21 @@
+#![cfg(all(feature = "use_std", target_os = "linux"))]
+
+use probe::control;
+use std::fs;
+use std::process;
+
+// In its own test binary, since it moves the flags for the whole process.
+#[test]
+fn share() {
+    let name = format!("/probe-test-{}", process::id());
+    control::enable("before");
+    control::share(&name).unwrap();
+    assert!(control::is_enabled("before"));
+
+    // Another process would see the flags in the segment.
+    control::enable_probe("after", "probe");
+    let path = format!("/dev/shm{}", name);
+    let segment = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(segment.iter().map(|b| b.count_ones()).sum::<u32>(), 2);
+}
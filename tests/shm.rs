@@ -0,0 +1,81 @@
+#![cfg(all(
+    feature = "shm",
+    target_has_atomic = "64",
+    target_pointer_width = "64",
+    target_os = "linux"
+))]
+
+use probe::{control, probe, shm};
+use std::io::ErrorKind;
+
+#[test]
+fn exported() {
+    let name = format!("/probe-test-shm-{}", std::process::id());
+    shm::start(&name, 8).unwrap();
+    control::enable("exported");
+    let mut reader = shm::Reader::open(&name).unwrap();
+
+    probe!(exported, first, 1, -2);
+    probe!(exported, wide, 1, 2, 3, 4, 5, 6, 7);
+    probe!(unexported, other, 3);
+
+    let events = reader.read();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        (&*events[0].provider, &*events[0].name, &*events[0].args),
+        ("exported", "first", &[1, -2][..])
+    );
+    assert_eq!(events[1].name, "wide");
+    assert_eq!(events[1].len, 7);
+    assert_eq!(events[1].args, [1, 2, 3, 4, 5, 6]);
+    assert!(events[0].time <= events[1].time);
+    assert!(reader.read().is_empty());
+
+    // Falling behind a full ring skips what was overwritten.
+    for i in 0..12 {
+        probe!(exported, looped, i);
+    }
+    let events = reader.read();
+    assert_eq!(reader.missed(), 4);
+    let args: Vec<_> = events.iter().map(|event| event.args[0]).collect();
+    assert_eq!(args, (4..12).collect::<Vec<_>>());
+
+    shm::stop();
+    probe!(exported, first, 1, -2);
+    assert!(reader.read().is_empty());
+
+    // A segment that's started with another capacity has to be opened again.
+    shm::start(&name, 16).unwrap();
+    probe!(exported, first, 1, -2);
+    assert!(reader.read().is_empty());
+    drop(reader);
+    let mut reader = shm::Reader::open(&name).unwrap();
+    probe!(exported, first, 3, -4);
+    assert_eq!(reader.read()[0].args, [3, -4]);
+    shm::stop();
+    control::disable("exported");
+    std::fs::remove_file(format!("/dev/shm{}", name)).unwrap();
+}
+
+#[test]
+fn not_a_segment() {
+    let name = format!("/probe-test-not-shm-{}", std::process::id());
+    let path = format!("/dev/shm{}", name);
+
+    // A header whose sizes are too large for the segment, or for any.
+    for capacity in [1u64 << 20, u64::MAX / 8] {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"probeshm");
+        for field in [1, capacity, 1024, 0, 0, 0, 0] {
+            header.extend_from_slice(&u64::to_le_bytes(field));
+        }
+        std::fs::write(&path, &header).unwrap();
+        let error = shm::Reader::open(&name).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    std::fs::write(&path, b"not a probe segment").unwrap();
+    let error = shm::Reader::open(&name).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "stderr")]
+
+use probe::{probe, probe_enabled, probe_fmt, probe_lazy};
+use std::env;
+use std::process::Command;
+
+// Fires the probes in a child process of the test, for its stderr.
+#[test]
+fn printed() {
+    if env::var_os("PROBE_STDERR_CHILD").is_some() {
+        assert_eq!(
+            probe_enabled!(printed, plain),
+            cfg!(feature = "force_enable")
+        );
+        probe!(printed, plain, 1, -2);
+        probe!(printed, named, len = 3, 4);
+        probe_fmt!(printed, formatted, "size=%d align=%#x", 64, 16);
+        if !probe_lazy!(printed, lazy, 5) {
+            probe!(printed, disabled);
+        }
+        return;
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["printed", "--exact", "--nocapture", "--test-threads=1"])
+        .env("PROBE_STDERR_CHILD", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = stderr
+        .lines()
+        .filter(|line| line.starts_with("probe printed:"))
+        .map(|line| line.split(" (").next().unwrap())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "probe printed:plain 1 -2",
+            "probe printed:named len=3 4",
+            "probe printed:formatted size=64 align=0x10",
+            if cfg!(feature = "force_enable") {
+                "probe printed:lazy 5"
+            } else {
+                "probe printed:disabled"
+            },
+        ]
+    );
+    assert!(stderr.contains(&format!(" ({}:", file!())));
+}
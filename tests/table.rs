@@ -0,0 +1,55 @@
+use probe::level::Level;
+use probe::rtt::id;
+use probe::table::{entries, Probe};
+
+#[test]
+fn decode() {
+    let mut section = Vec::new();
+    section.extend(id("foo", "loop").to_le_bytes());
+    section.extend(b"\x03foo\x04loop\x02\x06\x00\x07main.rs\x0a");
+    section.extend(id("foo", "begin").to_le_bytes());
+    section.extend(b"\x03foo\x05begin\x00\x04\x07begin%d\x07main.rs\x90\x03");
+    // A truncated entry ends the table.
+    section.extend(id("foo", "end").to_le_bytes());
+    section.extend(b"\x03fo");
+
+    let probes: Vec<_> = entries(&section).collect();
+    assert_eq!(
+        probes,
+        [
+            Probe {
+                id: id("foo", "loop"),
+                provider: "foo",
+                name: "loop",
+                args: 2,
+                level: Level::Trace,
+                format: None,
+                file: "main.rs",
+                line: 10,
+            },
+            Probe {
+                id: id("foo", "begin"),
+                provider: "foo",
+                name: "begin",
+                args: 0,
+                level: Level::Info,
+                format: Some("begin%d"),
+                file: "main.rs",
+                line: 400,
+            },
+        ]
+    );
+}
+
+#[test]
+fn format() {
+    let message = |format: &str, args: &[isize]| {
+        let mut message = String::new();
+        probe::format::write(&mut message, format, args).unwrap();
+        message
+    };
+    assert_eq!(message("size=%d align=%u", &[-1, 8]), "size=-1 align=8");
+    assert_eq!(message("%x %#X %p", &[255, 255, 16]), "ff 0XFF 0x10");
+    assert_eq!(message("100%% %ld", &[3]), "100% 3");
+    assert_eq!(message("%d %d %s", &[1]), "1 %d %s");
+}
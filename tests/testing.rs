@@ -0,0 +1,74 @@
+#![cfg(feature = "testing")]
+
+use probe::testing::{capture, Fired};
+use probe::{control, probe, probe_lazy};
+use std::thread;
+
+#[test]
+fn captured() {
+    assert!(!control::is_enabled("tested"));
+    let capture = capture("tested");
+    assert!(control::is_enabled("tested"));
+
+    probe!(tested, plain, 1, 2);
+    assert!(probe_lazy!(tested, lazy, len = 3));
+    probe!(untested, other, 4);
+    assert_eq!(
+        capture.fired(),
+        [
+            Fired {
+                provider: "tested",
+                name: "plain",
+                names: None,
+                args: vec![1, 2],
+            },
+            Fired {
+                provider: "tested",
+                name: "lazy",
+                names: Some("len"),
+                args: vec![3],
+            },
+        ]
+    );
+    capture.assert_fired("plain", &[1, 2]);
+    capture.assert_not_fired("other");
+    assert_eq!(capture.count("lazy"), 1);
+
+    // Only probes on this thread are captured.
+    thread::spawn(|| probe!(tested, threaded)).join().unwrap();
+    capture.assert_not_fired("threaded");
+
+    assert_eq!(capture.clear().len(), 2);
+    assert_eq!(capture.fired(), []);
+
+    drop(capture);
+    assert!(!control::is_enabled("tested"));
+}
+
+#[test]
+fn nested() {
+    let outer = capture("nested");
+    let inner = capture("nested");
+    probe!(nested, both);
+    drop(inner);
+    assert!(control::is_enabled("nested"));
+    probe!(nested, outer);
+    assert_eq!(outer.count("both"), 1);
+    assert_eq!(outer.count("outer"), 1);
+}
+
+#[test]
+#[should_panic(expected = "probe failed:fired [2] didn't fire, but these did:\n  failed:fired [1]")]
+fn not_fired() {
+    let capture = capture("failed");
+    probe!(failed, fired, 1);
+    capture.assert_fired("fired", &[2]);
+}
+
+#[test]
+#[should_panic(expected = "probe fails:fired fired:\n  fails:fired []")]
+fn fired() {
+    let capture = capture("fails");
+    probe!(fails, fired);
+    capture.assert_not_fired("fired");
+}
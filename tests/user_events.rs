@@ -0,0 +1,26 @@
+#![cfg(all(target_os = "linux", feature = "user_events", not(feature = "lttng")))]
+
+use probe::{probe, probe_lazy};
+
+#[test]
+fn not_enabled() {
+    // Nothing enables these events, whether or not they could be registered.
+    let mut x = 0;
+    probe!(test, foo);
+    probe!(
+        test,
+        bar,
+        {
+            x += 1;
+            x
+        },
+        42
+    );
+    assert_eq!(x, 1, "arguments are always evaluated");
+
+    assert!(!probe_lazy!(test, baz, {
+        x += 1;
+        x
+    }));
+    assert_eq!(x, 1, "lazy arguments are not evaluated");
+}